@@ -0,0 +1,101 @@
+//! Times parsing hdatas of buffer lines, like the relay sends for a sync or
+//! a backlog, at a few sizes. Run it with `cargo bench`.
+//!
+//! Parsing walks the message once, so the time per item should stay about
+//! the same as the hdata grows. This fails if the time per item for the
+//! largest hdata is more than MAX_GROWTH times that of the smallest, which
+//! is what a walk that copies the rest of the message at every step would
+//! look like.
+
+extern crate weechat_alert;
+
+use std::time::{Duration, Instant};
+
+use weechat_alert::hdata::HData;
+
+// Items in each hdata timed, smallest first
+const SIZES: [usize; 3] = [1000, 10000, 100000];
+
+// Times each hdata is parsed, the fastest of which counts
+const ROUNDS: usize = 5;
+
+// Most the time per item can grow from the smallest hdata to the largest.
+// Linear parsing stays near 1, leaving plenty of room for noise
+const MAX_GROWTH: f64 = 4.0;
+
+fn main() {
+    let mut per_item = Vec::new();
+    for &size in &SIZES {
+        let bytes = line_data(size);
+        let fastest = (0..ROUNDS).map(|_| time_parse(&bytes, size)).min().unwrap();
+        let total = nanos(fastest);
+        let item = total as f64 / size as f64;
+        println!("{:>6} items ({:>8} bytes): {:>6} us, {:.0} ns per item", size, bytes.len(), total / 1000, item);
+        per_item.push(item);
+    }
+
+    let growth = per_item[per_item.len() - 1] / per_item[0];
+    println!("Time per item grew {:.2}x from {} to {} items", growth, SIZES[0], SIZES[SIZES.len() - 1]);
+    if growth > MAX_GROWTH {
+        panic!("parsing an hdata doesn't scale linearly with its size (grew {:.2}x)", growth);
+    }
+}
+
+/// Parses the hdata once, checking it has every item
+fn time_parse(bytes: &[u8], size: usize) -> Duration {
+    let start = Instant::now();
+    let hdata = HData::new(bytes).unwrap();
+    let elapsed = start.elapsed();
+    assert_eq!(hdata.data.len(), size);
+    elapsed
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1000000000 + duration.subsec_nanos() as u64
+}
+
+/// The body of an hdata (after "hda") of buffer lines, with the keys a
+/// _buffer_line_added has
+fn line_data(items: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    push_str(&mut bytes, "line_data");
+    push_str(&mut bytes, "buffer:ptr,date:tim,displayed:chr,highlight:chr,tags_array:arr,prefix:str,message:str");
+    push_int(&mut bytes, items as i32);
+    for i in 0..items {
+        push_ptr(&mut bytes, &format!("{:x}", 0x55d3c0a00000 + i * 0x100));
+        push_ptr(&mut bytes, "55d3c0912340");
+        push_tim(&mut bytes, 1500000000 + i as i64);
+        bytes.push(1);
+        bytes.push(0);
+        bytes.extend_from_slice(b"str");
+        push_int(&mut bytes, 3);
+        push_str(&mut bytes, "irc_privmsg");
+        push_str(&mut bytes, "notify_message");
+        push_str(&mut bytes, &format!("nick_someone{}", i % 10));
+        push_str(&mut bytes, &format!("someone{}", i % 10));
+        push_str(&mut bytes, &format!("message number {} in a busy channel, long enough to be typical", i));
+    }
+    bytes
+}
+
+fn push_int(bytes: &mut Vec<u8>, value: i32) {
+    for shift in &[24, 16, 8, 0] {
+        bytes.push((value >> shift) as u8);
+    }
+}
+
+fn push_str(bytes: &mut Vec<u8>, s: &str) {
+    push_int(bytes, s.len() as i32);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+fn push_ptr(bytes: &mut Vec<u8>, ptr: &str) {
+    bytes.push(ptr.len() as u8);
+    bytes.extend_from_slice(ptr.as_bytes());
+}
+
+fn push_tim(bytes: &mut Vec<u8>, time: i64) {
+    let time = time.to_string();
+    bytes.push(time.len() as u8);
+    bytes.extend_from_slice(time.as_bytes());
+}
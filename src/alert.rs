@@ -0,0 +1,473 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use buffers::{self, Buffers};
+use commands::{Command, InputCommand};
+use handler::{CommandSender, RelayHandler};
+use line::BufferLine;
+use notify::{self, EventClass, NotificationEvent, Notifiers};
+use notify::template::Template;
+
+/// Tag on the lines we print when forwarding notifications into a buffer
+/// (see AlertHandler::set_forward). Lines with it never alert, so a
+/// forwarded line coming back through the sync can't forward itself again.
+pub const FORWARDED_TAG: &'static str = "weechat_alert_forward";
+
+/// Format of the lines forwarded into a buffer, if forward_format isn't set
+pub const DEFAULT_FORWARD_FORMAT: &'static str = "[{class}] {buffer} <{nick}> {message}";
+
+/// Why a line alerted. This is more detailed than its EventClass, which it
+/// decides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlertReason {
+    /// The line is a private message (see line_reason)
+    Private,
+    /// Weechat flagged or tagged the line as a highlight
+    Highlight,
+    /// The line is in a watched buffer, matched by this watch_buffers
+    /// pattern
+    Watch(String),
+}
+
+impl AlertReason {
+    /// The class of events alerting for this reason
+    pub fn class(&self) -> EventClass {
+        match *self {
+            AlertReason::Private   => EventClass::Private,
+            AlertReason::Highlight => EventClass::Highlight,
+            AlertReason::Watch(_)  => EventClass::Watch,
+        }
+    }
+}
+
+/// Describes the reason for logs and notifications (ex: "highlight", or
+/// "watched buffer 'irc.libera.#rust'")
+impl fmt::Display for AlertReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AlertReason::Private             => write!(f, "private message"),
+            AlertReason::Highlight           => write!(f, "highlight"),
+            AlertReason::Watch(ref pattern)  => write!(f, "watched buffer '{}'", pattern),
+        }
+    }
+}
+
+/// Rules deciding which buffer lines we should alert on
+#[derive(Debug)]
+pub struct AlertRules {
+    /// Buffer names or patterns where every line alerts, not just highlights
+    /// and private messages
+    pub watch_buffers: Vec<String>,
+    /// Buffer names or patterns that never alert
+    pub ignore_buffers: Vec<String>,
+    /// Never alert on lines we sent ourselves (from this or another client)
+    pub ignore_self: bool,
+    /// Our nicks, for spotting our own lines in buffers weechat hasn't told
+    /// us our nick in
+    pub own_nicks: Vec<String>,
+    /// Never alert on lines hidden by one of the user's weechat filters
+    pub respect_display_filter: bool,
+    /// Never alert on lines dated longer ago than this (ex: a backlog played
+    /// back after reconnecting). 0 alerts on lines of any age
+    pub max_line_age: Duration,
+}
+
+impl AlertRules {
+    /// Creates the default rules: alert on highlights and private messages
+    /// that we didn't send ourselves and that weechat shows
+    pub fn new() -> AlertRules {
+        AlertRules {
+            watch_buffers: Vec::new(),
+            ignore_buffers: Vec::new(),
+            ignore_self: true,
+            own_nicks: Vec::new(),
+            respect_display_filter: true,
+            max_line_age: Duration::from_secs(0),
+        }
+    }
+
+    /// Checks if the buffer is on the watch list
+    pub fn is_watched(&self, buffer_name: Option<&str>) -> bool {
+        self.watch_pattern(buffer_name).is_some()
+    }
+
+    /// The first watch_buffers pattern matching the buffer, if any
+    pub fn watch_pattern(&self, buffer_name: Option<&str>) -> Option<&str> {
+        match buffer_name {
+            Some(name) => self.watch_buffers.iter().find(|p| buffers::matches(p, name)).map(|p| p.as_str()),
+            None       => None,
+        }
+    }
+
+    /// Checks if the buffer is on the ignore list
+    pub fn is_ignored(&self, buffer_name: Option<&str>) -> bool {
+        match buffer_name {
+            Some(name) => self.ignore_buffers.iter().any(|p| buffers::matches(p, name)),
+            None       => false,
+        }
+    }
+
+    /// Decides if a line from _buffer_line_added should alert, and why. Our
+    /// own lines never alert (unless ignore_self is off), and neither do lines
+    /// a weechat filter hides (unless respect_display_filter is off), even in
+    /// watched buffers or if weechat flagged them as a highlight. Otherwise
+    /// private messages and highlights alert as such, and any other line in a
+    /// watched buffer alerts as a watch event (with the pattern that matched
+    /// the buffer). A buffer that is both watched and ignored counts as
+    /// watched, so a broad ignore pattern can be combined with watching a few
+    /// specific buffers it covers. Lines older than max_line_age never alert.
+    ///
+    /// `buffer_name` is None if we don't (yet) know the name of the buffer
+    /// the line was added to, in which case the watch and ignore lists don't
+    /// apply. `own_nick` is our nick in the buffer, if weechat told us it,
+    /// and `private_buffer` if the buffer is a private one (see
+    /// line_reason).
+    pub fn classify(&self, buffer_name: Option<&str>, own_nick: Option<&str>, private_buffer: Option<bool>,
+                    line: &BufferLine) -> Option<AlertReason> {
+        let buffer = buffer_name.unwrap_or(line.buffer());
+        if line.has_tag(FORWARDED_TAG) {
+            trace!("Not alerting on a line in {}: we forwarded it", buffer);
+            return None;
+        }
+        if self.is_stale(line, notify::now()) {
+            debug!("Not alerting on a line in {}: it is older than max_line_age", buffer);
+            return None;
+        }
+        if self.ignore_self && self.is_self(own_nick, line) {
+            debug!("Not alerting on a line in {}: it is our own, or tagged to never notify", buffer);
+            return None;
+        }
+        if self.respect_display_filter && !line.displayed() {
+            debug!("Not alerting on a line in {}: a weechat filter hides it", buffer);
+            return None;
+        }
+        let watched = self.watch_pattern(buffer_name);
+        if watched.is_none() && self.is_ignored(buffer_name) {
+            debug!("Not alerting on a line in {}: the buffer is in ignore_buffers", buffer);
+            return None;
+        }
+
+        let reason = line_reason(private_buffer, line)
+                         .or(watched.map(|pattern| AlertReason::Watch(pattern.to_string())));
+        if reason.is_none() {
+            trace!("Not alerting on a line in {}: it isn't a private message, a highlight or watched", buffer);
+        }
+        reason
+    }
+
+    /// Checks if a line is older than max_line_age at now (both epoch
+    /// timestamps, so the time zone doesn't matter). Lines without a date,
+    /// and ones dated after now (a clock ahead of ours), aren't stale
+    fn is_stale(&self, line: &BufferLine, now: i64) -> bool {
+        let max_age = self.max_line_age.as_secs() as i64;
+        max_age > 0 && line.date() != 0 && now - line.date() > max_age
+    }
+
+    /// Checks if a line was sent by us, or is otherwise flagged by weechat
+    /// as something that should never notify. Weechat tags our lines
+    /// self_msg, but not ones from another client that come back to us (ex:
+    /// through a bouncer, or the echo-message capability), so a line tagged
+    /// nick_<our nick> counts too. Nicks are compared ignoring case, like irc
+    /// does.
+    fn is_self(&self, own_nick: Option<&str>, line: &BufferLine) -> bool {
+        if line.has_tag("self_msg") || line.has_tag("no_notify") || line.has_tag("no_highlight") {
+            return true;
+        }
+        let sender = match line.tags().iter().find(|t| t.starts_with("nick_")) {
+            Some(tag) => tag[5..].to_lowercase(),
+            None      => return false,
+        };
+        own_nick.into_iter().chain(self.own_nicks.iter().map(|n| n.as_str()))
+            .any(|nick| nick.to_lowercase() == sender)
+    }
+}
+
+/// Decides if a line is a private message or a highlight, going by the kind
+/// of buffer it is in where we know it (from the buffer's "type" local
+/// variable), as the notify_private tag alone can be wrong both ways: some
+/// scripts add it to lines in channels, and some lines in queries (ex: played
+/// back by a bouncer) don't have it.
+///
+/// In a private buffer, any line weechat would notify on is a private message,
+/// which leaves out joins, quits and the like (lines with no notify tag, or
+/// notify_none). In any other buffer, only highlights (the highlight flag or
+/// the notify_highlight tag) count. When we don't know the kind of buffer,
+/// the notify_private tag decides, as weechat sets it.
+pub fn line_reason(private_buffer: Option<bool>, line: &BufferLine) -> Option<AlertReason> {
+    let highlight = line.highlight() || line.has_tag("notify_highlight");
+    match private_buffer {
+        Some(true)  => {
+            let notifies = highlight || line.has_tag("notify_private") || line.has_tag("notify_message");
+            if notifies { Some(AlertReason::Private) } else { None }
+        },
+        Some(false) => if highlight { Some(AlertReason::Highlight) } else { None },
+        None        => {
+            if line.has_tag("notify_private") {
+                Some(AlertReason::Private)
+            } else if highlight {
+                Some(AlertReason::Highlight)
+            } else {
+                None
+            }
+        },
+    }
+}
+
+/// The RelayHandler weechat-alert runs its relay with: every line added to a
+/// buffer is classified by the alert rules, and the ones that alert are
+/// logged and sent to the notifiers (the same ones the relay alerts on
+/// connection problems with).
+pub struct AlertHandler {
+    rules: AlertRules,
+    notifiers: Rc<Notifiers>,
+    /// The relay's buffers, for the name, our nick and the kind of the buffer
+    /// a line is in
+    buffers: Buffers,
+    /// Minimum time between notifications from the same buffer, unless the
+    /// buffer's settings have their own
+    cooldown: Duration,
+    /// When each buffer (by name, or pointer if we don't know its name) last
+    /// notified, and how many lines its cooldown has skipped since
+    last_alerts: HashMap<String, (Instant, usize)>,
+    /// Buffer every notification is also printed in, and the format of the
+    /// line, if we forward them
+    forward: Option<(String, Template)>,
+    /// Where forwarded lines are sent, once the relay has connected
+    commands: Option<CommandSender>,
+}
+
+impl AlertHandler {
+    /// A handler alerting by the rules, with the buffers and notifiers of
+    /// the relay it is for (see Relay::buffers and Relay::notifiers)
+    pub fn new(rules: AlertRules, buffers: Buffers, notifiers: Rc<Notifiers>) -> AlertHandler {
+        AlertHandler {
+            rules: rules,
+            notifiers: notifiers,
+            buffers: buffers,
+            cooldown: Duration::from_secs(0),
+            last_alerts: HashMap::new(),
+            forward: None,
+            commands: None,
+        }
+    }
+
+    /// Sets the minimum time between notifications from the same buffer. Zero
+    /// (the default) notifies on every line.
+    pub fn set_cooldown(&mut self, cooldown: Duration) {
+        self.cooldown = cooldown;
+    }
+
+    /// Also prints a line for every notification in a weechat buffer (by
+    /// its full name, ex: "core.weechat"), through the relay, so clients of
+    /// the relay (such as one on your phone) see every notification in one
+    /// place. The lines are only printed locally, never sent to irc, and are
+    /// tagged so they never notify themselves. Nothing is forwarded in a
+    /// replay, as there is no relay to print in.
+    pub fn set_forward(&mut self, buffer: String, format: Template) {
+        self.forward = Some((buffer, format));
+    }
+
+    /// Prints the line for a notification in the forward buffer, if there
+    /// is one
+    fn forward(&self, event: &NotificationEvent) {
+        if let (Some(&(ref buffer, ref format)), Some(commands)) = (self.forward.as_ref(), self.commands.as_ref()) {
+            if let Err(e) = commands.send(&forward_command(buffer, &format.render(event)).render()) {
+                error!("could not forward a notification to {}: {}", buffer, e);
+            }
+        }
+    }
+}
+
+impl RelayHandler for AlertHandler {
+    fn on_connect(&mut self, commands: &CommandSender) {
+        self.commands = Some(commands.clone());
+    }
+
+    fn on_buffer_line(&mut self, line: &BufferLine, buffer_name: Option<&str>) {
+        // Notify on any line that has a highlight, is a private message, or
+        // is in a watched buffer
+        let own_nick = self.buffers.nick(line.buffer());
+        let own_nick = own_nick.as_ref().map(|s| s.as_str());
+        let private_buffer = self.buffers.is_private(line.buffer());
+        let reason = match self.rules.classify(buffer_name, own_nick, private_buffer, line) {
+            Some(reason) => reason,
+            None         => return,
+        };
+
+        // A class that is turned off doesn't alert at all, so its lines
+        // aren't logged either
+        if !self.notifiers.enabled(reason.class()) {
+            debug!("Not alerting on a {} in {}: the {} class is disabled",
+                   reason, buffer_name.unwrap_or(line.buffer()), reason.class().name());
+            return;
+        }
+
+        // Only notify once per cooldown for each buffer, so a burst of lines
+        // in one buffer doesn't hold up another buffer's. Suppressed lines
+        // still make it to the json log, and are counted for the buffer's
+        // next notification. Nothing notifies while muted, and that doesn't
+        // start a cooldown
+        let mut suppressed = self.notifiers.muted();
+        let mut skipped = 0;
+        let cooldown = self.notifiers.buffer_cooldown(buffer_name).unwrap_or(self.cooldown);
+        if !suppressed && cooldown > Duration::from_secs(0) {
+            let now = Instant::now();
+            let key = buffer_name.unwrap_or(line.buffer()).to_string();
+            if let Some(&mut (last, ref mut count)) = self.last_alerts.get_mut(&key) {
+                if now.duration_since(last) < cooldown {
+                    *count += 1;
+                    suppressed = true;
+                }
+            }
+            if !suppressed {
+                skipped = self.last_alerts.insert(key, (now, 0)).map_or(0, |(_, count)| count);
+            }
+        }
+
+        let mut event = self.notifiers.event(reason, buffer_name, line);
+        self.notifiers.log(&event, suppressed);
+        let buffer = buffer_name.unwrap_or(line.buffer());
+        if suppressed {
+            let why = if self.notifiers.muted() { "muted" } else { "the buffer is in its cooldown" };
+            debug!("Suppressed a {} in {}: {}", event.reason, buffer, why);
+        } else {
+            debug!("Notifying on a {} in {}", event.reason, buffer);
+            if skipped > 0 {
+                event.message.push_str(&format!(" (+{} more since last alert)", skipped));
+            }
+            self.notifiers.notify(&event);
+            self.forward(&event);
+        }
+    }
+}
+
+/// The command printing a line in a buffer. It is run from the core buffer
+/// so it works whatever the target buffer is. The line is tagged so it
+/// never notifies (and weechat doesn't highlight or notify on it either).
+///
+/// The text can come from anyone on irc, so it is made safe for the relay
+/// protocol and /print: a newline would end the command (and start another
+/// one), a tab is where /print splits the prefix from the message, and a
+/// leading "-" would be read as an option.
+fn forward_command(buffer: &str, text: &str) -> InputCommand {
+    let mut text: String = text.chars()
+                               .filter(|&c| c != '\0')
+                               .map(|c| if c == '\n' || c == '\r' || c == '\t' { ' ' } else { c })
+                               .collect();
+    if text.starts_with('-') {
+        text.insert(0, '\\');
+    }
+    InputCommand::new("core.weechat",
+                      &format!("/print -buffer {} -tags {},no_highlight,notify_none {}", buffer, FORWARDED_TAG, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    use super::{line_reason, AlertHandler, AlertReason, AlertRules};
+    use buffers::Buffers;
+    use handler::RelayHandler;
+    use notify::{self, EventClass, NotificationEvent, Notifier, NotifyError, Notifiers, Settings};
+    use testing::{dated_line, line};
+
+    /// A backend keeping the reason and message of every notification
+    struct Recorder {
+        notified: Rc<RefCell<Vec<(AlertReason, String)>>>,
+    }
+
+    impl Notifier for Recorder {
+        fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+            self.notified.borrow_mut().push((event.reason.clone(), event.message.clone()));
+            Ok(())
+        }
+    }
+
+    /// A handler notifying the recorder, with the settings given
+    fn recorded(settings: Settings) -> (AlertHandler, Rc<RefCell<Vec<(AlertReason, String)>>>) {
+        let notified = Rc::new(RefCell::new(Vec::new()));
+        let mut notifiers = Notifiers::new(settings);
+        notifiers.register("recorder", Box::new(Recorder { notified: notified.clone() }));
+        let handler = AlertHandler::new(AlertRules::new(), Buffers::new(), Rc::new(notifiers));
+        (handler, notified)
+    }
+
+    #[test]
+    fn our_own_highlights_dont_alert() {
+        let line = line("55aa", "me", "me: testing my highlight", &["irc_privmsg", "self_msg"], true);
+        let mut rules = AlertRules::new();
+        assert_eq!(rules.classify(Some("irc.libera.#rust"), Some("me"), Some(false), &line), None);
+
+        rules.ignore_self = false;
+        assert_eq!(rules.classify(Some("irc.libera.#rust"), Some("me"), Some(false), &line),
+                   Some(AlertReason::Highlight));
+    }
+
+    #[test]
+    fn old_lines_dont_alert() {
+        let mut rules = AlertRules::new();
+        let now = notify::now();
+        let classify = |rules: &AlertRules, date| {
+            rules.classify(Some("irc.libera.alice"), Some("me"), Some(true), &dated_line(date, "55aa", "hi"))
+        };
+        // With max_line_age unset, even a day old line (ex: from a bouncer's
+        // playback) alerts
+        assert_eq!(classify(&rules, now - 86400), Some(AlertReason::Private));
+
+        rules.max_line_age = Duration::from_secs(600);
+        assert_eq!(classify(&rules, now - 86400), None);
+        assert_eq!(classify(&rules, now - 601), None);
+        assert_eq!(classify(&rules, now - 60), Some(AlertReason::Private));
+        // A sender whose clock is ahead, and a line without a date
+        assert_eq!(classify(&rules, now + 3600), Some(AlertReason::Private));
+        assert_eq!(classify(&rules, 0), Some(AlertReason::Private));
+        assert!(!rules.is_stale(&dated_line(now - 600, "55aa", "hi"), now));
+        assert!(rules.is_stale(&dated_line(now - 601, "55aa", "hi"), now));
+    }
+
+    #[test]
+    fn disabled_classes_dont_notify() {
+        let mut settings = Settings::new();
+        settings.classes.get_mut(EventClass::Highlight).enabled = false;
+        let (mut handler, notified) = recorded(settings);
+        handler.on_buffer_line(&line("55aa", "alice", "me: look", &["irc_privmsg"], true), Some("irc.libera.#rust"));
+        handler.on_buffer_line(&line("66bb", "bob", "hi", &["notify_private"], false), Some("irc.libera.bob"));
+        assert_eq!(*notified.borrow(), vec![(AlertReason::Private, "hi".to_string())]);
+
+        let (mut handler, notified) = recorded(Settings::new());
+        handler.on_buffer_line(&line("55aa", "alice", "me: look", &["irc_privmsg"], true), Some("irc.libera.#rust"));
+        assert_eq!(*notified.borrow(), vec![(AlertReason::Highlight, "me: look".to_string())]);
+    }
+
+    #[test]
+    fn line_reason_by_tags_flag_and_buffer_type() {
+        // (tags, highlight flag, private buffer, reason)
+        let cases: &[(&[&str], bool, Option<bool>, Option<AlertReason>)] = &[
+            (&["notify_private"],   false, Some(true),  Some(AlertReason::Private)),
+            (&["notify_message"],   false, Some(true),  Some(AlertReason::Private)),
+            (&["notify_highlight"], false, Some(true),  Some(AlertReason::Private)),
+            (&[],                   true,  Some(true),  Some(AlertReason::Private)),
+            (&["irc_join"],         false, Some(true),  None),
+            (&["notify_none"],      false, Some(true),  None),
+            (&["notify_private"],   false, Some(false), None),
+            (&["notify_message"],   false, Some(false), None),
+            (&["notify_highlight"], false, Some(false), Some(AlertReason::Highlight)),
+            (&[],                   true,  Some(false), Some(AlertReason::Highlight)),
+            (&["notify_private"],   false, None,        Some(AlertReason::Private)),
+            (&["notify_private"],   true,  None,        Some(AlertReason::Private)),
+            (&["notify_highlight"], false, None,        Some(AlertReason::Highlight)),
+            (&[],                   true,  None,        Some(AlertReason::Highlight)),
+            (&["notify_message"],   false, None,        None),
+            (&[],                   false, None,        None),
+        ];
+        for &(tags, highlight, private_buffer, ref reason) in cases {
+            let line = line("55aa", "alice", "hi", tags, highlight);
+            assert_eq!(line_reason(private_buffer, &line), *reason,
+                       "tags {:?}, highlight {}, private buffer {:?}", tags, highlight, private_buffer);
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use futures::{future, Future};
+use tokio_core::net::TcpStream;
+use tokio_core::reactor::Handle;
+use tokio_io::io::{read_exact, write_all};
+
+use commands::{Command, InitCommand, PingCommand};
+use connection::{is_closed, terminate, Compression, HEADER_LENGTH};
+use errors::WeechatError;
+use message::{Header, Message};
+
+/// A future resolving to the relay (so it can be used again) along with a
+/// value read from it
+pub type RelayFuture<T> = Box<Future<Item = (AsyncRelay, T), Error = WeechatError>>;
+
+/// A future resolving to the relay once a command has been sent
+pub type SendFuture = Box<Future<Item = AsyncRelay, Error = WeechatError>>;
+
+/// An asynchronous (tokio) client for a weechat relay, for running the relay
+/// protocol inside of an event loop. This only replaces the transport:
+/// messages are parsed with the same decoders as the blocking Relay.
+///
+/// Every call takes the relay by value and hands it back when its future
+/// resolves, so there is only ever one read or write in flight. Dropping the
+/// relay closes the socket.
+///
+/// Note: Ssl is not supported yet.
+pub struct AsyncRelay {
+    stream: TcpStream,
+    /// String terminating every command we send
+    terminator: String,
+}
+
+impl AsyncRelay {
+    /// Connects to the relay and inits the relay protocol with our password.
+    /// Resolves to a BadPassword error if the relay doesn't accept it, or
+    /// PasswordRequired if we have none and the relay wants one.
+    pub fn connect(host: &str, port: i32, password: Option<&str>, terminator: &str,
+                   handle: &Handle) -> SendFuture {
+        let addr = match resolve(host, port) {
+            Ok(addr) => addr,
+            Err(e)   => return Box::new(future::err(e)),
+        };
+        // Messages are read here without going through Connection, which
+        // is what inflates them
+        let init = InitCommand::new(password, Compression::Off).render();
+        let has_password = password.is_some();
+        let terminator = terminator.to_string();
+
+        // As with the blocking relay, a bad password is only reported by the
+        // relay closing the socket, so ping right after the init and treat
+        // the socket closing (or being reset) before the pong as a bad
+        // password
+        let relay = TcpStream::connect(&addr, handle)
+            .map_err(WeechatError::Io)
+            .map(move |stream| AsyncRelay { stream: stream, terminator: terminator })
+            .and_then(move |relay| relay.send_cmd(&init))
+            .and_then(|relay| relay.send_cmd(&PingCommand::new(None).render()))
+            .and_then(|relay| relay.next_message())
+            .map(|(relay, _)| relay)
+            .map_err(move |e| match e {
+                ref e if is_closed(e) => {
+                    if has_password { WeechatError::BadPassword } else { WeechatError::PasswordRequired }
+                },
+                e => e,
+            });
+        Box::new(relay)
+    }
+
+    /// Sends a command to the relay
+    pub fn send_cmd(self, cmd_str: &str) -> SendFuture {
+        let AsyncRelay { stream, terminator } = self;
+        let cmd_str = terminate(cmd_str, &terminator);
+        let sent = write_all(stream, cmd_str.into_bytes())
+            .map_err(WeechatError::Io)
+            .map(move |(stream, _)| AsyncRelay { stream: stream, terminator: terminator });
+        Box::new(sent)
+    }
+
+    /// Reads the next message from the relay, returning the (unparsed) bytes
+    /// that follow its header. This is useful for checking the identifier of
+    /// a message (see Message::identifier) before paying to parse all of it.
+    pub fn next_data(self) -> RelayFuture<Vec<u8>> {
+        let AsyncRelay { stream, terminator } = self;
+        let data = read_exact(stream, [0; HEADER_LENGTH])
+            .map_err(WeechatError::Io)
+            .and_then(|(stream, buffer)| Header::new(&buffer).map(|header| (stream, header)))
+            .and_then(|(stream, header)| {
+                read_exact(stream, vec![0; header.length]).map_err(WeechatError::Io)
+            })
+            .map(move |(stream, data)| {
+                (AsyncRelay { stream: stream, terminator: terminator }, data)
+            });
+        Box::new(data)
+    }
+
+    /// Reads and parses the next message from the relay
+    pub fn next_message(self) -> RelayFuture<Message> {
+        let msg = self.next_data().and_then(|(relay, data)| {
+            Message::new(data.as_slice()).map(|msg| (relay, msg))
+        });
+        Box::new(msg)
+    }
+}
+
+/// Looks up the address of the relay.
+///
+/// Note: This blocks the event loop while the host is resolved. Use an ip
+///       address as the host to avoid it.
+fn resolve(host: &str, port: i32) -> Result<SocketAddr, WeechatError> {
+    let mut addrs = try!((host, port as u16).to_socket_addrs());
+    addrs.next().ok_or(WeechatError::Io(io::Error::new(
+        io::ErrorKind::NotFound, format!("Could not resolve {}", host))))
+}
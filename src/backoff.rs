@@ -0,0 +1,185 @@
+use std::cmp;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sys;
+
+/// Seconds to wait before the first reconnect attempt, if not configured
+pub const DEFAULT_MIN_DELAY: u64 = 1;
+/// Most seconds to wait between reconnect attempts, if not configured
+pub const DEFAULT_MAX_DELAY: u64 = 300;
+
+/// How to reconnect after losing the relay: the delay starts at min_delay
+/// and doubles after every failed attempt, up to max_delay. The loss is only
+/// alerted on (disconnect sound, syslog, etc) once the relay has been gone
+/// for alert_after seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    pub min_delay: u64,
+    pub max_delay: u64,
+    pub alert_after: u64,
+}
+
+impl Backoff {
+    /// The default backoff, alerting as soon as the connection is lost
+    pub fn new() -> Backoff {
+        Backoff {
+            min_delay: DEFAULT_MIN_DELAY,
+            max_delay: DEFAULT_MAX_DELAY,
+            alert_after: 0,
+        }
+    }
+
+    /// The delay after the previous one, or the first delay if previous is 0
+    pub fn next_delay(&self, previous: u64) -> u64 {
+        if previous == 0 {
+            return self.min_delay;
+        }
+        cmp::max(self.min_delay, cmp::min(previous * 2, self.max_delay))
+    }
+}
+
+/// A time of day range (in local time) with its own backoff
+#[derive(Debug, Clone)]
+struct Window {
+    /// Minutes after midnight the window starts at
+    start: u32,
+    /// Minutes after midnight the window ends at (not included). If this is
+    /// before start, the window runs past midnight.
+    end: u32,
+    backoff: Backoff,
+}
+
+impl Window {
+    fn contains(&self, minute: u32) -> bool {
+        if self.start <= self.end {
+            self.start <= minute && minute < self.end
+        } else {
+            minute >= self.start || minute < self.end
+        }
+    }
+}
+
+/// Decides which backoff applies when the relay is lost: the default, or
+/// the first window covering the current time. This lets a known restart
+/// (ex: a nightly one at 04:00) be waited out patiently, while losing the
+/// relay any other time is retried quickly and alerted on.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    default: Backoff,
+    windows: Vec<Window>,
+}
+
+impl Schedule {
+    /// Creates a schedule that always uses the default backoff
+    pub fn new() -> Schedule {
+        Schedule {
+            default: Backoff::new(),
+            windows: Vec::new(),
+        }
+    }
+
+    /// Sets the backoff used outside of every window
+    pub fn set_default(&mut self, backoff: Backoff) {
+        self.default = backoff;
+    }
+
+    /// Adds a window, from start up to end in minutes after midnight. Windows
+    /// are checked in the order they were added.
+    pub fn add_window(&mut self, start: u32, end: u32, backoff: Backoff) {
+        self.windows.push(Window {
+            start: start,
+            end: end,
+            backoff: backoff,
+        });
+    }
+
+    /// The backoff for right now
+    pub fn current(&self) -> Backoff {
+        self.at(local_minute())
+    }
+
+    /// The backoff for a time of day, in minutes after midnight
+    pub fn at(&self, minute: u32) -> Backoff {
+        self.windows.iter().find(|w| w.contains(minute)).map(|w| w.backoff).unwrap_or(self.default)
+    }
+}
+
+/// Parses a time of day as "HH:MM" (24 hour) into minutes after midnight
+pub fn parse_time(time: &str) -> Option<u32> {
+    let mut parts = time.splitn(2, ':');
+    let hour = parts.next().and_then(|h| h.parse::<u32>().ok());
+    let minute = parts.next().and_then(|m| if m.len() == 2 { m.parse::<u32>().ok() } else { None });
+    match (hour, minute) {
+        (Some(h), Some(m)) if h < 24 && m < 60 => Some(h * 60 + m),
+        _                                      => None,
+    }
+}
+
+/// Minutes after midnight right now, in local time (or utc if the libc
+/// can't tell us the local time)
+fn local_minute() -> u32 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    match sys::local_time(now as i64) {
+        Some(t) => (t.hour * 60 + t.minute) as u32,
+        None    => (now % 86400 / 60) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_time, Backoff, Schedule};
+
+    fn backoff(min_delay: u64, max_delay: u64, alert_after: u64) -> Backoff {
+        Backoff {
+            min_delay: min_delay,
+            max_delay: max_delay,
+            alert_after: alert_after,
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_the_max() {
+        let backoff = backoff(2, 30, 0);
+        let mut delays = Vec::new();
+        let mut delay = 0;
+        for _ in 0..6 {
+            delay = backoff.next_delay(delay);
+            delays.push(delay);
+        }
+        assert_eq!(delays, vec![2, 4, 8, 16, 30, 30]);
+    }
+
+    #[test]
+    fn windows_override_the_default() {
+        let quick = backoff(1, 30, 0);
+        let night = backoff(30, 600, 1800);
+        let lunch = backoff(5, 60, 300);
+        let mut schedule = Schedule::new();
+        schedule.set_default(quick);
+        // 23:30 to 05:00, running past midnight
+        schedule.add_window(23 * 60 + 30, 5 * 60, night);
+        schedule.add_window(12 * 60, 13 * 60, lunch);
+        // The first window covering the time wins
+        schedule.add_window(4 * 60, 6 * 60, lunch);
+
+        assert_eq!(schedule.at(23 * 60 + 29), quick);
+        assert_eq!(schedule.at(23 * 60 + 30), night);
+        assert_eq!(schedule.at(0), night);
+        assert_eq!(schedule.at(4 * 60 + 30), night);
+        assert_eq!(schedule.at(5 * 60), lunch);
+        assert_eq!(schedule.at(12 * 60 + 59), lunch);
+        assert_eq!(schedule.at(13 * 60), quick);
+        assert_eq!(Schedule::new().at(4 * 60), Backoff::new());
+    }
+
+    #[test]
+    fn times_of_day() {
+        assert_eq!(parse_time("04:00"), Some(240));
+        assert_eq!(parse_time("4:05"), Some(245));
+        assert_eq!(parse_time("23:59"), Some(1439));
+        assert_eq!(parse_time("24:00"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("12:5"), None);
+        assert_eq!(parse_time("noon"), None);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use hdata::HData;
+use message::Object;
+
+/// What we know about a buffer
+#[derive(Debug, Clone)]
+struct Buffer {
+    /// Full name of the buffer (ex: irc.libera.#rust)
+    name: String,
+    /// Our nick in the buffer, from its "nick" local variable. Only irc
+    /// buffers have one.
+    nick: Option<String>,
+    /// Kind of buffer, from its "type" local variable (ex: "channel" or
+    /// "private"). Only irc buffers have one.
+    kind: Option<String>,
+}
+
+/// Keeps track of which weechat buffer lives at which pointer, so lines (which
+/// only reference their buffer by pointer) can be matched against buffer names
+/// and our nick in the buffer
+///
+/// Clones share the same map, so it can be read from other threads (ex: a
+/// backend's worker) while the relay loop keeps it up to date. The relay loop
+/// is the only writer. The lock is never held for longer than one call here:
+/// writers take it for the insertions and removals of a single hdata (and a
+/// refresh builds the new map before swapping it in), and readers copy what
+/// they need out rather than holding on to it. So no caller can deadlock on
+/// it or see a half updated hdata.
+#[derive(Debug, Clone)]
+pub struct Buffers {
+    buffers: Arc<RwLock<HashMap<String, Buffer>>>,
+}
+
+impl Buffers {
+    pub fn new() -> Buffers {
+        Buffers { buffers: Arc::new(RwLock::new(HashMap::new())) }
+    }
+
+    /// Replaces every buffer with the ones in an hdata, from the reply to the
+    /// initial buffer list request. Readers see either the old or the new
+    /// buffers, never none.
+    pub fn refresh(&self, hdata: &HData) {
+        let mut buffers = HashMap::new();
+        insert_all(&mut buffers, hdata);
+        *self.write() = buffers;
+    }
+
+    /// Adds (or updates) every buffer in an hdata whose items carry a buffer
+    /// pointer and a full_name. This covers the _buffer_opened,
+    /// _buffer_renamed and _buffer_localvar_* events.
+    pub fn add(&self, hdata: &HData) {
+        insert_all(&mut self.write(), hdata);
+    }
+
+    /// Forgets every buffer in an hdata (from a _buffer_closing event)
+    pub fn remove(&self, hdata: &HData) {
+        let mut buffers = self.write();
+        for item in &hdata.data {
+            if let Some(ptr) = item.get("buffer").and_then(|p| p.as_not_null_pointer().ok()) {
+                buffers.remove(ptr);
+            }
+        }
+    }
+
+    /// Returns the full name (ex: irc.libera.#rust) of the buffer at a pointer
+    pub fn name(&self, ptr: &str) -> Option<String> {
+        self.read().get(ptr).map(|b| b.name.clone())
+    }
+
+    /// If there is an open buffer with a full name (ex: irc.libera.#rust)
+    pub fn has_name(&self, name: &str) -> bool {
+        self.read().values().any(|b| b.name == name)
+    }
+
+    /// Returns our nick in the buffer at a pointer, if weechat told us it
+    pub fn nick(&self, ptr: &str) -> Option<String> {
+        self.read().get(ptr).and_then(|b| b.nick.clone())
+    }
+
+    /// If the buffer at a pointer is a private (query) buffer, or None if
+    /// weechat didn't tell us what kind of buffer it is
+    pub fn is_private(&self, ptr: &str) -> Option<bool> {
+        self.read().get(ptr).and_then(|b| b.kind.as_ref().map(|k| k == "private"))
+    }
+
+    // Every update leaves the map whole before it can panic, so a poisoned
+    // lock still holds a good map
+    fn read<'a>(&'a self) -> RwLockReadGuard<'a, HashMap<String, Buffer>> {
+        self.buffers.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write<'a>(&'a self) -> RwLockWriteGuard<'a, HashMap<String, Buffer>> {
+        self.buffers.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Inserts every buffer in an hdata (with its pointer, full_name, and nick
+/// and type from local_variables if they were asked for) into buffers
+fn insert_all(buffers: &mut HashMap<String, Buffer>, hdata: &HData) {
+    let nick_key = Object::Str(Some("nick".to_string()));
+    let type_key = Object::Str(Some("type".to_string()));
+    for item in &hdata.data {
+        let ptr = item.get("buffer").and_then(|p| p.as_not_null_pointer().ok());
+        let name = item.get("full_name").and_then(|n| n.as_not_null_str().ok());
+        let vars = item.get("local_variables").and_then(|v| v.as_hashtable().ok());
+        let var = |key: &Object| {
+            vars.and_then(|vars| vars.get(key))
+                .and_then(|v| v.as_not_null_str().ok())
+                .map(|v| v.to_string())
+        };
+        if let (Some(ptr), Some(name)) = (ptr, name) {
+            buffers.insert(ptr.to_string(), Buffer {
+                name: name.to_string(),
+                nick: var(&nick_key),
+                kind: var(&type_key),
+            });
+        }
+    }
+}
+
+/// Checks if a buffer name matches a pattern. A pattern is either a full
+/// buffer name, or a name where '*' matches any number of characters (ex:
+/// "irc.libera.*" or "*.#announcements"), the same as weechat does.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let name = name.as_bytes();
+
+    // Iterative wildcard match, backtracking to the last '*' seen
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            star = Some((p, n));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == name[n] {
+            p += 1;
+            n += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+
+    // Anything left in the pattern must be trailing stars
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    use super::Buffers;
+    use hdata::HData;
+    use testing::buffers_hdata;
+
+    fn buffers(buffers: &[(&str, &str)]) -> HData {
+        HData::new(&buffers_hdata(buffers)[3..]).unwrap()
+    }
+
+    #[test]
+    fn readers_see_whole_updates() {
+        let shared = Buffers::new();
+        let old = buffers(&[("55aa", "irc.libera.#rust"), ("66bb", "irc.libera.#weechat")]);
+        let new = buffers(&[("55aa", "irc.libera.#rust-beginners"), ("66bb", "irc.libera.#weechat-dev")]);
+        shared.refresh(&old);
+
+        // The reader checks that every buffer has a name from one hdata or
+        // the other, never none (a refresh caught half way)
+        let done = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (buffers, done) = (shared.clone(), done.clone());
+            thread::spawn(move || {
+                loop {
+                    let rust = buffers.name("55aa");
+                    let weechat = buffers.name("66bb");
+                    assert!(rust == Some("irc.libera.#rust".to_string()) ||
+                            rust == Some("irc.libera.#rust-beginners".to_string()), "{:?}", rust);
+                    assert!(weechat == Some("irc.libera.#weechat".to_string()) ||
+                            weechat == Some("irc.libera.#weechat-dev".to_string()), "{:?}", weechat);
+                    if done.load(Ordering::SeqCst) {
+                        return;
+                    }
+                }
+            })
+        };
+        for i in 0..2000 {
+            let hdata = if i % 2 == 0 { &new } else { &old };
+            if i % 3 == 0 { shared.add(hdata) } else { shared.refresh(hdata) }
+        }
+        done.store(true, Ordering::SeqCst);
+        reader.join().unwrap();
+        assert_eq!(shared.name("55aa"), Some("irc.libera.#rust".to_string()));
+    }
+}
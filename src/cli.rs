@@ -0,0 +1,362 @@
+use std::path::PathBuf;
+
+use getopts::{Matches, Options, ParsingStyle};
+
+use control;
+use notify::EventClass;
+use report;
+use test_notification::{self, TestEvent};
+
+// Subcommands, with what each does for the help. Anything else (including
+// no arguments at all) is run, so invocations from before there were
+// subcommands keep working
+const COMMANDS: [(&'static str, &'static str); 9] = [
+    ("run", "Connect to the relay and notify (the default)"),
+    ("check-config", "Check the config, printing every problem in it"),
+    ("test-notification", "Send a made up notification through every backend"),
+    ("parse-file", "Print every message in a file saved with --dump"),
+    ("ctl", "Send a command (ex: mute, history 5) to a running instance"),
+    ("report", "Sum up a day of the json_log"),
+    ("list-buffers", "List the buffers open in weechat"),
+    ("send", "Type text into a buffer, as if it were typed in weechat"),
+    ("help", "Print this, or the options of a subcommand (help <subcommand>)"),
+];
+
+/// Options every subcommand takes
+pub struct Global {
+    /// Config file to read instead of the usual one (--config)
+    pub config: Option<PathBuf>,
+    /// key=value overrides of the config (--set)
+    pub overrides: Vec<String>,
+    /// How many -v were given
+    pub verbosity: usize,
+}
+
+/// Options of run
+pub struct RunOptions {
+    /// Print how much we read from the relay every so often
+    pub stats: bool,
+    /// Print where our time goes every so often (and after a replay)
+    pub profile: bool,
+    /// Save every message read from the relay to a file
+    pub dump: Option<PathBuf>,
+    /// Save every message to a new file in a directory
+    pub dump_dir: Option<PathBuf>,
+    /// Run a dump through the notification logic instead of connecting
+    pub replay: Option<PathBuf>,
+    /// Print every message as it arrives
+    pub dump_messages: bool,
+    /// Print every message, without notifying
+    pub dump_only: bool,
+}
+
+/// Options of check-config
+pub struct CheckOptions {
+    /// Also check the server resolves
+    pub resolve: bool,
+}
+
+/// Options of test-notification
+pub struct TestOptions {
+    pub event: TestEvent,
+    /// Seconds to wait for backends that send in the background
+    pub wait: u64,
+}
+
+/// Options of report
+pub struct ReportOptions {
+    pub range: report::Range,
+    pub json: bool,
+}
+
+/// What to do, with the options for it
+pub enum Command {
+    Run(RunOptions),
+    /// Write the example config (--init-config, from before subcommands)
+    InitConfig,
+    CheckConfig(CheckOptions),
+    TestNotification(TestOptions),
+    /// A dump file to print the messages of
+    ParseFile(PathBuf),
+    /// A command for the control socket
+    Ctl(String),
+    Report(ReportOptions),
+    ListBuffers,
+    /// A buffer, and the text to type into it
+    Send(String, String),
+}
+
+/// Parses the command line (without the program name). Asking for help
+/// prints it and exits, and a bad command line is an error saying what is
+/// wrong with it.
+pub fn parse(args: &[String]) -> Result<(Global, Command), String> {
+    let (name, args) = match args.first().map(|a| a.as_str()) {
+        Some(name) if COMMANDS.iter().any(|&(command, _)| command == name) => (name, &args[1..]),
+        _                                                                  => ("run", args),
+    };
+    if name == "help" {
+        help(args.first().map(|a| a.as_str()));
+    }
+
+    let opts = options(name);
+    let matches = try!(opts.parse(args).map_err(|e| format!("{} (see weechat-alert help {})", e, name)));
+    if matches.opt_present("help") {
+        help(Some(name));
+    }
+    let global = Global {
+        config: matches.opt_str("config").map(PathBuf::from),
+        overrides: matches.opt_strs("set"),
+        verbosity: matches.opt_count("v"),
+    };
+
+    let command = match name {
+        "run"               => try!(parse_run(&matches)),
+        "check-config"      => {
+            try!(no_arguments(name, &matches));
+            Command::CheckConfig(CheckOptions { resolve: matches.opt_present("resolve") })
+        },
+        "test-notification" => try!(parse_test(&matches)),
+        "parse-file"        => Command::ParseFile(PathBuf::from(try!(one_argument(name, &matches, "a file")))),
+        "ctl"               => {
+            if matches.free.is_empty() {
+                return Err("ctl needs a command (ex: weechat-alert ctl mute)".to_string());
+            }
+            Command::Ctl(matches.free.join(" "))
+        },
+        "report"            => try!(parse_report(&matches)),
+        "list-buffers"      => {
+            try!(no_arguments(name, &matches));
+            Command::ListBuffers
+        },
+        "send"              => {
+            if matches.free.len() < 2 {
+                return Err("send needs a buffer and the text (ex: weechat-alert send irc.libera.#rust hi)"
+                           .to_string());
+            }
+            Command::Send(matches.free[0].clone(), matches.free[1..].join(" "))
+        },
+        _                   => unreachable!(),
+    };
+    Ok((global, command))
+}
+
+/// The options a subcommand takes, along with the global ones
+fn options(name: &str) -> Options {
+    let mut opts = Options::new();
+    opts.optopt("", "config", "Read this config file instead of the usual one", "FILE");
+    opts.optmulti("", "set", "Override a key of the config (ex: --set notifiers='[\"stdout\"]')", "KEY=VALUE");
+    opts.optflagmulti("v", "", "Log at debug level, whatever log_level says (-vv for trace)");
+    opts.optflag("h", "help", "Print the options of this subcommand");
+    match name {
+        "run"               => {
+            opts.optflag("", "stats", "Print how much is read from the relay every so often");
+            opts.optflag("", "profile", "Print where the time goes every so often, and after a replay");
+            opts.optopt("", "dump", "Append every message read from the relay to a file", "FILE");
+            opts.optopt("", "dump-dir", "Save every message to a new file in a directory", "DIR");
+            opts.optopt("", "replay", "Run a dump through the notification rules instead of connecting", "FILE");
+            opts.optflag("", "dump-messages", "Print every message as it arrives");
+            opts.optflag("", "dump-only", "Print every message, without notifying");
+            // From before there were subcommands
+            opts.optflag("", "init-config", "Write an example config for a new user");
+            opts.optflagopt("", "history", "Same as ctl history [COUNT]", "COUNT");
+        },
+        "check-config"      => {
+            opts.optflag("", "resolve", "Also check the server resolves (which needs the network)");
+        },
+        "test-notification" => {
+            opts.optopt("", "class", "Class of the notification (private, highlight or watch)", "CLASS");
+            opts.optopt("", "buffer", "Buffer the notification is from", "BUFFER");
+            opts.optopt("", "message", "Text of the notification", "TEXT");
+            opts.optopt("", "wait", "Seconds to wait for backends that send in the background (10)", "SECONDS");
+        },
+        "report"            => {
+            opts.optopt("", "since", "First day to report on (ex: 2024-05-01)", "DATE");
+            opts.optopt("", "until", "Last day to report on", "DATE");
+            opts.optflag("", "json", "Print the report as json");
+        },
+        _                   => (),
+    }
+    // ctl and send pass on whatever follows (ex: send core.weechat -x), so
+    // options are only read before their first argument
+    if name == "ctl" || name == "send" {
+        opts.parsing_style(ParsingStyle::StopAtFirstFree);
+    }
+    opts
+}
+
+/// Prints the subcommands, or the options of one of them, and exits
+fn help(name: Option<&str>) -> ! {
+    match name {
+        Some(name) if COMMANDS.iter().any(|&(command, _)| command == name) && name != "help" => {
+            let usage = match name {
+                "parse-file" => "weechat-alert parse-file [options] FILE",
+                "ctl"        => "weechat-alert ctl [options] COMMAND...",
+                "send"       => "weechat-alert send [options] BUFFER TEXT...",
+                _            => "",
+            };
+            let usage = if usage.is_empty() { format!("weechat-alert {} [options]", name) } else { usage.to_string() };
+            print!("{}", options(name).usage(&format!("Usage: {}", usage)));
+        },
+        _                                                                                    => {
+            println!("Usage: weechat-alert [subcommand] [options]\n\nSubcommands:");
+            for &(command, description) in &COMMANDS {
+                println!("    {:18} {}", command, description);
+            }
+            println!("\nWith no subcommand, weechat-alert runs. See weechat-alert help <subcommand> for the options \
+                      of each.");
+        },
+    }
+    ::std::process::exit(0);
+}
+
+fn parse_run(matches: &Matches) -> Result<Command, String> {
+    // The old flags for what are subcommands now run those instead
+    if matches.opt_present("init-config") {
+        return Ok(Command::InitConfig);
+    }
+    if matches.opt_present("history") {
+        // --history takes its count as the next argument too (--history 5)
+        let count = match matches.opt_str("history").or(matches.free.first().cloned()) {
+            Some(count) => {
+                try!(count.parse::<usize>().map_err(|_| format!("--history needs a count, not '{}'", count)))
+            },
+            None        => control::DEFAULT_HISTORY_COUNT,
+        };
+        return Ok(Command::Ctl(format!("history {}", count)));
+    }
+    // Subcommands went anywhere on the command line before there was a parser
+    if let Some(arg) = matches.free.first() {
+        if COMMANDS.iter().any(|&(command, _)| command == arg) {
+            return Err(format!("The subcommand has to come first (weechat-alert {} [options])", arg));
+        }
+    }
+    try!(no_arguments("run", matches));
+
+    let options = RunOptions {
+        stats: matches.opt_present("stats"),
+        profile: matches.opt_present("profile"),
+        dump: matches.opt_str("dump").map(PathBuf::from),
+        dump_dir: matches.opt_str("dump-dir").map(PathBuf::from),
+        replay: matches.opt_str("replay").map(PathBuf::from),
+        dump_messages: matches.opt_present("dump-messages"),
+        dump_only: matches.opt_present("dump-only"),
+    };
+    if options.dump.is_some() && options.dump_dir.is_some() {
+        return Err("--dump and --dump-dir can't be used together".to_string());
+    }
+    Ok(Command::Run(options))
+}
+
+fn parse_test(matches: &Matches) -> Result<Command, String> {
+    try!(no_arguments("test-notification", matches));
+    let mut event = TestEvent::new();
+    if let Some(name) = matches.opt_str("class") {
+        event.class = try!(EventClass::from_name(&name)
+            .ok_or(format!("--class must be private, highlight or watch, not '{}'", name)));
+    }
+    if let Some(buffer) = matches.opt_str("buffer") {
+        event.buffer = buffer;
+    }
+    if let Some(message) = matches.opt_str("message") {
+        event.message = message;
+    }
+    let wait = match matches.opt_str("wait") {
+        Some(seconds) => {
+            try!(seconds.parse::<u64>().map_err(|_| format!("--wait needs a number of seconds, not '{}'", seconds)))
+        },
+        None          => test_notification::DEFAULT_WAIT,
+    };
+    Ok(Command::TestNotification(TestOptions {
+        event: event,
+        wait: wait,
+    }))
+}
+
+fn parse_report(matches: &Matches) -> Result<Command, String> {
+    try!(no_arguments("report", matches));
+    let since = match matches.opt_str("since") {
+        Some(day) => Some(try!(report::parse_day("--since", &day))),
+        None      => None,
+    };
+    let until = match matches.opt_str("until") {
+        Some(day) => Some(try!(report::parse_day("--until", &day))),
+        None      => None,
+    };
+    let range = if since.is_none() && until.is_none() {
+        report::Range::today()
+    } else {
+        report::Range {
+            since: since,
+            until: until,
+        }
+    };
+    Ok(Command::Report(ReportOptions {
+        range: range,
+        json: matches.opt_present("json"),
+    }))
+}
+
+/// Fails if a subcommand that takes no arguments was given some
+fn no_arguments(name: &str, matches: &Matches) -> Result<(), String> {
+    match matches.free.first() {
+        Some(arg) => Err(format!("Unexpected argument '{}' (see weechat-alert help {})", arg, name)),
+        None      => Ok(()),
+    }
+}
+
+/// The only argument of a subcommand that takes one
+fn one_argument(name: &str, matches: &Matches, what: &str) -> Result<String, String> {
+    match matches.free.len() {
+        1 => Ok(matches.free[0].clone()),
+        0 => Err(format!("{} needs {}", name, what)),
+        _ => Err(format!("{} takes just {}", name, what)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Command};
+    use notify::EventClass;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_notification_options() {
+        match parse(&args("test-notification --class private --buffer irc.libera.bob --message hi --wait 2")) {
+            Ok((_, Command::TestNotification(options))) => {
+                assert_eq!(options.event.class, EventClass::Private);
+                assert_eq!(options.event.buffer, "irc.libera.bob");
+                assert_eq!(options.event.message, "hi");
+                assert_eq!(options.wait, 2);
+            },
+            _                                           => panic!("expected test-notification"),
+        }
+        match parse(&args("test-notification")) {
+            Ok((_, Command::TestNotification(options))) => {
+                assert_eq!(options.event.class, EventClass::Highlight);
+                assert_eq!(options.wait, 10);
+            },
+            _                                           => panic!("expected test-notification"),
+        }
+        assert!(parse(&args("test-notification --class loud")).err().unwrap().contains("--class"));
+        assert!(parse(&args("test-notification --wait soon")).err().unwrap().contains("--wait"));
+    }
+
+    #[test]
+    fn check_config_options() {
+        match parse(&args("check-config --resolve --config other.toml")) {
+            Ok((global, Command::CheckConfig(options))) => {
+                assert!(options.resolve);
+                assert_eq!(global.config.unwrap().to_str(), Some("other.toml"));
+            },
+            _                                           => panic!("expected check-config"),
+        }
+        match parse(&args("check-config")) {
+            Ok((_, Command::CheckConfig(options))) => assert!(!options.resolve),
+            _                                      => panic!("expected check-config"),
+        }
+        assert!(parse(&args("check-config relay.toml")).is_err());
+    }
+}
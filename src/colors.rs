@@ -0,0 +1,169 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+// Weechat color codes. See:
+// https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html#colors
+const WEECHAT_COLOR: char = '\x19';
+const WEECHAT_SET_ATTR: char = '\x1A';
+const WEECHAT_REMOVE_ATTR: char = '\x1B';
+const WEECHAT_RESET: char = '\x1C';
+
+// Raw irc (mIRC) formatting codes
+const IRC_BOLD: char = '\x02';
+const IRC_COLOR: char = '\x03';
+const IRC_HEX_COLOR: char = '\x04';
+const IRC_RESET: char = '\x0F';
+const IRC_MONOSPACE: char = '\x11';
+const IRC_REVERSE: char = '\x16';
+const IRC_ITALIC: char = '\x1D';
+const IRC_STRIKETHROUGH: char = '\x1E';
+const IRC_UNDERLINE: char = '\x1F';
+
+/// Removes weechat color codes and irc formatting codes (colors, bold,
+/// underline, etc) from a string, leaving just the text
+pub fn strip(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            WEECHAT_COLOR                             => skip_weechat_color(&mut chars),
+            WEECHAT_SET_ATTR | WEECHAT_REMOVE_ATTR    => { chars.next(); },
+            WEECHAT_RESET                             => (),
+            IRC_COLOR                                 => skip_irc_color(&mut chars, 2, is_digit),
+            IRC_HEX_COLOR                             => skip_irc_color(&mut chars, 6, is_hex_digit),
+            IRC_BOLD | IRC_RESET | IRC_MONOSPACE |
+            IRC_REVERSE | IRC_ITALIC |
+            IRC_STRIKETHROUGH | IRC_UNDERLINE         => (),
+            _                                         => out.push(c),
+        }
+    }
+    out
+}
+
+/// Skips the rest of a weechat color code, after the leading 0x19
+fn skip_weechat_color(chars: &mut Peekable<Chars>) {
+    match chars.peek().cloned() {
+        // Standard or extended color
+        Some(c) if is_digit(c) || c == '@' => skip_weechat_color_number(chars),
+        // Foreground or background color, with optional attributes
+        Some('F') | Some('B') => {
+            chars.next();
+            skip_weechat_attrs(chars);
+            skip_weechat_color_number(chars);
+        },
+        // Foreground color, optionally followed by a background color
+        Some('*') => {
+            chars.next();
+            skip_weechat_attrs(chars);
+            skip_weechat_color_number(chars);
+            if let Some(&sep) = chars.peek() {
+                if sep == ',' || sep == '~' {
+                    chars.next();
+                    skip_weechat_color_number(chars);
+                }
+            }
+        },
+        // Bar color codes are a single char
+        Some('b') => {
+            chars.next();
+            chars.next();
+        },
+        // Reset color, keeping attributes
+        Some(WEECHAT_RESET) => {
+            chars.next();
+        },
+        _ => (),
+    }
+}
+
+/// Skips weechat attribute chars (bold, reverse, italic, underline, keep)
+fn skip_weechat_attrs(chars: &mut Peekable<Chars>) {
+    while let Some(&c) = chars.peek() {
+        match c {
+            '*' | '!' | '/' | '_' | '|' => { chars.next(); },
+            _                           => break,
+        }
+    }
+}
+
+/// Skips a weechat color number: 2 digits, or '@' and 5 digits for an
+/// extended color
+fn skip_weechat_color_number(chars: &mut Peekable<Chars>) {
+    if chars.peek() == Some(&'@') {
+        chars.next();
+        skip_while(chars, 5, is_digit);
+    } else {
+        skip_while(chars, 2, is_digit);
+    }
+}
+
+/// Skips the rest of an irc color code, after the leading 0x03 (or 0x04 for
+/// hex colors): an optional foreground color, optionally followed by a comma
+/// and a background color. The comma is only part of the code if a color
+/// follows it, otherwise it is text.
+fn skip_irc_color(chars: &mut Peekable<Chars>, max_len: usize, valid: fn(char) -> bool) {
+    if skip_while(chars, max_len, valid) == 0 {
+        return;
+    }
+
+    if chars.peek() == Some(&',') {
+        let mut lookahead = chars.clone();
+        lookahead.next();
+        if lookahead.peek().map_or(false, |&c| valid(c)) {
+            chars.next();
+            skip_while(chars, max_len, valid);
+        }
+    }
+}
+
+/// Skips up to max chars that pass the check, returning how many were skipped
+fn skip_while(chars: &mut Peekable<Chars>, max: usize, valid: fn(char) -> bool) -> usize {
+    let mut skipped = 0;
+    while skipped < max && chars.peek().map_or(false, |&c| valid(c)) {
+        chars.next();
+        skipped += 1;
+    }
+    skipped
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_digit(10)
+}
+
+fn is_hex_digit(c: char) -> bool {
+    c.is_digit(16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip;
+
+    #[test]
+    fn strips_weechat_colors() {
+        // A nick prefix, with an extended color
+        assert_eq!(strip("\x19F@00214alice"), "alice");
+        // A join, as the relay sends the line
+        assert_eq!(strip("\x19F@00214alice\x19F@00028 (\x19F@00245~alice@example.org\x19F@00028)\x19F@00024 has \
+                          joined \x19F@00022#rust\x19\x1c"),
+                   "alice (~alice@example.org) has joined #rust");
+        // Foreground and background, with attributes
+        assert_eq!(strip("\x19*_08,05warning\x1c: \x1a\x01bold\x1b\x01 done"), "warning: bold done");
+        assert_eq!(strip("\x19*!05~03reversed"), "reversed");
+        assert_eq!(strip("\x1902standard \x19B12background \x19bFbar"), "standard background bar");
+    }
+
+    #[test]
+    fn strips_mirc_formatting() {
+        assert_eq!(strip("\x0304,01red on black\x0f normal"), "red on black normal");
+        assert_eq!(strip("\x02bold\x02 \x1ditalic\x1d \x1funderline\x1f \x1estrike\x1e \x11mono\x11 \x16rev\x16"),
+                   "bold italic underline strike mono rev");
+        assert_eq!(strip("\x034green\x03 plain"), "green plain");
+        // A comma is only part of the code if a background color follows
+        assert_eq!(strip("\x0312,hi"), ",hi");
+        assert_eq!(strip("score: 3\x03,4"), "score: 3,4");
+        // Colors are at most two digits
+        assert_eq!(strip("\x03123 apples"), "3 apples");
+        assert_eq!(strip("\x04FF0000,00FF00hex\x04 colors"), "hex colors");
+    }
+}
@@ -0,0 +1,345 @@
+//! Commands for the relay, built from their parts rather than formatted by
+//! hand. See the commands section of the protocol:
+//! https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html#commands
+//!
+//! Every command renders to what goes on the wire, without the terminator
+//! (the connection adds it):
+//!
+//! ```
+//! extern crate weechat_alert;
+//!
+//! use weechat_alert::commands::*;
+//! use weechat_alert::connection::Compression;
+//!
+//! # fn main() {
+//! assert_eq!(InitCommand::new(Some("mypass"), Compression::Zlib).render(),
+//!            "init password=mypass,compression=zlib");
+//! assert_eq!(InitCommand::new(Some("my,pass"), Compression::Off).render(),
+//!            "init password=my\\,pass,compression=off");
+//! assert_eq!(SyncCommand::new(&["irc.libera.#weechat"], &["buffer", "nicklist"]).render(),
+//!            "sync irc.libera.#weechat buffer,nicklist");
+//! assert_eq!(SyncCommand::all().render(), "sync");
+//! assert_eq!(DesyncCommand::new(&["irc.libera.#weechat"], &["nicklist"]).render(),
+//!            "desync irc.libera.#weechat nicklist");
+//! assert_eq!(InputCommand::new("core.weechat", "/help filter").render(),
+//!            "input core.weechat /help filter");
+//! assert_eq!(HdataCommand::new("buffer:gui_buffers(*)", &["number", "full_name"]).with_id("hdata_test").render(),
+//!            "(hdata_test) hdata buffer:gui_buffers(*) number,full_name");
+//! assert_eq!(InfoCommand::new("version").with_id("info_version").render(),
+//!            "(info_version) info version");
+//! assert_eq!(NicklistCommand::new(Some("irc.libera.#weechat")).render(),
+//!            "nicklist irc.libera.#weechat");
+//! assert_eq!(PingCommand::new(Some("1370802127000")).render(), "ping 1370802127000");
+//! assert_eq!(QuitCommand.render(), "quit");
+//! # }
+//! ```
+
+use connection::Compression;
+
+/// Something that can be sent to the relay (see Connection::send)
+pub trait Command {
+    /// The command as it goes on the wire, without the terminator
+    fn render(&self) -> String;
+
+    /// The command with an "(id)" prefix, which the relay puts on its reply
+    fn with_id(self, id: &str) -> WithId<Self>
+        where Self: Sized
+    {
+        WithId {
+            id: id.to_string(),
+            command: self,
+        }
+    }
+}
+
+/// A command written out by hand, sent as it is
+impl Command for str {
+    fn render(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<'a, C: Command + ?Sized> Command for &'a C {
+    fn render(&self) -> String {
+        (**self).render()
+    }
+}
+
+/// A command with an "(id)" prefix (see Command::with_id)
+#[derive(Debug, Clone)]
+pub struct WithId<C> {
+    pub id: String,
+    pub command: C,
+}
+
+impl<C: Command> Command for WithId<C> {
+    fn render(&self) -> String {
+        format!("({}) {}", self.id, self.command.render())
+    }
+}
+
+/// Authenticates with the relay and sets the connection's options. Without a
+/// password the password option is left out entirely, as some relays reject
+/// an empty one.
+#[derive(Debug, Clone)]
+pub struct InitCommand {
+    pub password: Option<String>,
+    pub compression: Compression,
+}
+
+impl InitCommand {
+    pub fn new(password: Option<&str>, compression: Compression) -> InitCommand {
+        InitCommand {
+            password: password.map(|p| p.to_string()),
+            compression: compression,
+        }
+    }
+}
+
+impl Command for InitCommand {
+    fn render(&self) -> String {
+        match self.password {
+            // The options are split on commas, so one in the password has
+            // to be escaped
+            Some(ref password) => {
+                format!("init password={},compression={}",
+                        one_line(password).replace(",", "\\,"), self.compression.name())
+            },
+            None               => format!("init compression={}", self.compression.name()),
+        }
+    }
+}
+
+/// Asks the relay to send events for buffers (by full name, or "*" for all
+/// of them), of some kinds ("buffers", "upgrade", "buffer" and "nicklist").
+/// No buffers and no options is every buffer and every kind.
+#[derive(Debug, Clone)]
+pub struct SyncCommand {
+    pub buffers: Vec<String>,
+    pub options: Vec<String>,
+}
+
+impl SyncCommand {
+    pub fn new(buffers: &[&str], options: &[&str]) -> SyncCommand {
+        SyncCommand {
+            buffers: to_strings(buffers),
+            options: to_strings(options),
+        }
+    }
+
+    /// Everything, for every buffer
+    pub fn all() -> SyncCommand {
+        SyncCommand::new(&[], &[])
+    }
+}
+
+impl Command for SyncCommand {
+    fn render(&self) -> String {
+        sync_command("sync", &self.buffers, &self.options)
+    }
+}
+
+/// Stops the events a SyncCommand asked for
+#[derive(Debug, Clone)]
+pub struct DesyncCommand {
+    pub buffers: Vec<String>,
+    pub options: Vec<String>,
+}
+
+impl DesyncCommand {
+    pub fn new(buffers: &[&str], options: &[&str]) -> DesyncCommand {
+        DesyncCommand {
+            buffers: to_strings(buffers),
+            options: to_strings(options),
+        }
+    }
+}
+
+impl Command for DesyncCommand {
+    fn render(&self) -> String {
+        sync_command("desync", &self.buffers, &self.options)
+    }
+}
+
+/// Sends text (or a command, ex: "/join #rust") to a buffer, by full name or
+/// pointer, as if it were typed there. Line breaks in the text become spaces,
+/// as the first one would end the command.
+#[derive(Debug, Clone)]
+pub struct InputCommand {
+    pub buffer: String,
+    pub text: String,
+}
+
+impl InputCommand {
+    pub fn new(buffer: &str, text: &str) -> InputCommand {
+        InputCommand {
+            buffer: buffer.to_string(),
+            text: text.to_string(),
+        }
+    }
+}
+
+impl Command for InputCommand {
+    fn render(&self) -> String {
+        format!("input {} {}", self.buffer, one_line(&self.text))
+    }
+}
+
+/// Asks for an hdata by its path (ex: "buffer:gui_buffers(*)"), with only
+/// some of its keys, or all of them if none are given
+#[derive(Debug, Clone)]
+pub struct HdataCommand {
+    pub path: String,
+    pub keys: Vec<String>,
+}
+
+impl HdataCommand {
+    pub fn new(path: &str, keys: &[&str]) -> HdataCommand {
+        HdataCommand {
+            path: path.to_string(),
+            keys: to_strings(keys),
+        }
+    }
+}
+
+impl Command for HdataCommand {
+    fn render(&self) -> String {
+        if self.keys.is_empty() {
+            format!("hdata {}", self.path)
+        } else {
+            format!("hdata {} {}", self.path, self.keys.join(","))
+        }
+    }
+}
+
+/// Asks for an info (ex: "version")
+#[derive(Debug, Clone)]
+pub struct InfoCommand {
+    pub name: String,
+}
+
+impl InfoCommand {
+    pub fn new(name: &str) -> InfoCommand {
+        InfoCommand { name: name.to_string() }
+    }
+}
+
+impl Command for InfoCommand {
+    fn render(&self) -> String {
+        format!("info {}", self.name)
+    }
+}
+
+/// Asks for the nicks in a buffer (by full name or pointer), or in every
+/// buffer
+#[derive(Debug, Clone)]
+pub struct NicklistCommand {
+    pub buffer: Option<String>,
+}
+
+impl NicklistCommand {
+    pub fn new(buffer: Option<&str>) -> NicklistCommand {
+        NicklistCommand { buffer: buffer.map(|b| b.to_string()) }
+    }
+}
+
+impl Command for NicklistCommand {
+    fn render(&self) -> String {
+        match self.buffer {
+            Some(ref buffer) => format!("nicklist {}", buffer),
+            None             => "nicklist".to_string(),
+        }
+    }
+}
+
+/// Asks the relay for a _pong, which sends back the arguments (if any)
+#[derive(Debug, Clone)]
+pub struct PingCommand {
+    pub arguments: Option<String>,
+}
+
+impl PingCommand {
+    pub fn new(arguments: Option<&str>) -> PingCommand {
+        PingCommand { arguments: arguments.map(|a| a.to_string()) }
+    }
+}
+
+impl Command for PingCommand {
+    fn render(&self) -> String {
+        match self.arguments {
+            Some(ref arguments) => format!("ping {}", one_line(arguments)),
+            None                => "ping".to_string(),
+        }
+    }
+}
+
+/// Tells the relay we are done, before closing the connection
+#[derive(Debug, Clone, Copy)]
+pub struct QuitCommand;
+
+impl Command for QuitCommand {
+    fn render(&self) -> String {
+        "quit".to_string()
+    }
+}
+
+/// A sync or desync. Options can only be given after buffers, so options
+/// with no buffers are for all of them
+fn sync_command(name: &str, buffers: &[String], options: &[String]) -> String {
+    match (buffers.is_empty(), options.is_empty()) {
+        (true, true)  => name.to_string(),
+        (false, true) => format!("{} {}", name, buffers.join(",")),
+        (true, false) => format!("{} * {}", name, options.join(",")),
+        _             => format!("{} {} {}", name, buffers.join(","), options.join(",")),
+    }
+}
+
+fn to_strings(strs: &[&str]) -> Vec<String> {
+    strs.iter().map(|s| s.to_string()).collect()
+}
+
+/// Free text with its line breaks made spaces, so it stays in one command
+fn one_line(text: &str) -> String {
+    text.chars().map(|c| if c == '\n' || c == '\r' { ' ' } else { c }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{one_line, Command, HdataCommand, InitCommand, InputCommand, PingCommand, SyncCommand};
+    use connection::Compression;
+
+    #[test]
+    fn init_escapes_commas_in_the_password() {
+        assert_eq!(InitCommand::new(Some("a,b,,c"), Compression::Zlib).render(),
+                   "init password=a\\,b\\,\\,c,compression=zlib");
+        assert_eq!(InitCommand::new(Some("line\nbreak"), Compression::Off).render(),
+                   "init password=line break,compression=off");
+        assert_eq!(InitCommand::new(None, Compression::Off).render(), "init compression=off");
+    }
+
+    #[test]
+    fn one_line_makes_line_breaks_spaces() {
+        assert_eq!(one_line("one\ntwo\r\nthree"), "one two  three");
+        assert_eq!(one_line("already one line"), "already one line");
+    }
+
+    #[test]
+    fn hdata_input_and_sync() {
+        assert_eq!(HdataCommand::new("buffer:gui_buffers(*)", &["full_name", "local_variables"]).render(),
+                   "hdata buffer:gui_buffers(*) full_name,local_variables");
+        assert_eq!(HdataCommand::new("buffer:gui_buffers(*)", &[]).render(), "hdata buffer:gui_buffers(*)");
+        assert_eq!(HdataCommand::new("buffer:gui_buffers(*)", &["full_name"]).with_id("buffers").render(),
+                   "(buffers) hdata buffer:gui_buffers(*) full_name");
+
+        assert_eq!(InputCommand::new("irc.libera.#rust", "hi\nthere").render(), "input irc.libera.#rust hi there");
+        assert_eq!(InputCommand::new("core.weechat", "/print hi").render(), "input core.weechat /print hi");
+
+        assert_eq!(SyncCommand::all().render(), "sync");
+        assert_eq!(SyncCommand::new(&["*"], &["buffer"]).render(), "sync * buffer");
+        assert_eq!(SyncCommand::new(&[], &["buffer", "nicklist"]).render(), "sync * buffer,nicklist");
+        assert_eq!(SyncCommand::new(&["irc.libera.#rust", "core.weechat"], &[]).render(),
+                   "sync irc.libera.#rust,core.weechat");
+
+        assert_eq!(PingCommand::new(Some("weechat-alert-1")).render(), "ping weechat-alert-1");
+    }
+}
@@ -0,0 +1,1842 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use log::LogLevelFilter;
+use toml;
+
+use alert;
+use connection::{Compression, Transport};
+use health;
+use http::{self, Trust, Url};
+use notify::{self, BufferSettings, EventClass, Priority, Settings, Urgency};
+use notify::template::{Template, DEFAULT_FORMAT};
+use notify::tts;
+#[cfg(feature = "pushbullet")]
+use notify::pushbullet::{self, PushbulletSettings};
+use notify::discord::{self, DiscordSettings};
+use notify::email::{self, EmailSettings, SmtpTls};
+use notify::fifo::{FifoFormat, FifoSettings};
+use notify::highlight_log::{self, HighlightLogSettings};
+use notify::history;
+use notify::gotify::{self, GotifySettings};
+use notify::matrix::MatrixSettings;
+use notify::ntfy::{self, NtfyAuth, NtfySettings};
+use notify::priority::{PriorityRule, Scale};
+use notify::pushover::{self, PushoverSettings};
+use notify::slack::{self, SlackSettings};
+use notify::sound::{self, SoundPolicy};
+use notify::syslog::{self, SyslogSettings};
+use notify::telegram::TelegramSettings;
+use notify::time_format::{self, TimeFormat};
+use notify::twilio::TwilioSettings;
+use notify::webhook::{self, WebhookSettings};
+#[cfg(feature = "xmpp")]
+use notify::xmpp::{self, XmppSettings};
+use parse;
+use policy::{self, ErrorPolicy};
+use backoff::{self, Backoff, Schedule};
+use websocket;
+
+/// Everything read from the config file
+pub struct Config {
+    pub host: String,
+    pub port: i32,
+    /// None if the relay has no password
+    pub password: Option<String>,
+    pub ssl: bool,
+    pub ssl_verify: bool,
+    pub ca_certs_path: Option<String>,
+    pub terminator: Option<String>,
+    pub transport: Transport,
+    pub compression: Compression,
+    /// How deeply arrays and hashtables can nest in a message
+    pub max_parse_depth: usize,
+    pub log_level: LogLevelFilter,
+    pub watch_buffers: Vec<String>,
+    pub ignore_buffers: Vec<String>,
+    pub ignore_self: bool,
+    pub own_nicks: Vec<String>,
+    pub respect_display_filter: bool,
+    /// Lines older than this never alert (0 alerts on any age)
+    pub max_line_age: Duration,
+    pub buffer_cooldown: Duration,
+    /// How long the relay can be quiet before we ping it (0 never pings)
+    pub keepalive: Duration,
+    pub error_policy: ErrorPolicy,
+    pub reconnect: Schedule,
+    /// Unix socket we take commands on, if it is set
+    pub control_socket: Option<PathBuf>,
+    /// Show the tray icon (with the tray feature)
+    pub tray: bool,
+    /// Buffer notifications are also printed in, if it is set
+    pub forward_buffer: Option<String>,
+    pub forward_format: Template,
+    /// Address to serve /healthz on, if it is set
+    #[cfg(feature = "health")]
+    pub health_listen: Option<String>,
+    /// File to touch while healthy, if it is set
+    pub health_file: Option<PathBuf>,
+    /// How long the relay can be quiet before we are unhealthy
+    pub health_max_age: Duration,
+    pub notifiers: Vec<String>,
+    pub notify: Settings,
+    /// Problems that don't stop the config from working (ex: an icon that
+    /// doesn't exist), to be logged once logging is started
+    pub warnings: Vec<String>,
+}
+
+/// Expands a leading ~ in a path to the user's home directory (which on
+/// windows is USERPROFILE if HOME isn't set, and ~\ works too)
+fn expand_tilde(path: &str) -> PathBuf {
+    let windows_home = cfg!(windows) && path.starts_with("~\\");
+    if path == "~" || path.starts_with("~/") || windows_home {
+        if let Some(mut home) = env::home_dir() {
+            if path.len() > 2 {
+                home.push(&path[2..]);
+            }
+            return home;
+        }
+    }
+    PathBuf::from(path)
+}
+
+/// Resolves a notification icon from the config. Anything that looks like a
+/// path has its ~ expanded and must exist, otherwise a warning is added and no
+/// icon is used (instead of every notification failing). Anything else is an
+/// icon theme name (ex: "dialog-information"), which is passed on as is.
+fn resolve_icon(key: &str, icon: &toml::Value, warnings: &mut Vec<String>) -> Result<Option<String>, String> {
+    let icon = try!(icon.as_str().ok_or(format!("'{}' is not a valid string", key)));
+    if !icon.contains('/') {
+        return Ok(Some(icon.to_string()));
+    }
+
+    let path = expand_tilde(icon);
+    if path.is_file() {
+        Ok(Some(path.to_string_lossy().into_owned()))
+    } else {
+        warnings.push(format!("'{}': icon {} does not exist, not using an icon", key, path.display()));
+        Ok(None)
+    }
+}
+
+/// Looks up an optional file path in the config, expanding its ~
+fn lookup_path(config: &toml::Value, key: &str) -> Result<Option<PathBuf>, String> {
+    match config.lookup(key) {
+        Some(path) => {
+            let path = try!(path.as_str().ok_or(format!("'{}' is not a valid string", key)));
+            Ok(Some(expand_tilde(path)))
+        },
+        None       => Ok(None),
+    }
+}
+
+/// Looks up an optional sound file in the config, which must exist
+fn lookup_sound(config: &toml::Value, key: &str) -> Result<Option<PathBuf>, String> {
+    let path = try!(lookup_path(config, key));
+    if let Some(ref path) = path {
+        try!(sound::check_sound(path).map_err(|e| format!("'{}': {}", key, e)));
+    }
+    Ok(path)
+}
+
+/// Looks up an optional list of strings in the config. A missing key is an
+/// empty list.
+fn lookup_string_list(config: &toml::Value, key: &str) -> Result<Vec<String>, String> {
+    let list = match config.lookup(key) {
+        Some(list) => list,
+        None       => return Ok(Vec::new()),
+    };
+    let list = try!(list.as_slice().ok_or(format!("'{}' is not a list", key)));
+
+    let mut strings = Vec::new();
+    for item in list {
+        let s = try!(item.as_str().ok_or(format!("'{}' must only contain strings", key)));
+        strings.push(s.to_string());
+    }
+    Ok(strings)
+}
+
+/// Looks up a table of a backend's own value for each priority (ex:
+/// [notifier.ntfy.priority]), as integers from min to max. Priorities it
+/// doesn't set keep their default.
+fn lookup_priorities<T>(config: &toml::Value, key: &str, default: Scale<T>, min: i64, max: i64)
+                        -> Result<Scale<i64>, String>
+    where T: Copy + Into<i64>
+{
+    let mut scale = Scale::new(default.low.into(), default.normal.into(), default.high.into(),
+                               default.critical.into());
+    for &priority in &Priority::all() {
+        let key = format!("{}.{}", key, priority.name());
+        if let Some(value) = config.lookup(&key) {
+            let value = try!(value.as_integer().ok_or(format!("'{}' is not an integer", key)));
+            if value < min || value > max {
+                return Err(format!("'{}' must be between {} and {}", key, min, max));
+            }
+            scale.set(priority, value);
+        }
+    }
+    Ok(scale)
+}
+
+/// Parses the [[priority_rule]]s, in order. Each needs a priority, and at
+/// least one of class, buffers and keywords to match on.
+fn parse_priority_rules(config: &toml::Value) -> Result<Vec<PriorityRule>, String> {
+    let rules = match config.lookup("priority_rule") {
+        Some(rules) => try!(rules.as_slice().ok_or("'priority_rule' is not a list of tables")),
+        None        => return Ok(Vec::new()),
+    };
+
+    let mut parsed = Vec::new();
+    for (i, rule) in rules.iter().enumerate() {
+        let key = format!("priority_rule[{}]", i);
+        if rule.as_table().is_none() {
+            return Err(format!("'{}' is not a table", key));
+        }
+        let priority = try!(rule.lookup("priority").and_then(|p| p.as_str()).and_then(Priority::from_name)
+            .ok_or(format!("'{}.priority' must be \"low\", \"normal\", \"high\" or \"critical\"", key)));
+        let class = match rule.lookup("class") {
+            Some(class) => Some(try!(class.as_str().and_then(EventClass::from_name)
+                .ok_or(format!("'{}.class' must be \"private\", \"highlight\" or \"watch\"", key)))),
+            None        => None,
+        };
+        let buffers = try!(lookup_string_list(rule, "buffers").map_err(|e| format!("{}: {}", key, e)));
+        let keywords = try!(lookup_string_list(rule, "keywords").map_err(|e| format!("{}: {}", key, e)));
+        if class.is_none() && buffers.is_empty() && keywords.is_empty() {
+            return Err(format!("'{}' needs a class, buffers or keywords to match on", key));
+        }
+        parsed.push(PriorityRule {
+            class: class,
+            buffers: buffers,
+            keywords: keywords,
+            priority: priority,
+        });
+    }
+    Ok(parsed)
+}
+
+/// Reads and parses a config file, merging in the files it includes (from
+/// its include list, in order). Later includes override keys from earlier
+/// ones, and the file's own keys override all of its includes. Relative
+/// include paths are relative to the directory of the including file.
+///
+/// stack holds the files currently being loaded, to catch include cycles.
+fn load(path: &Path, stack: &mut Vec<PathBuf>) -> Result<toml::Table, String> {
+    // Compare canonical paths, so a cycle is caught no matter how the paths
+    // in the include lists are written
+    let canonical = try!(fs::canonicalize(path).map_err(|e| format!("{}: {}", path.display(), e)));
+    if stack.contains(&canonical) {
+        let mut cycle: Vec<String> = stack.iter()
+            .skip_while(|p| **p != canonical)
+            .map(|p| p.display().to_string())
+            .collect();
+        cycle.push(canonical.display().to_string());
+        return Err(format!("Config files include each other: {}", cycle.join(" -> ")));
+    }
+
+    // Open the file and read the data
+    let mut file = try!(File::open(path).map_err(|e| format!("{}: {}", path.display(), e)));
+    let mut file_data = String::new();
+    try!(file.read_to_string(&mut file_data).map_err(|e| format!("{}: {}", path.display(), e)));
+
+    // Parse the config
+    let config: toml::Value = try!(file_data.parse().map_err(|errs| {
+        let mut err = format!("Error parsing config file {}:", path.display());
+        for e in errs {
+            err.push_str("\n  ");
+            err.push_str(Error::description(&e));
+        }
+        err
+    }));
+    let mut config = match config {
+        toml::Value::Table(table) => table,
+        _                         => unreachable!(),
+    };
+
+    let includes = match config.remove("include") {
+        Some(toml::Value::Array(includes)) => includes,
+        Some(_)                            => {
+            return Err(format!("{}: 'include' is not a list", path.display()));
+        },
+        None                               => Vec::new(),
+    };
+
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let mut merged = toml::Table::new();
+    for include in includes {
+        let include = try!(include.as_str()
+            .ok_or(format!("{}: 'include' must only contain strings", path.display())));
+        let include_path = dir.join(expand_tilde(include));
+        merge(&mut merged, try!(load(&include_path, stack)));
+    }
+    stack.pop();
+
+    merge(&mut merged, config);
+    Ok(merged)
+}
+
+/// Merges a table into another. Tables in both are merged key by key, and
+/// anything else from other replaces what was in base.
+fn merge(base: &mut toml::Table, other: toml::Table) {
+    for (key, value) in other {
+        let value = match (base.remove(&key), value) {
+            (Some(toml::Value::Table(mut table)), toml::Value::Table(other)) => {
+                merge(&mut table, other);
+                toml::Value::Table(table)
+            },
+            (_, value) => value,
+        };
+        base.insert(key, value);
+    }
+}
+
+/// Parses a `--set key=value` override into a table to merge over the
+/// config. The value is read as toml (so volume=0.2 is a number and
+/// ssl=true is a boolean), or as a string if it isn't valid toml (so paths
+/// don't need quotes). A dotted key sets a key in a table, such as
+/// notifier.slack.channel=#alerts.
+fn parse_override(arg: &str) -> Result<toml::Table, String> {
+    let (key, value) = match arg.find('=') {
+        Some(i) => (&arg[..i], &arg[i + 1..]),
+        None    => return Err(format!("'--set {}' is not key=value", arg)),
+    };
+    if key.split('.').any(|part| part.trim().is_empty()) {
+        return Err(format!("'--set {}' has an invalid key", arg));
+    }
+
+    let parsed: Option<toml::Value> = format!("value = {}", value).parse().ok();
+    let value = match parsed.as_ref().and_then(|t| t.lookup("value")) {
+        Some(v) => v.clone(),
+        None    => toml::Value::String(value.to_string()),
+    };
+
+    let mut parts = key.split('.').rev().map(|part| part.trim().to_string());
+    let mut table = toml::Table::new();
+    table.insert(parts.next().unwrap_or(String::new()), value);
+    for part in parts {
+        let mut outer = toml::Table::new();
+        outer.insert(part, toml::Value::Table(table));
+        table = outer;
+    }
+    Ok(table)
+}
+
+/// Looks up an optional string in the config
+fn lookup_optional_string(config: &toml::Value, key: &str) -> Result<Option<String>, String> {
+    match config.lookup(key) {
+        Some(s) => s.as_str().map(|s| Some(s.to_string())).ok_or(format!("'{}' is not a valid string", key)),
+        None    => Ok(None),
+    }
+}
+
+/// Looks up an optional template in the config, falling back to a default
+fn lookup_template(config: &toml::Value, key: &str, default: &str) -> Result<Template, String> {
+    let template = try!(lookup_optional_string(config, key)).unwrap_or(default.to_string());
+    Template::parse(&template).map_err(|e| format!("'{}': {}", key, e))
+}
+
+/// Parses [notifier.pushbullet], if it is there. Bodies are limited to the
+/// same length as the main format
+#[cfg(feature = "pushbullet")]
+fn parse_pushbullet(config: &toml::Value, format: &Template)
+                    -> Result<Option<PushbulletSettings>, String> {
+    if config.lookup("notifier.pushbullet").is_none() {
+        return Ok(None);
+    }
+
+    let token = try!(try!(lookup_optional_string(config, "notifier.pushbullet.token"))
+                     .ok_or("'notifier.pushbullet.token' not found in the config file"));
+    let device_iden = try!(lookup_optional_string(config, "notifier.pushbullet.device_iden"));
+    let channel_tag = try!(lookup_optional_string(config, "notifier.pushbullet.channel_tag"));
+    if device_iden.is_some() && channel_tag.is_some() {
+        return Err("'notifier.pushbullet' can only set one of device_iden and channel_tag".to_string());
+    }
+
+    let mut title = try!(lookup_template(config, "notifier.pushbullet.title", pushbullet::DEFAULT_TITLE));
+    title.set_max_length(format.max_length());
+    let mut body = try!(lookup_template(config, "notifier.pushbullet.body", pushbullet::DEFAULT_BODY));
+    body.set_max_length(format.max_length());
+
+    Ok(Some(PushbulletSettings {
+        token: token,
+        device_iden: device_iden,
+        channel_tag: channel_tag,
+        title: title,
+        body: body,
+    }))
+}
+
+/// Parses [notifier.telegram], if it is there. Messages default to the main
+/// format, and to its max length.
+fn parse_telegram(config: &toml::Value, format: &Template) -> Result<Option<TelegramSettings>, String> {
+    if config.lookup("notifier.telegram").is_none() {
+        return Ok(None);
+    }
+
+    let token = try!(try!(lookup_optional_string(config, "notifier.telegram.token"))
+                     .ok_or("'notifier.telegram.token' not found in the config file"));
+    // Chat ids of users and groups are numbers, channels can be "@name"
+    let chat_id = match config.lookup("notifier.telegram.chat_id") {
+        Some(&toml::Value::Integer(id))    => id.to_string(),
+        Some(&toml::Value::String(ref id)) => id.clone(),
+        Some(_)                            => return Err("'notifier.telegram.chat_id' is not a number or string".to_string()),
+        None                               => return Err("'notifier.telegram.chat_id' not found in the config file".to_string()),
+    };
+
+    let format = match try!(lookup_optional_string(config, "notifier.telegram.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.telegram.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+
+    let default_markdown = toml::Value::Boolean(false);
+    let markdown = config.lookup("notifier.telegram.markdown").unwrap_or(&default_markdown);
+    let markdown = try!(markdown.as_bool().ok_or("'notifier.telegram.markdown' is not true or false"));
+
+    let default_silent = toml::Value::Boolean(false);
+    let silent = config.lookup("notifier.telegram.silent").unwrap_or(&default_silent);
+    let silent = try!(silent.as_bool().ok_or("'notifier.telegram.silent' is not true or false"));
+
+    Ok(Some(TelegramSettings {
+        token: token,
+        chat_id: chat_id,
+        format: format,
+        markdown: markdown,
+        silent: silent,
+    }))
+}
+
+/// Parses [notifier.discord], if it is there. Plain messages default to the
+/// main format.
+fn parse_discord(config: &toml::Value, format: &Template) -> Result<Option<DiscordSettings>, String> {
+    if config.lookup("notifier.discord").is_none() {
+        return Ok(None);
+    }
+
+    let url = try!(try!(lookup_optional_string(config, "notifier.discord.url"))
+                   .ok_or("'notifier.discord.url' not found in the config file"));
+    let url = try!(discord::parse_url(&url).map_err(|e| format!("'notifier.discord.url' {}", e)));
+
+    let default_embed = toml::Value::Boolean(false);
+    let embed = config.lookup("notifier.discord.embed").unwrap_or(&default_embed);
+    let embed = try!(embed.as_bool().ok_or("'notifier.discord.embed' is not true or false"));
+
+    let format = match try!(lookup_optional_string(config, "notifier.discord.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.discord.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+
+    Ok(Some(DiscordSettings {
+        url: url,
+        embed: embed,
+        format: format,
+    }))
+}
+
+/// Parses [notifier.slack], if it is there. Messages default to the main
+/// format, and to its max length.
+fn parse_slack(config: &toml::Value, format: &Template) -> Result<Option<SlackSettings>, String> {
+    if config.lookup("notifier.slack").is_none() {
+        return Ok(None);
+    }
+
+    let url = try!(try!(lookup_optional_string(config, "notifier.slack.url"))
+                   .ok_or("'notifier.slack.url' not found in the config file"));
+    let url = try!(Url::parse(&url).map_err(|_| "'notifier.slack.url' is not a valid url".to_string()));
+
+    let format = match try!(lookup_optional_string(config, "notifier.slack.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.slack.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+
+    // An empty prefix turns off the default one
+    let mut prefixes = HashMap::new();
+    for &class in &[EventClass::Private, EventClass::Highlight, EventClass::Watch] {
+        let key = format!("notifier.slack.prefix.{}", class.name());
+        let prefix = match try!(lookup_optional_string(config, &key)) {
+            Some(prefix) => prefix,
+            None         => slack::default_prefix(class).unwrap_or("").to_string(),
+        };
+        if !prefix.is_empty() {
+            prefixes.insert(class, prefix);
+        }
+    }
+
+    Ok(Some(SlackSettings {
+        url: url,
+        channel: try!(lookup_optional_string(config, "notifier.slack.channel")),
+        username: try!(lookup_optional_string(config, "notifier.slack.username")),
+        icon_emoji: try!(lookup_optional_string(config, "notifier.slack.icon_emoji")),
+        format: format,
+        prefixes: prefixes,
+    }))
+}
+
+/// Parses [notifier.matrix], if it is there. Messages default to the main
+/// format, and to its max length.
+fn parse_matrix(config: &toml::Value, format: &Template) -> Result<Option<MatrixSettings>, String> {
+    if config.lookup("notifier.matrix").is_none() {
+        return Ok(None);
+    }
+
+    let homeserver = try!(try!(lookup_optional_string(config, "notifier.matrix.homeserver"))
+                          .ok_or("'notifier.matrix.homeserver' not found in the config file"));
+    try!(Url::parse(&homeserver).map_err(|e| format!("'notifier.matrix.homeserver': {}", e)));
+    let access_token = try!(try!(lookup_optional_string(config, "notifier.matrix.access_token"))
+                            .ok_or("'notifier.matrix.access_token' not found in the config file"));
+    let room_id = try!(try!(lookup_optional_string(config, "notifier.matrix.room_id"))
+                       .ok_or("'notifier.matrix.room_id' not found in the config file"));
+
+    let plain = match try!(lookup_optional_string(config, "notifier.matrix.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.matrix.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+    let html = match try!(lookup_optional_string(config, "notifier.matrix.html_format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f)
+                                    .map_err(|e| format!("'notifier.matrix.html_format': {}", e)));
+            template.set_max_length(format.max_length());
+            Some(template)
+        },
+        None    => None,
+    };
+
+    Ok(Some(MatrixSettings {
+        homeserver: homeserver,
+        access_token: access_token,
+        room_id: room_id,
+        format: plain,
+        html_format: html,
+    }))
+}
+
+/// Parses [notifier.ntfy], if it is there. Messages default to the main
+/// format, and to its max length.
+fn parse_ntfy(config: &toml::Value, format: &Template) -> Result<Option<NtfySettings>, String> {
+    if config.lookup("notifier.ntfy").is_none() {
+        return Ok(None);
+    }
+
+    let server = try!(lookup_optional_string(config, "notifier.ntfy.server"))
+                 .unwrap_or(ntfy::DEFAULT_SERVER.to_string());
+    try!(Url::parse(&server).map_err(|e| format!("'notifier.ntfy.server': {}", e)));
+    let topic = try!(try!(lookup_optional_string(config, "notifier.ntfy.topic"))
+                     .ok_or("'notifier.ntfy.topic' not found in the config file"));
+
+    let token = try!(lookup_optional_string(config, "notifier.ntfy.token"));
+    let user = try!(lookup_optional_string(config, "notifier.ntfy.user"));
+    let password = try!(lookup_optional_string(config, "notifier.ntfy.password"));
+    let auth = match (token, user, password) {
+        (Some(token), None, None)          => Some(NtfyAuth::Token(token)),
+        (None, Some(user), Some(password)) => Some(NtfyAuth::Basic(user, password)),
+        (None, None, None)                 => None,
+        (Some(_), _, _)                    => {
+            return Err("'notifier.ntfy' can only set one of token and user/password".to_string());
+        },
+        _                                  => {
+            return Err("'notifier.ntfy' needs both user and password".to_string());
+        },
+    };
+
+    let format = match try!(lookup_optional_string(config, "notifier.ntfy.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.ntfy.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+
+    // Our own notifications on the reply topic would be sent back into
+    // their buffers
+    let reply_topic = try!(lookup_optional_string(config, "notifier.ntfy.reply_topic"));
+    if reply_topic.as_ref().map_or(false, |t| t.is_empty() || *t == topic) {
+        return Err("'notifier.ntfy.reply_topic' must be a topic other than 'notifier.ntfy.topic'".to_string());
+    }
+
+    Ok(Some(NtfySettings {
+        server: server,
+        topic: topic,
+        auth: auth,
+        click: try!(lookup_optional_string(config, "notifier.ntfy.click")),
+        format: format,
+        reply_topic: reply_topic,
+        priorities: try!(lookup_priorities(config, "notifier.ntfy.priority", ntfy::DEFAULT_PRIORITIES, 1, 5))
+            .map(|p| p as u8),
+    }))
+}
+
+/// Parses [notifier.gotify], if it is there. Messages default to the main
+/// format, and to its max length. The server's certificate is checked
+/// against the relay's ca_certs_path too, unless the table sets its own.
+fn parse_gotify(config: &toml::Value, format: &Template, ca_certs: Option<&String>)
+                -> Result<Option<GotifySettings>, String> {
+    if config.lookup("notifier.gotify").is_none() {
+        return Ok(None);
+    }
+
+    let url = try!(try!(lookup_optional_string(config, "notifier.gotify.url"))
+                   .ok_or("'notifier.gotify.url' not found in the config file"));
+    try!(Url::parse(&url).map_err(|e| format!("'notifier.gotify.url': {}", e)));
+    let token = try!(try!(lookup_optional_string(config, "notifier.gotify.token"))
+                     .ok_or("'notifier.gotify.token' not found in the config file"));
+
+    let ca_file = match try!(lookup_path(config, "notifier.gotify.ca_certs_path")) {
+        Some(path) => Some(path),
+        None       => ca_certs.map(PathBuf::from),
+    };
+    let fingerprint = match try!(lookup_optional_string(config, "notifier.gotify.fingerprint")) {
+        Some(f) => Some(try!(http::parse_fingerprint(&f).map_err(|e| format!("'notifier.gotify.fingerprint': {}", e)))),
+        None    => None,
+    };
+
+    let format = match try!(lookup_optional_string(config, "notifier.gotify.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.gotify.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+
+    Ok(Some(GotifySettings {
+        url: url,
+        token: token,
+        trust: Trust {
+            ca_file: ca_file,
+            fingerprint: fingerprint,
+        },
+        format: format,
+        priorities: try!(lookup_priorities(config, "notifier.gotify.priority", gotify::DEFAULT_PRIORITIES, 0, 10))
+            .map(|p| p as u8),
+    }))
+}
+
+/// Runs a command through the shell and returns the first line it prints,
+/// for passwords kept in a password manager rather than the config file
+fn run_password_command(command: &str) -> Result<String, String> {
+    let output = try!(Command::new("sh").arg("-c").arg(command).output()
+                      .map_err(|e| format!("could not run '{}': {}", command, e)));
+    if !output.status.success() {
+        return Err(format!("'{}' failed ({})", command, output.status));
+    }
+    let stdout = try!(String::from_utf8(output.stdout)
+                      .map_err(|_| format!("'{}' printed something that isn't utf-8", command)));
+    Ok(stdout.lines().next().unwrap_or("").to_string())
+}
+
+/// Looks up the password in a notifier's table, which is either set as
+/// password or printed by password_command
+fn lookup_password(config: &toml::Value, table: &str) -> Result<Option<String>, String> {
+    let password = try!(lookup_optional_string(config, &format!("{}.password", table)));
+    let command = try!(lookup_optional_string(config, &format!("{}.password_command", table)));
+    match (password, command) {
+        (Some(_), Some(_))    => Err(format!("'{}' can only set one of password and password_command", table)),
+        (None, Some(command)) => {
+            run_password_command(&command).map(Some).map_err(|e| format!("'{}.password_command': {}", table, e))
+        },
+        (password, None)      => Ok(password),
+    }
+}
+
+/// Parses [notifier.email], if it is there. The body of emails defaults to
+/// the main format, and to its max length.
+fn parse_email(config: &toml::Value, format: &Template) -> Result<Option<EmailSettings>, String> {
+    if config.lookup("notifier.email").is_none() {
+        return Ok(None);
+    }
+
+    let host = try!(try!(lookup_optional_string(config, "notifier.email.host"))
+                    .ok_or("'notifier.email.host' not found in the config file"));
+    let tls = match try!(lookup_optional_string(config, "notifier.email.tls")) {
+        Some(ref tls) if tls == "starttls" => SmtpTls::StartTls,
+        Some(ref tls) if tls == "tls"      => SmtpTls::Implicit,
+        Some(_)                            => {
+            return Err("'notifier.email.tls' must be \"starttls\" or \"tls\"".to_string());
+        },
+        None                               => SmtpTls::StartTls,
+    };
+    let port = match config.lookup("notifier.email.port") {
+        Some(port) => try!(port.as_integer().ok_or("'notifier.email.port' is not an integer")),
+        None       => if tls == SmtpTls::Implicit { 465 } else { 587 },
+    };
+    if port < 1 || port > 65535 {
+        return Err("'notifier.email.port' is not a valid port".to_string());
+    }
+
+    let username = try!(lookup_optional_string(config, "notifier.email.username"));
+    let password = try!(lookup_password(config, "notifier.email"));
+    let credentials = match (username, password) {
+        (Some(username), Some(password)) => Some((username, password)),
+        (None, None)                     => None,
+        (Some(_), None)                  => {
+            return Err("'notifier.email' needs a password or password_command with its username".to_string());
+        },
+        (None, Some(_))                  => {
+            return Err("'notifier.email' needs a username with its password".to_string());
+        },
+    };
+
+    let from = try!(try!(lookup_optional_string(config, "notifier.email.from"))
+                    .ok_or("'notifier.email.from' not found in the config file"));
+    let to = match config.lookup("notifier.email.to") {
+        Some(&toml::Value::String(ref to)) => vec![to.clone()],
+        Some(_)                            => try!(lookup_string_list(config, "notifier.email.to")),
+        None                               => Vec::new(),
+    };
+    if to.is_empty() {
+        return Err("'notifier.email.to' not found in the config file".to_string());
+    }
+
+    let mut subject = try!(lookup_template(config, "notifier.email.subject", email::DEFAULT_SUBJECT));
+    subject.set_max_length(email::MAX_SUBJECT_LENGTH);
+    let format = match try!(lookup_optional_string(config, "notifier.email.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.email.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+
+    let classes = match config.lookup("notifier.email.classes") {
+        Some(_) => {
+            let mut classes = Vec::new();
+            for name in try!(lookup_string_list(config, "notifier.email.classes")) {
+                classes.push(try!(EventClass::from_name(&name)
+                    .ok_or("'notifier.email.classes' must only contain \"private\", \"highlight\" or \"watch\"")));
+            }
+            classes
+        },
+        None    => vec![EventClass::Private],
+    };
+
+    let max_per_hour = match config.lookup("notifier.email.max_per_hour") {
+        Some(m) => try!(m.as_integer().ok_or("'notifier.email.max_per_hour' is not an integer")),
+        None    => 10,
+    };
+    if max_per_hour < 1 {
+        return Err("'notifier.email.max_per_hour' must be at least 1".to_string());
+    }
+    let batch_window = match config.lookup("notifier.email.batch_window") {
+        Some(b) => try!(b.as_integer().ok_or("'notifier.email.batch_window' is not an integer")),
+        None    => 60,
+    };
+    if batch_window < 0 {
+        return Err("'notifier.email.batch_window' can't be negative".to_string());
+    }
+
+    Ok(Some(EmailSettings {
+        host: host,
+        port: port as u16,
+        tls: tls,
+        credentials: credentials,
+        from: from,
+        to: to,
+        subject: subject,
+        format: format,
+        classes: classes,
+        nicks: try!(lookup_string_list(config, "notifier.email.nicks")),
+        max_per_hour: max_per_hour as usize,
+        batch_window: Duration::from_secs(batch_window as u64),
+    }))
+}
+
+/// Parses [notifier.xmpp], if it is there. Messages default to the main
+/// format, and to its max length.
+#[cfg(feature = "xmpp")]
+fn parse_xmpp(config: &toml::Value, format: &Template) -> Result<Option<XmppSettings>, String> {
+    if config.lookup("notifier.xmpp").is_none() {
+        return Ok(None);
+    }
+
+    let jid = try!(try!(lookup_optional_string(config, "notifier.xmpp.jid"))
+                   .ok_or("'notifier.xmpp.jid' not found in the config file"));
+    if xmpp::split_jid(&jid).is_none() {
+        return Err("'notifier.xmpp.jid' is not a jid like user@example.com".to_string());
+    }
+    let recipient = try!(try!(lookup_optional_string(config, "notifier.xmpp.recipient"))
+                         .ok_or("'notifier.xmpp.recipient' not found in the config file"));
+
+    let password = try!(try!(lookup_password(config, "notifier.xmpp"))
+                        .ok_or("'notifier.xmpp' needs a password or password_command"));
+
+    let server = match try!(lookup_optional_string(config, "notifier.xmpp.server")) {
+        Some(s) => Some(try!(xmpp::parse_server(&s).map_err(|e| format!("'notifier.xmpp.server': {}", e)))),
+        None    => None,
+    };
+
+    let format = match try!(lookup_optional_string(config, "notifier.xmpp.format")) {
+        Some(f) => {
+            let mut template = try!(Template::parse(&f).map_err(|e| format!("'notifier.xmpp.format': {}", e)));
+            template.set_max_length(format.max_length());
+            template
+        },
+        None    => format.clone(),
+    };
+
+    Ok(Some(XmppSettings {
+        jid: jid,
+        password: password,
+        server: server,
+        recipient: recipient,
+        format: format,
+    }))
+}
+
+/// Parses [notifier.twilio], if it is there. As texts cost money, it has to
+/// say which buffers or keywords text.
+fn parse_twilio(config: &toml::Value) -> Result<Option<TwilioSettings>, String> {
+    if config.lookup("notifier.twilio").is_none() {
+        return Ok(None);
+    }
+
+    let required = |key: &str| {
+        lookup_optional_string(config, key)
+            .and_then(|value| value.ok_or(format!("'{}' not found in the config file", key)))
+    };
+    let account_sid = try!(required("notifier.twilio.account_sid"));
+    let auth_token = try!(required("notifier.twilio.auth_token"));
+    let from = try!(required("notifier.twilio.from"));
+    let to = try!(required("notifier.twilio.to"));
+
+    let buffers = try!(lookup_string_list(config, "notifier.twilio.buffers"));
+    let keywords = try!(lookup_string_list(config, "notifier.twilio.keywords"));
+    if buffers.is_empty() && keywords.is_empty() {
+        return Err("'notifier.twilio' needs buffers or keywords, so not every notification sends a text".to_string());
+    }
+
+    let max_per_day = match config.lookup("notifier.twilio.max_per_day") {
+        Some(m) => try!(m.as_integer().ok_or("'notifier.twilio.max_per_day' is not an integer")),
+        None    => 10,
+    };
+    if max_per_day < 1 {
+        return Err("'notifier.twilio.max_per_day' must be at least 1".to_string());
+    }
+
+    Ok(Some(TwilioSettings {
+        account_sid: account_sid,
+        auth_token: auth_token,
+        from: from,
+        to: to,
+        buffers: buffers,
+        keywords: keywords,
+        max_per_day: max_per_day as usize,
+    }))
+}
+
+/// Parses [notifier.syslog]. Every key is optional, so this always returns
+/// settings
+fn parse_syslog(config: &toml::Value) -> Result<SyslogSettings, String> {
+    let mut settings = SyslogSettings::new();
+    if let Some(name) = try!(lookup_optional_string(config, "notifier.syslog.facility")) {
+        settings.facility = try!(syslog::facility(&name)
+                                 .ok_or(format!("'notifier.syslog.facility' has an unknown facility '{}'", name)));
+    }
+    if let Some(ident) = try!(lookup_optional_string(config, "notifier.syslog.ident")) {
+        settings.ident = ident;
+    }
+    Ok(settings)
+}
+
+// The example config, written out by --init-config
+const EXAMPLE_CONFIG: &'static str = include_str!("relay.toml");
+
+/// Path of the config file, ~/.relay.toml, unless --config says otherwise
+#[cfg(not(windows))]
+pub fn config_path() -> Result<PathBuf, String> {
+    let homedir = try!(env::home_dir().ok_or("Cannot find home directory"));
+    let mut path = PathBuf::from(homedir);
+    path.push(".relay");
+    path.set_extension("toml");
+    Ok(path)
+}
+
+/// Path of the config file on windows, %APPDATA%\weechat-alert\relay.toml
+/// (where config goes rather than dot files in the home directory)
+#[cfg(windows)]
+pub fn config_path() -> Result<PathBuf, String> {
+    let appdata = try!(env::var_os("APPDATA").ok_or("Cannot find the APPDATA directory"));
+    Ok(PathBuf::from(appdata).join("weechat-alert").join("relay.toml"))
+}
+
+/// Writes the example config to a path (see config_path) for a new user to
+/// fill in. An existing config is never overwritten. The file is only
+/// readable by the user, as it will have the relay password in it.
+pub fn init_config(path: &Path) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e)));
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = match options.open(path) {
+        Ok(file)                                               => file,
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(format!("{} already exists, not overwriting it", path.display()));
+        },
+        Err(e)                                                 => {
+            return Err(format!("Could not create {}: {}", path.display(), e));
+        },
+    };
+    try!(file.write_all(EXAMPLE_CONFIG.as_bytes())
+         .map_err(|e| format!("Could not write {}: {}", path.display(), e)));
+    Ok(())
+}
+
+/// Parses sound_queue and sound_queue_depth
+fn parse_sound_policy(config: &toml::Value) -> Result<SoundPolicy, String> {
+    let depth = match config.lookup("sound_queue_depth") {
+        Some(depth) => {
+            let depth = try!(depth.as_integer().ok_or("'sound_queue_depth' is not an integer"));
+            if depth < 1 || depth > 100 {
+                return Err("'sound_queue_depth' must be between 1 and 100".to_string());
+            }
+            depth as usize
+        },
+        None        => sound::DEFAULT_QUEUE_DEPTH,
+    };
+    match try!(lookup_optional_string(config, "sound_queue")) {
+        Some(name) => SoundPolicy::from_name(&name, depth)
+                          .ok_or("'sound_queue' must be \"queue\", \"coalesce\" or \"drop\"".to_string()),
+        None       => Ok(SoundPolicy::Coalesce),
+    }
+}
+
+/// Parses fifo_path, fifo_format and fifo_remove_on_exit, if there is a
+/// fifo_path
+fn parse_fifo(config: &toml::Value) -> Result<Option<FifoSettings>, String> {
+    let path = match try!(lookup_path(config, "fifo_path")) {
+        Some(path) => path,
+        None       => return Ok(None),
+    };
+    let format = match try!(lookup_optional_string(config, "fifo_format")) {
+        Some(f) => try!(FifoFormat::from_name(&f).ok_or("'fifo_format' must be \"json\" or \"tsv\"")),
+        None    => FifoFormat::Json,
+    };
+    let default_remove_on_exit = toml::Value::Boolean(false);
+    let remove_on_exit = config.lookup("fifo_remove_on_exit").unwrap_or(&default_remove_on_exit);
+    let remove_on_exit = try!(remove_on_exit.as_bool().ok_or("'fifo_remove_on_exit' is not true or false"));
+
+    Ok(Some(FifoSettings {
+        path: path,
+        format: format,
+        remove_on_exit: remove_on_exit,
+    }))
+}
+
+/// Parses highlight_log and the settings for rotating it, if there is a
+/// highlight_log. Times are written with time_format
+fn parse_highlight_log(config: &toml::Value, time_format: &TimeFormat) -> Result<Option<HighlightLogSettings>, String> {
+    let path = match try!(lookup_path(config, "highlight_log")) {
+        Some(path) => path,
+        None       => return Ok(None),
+    };
+    let max_size = match config.lookup("highlight_log_max_mb") {
+        Some(max) => {
+            let max = try!(max.as_integer().ok_or("'highlight_log_max_mb' is not an integer"));
+            if max < 0 {
+                return Err("'highlight_log_max_mb' can't be negative".to_string());
+            }
+            max as u64 * 1024 * 1024
+        },
+        None      => 0,
+    };
+    let keep = match config.lookup("highlight_log_keep") {
+        Some(keep) => {
+            let keep = try!(keep.as_integer().ok_or("'highlight_log_keep' is not an integer"));
+            if keep < 0 || keep > 1000 {
+                return Err("'highlight_log_keep' must be between 0 and 1000".to_string());
+            }
+            keep as u32
+        },
+        None       => highlight_log::DEFAULT_KEEP,
+    };
+    let default_utc = toml::Value::Boolean(false);
+    let utc = config.lookup("highlight_log_utc").unwrap_or(&default_utc);
+    let utc = try!(utc.as_bool().ok_or("'highlight_log_utc' is not true or false"));
+    let time_format = if utc { time_format.in_utc() } else { time_format.clone() };
+
+    Ok(Some(HighlightLogSettings {
+        path: path,
+        max_size: max_size,
+        keep: keep,
+        time_format: time_format,
+    }))
+}
+
+/// Parses min_delay, max_delay and alert_after from a table in [reconnect]
+/// (named by key in errors), with any that aren't set taken from defaults
+fn parse_backoff(table: &toml::Value, key: &str, defaults: Backoff) -> Result<Backoff, String> {
+    let lookup = |name: &str, default: u64| -> Result<u64, String> {
+        match table.lookup(name) {
+            Some(v) => {
+                let v = try!(v.as_integer().ok_or(format!("'{}.{}' is not an integer", key, name)));
+                if v < 0 {
+                    return Err(format!("'{}.{}' can't be negative", key, name));
+                }
+                Ok(v as u64)
+            },
+            None    => Ok(default),
+        }
+    };
+    let backoff = Backoff {
+        min_delay: try!(lookup("min_delay", defaults.min_delay)),
+        max_delay: try!(lookup("max_delay", defaults.max_delay)),
+        alert_after: try!(lookup("alert_after", defaults.alert_after)),
+    };
+    if backoff.min_delay == 0 {
+        return Err(format!("'{}.min_delay' must be at least 1", key));
+    }
+    if backoff.max_delay < backoff.min_delay {
+        return Err(format!("'{}.max_delay' can't be less than its min_delay", key));
+    }
+    Ok(backoff)
+}
+
+/// Parses the [reconnect] table and its [[reconnect.window]]s. Windows
+/// default to the settings of the [reconnect] table for anything they don't
+/// set.
+fn parse_reconnect(config: &toml::Value) -> Result<Schedule, String> {
+    let mut schedule = Schedule::new();
+    let reconnect = match config.lookup("reconnect") {
+        Some(reconnect) => reconnect,
+        None            => return Ok(schedule),
+    };
+    if reconnect.as_table().is_none() {
+        return Err("'reconnect' is not a table".to_string());
+    }
+    let default = try!(parse_backoff(reconnect, "reconnect", Backoff::new()));
+    schedule.set_default(default);
+
+    if let Some(windows) = reconnect.lookup("window") {
+        let windows = try!(windows.as_slice().ok_or("'reconnect.window' is not a list of tables"));
+        for (i, window) in windows.iter().enumerate() {
+            let key = format!("reconnect.window[{}]", i);
+            if window.as_table().is_none() {
+                return Err(format!("'{}' is not a table", key));
+            }
+            let mut times = Vec::new();
+            for name in &["start", "end"] {
+                let time = try!(window.lookup(name).and_then(|t| t.as_str())
+                                .ok_or(format!("'{}.{}' must be set to a time, like \"04:00\"", key, name)));
+                times.push(try!(backoff::parse_time(time)
+                                .ok_or(format!("'{}.{}' is not a time like \"04:00\"", key, name))));
+            }
+            if times[0] == times[1] {
+                return Err(format!("'{}' starts and ends at the same time", key));
+            }
+            let backoff = try!(parse_backoff(window, &key, default));
+            schedule.add_window(times[0], times[1], backoff);
+        }
+    }
+    Ok(schedule)
+}
+
+/// Records why something couldn't be parsed, if it couldn't, and hands back
+/// what was parsed otherwise
+fn check<T>(problems: &mut Vec<String>, result: Result<T, String>) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e)    => {
+            problems.push(e);
+            None
+        },
+    }
+}
+
+/// Sets a setting to what was parsed for it, or records why it couldn't be
+/// parsed and leaves it as it was
+fn set<T>(problems: &mut Vec<String>, setting: &mut T, result: Result<T, String>) {
+    if let Some(value) = check(problems, result) {
+        *setting = value;
+    }
+}
+
+impl Config {
+    /// The values parse_config starts from, before each part of the config
+    /// is parsed over them. They are only seen by the parts that depend on
+    /// an earlier part which failed.
+    fn new() -> Config {
+        Config {
+            host: String::new(),
+            port: 0,
+            password: None,
+            ssl: false,
+            ssl_verify: false,
+            ca_certs_path: None,
+            terminator: None,
+            transport: Transport::Tcp,
+            compression: Compression::Off,
+            max_parse_depth: parse::DEFAULT_MAX_DEPTH,
+            log_level: LogLevelFilter::Info,
+            watch_buffers: Vec::new(),
+            ignore_buffers: Vec::new(),
+            ignore_self: true,
+            own_nicks: Vec::new(),
+            respect_display_filter: true,
+            max_line_age: Duration::from_secs(0),
+            buffer_cooldown: Duration::from_secs(0),
+            keepalive: Duration::from_secs(0),
+            error_policy: ErrorPolicy::new(),
+            reconnect: Schedule::new(),
+            control_socket: None,
+            tray: false,
+            forward_buffer: None,
+            forward_format: Template::parse(alert::DEFAULT_FORWARD_FORMAT).unwrap(),
+            #[cfg(feature = "health")]
+            health_listen: None,
+            health_file: None,
+            health_max_age: Duration::from_secs(health::DEFAULT_MAX_AGE),
+            notifiers: vec!["sound".to_string()],
+            notify: Settings::new(),
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// Reads and parses a config file (see config_path), with the `--set
+/// key=value` overrides from the command line applied over it (in order).
+/// Every part of the config is checked even if an earlier one has a problem,
+/// so this fails with all of the problems found, not just the first.
+pub fn parse_config(path: &Path, overrides: &[String]) -> Result<Config, Vec<String>> {
+    // Read the config, along with any files it includes. None of the rest
+    // can be checked without it
+    let mut config = try!(load(path, &mut Vec::new()).map_err(|e| vec![e]));
+    let mut problems = Vec::new();
+    for arg in overrides {
+        if let Some(table) = check(&mut problems, parse_override(arg)) {
+            merge(&mut config, table);
+        }
+    }
+    let config = toml::Value::Table(config);
+
+    // Parts that use what an earlier part parsed (ex: the backends use the
+    // format) get its default if that part failed
+    let mut parsed = Config::new();
+    let parts: [fn(&toml::Value, &mut Config) -> Result<(), String>; 6] = [parse_connection, parse_alerting,
+                                                                           parse_control, parse_formats,
+                                                                           parse_notify, parse_classes];
+    for part in &parts {
+        check(&mut problems, part(&config, &mut parsed));
+    }
+
+    let format = parsed.notify.format.clone();
+    let ca_certs = parsed.ca_certs_path.clone();
+    {
+        let notify = &mut parsed.notify;
+        set(&mut problems, &mut notify.webhook, parse_webhook(&config));
+        set(&mut problems, &mut notify.pushover, parse_pushover(&config));
+        #[cfg(feature = "pushbullet")]
+        {
+            set(&mut problems, &mut notify.pushbullet, parse_pushbullet(&config, &format));
+        }
+        set(&mut problems, &mut notify.telegram, parse_telegram(&config, &format));
+        set(&mut problems, &mut notify.discord, parse_discord(&config, &format));
+        set(&mut problems, &mut notify.slack, parse_slack(&config, &format));
+        set(&mut problems, &mut notify.matrix, parse_matrix(&config, &format));
+        set(&mut problems, &mut notify.ntfy, parse_ntfy(&config, &format));
+        set(&mut problems, &mut notify.gotify, parse_gotify(&config, &format, ca_certs.as_ref()));
+        #[cfg(feature = "xmpp")]
+        {
+            set(&mut problems, &mut notify.xmpp, parse_xmpp(&config, &format));
+        }
+        set(&mut problems, &mut notify.email, parse_email(&config, &format));
+        set(&mut problems, &mut notify.twilio, parse_twilio(&config));
+        set(&mut problems, &mut notify.syslog, parse_syslog(&config));
+        set(&mut problems, &mut notify.buffers, parse_buffers(&config));
+    }
+
+    if problems.is_empty() {
+        Ok(parsed)
+    } else {
+        Err(problems)
+    }
+}
+
+/// Looks up the relay's server, and checks it resolves to an address (which
+/// needs the network, so check-config only does it with --resolve)
+pub fn resolve_server(config: &Config) -> Result<(), String> {
+    let mut addrs = try!((config.host.as_str(), config.port as u16).to_socket_addrs()
+                         .map_err(|e| format!("'server': could not resolve {}: {}", config.host, e)));
+    match addrs.next() {
+        Some(_) => Ok(()),
+        None    => Err(format!("'server': {} has no addresses", config.host)),
+    }
+}
+
+/// The relay to connect to, how, and how to read what it sends
+fn parse_connection(config: &toml::Value, parsed: &mut Config) -> Result<(), String> {
+    let host = try!(config.lookup("server").ok_or("'server' not found in the config file"));
+    parsed.host = try!(host.as_str().map(|s| s.to_string()).ok_or("'server' is not a valid string"));
+
+    // An empty password is the same as none, as some relays reject an empty
+    // password in the init
+    parsed.password = match try!(lookup_optional_string(config, "password")) {
+        Some(ref pw) if pw.is_empty() => None,
+        pw                            => pw,
+    };
+
+    let port = try!(config.lookup("port").ok_or("'port' not found in the config file"));
+    let port = try!(port.as_integer().ok_or("'port' is not an integer"));
+    if port < 1 || port > 65535 {
+        return Err("'port' must be between 1 and 65535".to_string());
+    }
+    parsed.port = port as i32;
+
+    let default_ssl = toml::Value::Boolean(false);
+    let ssl = config.lookup("ssl").unwrap_or(&default_ssl);
+    parsed.ssl = try!(ssl.as_bool().ok_or("'ssl' is not true or false"));
+
+    let default_ssl_verify = toml::Value::Boolean(false);
+    let ssl_verify = config.lookup("ssl_verify").unwrap_or(&default_ssl_verify);
+    parsed.ssl_verify = try!(ssl_verify.as_bool().ok_or("'ssl_verify' is not a true or false"));
+
+    parsed.ca_certs_path = match config.lookup("ca_certs_path") {
+        Some(ca) => Some(try!(ca.as_str().map(|s| s.to_string())
+                         .ok_or("'ca_certs_path' is not a valid string"))),
+        None     => None
+    };
+
+    parsed.terminator = match config.lookup("command_terminator") {
+        Some(t) => match t.as_str() {
+            Some("\n")   => Some("\n".to_string()),
+            Some("\r\n") => Some("\r\n".to_string()),
+            _            => return Err("'command_terminator' must be \"\\n\" or \"\\r\\n\"".to_string()),
+        },
+        None    => None
+    };
+
+    parsed.transport = match try!(lookup_optional_string(config, "transport")).as_ref().map(|t| t.as_str()) {
+        Some("tcp") | None => Transport::Tcp,
+        Some("websocket")  => {
+            let path = try!(lookup_optional_string(config, "websocket_path"))
+                           .unwrap_or(websocket::DEFAULT_PATH.to_string());
+            if !path.starts_with('/') {
+                return Err("'websocket_path' must start with a /".to_string());
+            }
+            Transport::WebSocket(path)
+        },
+        _                  => return Err("'transport' must be \"tcp\" or \"websocket\"".to_string()),
+    };
+
+    parsed.compression = match try!(lookup_optional_string(config, "compression")).as_ref().map(|c| c.as_str()) {
+        Some("off") | None => Compression::Off,
+        Some("zlib")       => Compression::Zlib,
+        _                  => return Err("'compression' must be \"off\" or \"zlib\"".to_string()),
+    };
+
+    parsed.log_level = match try!(lookup_optional_string(config, "log_level")).as_ref().map(|l| l.as_str()) {
+        Some("error")       => LogLevelFilter::Error,
+        Some("warn")        => LogLevelFilter::Warn,
+        Some("info") | None => LogLevelFilter::Info,
+        Some("debug")       => LogLevelFilter::Debug,
+        Some("trace")       => LogLevelFilter::Trace,
+        _                   => {
+            return Err("'log_level' must be \"error\", \"warn\", \"info\", \"debug\" or \"trace\"".to_string())
+        },
+    };
+
+    let max_parse_depth = match config.lookup("max_parse_depth") {
+        Some(d) => try!(d.as_integer().ok_or("'max_parse_depth' is not an integer")),
+        None    => parse::DEFAULT_MAX_DEPTH as i64,
+    };
+    if max_parse_depth <= 0 {
+        return Err("'max_parse_depth' must be at least 1".to_string());
+    }
+    parsed.max_parse_depth = max_parse_depth as usize;
+    Ok(())
+}
+
+/// Which lines alert, how often, which backends they notify with, and what
+/// to do when the connection drops
+fn parse_alerting(config: &toml::Value, parsed: &mut Config) -> Result<(), String> {
+    parsed.watch_buffers = try!(lookup_string_list(config, "watch_buffers"));
+    parsed.ignore_buffers = try!(lookup_string_list(config, "ignore_buffers"));
+
+    let default_ignore_self = toml::Value::Boolean(true);
+    let ignore_self = config.lookup("ignore_self").unwrap_or(&default_ignore_self);
+    parsed.ignore_self = try!(ignore_self.as_bool().ok_or("'ignore_self' is not true or false"));
+    parsed.own_nicks = try!(lookup_string_list(config, "own_nicks"));
+
+    let default_respect_display_filter = toml::Value::Boolean(true);
+    let respect_display_filter = config.lookup("respect_display_filter")
+                                       .unwrap_or(&default_respect_display_filter);
+    parsed.respect_display_filter = try!(respect_display_filter.as_bool()
+                                         .ok_or("'respect_display_filter' is not true or false"));
+
+    let buffer_cooldown = match config.lookup("buffer_cooldown") {
+        Some(c) => try!(c.as_integer().ok_or("'buffer_cooldown' is not an integer")),
+        None    => 0,
+    };
+    if buffer_cooldown < 0 {
+        return Err("'buffer_cooldown' can't be negative".to_string());
+    }
+    parsed.buffer_cooldown = Duration::from_secs(buffer_cooldown as u64);
+
+    let max_line_age = match config.lookup("max_line_age") {
+        Some(a) => try!(a.as_integer().ok_or("'max_line_age' is not an integer")),
+        None    => 0,
+    };
+    if max_line_age < 0 {
+        return Err("'max_line_age' can't be negative".to_string());
+    }
+    parsed.max_line_age = Duration::from_secs(max_line_age as u64);
+
+    let keepalive = match config.lookup("keepalive") {
+        Some(k) => try!(k.as_integer().ok_or("'keepalive' is not an integer")),
+        None    => 0,
+    };
+    if keepalive < 0 {
+        return Err("'keepalive' can't be negative".to_string());
+    }
+    parsed.keepalive = Duration::from_secs(keepalive as u64);
+
+    // Overrides for which io errors we reconnect after
+    for &(key, action) in &[("retry_errors", policy::Action::Retry),
+                            ("fatal_errors", policy::Action::Fatal)] {
+        for name in try!(lookup_string_list(config, key)) {
+            let kind = try!(policy::error_kind(&name).ok_or(format!("'{}': unknown error '{}'", key, name)));
+            parsed.error_policy.set(kind, action);
+        }
+    }
+
+    // How long we wait to reconnect, and to alert, by time of day
+    parsed.reconnect = try!(parse_reconnect(config));
+
+    // Which backends to notify with, defaulting to just playing a sound
+    if config.lookup("notifiers").is_some() {
+        let notifiers = try!(lookup_string_list(config, "notifiers"));
+        for name in &notifiers {
+            try!(notify::check_name(name).map_err(|e| format!("'notifiers': {}", e)));
+        }
+        parsed.notifiers = notifiers;
+    }
+    Ok(())
+}
+
+/// The control socket, the tray icon, forwarding to a buffer and health
+/// checks
+fn parse_control(config: &toml::Value, parsed: &mut Config) -> Result<(), String> {
+    parsed.control_socket = try!(lookup_path(config, "control_socket"));
+
+    let default_tray = toml::Value::Boolean(false);
+    let tray = config.lookup("tray").unwrap_or(&default_tray);
+    parsed.tray = try!(tray.as_bool().ok_or("'tray' is not true or false"));
+
+    let forward_buffer = try!(lookup_optional_string(config, "forward_buffer"));
+    if forward_buffer.as_ref().map_or(false, |b| b.is_empty() || b.contains(char::is_whitespace)) {
+        return Err("'forward_buffer' must be the full name of a buffer (ex: \"core.weechat\")".to_string());
+    }
+    parsed.forward_buffer = forward_buffer;
+    parsed.forward_format = try!(lookup_template(config, "forward_format", alert::DEFAULT_FORWARD_FORMAT));
+
+    #[cfg(feature = "health")]
+    {
+        parsed.health_listen = try!(lookup_optional_string(config, "health_listen"));
+    }
+    #[cfg(not(feature = "health"))]
+    {
+        if config.lookup("health_listen").is_some() {
+            return Err("'health_listen' needs the health endpoint, which this was built without (see the health \
+                        feature)".to_string());
+        }
+    }
+    parsed.health_file = try!(lookup_path(config, "health_file"));
+    let health_max_age = match config.lookup("health_max_age") {
+        Some(a) => try!(a.as_integer().ok_or("'health_max_age' is not an integer")),
+        None    => health::DEFAULT_MAX_AGE as i64,
+    };
+    if health_max_age <= 0 {
+        return Err("'health_max_age' must be at least 1".to_string());
+    }
+    parsed.health_max_age = Duration::from_secs(health_max_age as u64);
+    Ok(())
+}
+
+/// How text based notifications are rendered, and how times are written
+fn parse_formats(config: &toml::Value, parsed: &mut Config) -> Result<(), String> {
+    let format = match config.lookup("format") {
+        Some(f) => try!(f.as_str().ok_or("'format' is not a valid string")),
+        None    => DEFAULT_FORMAT,
+    };
+    let mut format = try!(Template::parse(format).map_err(|e| format!("'format': {}", e)));
+    if let Some(max) = config.lookup("max_body_length") {
+        let max = try!(max.as_integer().ok_or("'max_body_length' is not an integer"));
+        if max < 0 {
+            return Err("'max_body_length' can't be negative".to_string());
+        }
+        format.set_max_length(max as usize);
+    }
+    parsed.notify.format = format;
+
+    // In local time unless time_utc is set
+    let time_format = match config.lookup("time_format") {
+        Some(f) => try!(f.as_str().ok_or("'time_format' is not a valid string")),
+        None    => time_format::DEFAULT_FORMAT,
+    };
+    let default_utc = toml::Value::Boolean(false);
+    let time_utc = config.lookup("time_utc").unwrap_or(&default_utc);
+    let time_utc = try!(time_utc.as_bool().ok_or("'time_utc' is not true or false"));
+    parsed.notify.time_format = try!(TimeFormat::parse(time_format, time_utc)
+                                     .map_err(|e| format!("'time_format': {}", e)));
+
+    let default_strip = toml::Value::Boolean(true);
+    let strip_formatting = config.lookup("strip_formatting").unwrap_or(&default_strip);
+    parsed.notify.strip_formatting = try!(strip_formatting.as_bool()
+                                          .ok_or("'strip_formatting' is not true or false"));
+    Ok(())
+}
+
+/// Sounds, logs of events, the history, desktop notifications, priority
+/// rules and text to speech
+fn parse_notify(config: &toml::Value, parsed: &mut Config) -> Result<(), String> {
+    let notify = &mut parsed.notify;
+    if let Some(sound_path) = try!(lookup_path(config, "sound_path")) {
+        notify.sound_path = sound_path;
+    }
+    notify.sound_policy = try!(parse_sound_policy(config));
+
+    // Sounds for when the connection to the relay drops and comes back.
+    // These are played whether or not the sound notifier is enabled
+    notify.disconnect_sound = try!(lookup_sound(config, "disconnect_sound"));
+    notify.reconnect_sound = try!(lookup_sound(config, "reconnect_sound"));
+    notify.bell_path = try!(lookup_path(config, "bell_path"));
+    notify.json_log = try!(lookup_path(config, "json_log"));
+    notify.fifo = try!(parse_fifo(config));
+    notify.highlight_log = try!(parse_highlight_log(config, &notify.time_format));
+    notify.history_size = match config.lookup("history_size") {
+        Some(size) => {
+            let size = try!(size.as_integer().ok_or("'history_size' is not an integer"));
+            if size < 0 || size > 100000 {
+                return Err("'history_size' must be between 0 and 100000".to_string());
+            }
+            size as usize
+        },
+        None       => history::DEFAULT_SIZE,
+    };
+    let default_bell_only_tty = toml::Value::Boolean(true);
+    let bell_only_tty = config.lookup("bell_only_tty").unwrap_or(&default_bell_only_tty);
+    notify.bell_only_tty = try!(bell_only_tty.as_bool().ok_or("'bell_only_tty' is not true or false"));
+    let default_desktop_reply = toml::Value::Boolean(false);
+    let desktop_reply = config.lookup("desktop_reply").unwrap_or(&default_desktop_reply);
+    notify.desktop_reply = try!(desktop_reply.as_bool().ok_or("'desktop_reply' is not true or false"));
+    for &priority in &Priority::all() {
+        let key = format!("desktop_urgency.{}", priority.name());
+        if let Some(urgency) = config.lookup(&key) {
+            let urgency = try!(urgency.as_str().and_then(Urgency::from_name)
+                .ok_or(format!("'{}' must be \"low\", \"normal\", or \"critical\"", key)));
+            notify.desktop_urgency.set(priority, urgency);
+        }
+    }
+    notify.priority_rules = try!(parse_priority_rules(config));
+
+    if config.lookup("tts_command").is_some() {
+        notify.tts_command = try!(lookup_string_list(config, "tts_command"));
+    }
+    if let Some(f) = config.lookup("tts_format") {
+        let f = try!(f.as_str().ok_or("'tts_format' is not a valid string"));
+        notify.tts_format = try!(Template::parse(f).map_err(|e| format!("'tts_format': {}", e)));
+    }
+    notify.tts_format.set_max_length(tts::DEFAULT_MAX_LENGTH);
+    if let Some(max) = config.lookup("tts_max_length") {
+        let max = try!(max.as_integer().ok_or("'tts_max_length' is not an integer"));
+        if max < 0 {
+            return Err("'tts_max_length' can't be negative".to_string());
+        }
+        notify.tts_format.set_max_length(max as usize);
+    }
+    Ok(())
+}
+
+/// Parses [notifier.webhook], if it has a url
+fn parse_webhook(config: &toml::Value) -> Result<Option<WebhookSettings>, String> {
+    let url = match config.lookup("notifier.webhook.url") {
+        Some(url) => try!(url.as_str().ok_or("'notifier.webhook.url' is not a valid string")),
+        None      => return Ok(None),
+    };
+    try!(Url::parse(url).map_err(|e| format!("'notifier.webhook.url': {}", e)));
+    let mut headers = Vec::new();
+    if let Some(table) = config.lookup("notifier.webhook.headers") {
+        let table = try!(table.as_table().ok_or("'notifier.webhook.headers' is not a table"));
+        for (name, value) in table {
+            let key = format!("notifier.webhook.headers.{}", name);
+            let value = try!(value.as_str().ok_or(format!("'{}' is not a valid string", key)));
+            if name.contains(|c| c == '\r' || c == '\n') || value.contains(|c| c == '\r' || c == '\n') {
+                return Err(format!("'{}' can't contain line breaks", key));
+            }
+            headers.push((name.clone(), value.to_string()));
+        }
+    }
+    let timeout = match config.lookup("notifier.webhook.timeout") {
+        Some(t) => try!(t.as_integer().ok_or("'notifier.webhook.timeout' is not an integer")),
+        None    => webhook::DEFAULT_TIMEOUT as i64,
+    };
+    if timeout <= 0 {
+        return Err("'notifier.webhook.timeout' must be positive".to_string());
+    }
+    Ok(Some(WebhookSettings {
+        url: url.to_string(),
+        headers: headers,
+        timeout: Duration::from_secs(timeout as u64),
+    }))
+}
+
+/// Parses [notifier.pushover], if it is there
+fn parse_pushover(config: &toml::Value) -> Result<Option<PushoverSettings>, String> {
+    if config.lookup("notifier.pushover").is_none() {
+        return Ok(None);
+    }
+
+    let token = try!(config.lookup("notifier.pushover.token")
+                     .ok_or("'notifier.pushover.token' not found in the config file"));
+    let token = try!(token.as_str().ok_or("'notifier.pushover.token' is not a valid string"));
+    let user = try!(config.lookup("notifier.pushover.user")
+                    .ok_or("'notifier.pushover.user' not found in the config file"));
+    let user = try!(user.as_str().ok_or("'notifier.pushover.user' is not a valid string"));
+    let device = match config.lookup("notifier.pushover.device") {
+        Some(d) => Some(try!(d.as_str().map(|s| s.to_string())
+                             .ok_or("'notifier.pushover.device' is not a valid string"))),
+        None    => None,
+    };
+
+    let priorities = try!(lookup_priorities(config, "notifier.pushover.priority",
+                                             pushover::DEFAULT_PRIORITIES, -2, 2));
+    // Older configs set the priority per class, in the same table
+    let mut class_priorities = HashMap::new();
+    for &class in &[EventClass::Private, EventClass::Highlight, EventClass::Watch] {
+        let key = format!("notifier.pushover.priority.{}", class.name());
+        if let Some(priority) = config.lookup(&key) {
+            let priority = try!(priority.as_integer().ok_or(format!("'{}' is not an integer", key)));
+            if priority < -2 || priority > 2 {
+                return Err(format!("'{}' must be between -2 and 2", key));
+            }
+            class_priorities.insert(class, priority as i32);
+        }
+    }
+
+    let retry = match config.lookup("notifier.pushover.retry") {
+        Some(r) => try!(r.as_integer().ok_or("'notifier.pushover.retry' is not an integer")),
+        None    => pushover::DEFAULT_RETRY as i64,
+    };
+    if retry < 30 {
+        return Err("'notifier.pushover.retry' can't be less than 30".to_string());
+    }
+    let expire = match config.lookup("notifier.pushover.expire") {
+        Some(e) => try!(e.as_integer().ok_or("'notifier.pushover.expire' is not an integer")),
+        None    => pushover::DEFAULT_EXPIRE as i64,
+    };
+    if expire <= 0 || expire > 10800 {
+        return Err("'notifier.pushover.expire' must be between 1 and 10800".to_string());
+    }
+
+    Ok(Some(PushoverSettings {
+        token: token.to_string(),
+        user: user.to_string(),
+        device: device,
+        priorities: priorities.map(|p| p as i32),
+        class_priorities: class_priorities,
+        retry: retry as u64,
+        expire: expire as u64,
+    }))
+}
+
+/// Per event class settings. The icon falls back to the global one if a
+/// class doesn't set its own
+fn parse_classes(config: &toml::Value, parsed: &mut Config) -> Result<(), String> {
+    let icon = match config.lookup("notification_icon") {
+        Some(icon) => try!(resolve_icon("notification_icon", icon, &mut parsed.warnings)),
+        None       => None,
+    };
+    let notify = &mut parsed.notify;
+    for &class in &[EventClass::Private, EventClass::Highlight, EventClass::Watch] {
+        let key = format!("class.{}.icon", class.name());
+        notify.classes.get_mut(class).icon = match config.lookup(&key) {
+            Some(class_icon) => try!(resolve_icon(&key, class_icon, &mut parsed.warnings)),
+            None             => icon.clone(),
+        };
+
+        // urgency is the older name for the priority. Its critical was the
+        // same as high is now, rather than the new critical
+        let key = format!("class.{}.urgency", class.name());
+        if let Some(urgency) = config.lookup(&key) {
+            let urgency = try!(urgency.as_str().and_then(Urgency::from_name)
+                .ok_or(format!("'{}' must be \"low\", \"normal\", or \"critical\"", key)));
+            notify.classes.get_mut(class).priority = match urgency {
+                Urgency::Low      => Priority::Low,
+                Urgency::Normal   => Priority::Normal,
+                Urgency::Critical => Priority::High,
+            };
+        }
+
+        let key = format!("class.{}.priority", class.name());
+        if let Some(priority) = config.lookup(&key) {
+            notify.classes.get_mut(class).priority = try!(priority.as_str().and_then(Priority::from_name)
+                .ok_or(format!("'{}' must be \"low\", \"normal\", \"high\" or \"critical\"", key)));
+        }
+
+        let key = format!("class.{}.sound", class.name());
+        notify.classes.get_mut(class).sound = try!(lookup_path(config, &key));
+
+        let key = format!("class.{}.enabled", class.name());
+        if let Some(enabled) = config.lookup(&key) {
+            notify.classes.get_mut(class).enabled = try!(enabled.as_bool()
+                .ok_or(format!("'{}' is not true or false", key)));
+        }
+
+        let key = format!("class.{}.volume", class.name());
+        if let Some(volume) = config.lookup(&key) {
+            // Allow whole numbers, so volume = 1 works
+            let volume = try!(volume.as_float().or(volume.as_integer().map(|v| v as f64))
+                .ok_or(format!("'{}' is not a number", key)));
+            if volume < 0.0 || volume > 1.0 {
+                return Err(format!("'{}' must be between 0.0 and 1.0", key));
+            }
+            notify.classes.get_mut(class).volume = volume as f32;
+        }
+    }
+    Ok(())
+}
+
+/// Per buffer settings, keyed by buffer name or pattern. Quote any name with
+/// a dot in it, ex: [buffer."irc.libera.#rust"]
+fn parse_buffers(config: &toml::Value) -> Result<Vec<BufferSettings>, String> {
+    let mut parsed = Vec::new();
+    let buffers = match config.lookup("buffer") {
+        Some(buffers) => try!(buffers.as_table().ok_or("'buffer' is not a table")),
+        None          => return Ok(parsed),
+    };
+    for (pattern, settings) in buffers {
+        let key = format!("buffer.\"{}\"", pattern);
+        // Buffer names have no spaces, so a pattern with one can't match
+        if pattern.is_empty() || pattern.contains(char::is_whitespace) {
+            return Err(format!("'{}' is not a buffer name or pattern (ex: \"irc.libera.*\")", key));
+        }
+        if settings.as_table().is_none() {
+            return Err(format!("'{}' is not a table", key));
+        }
+        let cooldown = match settings.lookup("cooldown") {
+            Some(c) => {
+                let c = try!(c.as_integer().ok_or(format!("'{}.cooldown' is not an integer", key)));
+                if c < 0 {
+                    return Err(format!("'{}.cooldown' can't be negative", key));
+                }
+                Some(Duration::from_secs(c as u64))
+            },
+            None    => None,
+        };
+        parsed.push(BufferSettings {
+            pattern: pattern.clone(),
+            sound: try!(lookup_path(settings, "sound")
+                        .map_err(|e| format!("'{}': {}", key, e))),
+            cooldown: cooldown,
+        });
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+    use std::time::Duration;
+
+    use toml;
+
+    use super::{parse_alerting, parse_classes, parse_config, parse_control, parse_notify, parse_reconnect,
+                parse_twilio, Config, EXAMPLE_CONFIG};
+    use backoff::Backoff;
+    use notify::{EventClass, Priority, Urgency};
+
+    fn toml(text: &str) -> toml::Value {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn default_desktop_urgency() {
+        let mut parsed = Config::new();
+        parse_notify(&toml(""), &mut parsed).unwrap();
+        let urgency = &parsed.notify.desktop_urgency;
+        assert_eq!(urgency.get(Priority::Low), Urgency::Low);
+        assert_eq!(urgency.get(Priority::Normal), Urgency::Normal);
+        assert_eq!(urgency.get(Priority::High), Urgency::Critical);
+        assert_eq!(urgency.get(Priority::Critical), Urgency::Critical);
+    }
+
+    #[test]
+    fn desktop_urgency_from_the_config() {
+        let mut parsed = Config::new();
+        parse_notify(&toml("[desktop_urgency]\nhigh = \"normal\"\nlow = \"normal\""), &mut parsed).unwrap();
+        let urgency = &parsed.notify.desktop_urgency;
+        assert_eq!(urgency.get(Priority::Low), Urgency::Normal);
+        assert_eq!(urgency.get(Priority::Normal), Urgency::Normal);
+        assert_eq!(urgency.get(Priority::High), Urgency::Normal);
+        assert_eq!(urgency.get(Priority::Critical), Urgency::Critical);
+
+        let err = parse_notify(&toml("[desktop_urgency]\nhigh = \"loud\""), &mut Config::new()).unwrap_err();
+        assert!(err.contains("desktop_urgency.high"), "{}", err);
+    }
+
+    #[test]
+    fn class_urgency_sets_the_priority() {
+        // urgency is the older name for the priority, and its critical is
+        // high now
+        let mut parsed = Config::new();
+        parse_classes(&toml("[class.private]\nurgency = \"critical\"\n[class.watch]\nurgency = \"low\""),
+                      &mut parsed).unwrap();
+        assert_eq!(parsed.notify.classes.get(EventClass::Private).priority, Priority::High);
+        assert_eq!(parsed.notify.classes.get(EventClass::Watch).priority, Priority::Low);
+    }
+
+    #[test]
+    fn class_enabled_and_volume() {
+        let mut parsed = Config::new();
+        parse_classes(&toml("[class.watch]\nenabled = false\n[class.private]\nvolume = 0.5\n\
+                             [class.highlight]\nvolume = 0"), &mut parsed).unwrap();
+        let classes = &parsed.notify.classes;
+        assert!(!classes.get(EventClass::Watch).enabled);
+        assert_eq!(classes.get(EventClass::Watch).volume, 1.0);
+        assert!(classes.get(EventClass::Private).enabled);
+        assert_eq!(classes.get(EventClass::Private).volume, 0.5);
+        assert_eq!(classes.get(EventClass::Highlight).volume, 0.0);
+
+        let err = parse_classes(&toml("[class.private]\nvolume = 1.5"), &mut Config::new()).unwrap_err();
+        assert!(err.contains("'class.private.volume'"), "{}", err);
+        let err = parse_classes(&toml("[class.watch]\nenabled = \"no\""), &mut Config::new()).unwrap_err();
+        assert!(err.contains("'class.watch.enabled'"), "{}", err);
+    }
+
+    #[test]
+    fn missing_icons_are_warnings() {
+        let icon = env::temp_dir().join("weechat-alert-test-icon.png");
+        File::create(&icon).unwrap();
+        let mut parsed = Config::new();
+        parse_classes(&toml(&format!("notification_icon = \"dialog-information\"\n\
+                                      [class.private]\nicon = '/weechat-alert/no-such-icon.png'\n\
+                                      [class.highlight]\nicon = '{}'", icon.display())), &mut parsed).unwrap();
+        fs::remove_file(&icon).unwrap();
+
+        let classes = &parsed.notify.classes;
+        assert_eq!(classes.get(EventClass::Watch).icon, Some("dialog-information".to_string()));
+        assert_eq!(classes.get(EventClass::Private).icon, None);
+        assert_eq!(classes.get(EventClass::Highlight).icon, Some(icon.to_string_lossy().into_owned()));
+        assert_eq!(parsed.warnings.len(), 1);
+        assert!(parsed.warnings[0].contains("'class.private.icon'"), "{}", parsed.warnings[0]);
+    }
+
+    #[test]
+    fn health_listen_needs_the_health_feature() {
+        let mut parsed = Config::new();
+        let result = parse_control(&toml("health_listen = \"127.0.0.1:8080\""), &mut parsed);
+        #[cfg(feature = "health")]
+        {
+            assert_eq!(result, Ok(()));
+            assert_eq!(parsed.health_listen, Some("127.0.0.1:8080".to_string()));
+        }
+        #[cfg(not(feature = "health"))]
+        {
+            assert!(result.unwrap_err().contains("health feature"));
+        }
+    }
+
+    #[test]
+    fn twilio_needs_buffers_or_keywords() {
+        let account = "[notifier.twilio]\naccount_sid = \"AC123\"\nauth_token = \"t\"\n\
+                       from = \"+1555\"\nto = \"+1556\"\n";
+        assert_eq!(parse_twilio(&toml("")).unwrap().map(|t| t.to), None);
+        assert!(parse_twilio(&toml(account)).unwrap_err().contains("buffers or keywords"));
+        let twilio = parse_twilio(&toml(&format!("{}keywords = [\"down\"]", account))).unwrap().unwrap();
+        assert_eq!(twilio.keywords, vec!["down".to_string()]);
+        assert_eq!(twilio.max_per_day, 10);
+        assert!(parse_twilio(&toml("[notifier.twilio]\nauth_token = \"t\"")).unwrap_err().contains("account_sid"));
+    }
+
+    #[test]
+    fn reconnect_windows_default_to_the_reconnect_table() {
+        let schedule = parse_reconnect(&toml("[reconnect]\nmin_delay = 2\nalert_after = 60\n\
+                                              [[reconnect.window]]\nstart = \"03:30\"\nend = \"04:30\"\n\
+                                              max_delay = 900\nalert_after = 1800\n")).unwrap();
+        assert_eq!(schedule.at(4 * 60), Backoff { min_delay: 2, max_delay: 900, alert_after: 1800 });
+        assert_eq!(schedule.at(5 * 60), Backoff { min_delay: 2, max_delay: 300, alert_after: 60 });
+
+        let err = parse_reconnect(&toml("[[reconnect.window]]\nstart = \"4am\"\nend = \"05:00\"")).unwrap_err();
+        assert!(err.contains("reconnect.window[0].start"), "{}", err);
+        let err = parse_reconnect(&toml("[[reconnect.window]]\nstart = \"04:00\"\nend = \"04:00\"")).unwrap_err();
+        assert!(err.contains("same time"), "{}", err);
+        let err = parse_reconnect(&toml("[reconnect]\nmin_delay = 10\nmax_delay = 5")).unwrap_err();
+        assert!(err.contains("'reconnect.max_delay'"), "{}", err);
+    }
+
+    #[test]
+    fn max_line_age_is_seconds() {
+        let mut parsed = Config::new();
+        parse_alerting(&toml(""), &mut parsed).unwrap();
+        assert_eq!(parsed.max_line_age, Duration::from_secs(0));
+        parse_alerting(&toml("max_line_age = 600"), &mut parsed).unwrap();
+        assert_eq!(parsed.max_line_age, Duration::from_secs(600));
+        assert!(parse_alerting(&toml("max_line_age = -1"), &mut parsed).unwrap_err().contains("negative"));
+        assert!(parse_alerting(&toml("max_line_age = \"10m\""), &mut parsed).unwrap_err().contains("integer"));
+    }
+
+    #[test]
+    fn check_config_finds_every_problem() {
+        let dir = env::temp_dir().join("weechat-alert-check-config-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("relay.toml");
+        let write = |text: &str| File::create(&path).unwrap().write_all(text.as_bytes()).unwrap();
+
+        // The example config init-config writes checks out, once its port
+        // is filled in
+        write(&EXAMPLE_CONFIG.replace("<relay_port>", "9001"));
+        if let Err(problems) = parse_config(&path, &[]) {
+            panic!("the example config has problems: {:?}", problems);
+        }
+
+        // One problem in each of a few parts, all of which are found
+        write("server = \"localhost\"\nport = 70000\nmax_line_age = -1\n\
+               [class.private]\nvolume = 2.0\n[notifier.twilio]\nauth_token = \"t\"\n");
+        let problems = parse_config(&path, &[]).err().unwrap();
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems[0].contains("'port'"), "{:?}", problems);
+        assert!(problems[1].contains("'max_line_age'"), "{:?}", problems);
+        assert!(problems[2].contains("'class.private.volume'"), "{:?}", problems);
+        assert!(problems[3].contains("account_sid"), "{:?}", problems);
+
+        // Overrides are checked like the rest, and can fix a problem
+        let problems = parse_config(&path, &["port=9001".to_string(), "nonsense".to_string()]).err().unwrap();
+        assert_eq!(problems.len(), 4, "{:?}", problems);
+        assert!(problems.iter().all(|p| !p.contains("'port'")), "{:?}", problems);
+
+        fs::remove_dir_all(&dir).unwrap();
+        let problems = parse_config(&path, &[]).err().unwrap();
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+    }
+}
@@ -0,0 +1,617 @@
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::net::Shutdown;
+use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use openssl::ssl;
+use openssl::ssl::{Ssl, SslMethod, SslContext, SslStream, MaybeSslStream,
+                   SslVerifyMode, SSL_VERIFY_NONE, SSL_VERIFY_PEER};
+
+use commands::{Command, QuitCommand};
+use errors::WeechatError;
+use inflate;
+use message::{Header, Message};
+use parse::DEFAULT_MAX_DEPTH;
+use profile::Reading;
+use stats::Counts;
+use websocket::{self, WebSocket};
+
+/// number of bytes that make up the message header
+pub const HEADER_LENGTH: usize = 5;
+
+/// Data for enabling SSL on the weechat relay
+pub struct SslConfig {
+    /// SSL verify mode
+    verify: SslVerifyMode,
+    /// Optional path to a file containing ca certificates. This is may be needed
+    /// if you are verifying the ssl cert. On linux, this is normally at
+    /// /etc/ssl/certs/ca-certificates.crt.
+    ca_cert_path: Option<PathBuf>,
+}
+
+impl SslConfig {
+    pub fn new(verify: bool, ca_cert_path: Option<String>) -> SslConfig {
+        let path = match ca_cert_path {
+            Some(s) => Some(PathBuf::from(s)),
+            None    => None,
+        };
+        let verify_mode = if verify == true { SSL_VERIFY_PEER } else { SSL_VERIFY_NONE };
+
+        SslConfig {
+            verify: verify_mode,
+            ca_cert_path: path,
+        }
+    }
+}
+
+/// How relay messages are carried over the socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Straight over the socket, as the relay protocol was designed
+    Tcp,
+    /// Inside websocket frames, from/to the given path (ex: "/weechat"). For
+    /// relays that are only reachable through a reverse proxy.
+    WebSocket(String),
+}
+
+/// If we ask the relay to compress the messages it sends
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Off,
+    /// Zlib, which relays have supported since weechat 0.3.7
+    Zlib,
+}
+
+impl Compression {
+    /// Name of the compression in the config and the init command
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Compression::Off  => "off",
+            Compression::Zlib => "zlib",
+        }
+    }
+}
+
+/// The socket to a relay, with or without ssl
+pub type Stream = MaybeSslStream<TcpStream>;
+
+/// An open socket to a weechat relay, which sends commands and reads the
+/// messages the relay sends back. This is over a Stream (see connect) unless
+/// it was made with from_stream.
+pub struct Connection<S = Stream> {
+    stream: S,
+    /// String terminating every command we send
+    terminator: String,
+    /// The websocket the relay protocol is framed in, if we use one
+    websocket: Option<WebSocket>,
+    /// File every message we read is copied to, for --dump
+    dump: Option<File>,
+    /// How much we have read on this connection
+    counts: Counts,
+    /// Time spent parsing and inflating messages, for --profile
+    reading: Option<Reading>,
+    /// How deeply arrays and hashtables can nest in the messages we parse
+    max_parse_depth: usize,
+}
+
+impl Connection {
+    /// Opens a connection to the relay. This only connects the socket (and
+    /// does the ssl and websocket handshakes), it doesn't init the relay
+    /// protocol.
+    pub fn connect(host: &str, port: i32, ssl: Option<&SslConfig>, terminator: &str,
+                   transport: &Transport) -> Result<Connection, WeechatError> {
+        // The initial tpc connection to the server
+        let addr = format!("{}:{}", host, port);
+        let tcp_stream = try!(TcpStream::connect(&*addr));
+
+        // Turn on ssl if configured
+        let mut stream = match ssl {
+            Some(ssl) => {
+                let mut ctx = try!(SslContext::new(SslMethod::Sslv23));
+                ctx.set_verify(ssl.verify, None);
+                match ssl.ca_cert_path {
+                    Some(ref path) => try!(ctx.set_CA_file(path)),
+                    None       => (),
+                }
+                let ssl = try!(Ssl::new(&ctx));
+                let ssl_stream = try!(SslStream::connect(ssl, tcp_stream));
+                MaybeSslStream::Ssl(ssl_stream)
+            },
+            None      => MaybeSslStream::Normal(tcp_stream)
+        };
+
+        let websocket = match *transport {
+            Transport::WebSocket(ref path) => {
+                try!(websocket::handshake(&mut stream, host, port, path));
+                Some(WebSocket::new())
+            },
+            Transport::Tcp                 => None,
+        };
+
+        let mut conn = Connection::from_stream(stream, terminator);
+        conn.websocket = websocket;
+        Ok(conn)
+    }
+
+    /// Sets how long a read waits for the relay before failing (with
+    /// WouldBlock or TimedOut, depending on the platform). None waits
+    /// forever, as a new connection does.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<(), WeechatError> {
+        Ok(try!(self.stream.get_ref().set_read_timeout(timeout)))
+    }
+
+    /// Waits up to timeout for the relay to send something, returning false
+    /// if it didn't. Nothing is read, so this never leaves part of a message
+    /// behind. A closed socket counts as something, for the next read to
+    /// fail on.
+    pub fn wait_readable(&mut self, timeout: Duration) -> Result<bool, WeechatError> {
+        if self.buffered() {
+            return Ok(true);
+        }
+        let socket = self.stream.get_ref();
+        try!(socket.set_read_timeout(Some(timeout)));
+        let mut byte = [0; 1];
+        let peeked = socket.peek(&mut byte);
+        try!(socket.set_read_timeout(None));
+        match peeked {
+            Ok(_)                                             => Ok(true),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock ||
+                          e.kind() == io::ErrorKind::TimedOut => Ok(false),
+            Err(e)                                            => Err(WeechatError::Io(e)),
+        }
+    }
+
+    /// File descriptor of the socket, for polling it
+    #[cfg(unix)]
+    pub fn raw_fd(&self) -> RawFd {
+        self.stream.get_ref().as_raw_fd()
+    }
+
+    /// Returns true if ssl (or the websocket) has already read part of the
+    /// next message off of the socket. The socket may not poll as readable
+    /// when this happens, even though there is a message waiting.
+    pub fn buffered(&self) -> bool {
+        if self.websocket.as_ref().map(|w| w.buffered()).unwrap_or(false) {
+            return true;
+        }
+        match self.stream {
+            MaybeSslStream::Ssl(ref stream) => stream.ssl().pending() > 0,
+            _                               => false,
+        }
+    }
+
+    /// Tell weechat we are done, and close our socket. The connection can no
+    /// longer be used after a call to close. Any errors here are ignored
+    ///
+    /// Note: Over ssl, we don't send a close_notify first (the openssl crate
+    ///       we use has no way to), so the relay sees the tls session end
+    ///       without one. Weechat closes its side on quit either way.
+    pub fn close(&mut self) {
+        let _ = self.send(&QuitCommand);
+        let _ = self.stream.flush();
+        let _ = self.stream.get_mut().shutdown(Shutdown::Both);
+    }
+}
+
+impl<S: Read + Write> Connection<S> {
+    /// A connection over a stream that is already open to a relay (ex: a
+    /// unix socket, or bytes saved from one), with the relay protocol
+    /// straight over it. As with connect, the relay protocol isn't inited.
+    pub fn from_stream(stream: S, terminator: &str) -> Connection<S> {
+        Connection {
+            stream: stream,
+            terminator: terminator.to_string(),
+            websocket: None,
+            dump: None,
+            counts: Counts::new(),
+            reading: None,
+            max_parse_depth: DEFAULT_MAX_DEPTH,
+        }
+    }
+
+    /// Sends a command to the relay, in one write (one frame over a
+    /// websocket), so nothing else can land in the middle of it.
+    ///
+    /// Note: This takes &mut self, so only whoever owns the connection can
+    ///       send on it, and commands go out in the order they are sent.
+    ///       Other threads queue input through a reply::Replies, which the
+    ///       relay loop sends between messages.
+    pub fn send_cmd(&mut self, cmd_str: &str) -> Result<(), WeechatError> {
+        debug!("Sending {}", redact_password(cmd_str.trim_right_matches(|c| c == '\r' || c == '\n')));
+        let cmd_str = terminate(cmd_str, &self.terminator);
+        match self.websocket {
+            Some(ref mut websocket) => try!(websocket.send(&mut self.stream, cmd_str.as_bytes())),
+            None                    => try!(self.stream.write_all(cmd_str.as_bytes())),
+        }
+        Ok(())
+    }
+
+    /// Sends a command built with the commands module (or a str, as it is)
+    pub fn send<C: Command + ?Sized>(&mut self, cmd: &C) -> Result<(), WeechatError> {
+        self.send_cmd(&cmd.render())
+    }
+
+    /// Reads the next message from the relay, returning the (unparsed) bytes
+    /// that follow its header.
+    ///
+    /// Note: Once the first byte of a message is read, the connection is
+    ///       treated as closed (see is_closed) if the rest of it doesn't come:
+    ///       a read timing out part way through (ex: while ssl renegotiates
+    ///       on a connection with a read timeout) has lost bytes we can't get
+    ///       back, and anything read after that would fail to parse.
+    pub fn recv_data(&mut self) -> Result<Vec<u8>, WeechatError> {
+        // header is first 5 bytes. The first 4 are the length, and the last
+        // one is if compression is enabled or not. Only a failure reading the
+        // first byte leaves us between messages
+        let mut buffer = [0; HEADER_LENGTH];
+        try!(self.read_part(&mut buffer[..1], false));
+        try!(self.read_part(&mut buffer[1..], true));
+        let header = try!(Header::new(&buffer));
+
+        // Now that we have the header, get the rest of the message.
+        let mut data = vec![0; header.length];
+        try!(self.read_part(data.as_mut_slice(), true));
+        self.counts.record(HEADER_LENGTH + header.length);
+
+        // A dump that can't be written is not worth losing the connection
+        // over, so we just stop dumping
+        let dumped = self.dump.as_mut().map(|f| f.write_all(&buffer).and_then(|_| f.write_all(&data)));
+        if let Some(Err(e)) = dumped {
+            error!("could not write to the dump file ({}), no longer dumping", e);
+            self.dump = None;
+        }
+        // The relay can send any message uncompressed, even with compression
+        // on (ex: if it is too short to be worth compressing), so this goes by
+        // the header rather than what we asked for
+        if header.compression {
+            let reading = match self.reading {
+                Some(ref mut reading) => reading,
+                None                  => return inflate::zlib(&data),
+            };
+            let start = Instant::now();
+            let inflated = inflate::zlib(&data);
+            reading.inflate.record(start);
+            return inflated;
+        }
+        Ok(data)
+    }
+
+    /// Starts timing how long parsing and inflating messages takes, for
+    /// --profile. Only recv_msg (and so messages) times parsing
+    pub fn profile(&mut self) {
+        self.reading = Some(Reading::new());
+    }
+
+    /// Time spent reading on this connection, if it is being timed
+    pub fn reading(&self) -> Option<&Reading> {
+        self.reading.as_ref()
+    }
+
+    /// Makes recv_msg fail on messages with arrays and hashtables nested
+    /// more than depth deep (parse::DEFAULT_MAX_DEPTH unless this is called)
+    pub fn set_max_parse_depth(&mut self, depth: usize) {
+        self.max_parse_depth = depth;
+    }
+
+    /// How deeply arrays and hashtables can nest in the messages recv_msg
+    /// parses
+    pub fn max_parse_depth(&self) -> usize {
+        self.max_parse_depth
+    }
+
+    /// Copies every message read from now on to a file, exactly as the
+    /// relay sent it (header included), so it can be replayed later
+    pub fn dump_to(&mut self, file: File) {
+        self.dump = Some(file);
+    }
+
+    /// Fills buf from the socket, or from the payload of the websocket frames
+    /// if we are using a websocket
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), WeechatError> {
+        match self.websocket {
+            Some(ref mut websocket) => websocket.read_exact(&mut self.stream, buf),
+            None                    => Ok(try!(self.stream.read_exact(buf))),
+        }
+    }
+
+    /// Fills buf with part of a message (mid_message if it isn't the start
+    /// of one), making the errors for the relay going away say so. An ssl
+    /// close_notify reads as the end of the stream, so the relay closing the
+    /// tls session shows up the same as it closing the socket.
+    fn read_part(&mut self, buf: &mut [u8], mid_message: bool) -> Result<(), WeechatError> {
+        let err = match self.read_exact(buf) {
+            Ok(())                     => return Ok(()),
+            Err(WeechatError::Io(err)) => err,
+            Err(err)                   => return Err(err),
+        };
+        // Only the end of the stream between messages is a clean close (see
+        // is_clean_close). Anything cut short is aborted, so is_closed sees
+        // it and we reconnect
+        let (kind, why) = match err.kind() {
+            io::ErrorKind::UnexpectedEof if !mid_message => {
+                (io::ErrorKind::UnexpectedEof, "The relay closed the connection")
+            },
+            io::ErrorKind::UnexpectedEof                 => {
+                (io::ErrorKind::ConnectionAborted, "The relay closed the connection part way through a message")
+            },
+            io::ErrorKind::WouldBlock |
+            io::ErrorKind::TimedOut if mid_message       => {
+                (io::ErrorKind::ConnectionAborted,
+                 "Timed out part way through a message, so the connection can't be read from any more")
+            },
+            _                                            => return Err(WeechatError::Io(err)),
+        };
+        Err(WeechatError::Io(io::Error::new(kind, why)))
+    }
+
+    /// How much has been read on this connection so far
+    pub fn counts(&self) -> &Counts {
+        &self.counts
+    }
+
+    /// Reads and parses the next message from the relay (see
+    /// message::read_message to do the same from any reader)
+    pub fn recv_msg(&mut self) -> Result<Message, WeechatError> {
+        let data = try!(self.recv_data());
+        self.parse(data.as_slice())
+    }
+
+    /// Reads the next message from the relay, only parsing it if wanted says
+    /// its identifier is one we want (otherwise it is None)
+    fn recv_wanted<F: FnMut(Option<&str>) -> bool>(&mut self, wanted: &mut F)
+                                                   -> Result<Option<Message>, WeechatError> {
+        let data = try!(self.recv_data());
+        let identifier = try!(Message::identifier(data.as_slice()));
+        if wanted(identifier.as_ref().map(|id| id.as_str())) {
+            self.parse(data.as_slice()).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses the bytes of a message (after its header), timing it if we are
+    /// being timed
+    fn parse(&mut self, data: &[u8]) -> Result<Message, WeechatError> {
+        let reading = match self.reading {
+            Some(ref mut reading) => reading,
+            None                  => return Message::with_max_depth(data, self.max_parse_depth),
+        };
+        let start = Instant::now();
+        let msg = try!(Message::with_max_depth(data, self.max_parse_depth));
+        match msg.as_hdata() {
+            Ok(_)  => reading.hdata.record(start),
+            Err(_) => reading.other.record(start),
+        }
+        Ok(msg)
+    }
+
+    /// Iterates over the messages the relay sends, reading and parsing one
+    /// for each call to next (which waits for it), like:
+    ///
+    /// ```ignore
+    /// for msg in conn.messages() {
+    ///     let msg = try!(msg);
+    ///     println!("{:?}", msg.identifier);
+    /// }
+    /// ```
+    ///
+    /// This ends when the relay closes the connection between messages. Any
+    /// other error is returned, and a message that doesn't parse doesn't end
+    /// it, but the iteration ends after an error reading from the socket, as
+    /// nothing more can be read after one.
+    pub fn messages<'a>(&'a mut self) -> Messages<'a, S> {
+        Messages {
+            conn: self,
+            done: false,
+        }
+    }
+
+    /// The messages the relay sends, as messages does, except that only the
+    /// ones whose identifier wanted is true for are parsed. The rest are
+    /// read and dropped, and come out as None, so that reading one still
+    /// shows that the relay sent something.
+    pub fn messages_filtered<'a, F: FnMut(Option<&str>) -> bool>(&'a mut self, wanted: F)
+                                                                 -> FilteredMessages<'a, F, S> {
+        FilteredMessages {
+            conn: self,
+            wanted: wanted,
+            done: false,
+        }
+    }
+}
+
+/// The messages read from a connection, see Connection::messages
+pub struct Messages<'a, S: 'a = Stream> {
+    conn: &'a mut Connection<S>,
+    /// Set once reading has failed, after which there is nothing more to read
+    done: bool,
+}
+
+impl<'a, S: Read + Write> Iterator for Messages<'a, S> {
+    type Item = Result<Message, WeechatError>;
+
+    fn next(&mut self) -> Option<Result<Message, WeechatError>> {
+        if self.done {
+            return None;
+        }
+        let read = self.conn.recv_msg();
+        end_after_close(&mut self.done, read)
+    }
+}
+
+/// The messages read from a connection, parsed only if they are wanted, see
+/// Connection::messages_filtered
+pub struct FilteredMessages<'a, F, S: 'a = Stream> {
+    conn: &'a mut Connection<S>,
+    /// If a message with an identifier (or without one) is parsed
+    wanted: F,
+    /// Set once reading has failed, after which there is nothing more to read
+    done: bool,
+}
+
+impl<'a, F: FnMut(Option<&str>) -> bool, S: Read + Write> Iterator for FilteredMessages<'a, F, S> {
+    type Item = Result<Option<Message>, WeechatError>;
+
+    fn next(&mut self) -> Option<Result<Option<Message>, WeechatError>> {
+        if self.done {
+            return None;
+        }
+        let read = self.conn.recv_wanted(&mut self.wanted);
+        end_after_close(&mut self.done, read)
+    }
+}
+
+/// What the message iterators return for a read: nothing once the relay
+/// closed the connection between messages, and the error otherwise, marking
+/// the iteration done after an error reading from the socket.
+fn end_after_close<T>(done: &mut bool, read: Result<T, WeechatError>) -> Option<Result<T, WeechatError>> {
+    match read {
+        Ok(read)                            => Some(Ok(read)),
+        Err(ref err) if is_clean_close(err) => {
+            *done = true;
+            None
+        },
+        Err(err)                            => {
+            if let WeechatError::Io(_) = err {
+                *done = true;
+            }
+            Some(Err(err))
+        },
+    }
+}
+
+/// If an error means the relay closed (or reset) the connection on us. How
+/// that shows up depends on the platform and transport: an EOF, a reset or
+/// aborted connection, a broken pipe if we were writing, a tls error if the
+/// relay didn't close the tls session cleanly, or the websocket closing.
+pub fn is_closed(err: &WeechatError) -> bool {
+    match *err {
+        WeechatError::Io(ref err) => match err.kind() {
+            io::ErrorKind::UnexpectedEof |
+            io::ErrorKind::ConnectionReset |
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::BrokenPipe => true,
+            // Tls errors while reading or writing come wrapped in an io error
+            _                         => err.get_ref().map_or(false, |e| e.is::<ssl::Error>()),
+        },
+        _                         => false,
+    }
+}
+
+/// If an error is the relay closing the connection between messages, rather
+/// than part way through one or with a reset
+pub fn is_clean_close(err: &WeechatError) -> bool {
+    match *err {
+        WeechatError::Io(ref err) => err.kind() == io::ErrorKind::UnexpectedEof,
+        _                         => false,
+    }
+}
+
+/// A command with the value of any password option (in an init) replaced by
+/// ***, for logging. The value ends at the first comma that isn't escaped.
+fn redact_password(cmd_str: &str) -> String {
+    let start = match cmd_str.find("password=") {
+        Some(i) => i + "password=".len(),
+        None    => return cmd_str.to_string(),
+    };
+    let mut end = cmd_str.len();
+    let mut escaped = false;
+    for (i, c) in cmd_str[start..].char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            ',' if !escaped  => {
+                end = start + i;
+                break;
+            },
+            _                => escaped = false,
+        }
+    }
+    format!("{}***{}", &cmd_str[..start], &cmd_str[end..])
+}
+
+/// Ends a command with the terminator. Commands must end in \n per spec (or
+/// whatever terminator the user configured), so any line ending the caller
+/// added is stripped first so we never send a mixed one
+pub fn terminate(cmd_str: &str, terminator: &str) -> String {
+    let mut cmd_str = cmd_str.trim_right_matches(|c| c == '\r' || c == '\n').to_string();
+    cmd_str.push_str(terminator);
+    cmd_str
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::Connection;
+    use testing::{hdata, int, message, pointer, str_message};
+
+    #[test]
+    fn messages_end_at_eof() {
+        let mut bytes = Vec::new();
+        for i in 0..3 {
+            bytes.extend_from_slice(&str_message("_pong", &format!("ping {}", i)));
+        }
+        let mut conn = Connection::from_stream(Cursor::new(bytes), "\n");
+        let mut messages = conn.messages();
+        for i in 0..3 {
+            let msg = messages.next().unwrap().unwrap();
+            assert_eq!(msg.identifier, Some("_pong".to_string()));
+            assert_eq!(msg.as_strdata().unwrap().as_str(), Some(&*format!("ping {}", i)));
+        }
+        assert!(messages.next().is_none());
+        assert!(messages.next().is_none());
+    }
+
+    #[test]
+    fn messages_fail_on_a_cut_short_message() {
+        let mut bytes = str_message("_pong", "ping");
+        let second = str_message("_pong", "cut short");
+        bytes.extend_from_slice(&second[..second.len() - 3]);
+        let mut conn = Connection::from_stream(Cursor::new(bytes), "\n");
+        let mut messages = conn.messages();
+        assert!(messages.next().unwrap().is_ok());
+        assert!(messages.next().unwrap().is_err());
+        assert!(messages.next().is_none());
+    }
+
+    #[test]
+    fn each_connection_has_its_own_max_parse_depth() {
+        // An hdata with an array of arrays of ints in it
+        let mut item = Vec::new();
+        pointer(&mut item, "55aa");
+        item.extend_from_slice(b"arr");
+        int(&mut item, 1);
+        item.extend_from_slice(b"int");
+        int(&mut item, 1);
+        int(&mut item, 7);
+        let bytes = message("nested", &hdata("buffer", "values:arr", &[item]));
+
+        let mut shallow = Connection::from_stream(Cursor::new(bytes.clone()), "\n");
+        shallow.set_max_parse_depth(1);
+        assert!(shallow.recv_msg().is_err());
+
+        let mut deep = Connection::from_stream(Cursor::new(bytes), "\n");
+        assert_eq!(deep.max_parse_depth(), ::parse::DEFAULT_MAX_DEPTH);
+        deep.set_max_parse_depth(2);
+        assert!(deep.recv_msg().is_ok());
+    }
+
+    #[test]
+    fn filtered_messages_only_parse_the_wanted_ones() {
+        let mut bytes = str_message("_pong", "ping");
+        // Not even a valid body, so this fails if it is parsed
+        bytes.extend_from_slice(&message("_nicklist", b"nop"));
+        bytes.extend_from_slice(&str_message("_pong", "again"));
+        let mut conn = Connection::from_stream(Cursor::new(bytes), "\n");
+        let read: Vec<Option<String>> = conn.messages_filtered(|id| id == Some("_pong"))
+            .map(|msg| msg.unwrap().map(|msg| msg.as_strdata().unwrap().as_str().unwrap().to_string()))
+            .collect();
+        assert_eq!(read, vec![Some("ping".to_string()), None, Some("again".to_string())]);
+        assert_eq!(conn.counts().messages, 3);
+    }
+}
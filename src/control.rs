@@ -0,0 +1,242 @@
+#[cfg(unix)]
+use std::fs;
+#[cfg(unix)]
+use std::io::{BufRead, BufReader};
+#[cfg(unix)]
+use std::io::prelude::*;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+#[cfg(unix)]
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+use notify::history::History;
+use notify::status::Status;
+#[cfg(unix)]
+use notify::status::Summary;
+use reply::Replies;
+
+/// Events sent back for "history" if it doesn't say how many
+pub const DEFAULT_HISTORY_COUNT: usize = 20;
+
+// Longest command we read, so a confused client can't make us buffer forever
+#[cfg(unix)]
+const MAX_COMMAND_LENGTH: u64 = 1024;
+
+/// Listens on a unix socket for commands, on its own thread so a slow client
+/// never holds up the relay. A client sends one line and gets the reply back
+/// before the socket is closed. The commands are:
+///
+/// - "history [count]" sends back the latest events, newest first (ex: with
+///   `echo history 20 | socat - UNIX-CONNECT:<path>`, or ctl history)
+/// - "mute", "snooze <minutes>" and "unmute" hold back notifications (they
+///   are still logged) until unmuted, for a while, or no longer
+/// - "clear" sets the count of notifications the tray shows back to 0
+/// - "status" sends back the count, whether we are muted and if we are
+///   connected to the relay
+/// - "reply <buffer> <text>" sends the text into a buffer (by full name, ex:
+///   irc.libera.#rust) as if it was typed there, for answering a
+///   notification without opening a client
+///
+/// Everything but history and status replies "ok".
+///
+/// The socket is only usable by us. A socket left behind by an instance that
+/// exited is replaced, but not one another instance is still listening on.
+#[cfg(unix)]
+pub fn listen(path: &Path, history: History, status: Status, replies: Replies) -> Result<(), String> {
+    if let Ok(metadata) = fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} is already there and is not a socket", path.display()));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(format!("Something is already listening on {}", path.display()));
+        }
+        try!(fs::remove_file(path).map_err(|e| format!("Could not remove {}: {}", path.display(), e)));
+    }
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e)));
+    }
+
+    let listener = try!(UnixListener::bind(path).map_err(|e| format!("Could not listen on {}: {}", path.display(), e)));
+    try!(fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+         .map_err(|e| format!("Could not set the permissions of {}: {}", path.display(), e)));
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = serve(stream, &history, &status, &replies) {
+                        error!("control socket client: {}", e);
+                    }
+                },
+                Err(e)     => error!("control socket: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Reads a command from a client and writes back the reply
+#[cfg(unix)]
+fn serve(stream: UnixStream, history: &History, status: &Status, replies: &Replies) -> Result<(), String> {
+    let mut command = String::new();
+    {
+        let mut reader = BufReader::new((&stream).take(MAX_COMMAND_LENGTH));
+        try!(reader.read_line(&mut command).map_err(|e| e.to_string()));
+    }
+    // Connecting without sending anything is how another instance checks if
+    // we are running, so there's nobody to reply to
+    if command.is_empty() {
+        return Ok(());
+    }
+
+    let mut words = command.split_whitespace();
+    let reply = match (words.next(), words.next(), words.next()) {
+        (Some("history"), count, None)   => match count.map(|c| c.parse::<usize>()) {
+            Some(Ok(count)) => history_reply(history, count),
+            Some(Err(_))    => "error: the count for history must be a number\n".to_string(),
+            None            => history_reply(history, DEFAULT_HISTORY_COUNT),
+        },
+        (Some("snooze"), mins, None)     => match mins.map(|m| m.parse::<u64>()) {
+            Some(Ok(minutes)) if minutes > 0 => {
+                status.snooze(Duration::from_secs(minutes * 60));
+                "ok\n".to_string()
+            },
+            _                                => "error: snooze needs a number of minutes\n".to_string(),
+        },
+        (Some("mute"), None, None)       => {
+            status.mute();
+            "ok\n".to_string()
+        },
+        (Some("unmute"), None, None)     => {
+            status.unmute();
+            "ok\n".to_string()
+        },
+        (Some("clear"), None, None)      => {
+            status.clear();
+            "ok\n".to_string()
+        },
+        (Some("status"), None, None)     => status_reply(&status.summary()),
+        (Some("reply"), Some(buffer), _) => match replies.send(buffer, after_words(&command, 2)) {
+            Ok(()) => "ok\n".to_string(),
+            Err(e) => format!("error: {}\n", e),
+        },
+        (Some("reply"), None, _)         => "error: reply needs a buffer and the text\n".to_string(),
+        (Some(name), _, _)               => format!("error: unknown command '{}'\n", name),
+        (None, _, _)                     => "error: no command\n".to_string(),
+    };
+    (&stream).write_all(reply.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// The rest of a command after its first few words, with the spacing inside
+/// it kept
+#[cfg(unix)]
+fn after_words(command: &str, words: usize) -> &str {
+    let mut rest = command;
+    for _ in 0..words {
+        rest = rest.trim_left();
+        rest = &rest[rest.find(char::is_whitespace).unwrap_or(rest.len())..];
+    }
+    rest.trim()
+}
+
+#[cfg(unix)]
+fn history_reply(history: &History, count: usize) -> String {
+    history.lines(count).iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[cfg(unix)]
+fn status_reply(summary: &Summary) -> String {
+    let muted = match (summary.muted, summary.snoozed_for) {
+        (true, _)                        => "muted".to_string(),
+        (false, Some(left))              => format!("snoozed for {} more minutes", left),
+        (false, None)                    => "not muted".to_string(),
+    };
+    let connected = if summary.connected { "connected" } else { "not connected" };
+    format!("{} unread, {}, {}\n", summary.unread, muted, connected)
+}
+
+/// Sends a command to the control socket of a running instance, returning
+/// its reply
+#[cfg(unix)]
+pub fn send(path: &Path, command: &str) -> Result<String, String> {
+    let mut stream = try!(UnixStream::connect(path)
+                          .map_err(|e| format!("Could not connect to {} (is weechat-alert running?): {}",
+                                               path.display(), e)));
+    try!(stream.write_all(format!("{}\n", command).as_bytes()).map_err(|e| e.to_string()));
+    let mut reply = String::new();
+    try!(stream.read_to_string(&mut reply).map_err(|e| e.to_string()));
+    Ok(reply)
+}
+
+// The control socket is a unix socket, which std has no windows version of
+#[cfg(windows)]
+pub fn listen(_path: &Path, _history: History, _status: Status, _replies: Replies) -> Result<(), String> {
+    Err("The control socket is unix only".to_string())
+}
+
+#[cfg(windows)]
+pub fn send(_path: &Path, _command: &str) -> Result<String, String> {
+    Err("The control socket is unix only".to_string())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use super::{after_words, listen, send};
+    use alert::AlertReason;
+    use notify::{Notifiers, Settings};
+    use notify::history::History;
+    use notify::status::Status;
+    use relay::Relay;
+    use reply::Replies;
+
+    #[test]
+    fn commands_and_their_replies() {
+        let dir = env::temp_dir().join("weechat-alert-control-test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("control.sock");
+        let history = History::new(10);
+        let status = Status::new();
+        let replies = Replies::new(Relay::new("127.0.0.1".to_string(), 9001, None, None).buffers());
+        listen(&path, history.clone(), status.clone(), replies.clone()).unwrap();
+        let err = listen(&path, history.clone(), status.clone(), replies).unwrap_err();
+        assert!(err.contains("already listening"), "{}", err);
+
+        let notifiers = Notifiers::new(Settings::new());
+        for message in &["one", "two"] {
+            let mut event = notifiers.test_event(AlertReason::Private, "irc.libera.alice", "alice", message);
+            event.time = "14:00".to_string();
+            history.add(&event, false);
+        }
+        assert_eq!(send(&path, "history 1").unwrap(), "14:00 irc.libera.alice <alice> two\n");
+        assert_eq!(send(&path, "history").unwrap().lines().count(), 2);
+        assert!(send(&path, "history lots").unwrap().starts_with("error: "));
+
+        assert_eq!(send(&path, "status").unwrap(), "0 unread, not muted, not connected\n");
+        assert_eq!(send(&path, "mute").unwrap(), "ok\n");
+        assert_eq!(send(&path, "status").unwrap(), "0 unread, muted, not connected\n");
+        assert_eq!(send(&path, "unmute").unwrap(), "ok\n");
+        assert_eq!(send(&path, "snooze 30").unwrap(), "ok\n");
+        assert!(status.is_muted());
+        assert!(send(&path, "snooze").unwrap().starts_with("error: "));
+
+        assert_eq!(send(&path, "reply irc.libera.#nowhere hi").unwrap(),
+                   "error: there is no buffer named irc.libera.#nowhere\n");
+        assert_eq!(send(&path, "dance").unwrap(), "error: unknown command 'dance'\n");
+        assert_eq!(send(&path, "").unwrap(), "error: no command\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reply_text_keeps_its_spacing() {
+        assert_eq!(after_words("reply irc.libera.#rust  two  spaces\n", 2), "two  spaces");
+        assert_eq!(after_words("reply irc.libera.#rust", 2), "");
+    }
+}
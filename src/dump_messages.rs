@@ -0,0 +1,27 @@
+use weechat_alert::handler::RelayHandler;
+use weechat_alert::message::Message;
+
+/// Prints every message from the relay as it comes in, for --dump-messages:
+/// the identifier and type, then the body, between lines marking where each
+/// message starts and ends (so a bug report can include a run of them).
+pub struct MessagePrinter {
+    count: u64,
+}
+
+impl MessagePrinter {
+    pub fn new() -> MessagePrinter {
+        MessagePrinter { count: 0 }
+    }
+}
+
+impl RelayHandler for MessagePrinter {
+    fn on_message(&mut self, msg: &Message) {
+        self.count += 1;
+        println!("--- message {}: {}", self.count, msg);
+        println!("--- end of message {}", self.count);
+    }
+
+    fn wants_all_messages(&self) -> bool {
+        true
+    }
+}
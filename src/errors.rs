@@ -10,7 +10,9 @@ pub enum WeechatError {
     Io(io::Error),  // Errors reading, writing, or connecting to socket
     SslError(SslError),
     BadPassword,    // Bad password for weechat init protocol
+    PasswordRequired,   // We have no password, but the relay wants one
     ParseError(String),     // Recieved unparsable bytes from a weechat message
+    WebSocketError(String), // The relay did not accept our websocket handshake
 }
 
 /// Convert io::Error to WeechatErrors
@@ -38,10 +40,12 @@ impl From<SslError> for WeechatError {
 impl fmt::Display for WeechatError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            WeechatError::Io(ref err)          => err.fmt(f),
-            WeechatError::SslError(ref err)    => err.fmt(f),
-            WeechatError::BadPassword          => write!(f, "Invalid password"),
-            WeechatError::ParseError(ref s)    => write!(f, "Parse error: {}", s),
+            WeechatError::Io(ref err)           => err.fmt(f),
+            WeechatError::SslError(ref err)     => err.fmt(f),
+            WeechatError::BadPassword           => write!(f, "Invalid password"),
+            WeechatError::PasswordRequired      => write!(f, "The relay requires a password"),
+            WeechatError::ParseError(ref s)     => write!(f, "Parse error: {}", s),
+            WeechatError::WebSocketError(ref s) => write!(f, "Websocket error: {}", s),
         }
     }
 }
@@ -53,7 +57,9 @@ impl Error for WeechatError {
             WeechatError::Io(ref err)       => err.description(),
             WeechatError::SslError(ref err) => err.description(),
             WeechatError::BadPassword       => "Invalid username or password",
+            WeechatError::PasswordRequired  => "The relay requires a password",
             WeechatError::ParseError(_)     => "Message parse error",
+            WeechatError::WebSocketError(_) => "Websocket handshake error",
         }
     }
 }
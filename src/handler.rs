@@ -0,0 +1,112 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use errors::WeechatError;
+use line::BufferLine;
+use message::Message;
+
+/// What a Relay::run_with callback wants the relay to do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// Keep reading messages
+    Continue,
+    /// Close the connection and return from run_with
+    Stop,
+}
+
+/// Your own logic for a relay, run from its read loop by
+/// Relay::run_with_handler. Alerting on lines is a handler too
+/// (alert::AlertHandler), and a pair of handlers runs both, the first first.
+/// Every method does nothing unless you implement it.
+pub trait RelayHandler {
+    /// The relay is connected, and synced. Keep the sender (it is a clone of
+    /// the relay's) to send commands later
+    fn on_connect(&mut self, _commands: &CommandSender) {}
+
+    /// A message came from the relay. This is only the messages the relay
+    /// acts on itself (or has handlers registered with on), unless
+    /// wants_all_messages says otherwise, as the rest aren't parsed
+    fn on_message(&mut self, _msg: &Message) {}
+
+    /// If on_message should get every message, including the ones the relay
+    /// ignores. Parsing those costs a lot in a busy sync, so this is false
+    /// unless you implement it.
+    fn wants_all_messages(&self) -> bool {
+        false
+    }
+
+    /// A line was added to a buffer, with the buffer's full name if the relay
+    /// knows it. This comes before the line is classified, so it sees every
+    /// line, not just the ones that alert
+    fn on_buffer_line(&mut self, _line: &BufferLine, _buffer: Option<&str>) {}
+
+    /// The connection failed, with why. The relay reconnects after this
+    /// unless the error policy says the error is fatal
+    fn on_disconnect(&mut self, _err: &WeechatError) {}
+}
+
+impl<A: RelayHandler, B: RelayHandler> RelayHandler for (A, B) {
+    fn on_connect(&mut self, commands: &CommandSender) {
+        self.0.on_connect(commands);
+        self.1.on_connect(commands);
+    }
+
+    fn on_message(&mut self, msg: &Message) {
+        self.0.on_message(msg);
+        self.1.on_message(msg);
+    }
+
+    fn wants_all_messages(&self) -> bool {
+        self.0.wants_all_messages() || self.1.wants_all_messages()
+    }
+
+    fn on_buffer_line(&mut self, line: &BufferLine, buffer: Option<&str>) {
+        self.0.on_buffer_line(line, buffer);
+        self.1.on_buffer_line(line, buffer);
+    }
+
+    fn on_disconnect(&mut self, err: &WeechatError) {
+        self.0.on_disconnect(err);
+        self.1.on_disconnect(err);
+    }
+}
+
+/// Commands for the relay, from a RelayHandler, waiting for the relay loop
+/// to send them. Like reply::Replies, clones share the same queue (so it can
+/// be handed to other threads), and only the relay loop writes to the
+/// connection, a whole command at a time.
+#[derive(Debug, Clone)]
+pub struct CommandSender {
+    pending: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl CommandSender {
+    pub fn new() -> CommandSender {
+        CommandSender {
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // Nothing here can be left half changed by a panic
+    fn lock<'a>(&'a self) -> MutexGuard<'a, VecDeque<String>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues a command (ex: "input core.weechat /print hi"), which is sent
+    /// as it is, terminated like every other command. Fails on a command with
+    /// a line break in it, which would end it early and send the rest as a
+    /// command of its own.
+    pub fn send(&self, cmd: &str) -> Result<(), String> {
+        let cmd = cmd.trim_right_matches(|c| c == '\r' || c == '\n');
+        if cmd.contains(|c| c == '\r' || c == '\n') {
+            return Err("a command can't have a line break in it".to_string());
+        }
+        self.lock().push_back(cmd.to_string());
+        Ok(())
+    }
+
+    /// Takes every queued command, oldest first
+    pub fn take(&self) -> Vec<String> {
+        self.lock().drain(..).collect()
+    }
+}
@@ -1,11 +1,37 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::marker::PhantomData;
+use std::slice;
 
 use errors::WeechatError;
 use errors::WeechatError::ParseError;
 use message;
-use parse::Parse;
+use parse::{Parse, DEFAULT_MAX_DEPTH};
 
 
+/// Something that can be built from one item of an hdata, such as a
+/// line::BufferLine from a _buffer_line_added. See HData::items_as.
+pub trait FromHData: Sized {
+    /// Builds it from an item, erroring if the item is missing a key it
+    /// needs or has one of the wrong type
+    fn from_item(item: &HashMap<String, message::Object>) -> Result<Self, WeechatError>;
+}
+
+/// Iterator over the items of an hdata converted with FromHData (see
+/// HData::items_as)
+pub struct ItemsAs<'a, T> {
+    items: slice::Iter<'a, HashMap<String, message::Object>>,
+    item_type: PhantomData<T>,
+}
+
+impl<'a, T: FromHData> Iterator for ItemsAs<'a, T> {
+    type Item = Result<T, WeechatError>;
+
+    fn next(&mut self) -> Option<Result<T, WeechatError>> {
+        self.items.next().map(T::from_item)
+    }
+}
+
 /// A list of key/value mappings of data received from relay. This data conststs
 /// of the paths and the keys declared in the weechat relay messages protocol:
 /// https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html
@@ -26,26 +52,58 @@ impl HData {
     /// You can see the protocol for encoding an hdata object here:
     /// https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html#object_hdata
     pub fn new(bytes: &[u8]) -> Result<HData, WeechatError> {
+        HData::with_max_depth(bytes, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Parses an HData like new, failing if arrays and hashtables in its
+    /// values nest more than max_depth deep
+    pub fn with_max_depth(bytes: &[u8], max_depth: usize) -> Result<HData, WeechatError> {
         let mut cur_pos = 0; // Rolling counter of where we are in the byte array
 
-        // Parse out paths
+        // Parse out paths. An hdata with no items can have an empty (or
+        // null) path and keys
         let parsed = try!(Parse::string(&bytes[cur_pos..]));
-        let paths = try!(parsed.object.as_not_null_str());
-        let paths: Vec<String> = paths.split(',').map(|s| s.to_string()).collect();
+        let paths = split_list(try!(parsed.object.as_str()));
         cur_pos += parsed.bytes_read;
 
         // Parse out key names and types
         let parsed = try!(Parse::string(&bytes[cur_pos..]));
-        let keys = try!(parsed.object.as_not_null_str());
-        let keys: Vec<String> = keys.split(',').map(|s| s.to_string()).collect();
+        let keys = split_list(try!(parsed.object.as_str()));
         cur_pos += parsed.bytes_read;
 
+        // Split each key into its name and type once, rather than for every
+        // item
+        let mut typed_keys = Vec::new();
+        for key in &keys {
+            match key.find(':') {
+                Some(i) => typed_keys.push((&key[..i], &key[i + 1..])),
+                None    => return Err(ParseError(format!("Key '{}' in hdata has no type", key))),
+            }
+        }
+
+        // Every path and key is stored in the same map for each item, so a
+        // duplicated name would silently overwrite an earlier value
+        let mut names = HashSet::new();
+        for name in paths.iter().map(|p| p.as_str()).chain(typed_keys.iter().map(|&(name, _)| name)) {
+            if !names.insert(name) {
+                return Err(ParseError(format!("Duplicate key '{}' in hdata", name)));
+            }
+        }
+
         // Number of items in this hdata
         let parsed = try!(Parse::integer(&bytes[cur_pos..]));
         let num_hdata_items = try!(parsed.object.as_integer());
         cur_pos += parsed.bytes_read;
+        if num_hdata_items < 0 {
+            return Err(ParseError(format!("Negative number of items ({}) in hdata", num_hdata_items)));
+        }
+        if num_hdata_items > 0 && paths.is_empty() {
+            return Err(ParseError("Hdata has items but no path".to_string()));
+        }
 
-        // Store pointers and keys for each item
+        // Store pointers and keys for each item. Everything is parsed from a
+        // slice of the message starting at cur_pos, so the walk is linear in
+        // the size of the message; only the values themselves are copied
         let mut data_list = Vec::new();
         for _ in 0..num_hdata_items {
             let mut key_value_map = HashMap::new();
@@ -58,11 +116,8 @@ impl HData {
             }
 
             // Pull out the data for all of the keys
-            for key in &keys {
-                let key_parse: Vec<&str> = key.split(':').collect();
-                let key_name = key_parse[0];
-                let key_type = key_parse[1];
-                let parsed = try!(Parse::parse_type(key_type, &bytes[cur_pos..]));
+            for &(key_name, key_type) in &typed_keys {
+                let parsed = try!(Parse::parse_type_with_max_depth(key_type, &bytes[cur_pos..], max_depth));
                 key_value_map.insert(String::from(key_name), parsed.object);
                 cur_pos += parsed.bytes_read;
             }
@@ -74,9 +129,152 @@ impl HData {
         // Sanity check, make sure all the bytes of this message are used
         // and accounted for
         if bytes.len() != cur_pos {
-            Err(ParseError("Not all bytes in message consumed".to_string()))
+            Err(ParseError(format!("Not all bytes in message consumed (consumed {} of {} bytes)",
+                                   cur_pos, bytes.len())))
         } else {
             Ok(HData{ data: data_list })
         }
     }
+
+    /// Returns true if the hdata has no items
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Converts each item in turn, ex: `hdata.items_as::<BufferLine>()`.
+    /// An item that doesn't convert is an error for that item only, so the
+    /// caller decides whether to skip it or give up.
+    pub fn items_as<'a, T: FromHData>(&'a self) -> ItemsAs<'a, T> {
+        ItemsAs {
+            items: self.data.iter(),
+            item_type: PhantomData,
+        }
+    }
+
+    /// Returns the first item whose pointer at key (a path, or a key of type
+    /// pointer, ex: "buffer") is ptr. Pointers are compared by value, so
+    /// "0x0af3" finds an item with the pointer "af3". A null pointer matches
+    /// "0".
+    pub fn find_by_pointer(&self, key: &str, ptr: &str) -> Option<&HashMap<String, message::Object>> {
+        let wanted = match message::pointer_value(ptr) {
+            Some(wanted) => wanted,
+            None         => return None,
+        };
+        self.data.iter().find(|item| {
+            let value = match item.get(key).map(|p| p.as_pointer()) {
+                Some(Ok(Some(p))) => message::pointer_value(p),
+                Some(Ok(None))    => Some(0),
+                _                 => None,
+            };
+            value == Some(wanted)
+        })
+    }
+}
+
+/// Splits a comma separated list of paths or keys. An empty or null list has
+/// nothing in it, rather than one empty name.
+fn split_list(list: Option<&str>) -> Vec<String> {
+    match list {
+        Some(list) if !list.is_empty() => list.split(',').map(|s| s.to_string()).collect(),
+        _                              => Vec::new(),
+    }
+}
+
+/// Every item, one key per line (sorted, so items are easy to compare),
+/// like:
+///
+/// item 0:
+///   buffer: 0x55d0c5a0e0f0
+///   message: "hi"
+///
+/// This is for debugging (ex: --dump-messages)
+impl fmt::Display for HData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.data.is_empty() {
+            return write!(f, "(no items)");
+        }
+        for (i, item) in self.data.iter().enumerate() {
+            if i > 0 {
+                try!(writeln!(f));
+            }
+            try!(write!(f, "item {}:", i));
+            let mut keys: Vec<&String> = item.keys().collect();
+            keys.sort();
+            for key in keys {
+                try!(write!(f, "\n  {}: {}", key, item[key]));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HData;
+    use errors::WeechatError;
+    use line::BufferLine;
+    use testing::{buffers_hdata, hdata, pointer, string};
+
+    /// Parses an hdata made with testing::hdata, which starts with its type
+    fn parse(bytes: &[u8]) -> Result<HData, WeechatError> {
+        HData::new(&bytes[3..])
+    }
+
+    #[test]
+    fn repeated_key_is_an_error() {
+        let mut item = Vec::new();
+        pointer(&mut item, "55aa");
+        string(&mut item, "irc.libera.#rust");
+        string(&mut item, "#rust");
+        match parse(&hdata("buffer", "full_name:str,full_name:str", &[item])) {
+            Err(WeechatError::ParseError(e)) => assert!(e.contains("full_name"), "{}", e),
+            other                            => panic!("expected a ParseError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn key_named_like_a_path_is_an_error() {
+        let mut item = Vec::new();
+        pointer(&mut item, "55aa");
+        pointer(&mut item, "66bb");
+        match parse(&hdata("buffer", "buffer:ptr", &[item])) {
+            Err(WeechatError::ParseError(e)) => assert!(e.contains("buffer"), "{}", e),
+            other                            => panic!("expected a ParseError, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hdata_without_items() {
+        let empty = parse(&hdata("", "", &[])).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.items_as::<BufferLine>().count(), 0);
+        assert!(empty.find_by_pointer("buffer", "55aa").is_none());
+        assert_eq!(empty.to_string(), "(no items)");
+
+        // A relay can also send the path and keys of an hdata it found
+        // nothing for
+        let empty = parse(&hdata("buffer", "full_name:str", &[])).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.items_as::<BufferLine>().count(), 0);
+    }
+
+    #[test]
+    fn find_by_pointer_compares_values() {
+        let buffers = parse(&buffers_hdata(&[("0af3", "core.weechat"), ("55aa", "irc.libera.#rust")])).unwrap();
+        let name = |ptr| buffers.find_by_pointer("buffer", ptr).map(|item| item["full_name"].to_string());
+        assert_eq!(name("55aa"), Some("\"irc.libera.#rust\"".to_string()));
+        assert_eq!(name("0x0af3"), Some("\"core.weechat\"".to_string()));
+        assert_eq!(name("0xAF3"), Some("\"core.weechat\"".to_string()));
+        assert_eq!(name("66bb"), None);
+        assert_eq!(name("not a pointer"), None);
+        // Keys that aren't pointers never match
+        assert!(buffers.find_by_pointer("full_name", "55aa").is_none());
+
+        let mut item = Vec::new();
+        pointer(&mut item, "1a2b");
+        pointer(&mut item, "0");
+        let lines = parse(&hdata("line", "next_line:ptr", &[item])).unwrap();
+        assert!(lines.find_by_pointer("next_line", "0").is_some());
+        assert!(lines.find_by_pointer("line", "0x1a2b").is_some());
+    }
 }
@@ -0,0 +1,220 @@
+use std::fs::{self, File};
+#[cfg(feature = "health")]
+use std::io::{BufRead, BufReader};
+use std::io::prelude::*;
+#[cfg(feature = "health")]
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How long the relay can go without sending anything before we are
+/// unhealthy, if health_max_age isn't set
+pub const DEFAULT_MAX_AGE: u64 = 300;
+
+// Seconds between touches of the health file while we are healthy
+const FILE_INTERVAL: u64 = 10;
+
+// Longest request line we read, so a confused client can't make us buffer
+// forever
+#[cfg(feature = "health")]
+const MAX_REQUEST_LENGTH: u64 = 1024;
+
+#[derive(Debug)]
+struct State {
+    connected: bool,
+    /// When we last read a message from the relay (a pong included)
+    last_read: Option<Instant>,
+}
+
+/// If we are connected to the relay and hearing from it, for health checks.
+/// The relay loop keeps this up to date, and clones share it, so the health
+/// endpoint and file can read it from their own threads.
+#[derive(Debug, Clone)]
+pub struct Health {
+    state: Arc<Mutex<State>>,
+}
+
+impl Health {
+    pub fn new() -> Health {
+        Health {
+            state: Arc::new(Mutex::new(State {
+                connected: false,
+                last_read: None,
+            })),
+        }
+    }
+
+    /// Records that a connection made it through init (connected) or was
+    /// closed
+    pub fn set_connected(&self, connected: bool) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.connected = connected;
+        state.last_read = if connected { Some(Instant::now()) } else { None };
+    }
+
+    /// Records that a message was read from the relay
+    pub fn read(&self) {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).last_read = Some(Instant::now());
+    }
+
+    /// Ok if we are connected and the relay sent something within max_age,
+    /// otherwise why not
+    pub fn check(&self, max_age: Duration) -> Result<(), String> {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.connected {
+            return Err("not connected to the relay".to_string());
+        }
+        match state.last_read {
+            Some(at) if at.elapsed() <= max_age => Ok(()),
+            Some(at)                            => {
+                Err(format!("nothing from the relay for {} seconds", at.elapsed().as_secs()))
+            },
+            None                                => Err("nothing from the relay yet".to_string()),
+        }
+    }
+}
+
+/// Serves GET /healthz over http on its own thread, for liveness and
+/// readiness probes: 200 "ok" while healthy (see Health::check), and 503
+/// with the reason otherwise. Any other path is a 404. This needs the health
+/// feature.
+#[cfg(feature = "health")]
+pub fn listen(addr: &str, health: Health, max_age: Duration) -> Result<(), String> {
+    let listener = try!(TcpListener::bind(addr).map_err(|e| format!("Could not listen on {}: {}", addr, e)));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = serve(stream, &health, max_age) {
+                        error!("health check client: {}", e);
+                    }
+                },
+                Err(e)     => error!("health check listener: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Reads a request line from a client and writes back the response. The
+/// headers are never read, as nothing in them changes the answer.
+#[cfg(feature = "health")]
+fn serve(stream: TcpStream, health: &Health, max_age: Duration) -> Result<(), String> {
+    // A probe that never sends its request shouldn't tie up the listener
+    try!(stream.set_read_timeout(Some(Duration::from_secs(5))).map_err(|e| e.to_string()));
+    let mut request = String::new();
+    {
+        let mut reader = BufReader::new((&stream).take(MAX_REQUEST_LENGTH));
+        try!(reader.read_line(&mut request).map_err(|e| e.to_string()));
+    }
+
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/healthz")) => match health.check(max_age) {
+            Ok(())      => ("200 OK", "ok".to_string()),
+            Err(reason) => ("503 Service Unavailable", reason),
+        },
+        (Some(_), Some(_))              => ("404 Not Found", "not found".to_string()),
+        _                               => ("400 Bad Request", "bad request".to_string()),
+    };
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}\n",
+                           status, body.len() + 1, body);
+    (&stream).write_all(response.as_bytes()).map_err(|e| e.to_string())
+}
+
+/// Rewrites a file with the current time every FILE_INTERVAL seconds while
+/// we are healthy, on its own thread, for exec probes that check how old it
+/// is (ex: `find <path> -mmin -1`). The file is left alone while we are
+/// unhealthy, so it goes stale.
+pub fn touch(path: &Path, health: Health, max_age: Duration) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        try!(fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e)));
+    }
+    let path = path.to_path_buf();
+    thread::spawn(move || {
+        // Only log the first failure in a row, so a bad path doesn't log
+        // every interval
+        let mut failing = false;
+        loop {
+            if health.check(max_age).is_ok() {
+                match write_time(&path) {
+                    Ok(())                => failing = false,
+                    Err(_) if failing     => (),
+                    Err(e)                => {
+                        error!("{}", e);
+                        failing = true;
+                    },
+                }
+            }
+            thread::sleep(Duration::from_secs(FILE_INTERVAL));
+        }
+    });
+    Ok(())
+}
+
+fn write_time(path: &Path) -> Result<(), String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    File::create(path).and_then(|mut f| f.write_all(format!("{}\n", now).as_bytes()))
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "health")]
+    use std::io::prelude::*;
+    #[cfg(feature = "health")]
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Health;
+
+    #[test]
+    fn healthy_while_connected_and_reading() {
+        let health = Health::new();
+        assert_eq!(health.check(Duration::from_secs(60)), Err("not connected to the relay".to_string()));
+        health.set_connected(true);
+        assert_eq!(health.check(Duration::from_secs(60)), Ok(()));
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(health.check(Duration::from_millis(10)).is_err());
+        health.read();
+        assert_eq!(health.check(Duration::from_millis(10)), Ok(()));
+
+        health.set_connected(false);
+        assert!(health.check(Duration::from_secs(60)).is_err());
+    }
+
+    /// Sends a request to serve, and returns the response
+    #[cfg(feature = "health")]
+    fn request(health: &Health, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        super::serve(stream, health, Duration::from_secs(60)).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[cfg(feature = "health")]
+    #[test]
+    fn healthz_answers_with_the_health() {
+        let health = Health::new();
+        let response = request(&health, "GET /healthz HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nnot connected to the relay\n"), "{}", response);
+
+        health.set_connected(true);
+        let response = request(&health, "GET /healthz HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Content-Length: 3\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nok\n"), "{}", response);
+
+        assert!(request(&health, "GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(request(&health, "\r\n").starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
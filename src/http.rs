@@ -0,0 +1,415 @@
+use std::io::prelude::*;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use openssl::crypto::hash;
+use openssl::nid::Nid;
+use openssl::ssl::{Ssl, SslContext, SslMethod, SslStream, SSL_VERIFY_NONE, SSL_VERIFY_PEER};
+use openssl::x509::X509;
+
+/// The parts of an http or https url needed to make a request to it
+#[derive(Debug, Clone)]
+pub struct Url {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path of the url, including any query string
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(url: &str) -> Result<Url, String> {
+        let (https, rest) = if url.starts_with("https://") {
+            (true, &url[8..])
+        } else if url.starts_with("http://") {
+            (false, &url[7..])
+        } else {
+            return Err(format!("{} is not an http or https url", url));
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None    => (rest, "/"),
+        };
+        let (host, port) = match authority.rfind(':') {
+            Some(i) => {
+                let port = try!(authority[i + 1..].parse()
+                                .map_err(|_| format!("{} has an invalid port", url)));
+                (&authority[..i], port)
+            },
+            None    => (authority, if https { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(format!("{} has no host", url));
+        }
+
+        Ok(Url {
+            https: https,
+            host: host.to_string(),
+            port: port,
+            path: path.to_string(),
+        })
+    }
+}
+
+/// Which certificates https requests trust, for servers with a self-signed
+/// certificate. By default only certificates signed by the system's ca
+/// certificates are trusted.
+#[derive(Debug, Clone)]
+pub struct Trust {
+    /// Extra ca certificates to trust
+    pub ca_file: Option<PathBuf>,
+    /// Sha256 fingerprint of the only certificate to accept. This replaces
+    /// checking the certificate against ca certificates and the host name.
+    pub fingerprint: Option<Vec<u8>>,
+}
+
+impl Trust {
+    /// Trusts just the system's ca certificates
+    pub fn system() -> Trust {
+        Trust {
+            ca_file: None,
+            fingerprint: None,
+        }
+    }
+}
+
+/// Parses a sha256 fingerprint, written as hex with or without colons
+/// between the bytes (ex: "AB:CD:...")
+pub fn parse_fingerprint(fingerprint: &str) -> Result<Vec<u8>, String> {
+    let hex: String = fingerprint.chars().filter(|&c| c != ':').collect();
+    if hex.len() != 64 || !hex.chars().all(|c| c.is_digit(16)) {
+        return Err(format!("{} is not a sha256 fingerprint", fingerprint));
+    }
+    Ok((0..32).map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap()).collect())
+}
+
+/// A response to an http request
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    /// Headers of the response, with lowercased names
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl Response {
+    /// Returns true for a 2xx status
+    pub fn success(&self) -> bool {
+        self.status / 100 == 2
+    }
+
+    /// Looks up a header by its (lowercase) name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|h| h.0 == name).map(|h| h.1.as_str())
+    }
+}
+
+/// Posts a body to a url and returns the response, whatever its status. The
+/// timeout applies to connecting and to each read and write. https urls are
+/// verified against the system's ca certificates.
+pub fn post(url: &Url, headers: &[(String, String)], content_type: &str, body: &[u8],
+            timeout: Duration) -> Result<Response, String> {
+    request("POST", url, headers, Some((content_type, body)), timeout, &Trust::system())
+}
+
+/// Puts a body at a url and returns the response, whatever its status. See
+/// post.
+pub fn put(url: &Url, headers: &[(String, String)], content_type: &str, body: &[u8],
+           timeout: Duration) -> Result<Response, String> {
+    request("PUT", url, headers, Some((content_type, body)), timeout, &Trust::system())
+}
+
+/// Gets a url and returns the response, whatever its status. See post.
+pub fn get(url: &Url, headers: &[(String, String)], timeout: Duration) -> Result<Response, String> {
+    request("GET", url, headers, None, timeout, &Trust::system())
+}
+
+/// Makes a request to a url, with an optional body and its content type,
+/// trusting the given certificates for https. See post.
+pub fn request(method: &str, url: &Url, headers: &[(String, String)], body: Option<(&str, &[u8])>,
+               timeout: Duration, trust: &Trust) -> Result<Response, String> {
+    // This is a http/1.0 request so the response is never chunked, and the
+    // server closes the connection once it is done with it
+    let mut request = format!("{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: weechat-alert\r\n",
+                              method, url.path, url.host);
+    if let Some((content_type, body)) = body {
+        request.push_str(&format!("Content-Type: {}\r\nContent-Length: {}\r\n",
+                                  content_type, body.len()));
+    }
+    for &(ref name, ref value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    let mut request = request.into_bytes();
+    if let Some((_, body)) = body {
+        request.extend_from_slice(body);
+    }
+
+    let tcp_stream = try!(connect(&url.host, url.port, timeout));
+    if url.https {
+        let stream = try!(connect_ssl(&url.host, tcp_stream, trust));
+        exchange(stream, &request)
+    } else {
+        exchange(tcp_stream, &request)
+    }
+}
+
+/// Encodes pairs as an application/x-www-form-urlencoded body
+pub fn form_encode(pairs: &[(&str, &str)]) -> String {
+    let mut encoded = String::new();
+    for &(name, value) in pairs {
+        if !encoded.is_empty() {
+            encoded.push('&');
+        }
+        encoded.push_str(&percent_encode(name));
+        encoded.push('=');
+        encoded.push_str(&percent_encode(value));
+    }
+    encoded
+}
+
+/// Percent encodes everything except unreserved characters (ex: for a path
+/// segment)
+pub fn percent_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for &b in s.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' |
+            b'-' | b'_' | b'.' | b'~'                => encoded.push(b as char),
+            _                                        => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+/// Opens a tcp connection to a host, with the timeout for reads and writes
+/// too
+pub fn connect(host: &str, port: u16, timeout: Duration) -> Result<TcpStream, String> {
+    let addrs = try!((host, port).to_socket_addrs()
+                     .map_err(|e| format!("could not resolve {}: {}", host, e)));
+
+    let mut err = format!("could not resolve {}", host);
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => {
+                try!(stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string()));
+                try!(stream.set_write_timeout(Some(timeout)).map_err(|e| e.to_string()));
+                return Ok(stream);
+            },
+            Err(e)     => err = format!("could not connect to {}: {}", host, e),
+        }
+    }
+    Err(err)
+}
+
+/// Does the ssl handshake, checking the certificate is valid and for the host
+/// (or that it is the pinned one)
+pub fn connect_ssl(host: &str, stream: TcpStream, trust: &Trust) -> Result<SslStream<TcpStream>, String> {
+    let mut ctx = try!(SslContext::new(SslMethod::Sslv23).map_err(|e| e.to_string()));
+    if trust.fingerprint.is_some() {
+        ctx.set_verify(SSL_VERIFY_NONE, None);
+    } else {
+        ctx.set_verify(SSL_VERIFY_PEER, None);
+        try!(ctx.set_default_verify_paths().map_err(|e| e.to_string()));
+        if let Some(ref path) = trust.ca_file {
+            try!(ctx.set_CA_file(path).map_err(|e| format!("could not load {}: {}", path.display(), e)));
+        }
+    }
+
+    let ssl = try!(Ssl::new(&ctx).map_err(|e| e.to_string()));
+    try!(ssl.set_hostname(host).map_err(|e| e.to_string()));
+    let stream = try!(SslStream::connect(ssl, stream)
+                      .map_err(|e| format!("ssl error with {}: {}", host, e)));
+
+    if let Some(ref fingerprint) = trust.fingerprint {
+        let matches = match stream.ssl().peer_certificate() {
+            Some(cert) => cert.fingerprint(hash::Type::SHA256).as_ref() == Some(fingerprint),
+            None       => false,
+        };
+        if !matches {
+            return Err(format!("certificate of {} does not match the pinned fingerprint", host));
+        }
+        return Ok(stream);
+    }
+
+    // openssl only checks that the certificate is signed by a trusted ca, not
+    // that it is for the host we wanted
+    let matches = match stream.ssl().peer_certificate() {
+        Some(cert) => cert_matches(&cert, host),
+        None       => false,
+    };
+    if !matches {
+        return Err(format!("certificate of {} is not valid for that host", host));
+    }
+    Ok(stream)
+}
+
+/// Checks a certificate is for a host, by its subject alt names or (if it
+/// has none) its common name
+fn cert_matches(cert: &X509, host: &str) -> bool {
+    match cert.subject_alt_names() {
+        Some(names) => names.iter().filter_map(|n| n.dnsname().map(|s| s.to_string()))
+                                   .any(|name| name_matches(&name, host)),
+        None        => match cert.subject_name().text_by_nid(Nid::CN) {
+            Some(name) => name_matches(&name, host),
+            None       => false,
+        },
+    }
+}
+
+/// Checks a certificate name against a host. The name can start with a "*."
+/// wildcard, which matches exactly one label.
+fn name_matches(name: &str, host: &str) -> bool {
+    let name = name.to_lowercase();
+    let host = host.to_lowercase();
+    if name.starts_with("*.") {
+        match host.find('.') {
+            Some(i) => i > 0 && host[i..] == name[1..],
+            None    => false,
+        }
+    } else {
+        name == host
+    }
+}
+
+/// Sends the request and reads the whole response
+fn exchange<S: Read + Write>(mut stream: S, request: &[u8]) -> Result<Response, String> {
+    try!(stream.write_all(request).and_then(|_| stream.flush()).map_err(|e| e.to_string()));
+
+    // Some servers close the connection without an ssl shutdown, which shows
+    // up as an error. That's fine as long as we got the whole response first,
+    // but not if the connection broke partway through it
+    let mut data = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0)  => break,
+            Ok(n)  => data.extend_from_slice(&buf[..n]),
+            Err(e) => {
+                if !is_complete(&data) {
+                    return Err(e.to_string());
+                }
+                break;
+            },
+        }
+    }
+    parse_response(&data)
+}
+
+/// Checks a response has all of its headers, and as much of the body as its
+/// Content-Length says (if it has one; without it the body is everything up
+/// to the end of the connection)
+fn is_complete(data: &[u8]) -> bool {
+    let end = match data.windows(4).position(|w| w == b"\r\n\r\n") {
+        Some(i) => i,
+        None    => return false,
+    };
+    let head = String::from_utf8_lossy(&data[..end]);
+    let length = head.split("\r\n")
+                     .filter_map(|line| line.find(':').map(|i| (&line[..i], &line[i + 1..])))
+                     .find(|&(name, _)| name.trim().to_lowercase() == "content-length")
+                     .map(|(_, value)| value.trim().parse::<usize>());
+    match length {
+        Some(Ok(length)) => data.len() - (end + 4) == length,
+        Some(Err(_))     => false,
+        None             => true,
+    }
+}
+
+fn parse_response(data: &[u8]) -> Result<Response, String> {
+    let text = String::from_utf8_lossy(data);
+    let (head, body) = match text.find("\r\n\r\n") {
+        Some(i) => (&text[..i], &text[i + 4..]),
+        None    => (&text[..], ""),
+    };
+
+    // The status line looks like "HTTP/1.1 200 OK"
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or("");
+    let status = try!(status_line.split(' ').nth(1).and_then(|s| s.parse().ok())
+                      .ok_or(format!("invalid http response '{}'", status_line)));
+
+    let mut headers = Vec::new();
+    for line in lines {
+        if let Some(i) = line.find(':') {
+            headers.push((line[..i].trim().to_lowercase(), line[i + 1..].trim().to_string()));
+        }
+    }
+
+    Ok(Response {
+        status: status,
+        headers: headers,
+        body: body.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::io::{self, ErrorKind};
+    use std::io::prelude::*;
+
+    use super::exchange;
+
+    /// A connection that sends back a response a few bytes at a time, then
+    /// breaks (like a server closing it without an ssl shutdown)
+    struct Broken {
+        response: Vec<u8>,
+        read: usize,
+    }
+
+    impl Read for Broken {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.read == self.response.len() {
+                return Err(io::Error::new(ErrorKind::ConnectionReset, "connection reset"));
+            }
+            let n = cmp::min(cmp::min(buf.len(), 7), self.response.len() - self.read);
+            buf[..n].copy_from_slice(&self.response[self.read..self.read + n]);
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    impl Write for Broken {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn broken(response: &str) -> Broken {
+        Broken {
+            response: response.as_bytes().to_vec(),
+            read: 0,
+        }
+    }
+
+    #[test]
+    fn a_break_after_the_whole_response_is_fine() {
+        let response = exchange(broken("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"), b"GET /").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, "hello");
+
+        // Without a length, the body is whatever came before the break
+        let response = exchange(broken("HTTP/1.0 204 No Content\r\nServer: x\r\n\r\n"), b"GET /").unwrap();
+        assert_eq!(response.status, 204);
+        assert_eq!(response.body, "");
+    }
+
+    #[test]
+    fn a_break_partway_through_is_an_error() {
+        let partial = ["",
+                       "HTTP/1.1 200 OK\r\nContent-",
+                       "HTTP/1.1 200 OK\r\ncontent-length: 10\r\n\r\nhello",
+                       "HTTP/1.1 200 OK\r\nContent-Length: lots\r\n\r\nhello"];
+        for response in &partial {
+            let err = exchange(broken(response), b"GET /").err().unwrap();
+            assert_eq!(err, "connection reset", "{:?}", response);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use errors::WeechatError;
+use errors::WeechatError::ParseError;
+
+/// Longest a message can be once inflated, so a small compressed message
+/// can't use up all of our memory
+pub const MAX_INFLATED_LENGTH: usize = 64 * 1024 * 1024;
+
+// Longest huffman code deflate uses
+const MAX_BITS: usize = 15;
+
+// Base lengths and extra bits of the length symbols (257 to 285)
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83,
+                                99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+// Base distances and extra bits of the distance symbols (0 to 29)
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769,
+                                  1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11,
+                                  12, 12, 13, 13];
+
+// Order the code length code lengths are sent in, in a dynamic block
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Inflates zlib data (rfc 1950), as the relay sends the body of a message
+/// with compression=zlib. Fails on a bad stream, a bad checksum, or if it
+/// inflates to more than MAX_INFLATED_LENGTH.
+pub fn zlib(data: &[u8]) -> Result<Vec<u8>, WeechatError> {
+    if data.len() < 6 {
+        return Err(bad("the stream is too short"));
+    }
+    let (cmf, flg) = (data[0], data[1]);
+    if cmf & 0x0f != 8 || cmf >> 4 > 7 || ((cmf as u16) << 8 | flg as u16) % 31 != 0 {
+        return Err(bad("the stream has a bad header"));
+    }
+    if flg & 0x20 != 0 {
+        return Err(bad("the stream needs a preset dictionary"));
+    }
+
+    let mut bits = Bits::new(&data[2..]);
+    let out = try!(inflate(&mut bits));
+    bits.align();
+    let end = 2 + bits.pos;
+    if data.len() < end + 4 {
+        return Err(bad("the stream is missing its checksum"));
+    }
+    let expected = (data[end] as u32) << 24 | (data[end + 1] as u32) << 16 | (data[end + 2] as u32) << 8 |
+                   data[end + 3] as u32;
+    if adler32(&out) != expected {
+        return Err(bad("the checksum doesn't match"));
+    }
+    Ok(out)
+}
+
+fn bad(why: &str) -> WeechatError {
+    ParseError(format!("Could not inflate a compressed message: {}", why))
+}
+
+/// Reads a deflate stream (rfc 1951) a few bits at a time, lowest bit first
+struct Bits<'a> {
+    data: &'a [u8],
+    /// Next byte to load into buf
+    pos: usize,
+    buf: u32,
+    /// How many bits are in buf
+    count: u32,
+}
+
+impl<'a> Bits<'a> {
+    fn new(data: &'a [u8]) -> Bits<'a> {
+        Bits {
+            data: data,
+            pos: 0,
+            buf: 0,
+            count: 0,
+        }
+    }
+
+    /// Takes the next n (0 to 16) bits
+    fn take(&mut self, n: u32) -> Result<u32, WeechatError> {
+        while self.count < n {
+            if self.pos >= self.data.len() {
+                return Err(bad("the stream ends part way through"));
+            }
+            self.buf |= (self.data[self.pos] as u32) << self.count;
+            self.pos += 1;
+            self.count += 8;
+        }
+        let value = self.buf & ((1 << n) - 1);
+        self.buf >>= n;
+        self.count -= n;
+        Ok(value)
+    }
+
+    /// Drops the bits left in the current byte, giving back any whole bytes
+    /// that were loaded but not used
+    fn align(&mut self) {
+        self.pos -= (self.count / 8) as usize;
+        self.buf = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical huffman code, as the number of codes of each length and the
+/// symbols in code order
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds the code from the code length of each symbol (0 for a symbol
+    /// that isn't used). Fails if the lengths make more codes than fit.
+    fn new(lengths: &[u8]) -> Result<Huffman, WeechatError> {
+        let mut counts = [0; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for length in 1..MAX_BITS + 1 {
+            left = (left << 1) - counts[length] as i32;
+            if left < 0 {
+                return Err(bad("a huffman code is over-subscribed"));
+            }
+        }
+
+        let mut offsets = [0; MAX_BITS + 2];
+        for length in 1..MAX_BITS + 1 {
+            offsets[length + 1] = offsets[length] + counts[length] as usize;
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize]] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        counts[0] = 0;
+        Ok(Huffman {
+            counts: counts,
+            symbols: symbols,
+        })
+    }
+
+    /// Reads the next symbol
+    fn decode(&self, bits: &mut Bits) -> Result<u16, WeechatError> {
+        // Codes of each length are consecutive, so walk down the lengths
+        // until the code read so far falls in the range of one
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..MAX_BITS + 1 {
+            code |= try!(bits.take(1)) as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(bad("a code isn't in its huffman code"))
+    }
+}
+
+/// Inflates every block of a deflate stream
+fn inflate(bits: &mut Bits) -> Result<Vec<u8>, WeechatError> {
+    let mut out = Vec::new();
+    loop {
+        let last = try!(bits.take(1)) == 1;
+        match try!(bits.take(2)) {
+            0 => try!(stored(bits, &mut out)),
+            1 => {
+                let (literals, distances) = try!(fixed_codes());
+                try!(codes(bits, &mut out, &literals, &distances));
+            },
+            2 => {
+                let (literals, distances) = try!(dynamic_codes(bits));
+                try!(codes(bits, &mut out, &literals, &distances));
+            },
+            _ => return Err(bad("a block has a bad type")),
+        }
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+/// Copies a stored (uncompressed) block
+fn stored(bits: &mut Bits, out: &mut Vec<u8>) -> Result<(), WeechatError> {
+    bits.align();
+    let data = &bits.data[bits.pos..];
+    if data.len() < 4 {
+        return Err(bad("the stream ends part way through"));
+    }
+    let length = data[0] as usize | (data[1] as usize) << 8;
+    let complement = data[2] as usize | (data[3] as usize) << 8;
+    if length != !complement & 0xffff {
+        return Err(bad("a stored block has a bad length"));
+    }
+    if data.len() < 4 + length {
+        return Err(bad("the stream ends part way through"));
+    }
+    if out.len() + length > MAX_INFLATED_LENGTH {
+        return Err(bad("it is too big"));
+    }
+    out.extend_from_slice(&data[4..4 + length]);
+    bits.pos += 4 + length;
+    Ok(())
+}
+
+/// The codes of a block with fixed huffman codes
+fn fixed_codes() -> Result<(Huffman, Huffman), WeechatError> {
+    let mut lengths = [0; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            s if s < 144 => 8,
+            s if s < 256 => 9,
+            s if s < 280 => 7,
+            _            => 8,
+        };
+    }
+    Ok((try!(Huffman::new(&lengths)), try!(Huffman::new(&[5; 30]))))
+}
+
+/// Reads the codes at the start of a block with dynamic huffman codes
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), WeechatError> {
+    let literal_count = try!(bits.take(5)) as usize + 257;
+    let distance_count = try!(bits.take(5)) as usize + 1;
+    let code_length_count = try!(bits.take(4)) as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(bad("a block has too many codes"));
+    }
+
+    let mut code_lengths = [0; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = try!(bits.take(3)) as u8;
+    }
+    let code_lengths = try!(Huffman::new(&code_lengths));
+
+    // The literal and distance code lengths are sent as one run, which
+    // repeats can cross
+    let mut lengths = vec![0; literal_count + distance_count];
+    let mut i = 0;
+    while i < lengths.len() {
+        let symbol = try!(code_lengths.decode(bits));
+        let (length, repeat) = match symbol {
+            16 if i == 0 => return Err(bad("a block repeats a code length before the first")),
+            16           => (lengths[i - 1], 3 + try!(bits.take(2)) as usize),
+            17           => (0, 3 + try!(bits.take(3)) as usize),
+            18           => (0, 11 + try!(bits.take(7)) as usize),
+            _            => (symbol as u8, 1),
+        };
+        if i + repeat > lengths.len() {
+            return Err(bad("a block has too many code lengths"));
+        }
+        for length_at in &mut lengths[i..i + repeat] {
+            *length_at = length;
+        }
+        i += repeat;
+    }
+    if lengths[256] == 0 {
+        return Err(bad("a block has no end code"));
+    }
+
+    let literals = try!(Huffman::new(&lengths[..literal_count]));
+    let distances = try!(Huffman::new(&lengths[literal_count..]));
+    Ok((literals, distances))
+}
+
+/// Inflates the body of a compressed block, up to its end code
+fn codes(bits: &mut Bits, out: &mut Vec<u8>, literals: &Huffman, distances: &Huffman) -> Result<(), WeechatError> {
+    loop {
+        let symbol = try!(literals.decode(bits)) as usize;
+        if symbol < 256 {
+            out.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(bad("a block has a bad length code"));
+            }
+            let length = LENGTH_BASE[symbol] as usize + try!(bits.take(LENGTH_EXTRA[symbol] as u32)) as usize;
+
+            let symbol = try!(distances.decode(bits)) as usize;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(bad("a block has a bad distance code"));
+            }
+            let distance = DISTANCE_BASE[symbol] as usize +
+                           try!(bits.take(DISTANCE_EXTRA[symbol] as u32)) as usize;
+            if distance > out.len() {
+                return Err(bad("a block refers back past the start"));
+            }
+            // The copy can overlap what it is copying (ex: a distance of 1
+            // repeats the last byte), so it goes a byte at a time
+            let start = out.len() - distance;
+            for i in 0..length {
+                let byte = out[start + i];
+                out.push(byte);
+            }
+        }
+        if out.len() > MAX_INFLATED_LENGTH {
+            return Err(bad("it is too big"));
+        }
+    }
+}
+
+/// The adler-32 checksum zlib ends its streams with
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    // Sums can go this many bytes before they have to be reduced
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
@@ -0,0 +1,89 @@
+//! A client for the weechat relay protocol, and the notification code
+//! weechat-alert is built from. See:
+//! https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html
+//!
+//! The protocol side is what most users of the crate want:
+//!
+//! - `relay::Relay` connects to a relay, syncs with it and hands every line
+//!   added to a buffer to its handler (`alert::AlertHandler` checks them
+//!   against the alert rules and notifies). `connection::Connection` is the
+//!   bare connection under it, for sending commands and reading messages
+//!   yourself.
+//! - `message::Message` is a parsed message, with its body as a
+//!   `strdata::StrData` or `hdata::HData` of `message::Object`s.
+//! - `commands` builds the commands to send (`Connection::send`).
+//! - `errors::WeechatError` is the error for all of it.
+//! - `handler::RelayHandler` runs your own logic from the relay's read loop
+//!   (see `Relay::run_with_handler`).
+//!
+//! Parsing a message someone captured off the wire (a pong, here):
+//!
+//! ```
+//! extern crate weechat_alert;
+//!
+//! use weechat_alert::message::{Header, Message};
+//!
+//! # fn main() {
+//! let bytes = b"\x00\x00\x00\x19\x00\x00\x00\x00\x05_pongstr\x00\x00\x00\x04ping";
+//! let header = Header::new(bytes).unwrap();
+//! assert!(!header.compression);
+//!
+//! let message = Message::new(&bytes[5..5 + header.length]).unwrap();
+//! assert_eq!(message.identifier, Some("_pong".to_string()));
+//! assert_eq!(message.as_strdata().unwrap().as_str(), Some("ping"));
+//! # }
+//! ```
+//!
+//! Diagnostics (errors from the backends, reconnects, and at debug level
+//! every command sent, message received and alert decision) go through the
+//! log crate. Nothing here sets up a logger, so set one up (ex: env_logger)
+//! to see them.
+//!
+//! `notify` has the notification backends and their settings, and `alert`
+//! the rules for which lines notify. The rest of the public modules are
+//! there for the weechat-alert binary (its config needs their types), and
+//! aren't meant to be stable.
+
+#[cfg(feature = "ears")]
+extern crate ears;
+extern crate libc;
+#[macro_use]
+extern crate log;
+extern crate openssl;
+extern crate rustc_serialize;
+#[cfg(feature = "async")]
+extern crate futures;
+#[cfg(feature = "async")]
+extern crate tokio_core;
+#[cfg(feature = "async")]
+extern crate tokio_io;
+
+pub mod alert;
+#[cfg(feature = "async")]
+pub mod async_relay;
+pub mod backoff;
+mod buffers;
+mod colors;
+pub mod commands;
+pub mod connection;
+pub mod errors;
+pub mod handler;
+pub mod hdata;
+pub mod health;
+pub mod http;
+mod inflate;
+pub mod line;
+pub mod message;
+pub mod nicklist;
+pub mod notify;
+pub mod parse;
+pub mod policy;
+mod profile;
+pub mod relay;
+pub mod reply;
+mod stats;
+pub mod strdata;
+mod sys;
+#[cfg(test)]
+mod testing;
+pub mod websocket;
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+use errors::WeechatError;
+use errors::WeechatError::ParseError;
+use hdata::FromHData;
+use message::Object;
+
+/// A line added to a buffer, as sent in a _buffer_line_added hdata. See:
+/// https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html#message_buffer_line_added
+#[derive(Debug)]
+pub struct BufferLine {
+    /// Pointer to the buffer this line was added to
+    buffer: String,
+    /// Date of the line (which may be in the past for lines from a backlog),
+    /// or 0 if weechat didn't send one
+    date: i64,
+    /// Date weechat actually printed the line
+    date_printed: i64,
+    /// If the line is shown, or hidden by one of the user's weechat filters
+    displayed: bool,
+    /// If weechat flagged the line as a highlight
+    highlight: bool,
+    /// Tags weechat attached to the line (ex: irc_privmsg, notify_private)
+    tags: Vec<String>,
+    /// Prefix of the line, normally the nick of who sent it
+    prefix: Option<String>,
+    /// The line itself
+    message: Option<String>,
+}
+
+impl BufferLine {
+    /// Builds a line from one of the items in a _buffer_line_added hdata
+    pub fn new(item: &HashMap<String, Object>) -> Result<BufferLine, WeechatError> {
+        let buffer = try!(try!(get(item, "buffer")).as_not_null_pointer()).to_string();
+        let date = match item.get("date") {
+            Some(date) => try!(date.as_time()),
+            None       => 0,
+        };
+        let date_printed = try!(try!(get(item, "date_printed")).as_time());
+        let displayed = try!(try!(get(item, "displayed")).as_character()) == (1 as char);
+        let highlight = try!(try!(get(item, "highlight")).as_character()) == (1 as char);
+        let prefix = try!(try!(get(item, "prefix")).as_str()).map(|s| s.to_string());
+        let message = try!(try!(get(item, "message")).as_str()).map(|s| s.to_string());
+
+        // A null tags array is parsed as an empty one, and a null tag in the
+        // array doesn't tell us anything, so skip those
+        let mut tags = Vec::new();
+        for tag in try!(try!(get(item, "tags_array")).as_array()) {
+            if let Some(tag) = try!(tag.as_str()) {
+                tags.push(tag.to_string());
+            }
+        }
+
+        Ok(BufferLine {
+            buffer: buffer,
+            date: date,
+            date_printed: date_printed,
+            displayed: displayed,
+            highlight: highlight,
+            tags: tags,
+            prefix: prefix,
+            message: message,
+        })
+    }
+
+    /// Pointer to the buffer this line was added to
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+
+    /// Date of the line as an epoch timestamp, or 0 if it doesn't have one
+    pub fn date(&self) -> i64 {
+        self.date
+    }
+
+    /// Date weechat printed the line as an epoch timestamp
+    pub fn date_printed(&self) -> i64 {
+        self.date_printed
+    }
+
+    /// Returns false if the line is hidden by a weechat filter
+    pub fn displayed(&self) -> bool {
+        self.displayed
+    }
+
+    /// Returns true if weechat flagged the line as a highlight
+    pub fn highlight(&self) -> bool {
+        self.highlight
+    }
+
+    /// Tags attached to the line. This is empty if the line has no tags.
+    pub fn tags(&self) -> &[String] {
+        self.tags.as_slice()
+    }
+
+    /// Checks if the line has a tag
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+
+    /// Prefix of the line (normally the nick who sent it), if it has one
+    pub fn prefix(&self) -> Option<&str> {
+        self.prefix.as_ref().map(|s| s.as_str())
+    }
+
+    /// The line itself, if it isn't null
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(|s| s.as_str())
+    }
+}
+
+impl FromHData for BufferLine {
+    fn from_item(item: &HashMap<String, Object>) -> Result<BufferLine, WeechatError> {
+        BufferLine::new(item)
+    }
+}
+
+/// Looks up a key in an hdata item, erroring if it isn't there
+fn get<'a>(item: &'a HashMap<String, Object>, key: &str) -> Result<&'a Object, WeechatError> {
+    item.get(key).ok_or(ParseError(format!("Line is missing '{}'", key)))
+}
@@ -1,114 +1,391 @@
 use std::env;
-use std::error::Error;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::PathBuf;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
 use std::process::exit;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::thread;
+use std::time::Duration;
 
+#[cfg(feature = "ears")]
 extern crate ears;
-extern crate openssl;
+extern crate env_logger;
+extern crate getopts;
+#[macro_use]
+extern crate log;
+extern crate rustc_serialize;
 extern crate toml;
+extern crate weechat_alert;
 
-mod message;
-mod errors;
-mod hdata;
-mod parse;
-mod relay;
-mod strdata;
+mod cli;
+mod config;
+mod control;
+mod dump_messages;
+mod report;
+mod test_notification;
 
-use relay::{Relay, SslConfig};
+// The library's modules, so ours can use them by the same paths as the
+// library does (ex: notify::Settings)
+use weechat_alert::{alert, backoff, commands, connection, errors, health, http, message, notify, parse, policy, relay,
+                    reply, websocket};
 
+use env_logger::LogBuilder;
+use log::{LogLevel, LogLevelFilter, LogRecord};
 
-struct Config {
-    host: String,
-    port: i32,
-    password: String,
-    ssl: bool,
-    ssl_verify: bool,
-    ca_certs_path: Option<String>
+use alert::{AlertHandler, AlertRules};
+use cli::{CheckOptions, Command, Global, ReportOptions, RunOptions, TestOptions};
+use commands::{HdataCommand, InputCommand};
+use config::{config_path, init_config, parse_config, resolve_server, Config};
+use dump_messages::MessagePrinter;
+use errors::WeechatError;
+use message::read_message;
+use notify::Notifiers;
+use connection::SslConfig;
+use relay::Relay;
+use reply::Replies;
+use report::Report;
+use weechat_alert::handler::RelayHandler;
+
+// Errors logged so far, so test-notification can tell if a backend sending
+// from its own thread failed
+static ERRORS_LOGGED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+
+fn main() {
+    // See cli.rs (or weechat-alert help) for the subcommands and their
+    // options. Each has a function here, given its options
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (global, command) = match cli::parse(&args) {
+        Ok(parsed) => parsed,
+        Err(e)     => {
+            println!("Error: {}", e);
+            exit(1);
+        },
+    };
+    let result = match command {
+        Command::Run(options)              => run(&global, options),
+        Command::InitConfig                => init(&global),
+        Command::CheckConfig(options)      => check_config(&global, options),
+        Command::TestNotification(options) => test_notification(&global, options),
+        Command::ParseFile(path)           => parse_file(&global, &path),
+        Command::Ctl(command)              => ctl(&global, &command),
+        Command::Report(options)           => report(&global, options),
+        Command::ListBuffers               => list_buffers(&global),
+        Command::Send(buffer, text)        => send(&global, &buffer, &text),
+    };
+    if let Err(e) = result {
+        println!("Error: {}", e);
+        exit(1);
+    }
 }
 
-fn parse_config() -> Result<Config, String> {
-    // Get config filepath
-    let homedir = try!(env::home_dir().ok_or("Cannot find home directory"));
-    let mut path = PathBuf::from(homedir);
-    path.push(".relay");
-    path.set_extension("toml");
-
-    // Open the file and read the data
-    let mut file = try!(File::open(&path).map_err(|e| format!("{}: {}", path.display(), e)));
-    let mut file_data = String::new();
-    try!(file.read_to_string(&mut file_data).map_err(|e| format!("{}: {}", path.display(), e)));
-
-    // Parse the config
-    let config: toml::Value = try!(file_data.parse().map_err(|errs| {
-        let mut err = "Error parsing config file:".to_string();
-        for e in errs {
-            err.push_str("\n  ");
-            err.push_str(Error::description(&e));
+/// Connects to the relay and notifies until stopped (or replays a dump)
+fn run(global: &Global, options: RunOptions) -> Result<(), String> {
+    let config = read_config(global);
+    start_logging(global, config.log_level, &config.warnings);
+    let mut relay = new_relay(&config);
+
+    // Replies to notifications (from the control socket, ntfy or desktop
+    // notifications) are typed into their buffers through the relay
+    let replies = Replies::new(relay.buffers());
+    relay.set_replies(replies.clone());
+
+    // Set up the notification backends. --dump-only leaves them (and the
+    // logs of events) out, so nothing notifies
+    let mut notifiers = Notifiers::new(config.notify);
+    notifiers.set_replies(replies.clone());
+    let notifier_names = if options.dump_only { Vec::new() } else { config.notifiers };
+    for name in &notifier_names {
+        try!(notifiers.enable(name));
+    }
+    if !options.dump_only {
+        try!(notifiers.open_logs());
+    }
+    if let Some(ref path) = config.control_socket {
+        try!(control::listen(path, notifiers.history(), notifiers.status(), replies));
+    }
+    if config.tray {
+        #[cfg(feature = "tray")]
+        notify::tray::start(notifiers.status());
+        #[cfg(not(feature = "tray"))]
+        warn!("built without the tray icon (see the tray feature), so tray does nothing");
+    }
+
+    // Call ears_init() function tlo insure that the ears context is not
+    // destroyed by a task
+    #[cfg(feature = "ears")]
+    ears::init();
+
+    // Run our program
+    let mut rules = AlertRules::new();
+    rules.watch_buffers = config.watch_buffers;
+    rules.ignore_buffers = config.ignore_buffers;
+    rules.ignore_self = config.ignore_self;
+    rules.own_nicks = config.own_nicks;
+    rules.respect_display_filter = config.respect_display_filter;
+    rules.max_line_age = config.max_line_age;
+    relay.set_keepalive(config.keepalive);
+    #[cfg(feature = "health")]
+    {
+        if let Some(ref addr) = config.health_listen {
+            try!(health::listen(addr, relay.health(), config.health_max_age));
         }
-        err
-    }));
+    }
+    if let Some(ref path) = config.health_file {
+        try!(health::touch(path, relay.health(), config.health_max_age));
+    }
+    relay.set_error_policy(config.error_policy);
+    relay.set_schedule(config.reconnect);
+    relay.set_notifiers(notifiers);
+    if options.stats {
+        relay.enable_stats();
+    }
+    if options.profile {
+        relay.enable_profile();
+    }
 
-    // Get data and return
-    let host = try!(config.lookup("server").ok_or("'server' not found in the config file"));
-    let host = try!(host.as_str().map(|s| s.to_string()).ok_or("'server' is not a valid string"));
+    // Lines are alerted on by a handler sharing the relay's notifiers, and
+    // --dump-messages prints every message before it
+    let mut alerts = AlertHandler::new(rules, relay.buffers(), relay.notifiers());
+    alerts.set_cooldown(config.buffer_cooldown);
+    if let Some(buffer) = config.forward_buffer {
+        alerts.set_forward(buffer, config.forward_format);
+    }
+    if options.dump_only {
+        relay.set_handler(MessagePrinter::new());
+    } else if options.dump_messages {
+        relay.set_handler((MessagePrinter::new(), alerts));
+    } else {
+        relay.set_handler(alerts);
+    }
 
-    let pw = try!(config.lookup("password").ok_or("'password' not found in the config file"));
-    let pw = try!(pw.as_str().map(|s| s.to_string()).ok_or("'password' is not a valid string"));
+    if let Some(path) = options.replay {
+        let count = try!(relay.replay(&path).map_err(|e| e.to_string()));
+        println!("Replayed {} messages from {}", count, path.display());
+        return Ok(());
+    }
+    let dump = options.dump.or(options.dump_dir.map(|dir| dir.join(format!("weechat-alert-{}.dump", notify::now()))));
+    if let Some(path) = dump {
+        let file = try!(OpenOptions::new().create(true).append(true).open(&path)
+                        .map_err(|e| format!("could not open {} to dump to: {}", path.display(), e)));
+        relay.dump_to(file);
+    }
+    match relay.run() {
+        Err(e) => error!("{}", e),
+        Ok(_) => ()
+    }
+    Ok(())
+}
 
-    let port = try!(config.lookup("port").ok_or("'port' not found in the config file"));
-    let port = try!(port.as_integer().map(|s| s as i32).ok_or("'port' is not an integer"));
+/// Writes an example config for a new user
+fn init(global: &Global) -> Result<(), String> {
+    let path = try!(path_of_config(global));
+    try!(init_config(&path));
+    println!("Wrote an example config to {}. Fill in server, port and password (and anything else you want) \
+              before running again.", path.display());
+    Ok(())
+}
 
-    let default_ssl = toml::Value::Boolean(false);
-    let ssl = config.lookup("ssl").unwrap_or(&default_ssl);
-    let ssl = try!(ssl.as_bool().ok_or("'ssl' is not true or false"));
+/// Checks the config, printing every problem in it (or its warnings and OK).
+/// The server is only looked up with --resolve, as that needs the network
+fn check_config(global: &Global, options: CheckOptions) -> Result<(), String> {
+    let config = read_config(global);
+    for warning in &config.warnings {
+        println!("Warning: {}", warning);
+    }
+    if options.resolve {
+        try!(resolve_server(&config));
+    }
+    println!("OK");
+    Ok(())
+}
 
-    let default_ssl_verify = toml::Value::Boolean(false);
-    let ssl_verify = config.lookup("ssl_verify").unwrap_or(&default_ssl_verify);
-    let ssl_verify = try!(ssl_verify.as_bool().ok_or("'ssl_verify' is not a true or false"));
+/// Sends a made up notification through every configured backend, and says
+/// how each did
+fn test_notification(global: &Global, options: TestOptions) -> Result<(), String> {
+    let config = read_config(global);
+    start_logging(global, config.log_level, &config.warnings);
 
-    let ca_certs = match config.lookup("ca_certs_path") {
-        Some(ca) => Some(try!(ca.as_str().map(|s| s.to_string())
-                         .ok_or("'ca_certs_path' is not a valid string"))),
-        None     => None
-    };
+    #[cfg(feature = "ears")]
+    ears::init();
 
-    Ok(Config {
-        host: host,
-        port: port,
-        password: pw,
-        ssl: ssl,
-        ssl_verify: ssl_verify,
-        ca_certs_path: ca_certs,
-    })
+    let sent = test_notification::send(config.notify, &config.notifiers, &options.event);
+    if options.wait > 0 {
+        println!("Waiting {} seconds for backends that send in the background (their errors show up here)",
+                 options.wait);
+        thread::sleep(Duration::from_secs(options.wait));
+    }
+    if !sent || ERRORS_LOGGED.load(Ordering::SeqCst) > 0 {
+        return Err("Not every backend worked, see above".to_string());
+    }
+    println!("Every backend took the notification");
+    Ok(())
 }
 
-fn main() {
-    // Parse config
-    let config = match parse_config() {
-        Ok(config) => config,
-        Err(e)     => {
-            println!("Error: {}", e);
-            exit(1);
+/// Prints every message in a file of them (from --dump), the way
+/// --dump-messages does. This doesn't need a config
+fn parse_file(global: &Global, path: &Path) -> Result<(), String> {
+    start_logging(global, LogLevelFilter::Info, &[]);
+    let file = try!(File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e)));
+    let mut reader = BufReader::new(file);
+    let mut printer = MessagePrinter::new();
+    loop {
+        match read_message(&mut reader) {
+            Ok(msg)                                                                 => printer.on_message(&msg),
+            Err(WeechatError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e)                                                                  => {
+                return Err(format!("Could not parse {}: {}", path.display(), e));
+            },
         }
-    };
+    }
+}
+
+/// Sends a command to a running instance over its control socket, and
+/// prints what it sends back
+fn ctl(global: &Global, command: &str) -> Result<(), String> {
+    let config = read_config(global);
+    start_logging(global, config.log_level, &config.warnings);
+    let path = try!(config.control_socket.ok_or("ctl needs control_socket set in the config"));
+    match try!(control::send(&path, command)) {
+        ref reply if reply.starts_with("error: ") => Err(reply[7..].trim_right().to_string()),
+        ref reply if reply.is_empty()             => {
+            println!("Nothing has notified yet");
+            Ok(())
+        },
+        reply                                     => {
+            print!("{}", reply);
+            Ok(())
+        },
+    }
+}
+
+/// Sums up days of the json_log
+fn report(global: &Global, options: ReportOptions) -> Result<(), String> {
+    let config = read_config(global);
+    start_logging(global, config.log_level, &config.warnings);
+    let path = try!(config.notify.json_log.ok_or("report needs json_log set in the config"));
+    let report = try!(Report::read(&path, options.range));
+    if options.json {
+        print!("{}", report.json());
+    } else {
+        print!("{}", report.text());
+    }
+    Ok(())
+}
+
+/// Prints the number and full name of every buffer open in weechat
+fn list_buffers(global: &Global) -> Result<(), String> {
+    let config = read_config(global);
+    start_logging(global, config.log_level, &config.warnings);
+    let mut relay = new_relay(&config);
+    try!(relay.connect().map_err(|e| e.to_string()));
+    let buffers = HdataCommand::new("buffer:gui_buffers(*)", &["number", "full_name"]);
+    let msg = relay.command(&buffers, "list_buffers");
+    relay.close();
+    let msg = try!(msg.map_err(|e| e.to_string()));
+    for buffer in &try!(msg.as_hdata().map_err(|e| e.to_string())).data {
+        let number = buffer.get("number").and_then(|n| n.as_integer().ok());
+        let name = buffer.get("full_name").and_then(|n| n.as_str().ok()).and_then(|n| n);
+        match (number, name) {
+            (Some(number), Some(name)) => println!("{:>4} {}", number, name),
+            _                          => warn!("the relay sent a buffer without a number or full_name"),
+        }
+    }
+    Ok(())
+}
+
+/// Types text into a buffer (by its full name), as if it were typed in
+/// weechat, so it can be a command (ex: /away)
+fn send(global: &Global, buffer: &str, text: &str) -> Result<(), String> {
+    let config = read_config(global);
+    start_logging(global, config.log_level, &config.warnings);
+    let mut relay = new_relay(&config);
+    try!(relay.connect().map_err(|e| e.to_string()));
+    let sent = relay.send(&InputCommand::new(buffer, text));
+    relay.close();
+    sent.map_err(|e| e.to_string())
+}
+
+/// Where the config is, from --config or the usual place
+fn path_of_config(global: &Global) -> Result<PathBuf, String> {
+    match global.config {
+        Some(ref path) => Ok(path.clone()),
+        None           => config_path(),
+    }
+}
+
+/// Reads the config, exiting with every problem in it if it has any
+fn read_config(global: &Global) -> Config {
+    let parsed = path_of_config(global).map_err(|e| vec![e])
+        .and_then(|path| parse_config(&path, &global.overrides));
+    match parsed {
+        Ok(config)    => config,
+        Err(problems) => {
+            for problem in &problems {
+                println!("Error: {}", problem);
+            }
+            exit(1);
+        },
+    }
+}
 
-    // Handle ssl if its configured
-    let ssl = if config.ssl == true {
-        Some(SslConfig::new(config.ssl_verify, config.ca_certs_path))
+/// Sets up logging at the level given, unless -v asks for more, then logs
+/// the warnings the config had
+fn start_logging(global: &Global, level: LogLevelFilter, warnings: &[String]) {
+    init_logging(match global.verbosity {
+        0 => level,
+        1 => LogLevelFilter::Debug,
+        _ => LogLevelFilter::Trace,
+    });
+    for warning in warnings {
+        warn!("{}", warning);
+    }
+}
+
+/// A relay for the server in the config, not connected yet
+fn new_relay(config: &Config) -> Relay {
+    // Without verifying the cert, anyone in the middle can read the password
+    // and everything else, so say so loudly
+    if config.ssl && !config.ssl_verify {
+        warn!("ssl is on but ssl_verify is off, so the relay's certificate is not checked. Anyone between \
+               you and the relay can pretend to be it and read your password and messages. Set ssl_verify \
+               = true (with ca_certs_path if the cert isn't from a ca the system trusts)");
+    }
+    let ssl = if config.ssl {
+        Some(SslConfig::new(config.ssl_verify, config.ca_certs_path.clone()))
     } else {
         None
     };
+    let mut relay = Relay::new(config.host.clone(), config.port, config.password.clone(), ssl);
+    if let Some(ref terminator) = config.terminator {
+        relay.set_terminator(terminator.clone());
+    }
+    relay.set_transport(config.transport.clone());
+    relay.set_compression(config.compression);
+    relay.set_max_parse_depth(config.max_parse_depth);
+    relay
+}
 
-    // Call ears_init() function tlo insure that the ears context is not
-    // destroyed by a task
-    ears::init();
-
-    // Run our program
-    let relay =  Relay::new(config.host, config.port, config.password, ssl);
-    match relay.run() {
-        Err(e) => println!("Error: {}", e),
-        Ok(_) => ()
+/// Prints what the library and our modules log, at the level given unless
+/// RUST_LOG says otherwise. Errors and warnings look the way they always
+/// have ("Error: ...", "Warning: ..."), and debug and trace messages say
+/// where they come from
+fn init_logging(level: LogLevelFilter) {
+    let format = |record: &LogRecord| match record.level() {
+        LogLevel::Error => {
+            ERRORS_LOGGED.fetch_add(1, Ordering::SeqCst);
+            format!("Error: {}", record.args())
+        },
+        LogLevel::Warn  => format!("Warning: {}", record.args()),
+        LogLevel::Info  => format!("{}", record.args()),
+        _               => format!("{} {}: {}", record.level(), record.target(), record.args()),
+    };
+    let mut builder = LogBuilder::new();
+    builder.format(format).filter(None, level);
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse(&filters);
+    }
+    if let Err(e) = builder.init() {
+        println!("Error: could not set up logging: {}", e);
     }
 }
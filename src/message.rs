@@ -2,11 +2,15 @@ use std::fmt;
 use std::hash::{Hash, Hasher};
 use std::str::from_utf8;
 use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::io::Read;
 
+use connection::HEADER_LENGTH;
 use hdata::HData;
 use errors::WeechatError;
 use errors::WeechatError::ParseError;
-use parse::Parse;
+use inflate;
+use parse::{Parse, DEFAULT_MAX_DEPTH};
 use strdata::StrData;
 
 
@@ -45,6 +49,10 @@ impl Header {
 
         // Headers has length of full message, we need to chop off the
         // legth of the header as we have already read that from the socket
+        if (total_msg_length as i64) < cur_pos as i64 {
+            return Err(WeechatError::ParseError(format!("Message length {} is shorter than its header",
+                                                        total_msg_length)));
+        }
         let length = total_msg_length as usize - cur_pos;
 
         // Create the struct
@@ -55,12 +63,43 @@ impl Header {
     }
 }
 
+/// Reads one whole message (header and body) from any reader, inflating it
+/// if it was compressed, and parses it. This is the framing recv_msg does on
+/// a connection, for reading messages from files (ex: a dump), pipes or
+/// buffers:
+///
+/// ```
+/// # extern crate weechat_alert;
+/// # use weechat_alert::message::read_message;
+/// # fn main() {
+/// let mut bytes: &[u8] = b"\x00\x00\x00\x19\x00\x00\x00\x00\x05_pongstr\x00\x00\x00\x04ping";
+/// let message = read_message(&mut bytes).unwrap();
+/// assert_eq!(message.identifier, Some("_pong".to_string()));
+/// # }
+/// ```
+///
+/// A reader that ends before the message does fails with an UnexpectedEof
+/// io error, the same as one with no message left in it.
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Message, WeechatError> {
+    let mut buffer = [0; HEADER_LENGTH];
+    try!(reader.read_exact(&mut buffer));
+    let header = try!(Header::new(&buffer));
+
+    let mut data = vec![0; header.length];
+    try!(reader.read_exact(data.as_mut_slice()));
+    if header.compression {
+        data = try!(inflate::zlib(&data));
+    }
+    Message::new(data.as_slice())
+}
+
 /// Message received from weechat
 #[derive(Debug)]
 pub struct Message {
     /// Identifier of the message. For a complete list of identifiers, see:
     /// https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html#message_identifier
-    pub identifier: String,
+    /// It is None for the reply to a command sent without an "(id)" prefix.
+    pub identifier: Option<String>,
     /// Data contained in this message
     data_type: Type,
 }
@@ -74,27 +113,52 @@ pub enum Type {
 }
 
 impl Message {
+    /// Parses only the identifier out of the bytes of a message (after the
+    /// header). This is much cheaper than parsing the whole message, so it
+    /// can be used to skip messages we don't care about.
+    pub fn identifier(bytes: &[u8]) -> Result<Option<String>, WeechatError> {
+        let parsed = try!(Parse::string(bytes));
+        let identifier = try!(parsed.object.as_str());
+        Ok(identifier.map(String::from))
+    }
+
     pub fn new(bytes: &[u8]) -> Result<Message, WeechatError> {
+        Message::with_max_depth(bytes, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Parses a message like new, failing if arrays and hashtables in it
+    /// nest more than max_depth deep (see Connection::set_max_parse_depth)
+    pub fn with_max_depth(bytes: &[u8], max_depth: usize) -> Result<Message, WeechatError> {
         // First thing encoded is the identifier for what this command is
+        // (null if the command had no id)
         let parsed = try!(Parse::string(bytes));
-        let identifier = try!(parsed.object.as_not_null_str());
+        let identifier = try!(parsed.object.as_str());
 
         // Next 3 bytes determin type of data in this command (hdata or str).
         let start = parsed.bytes_read;
         let end = start + 3;
         let msg_type = match try!(from_utf8(&bytes[start..end])) {
             "str" => Type::StrData(try!(StrData::new(&bytes[end..]))),
-            "hda" => Type::HData(try!(HData::new(&bytes[end..]))),
+            "hda" => Type::HData(try!(HData::with_max_depth(&bytes[end..], max_depth))),
             _ => return Err(WeechatError::ParseError("Unknown message type".to_string())),
         };
 
         // Return our struct
         Ok(Message {
-            identifier: String::from(identifier),
+            identifier: identifier.map(String::from),
             data_type: msg_type,
         })
     }
 
+    /// The type of the message's body, as the protocol names it ("hda" or
+    /// "str")
+    pub fn type_name(&self) -> &'static str {
+        match self.data_type {
+            Type::StrData(_) => "str",
+            Type::HData(_)   => "hda",
+        }
+    }
+
     /// Returns the contents of this message as an HData (if it is an HData)
     pub fn as_hdata(&self) -> Result<&HData, WeechatError> {
         match self.data_type {
@@ -124,18 +188,28 @@ pub enum Object {
     Lon(i64),
     Ptr(Option<String>),
     Str(Option<String>),
-    Tim(i32),
+    Tim(i64),
 }
 
-// I need to implement this to get nested hash tables to work. Derive hash
-// on Object isn't working for it, so I'm manually doing it here
+// I need to implement this to get nested hash tables to work, as HashMap
+// doesn't implement Hash. Equal hash tables have to hash the same no matter
+// what order their entries iterate in, so the entry hashes are summed
 impl Hash for Object {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match *self {
             Object::Arr(ref x) => x.hash(state),
             Object::Buf(ref x) => x.hash(state),
             Object::Chr(ref x) => x.hash(state),
-            Object::Htb(ref x) => format!("{:?}", x).hash(state), // Not ideal
+            Object::Htb(ref x) => {
+                let sum = x.iter().fold(0u64, |sum, (key, value)| {
+                    let mut hasher = DefaultHasher::new();
+                    key.hash(&mut hasher);
+                    value.hash(&mut hasher);
+                    sum.wrapping_add(hasher.finish())
+                });
+                x.len().hash(state);
+                sum.hash(state);
+            },
             Object::Int(ref x) => x.hash(state),
             Object::Lon(ref x) => x.hash(state),
             Object::Ptr(ref x) => x.hash(state),
@@ -153,6 +227,14 @@ impl Object {
         }
     }
 
+    /// Returns this data as a hashtable if it is a hashtable.
+    pub fn as_hashtable(&self) -> Result<&HashMap<Object, Object>, WeechatError> {
+        match *self {
+            Object::Htb(ref h) => Ok(h),
+            _                  => Err(ParseError("Item is not a hashtable".to_string())),
+        }
+    }
+
     /// Returns this data as a buffer if it is a buffer.
     pub fn as_buffer(&self) -> Result<Option<&[u8]>, WeechatError> {
         match *self {
@@ -220,8 +302,8 @@ impl Object {
         try!(self.as_str().map(|s| s.ok_or(ParseError("String is null".to_string()))))
     }
 
-    /// Returns this data as an epoch time if it is a time (encdoed as an i32)
-    pub fn as_time(&self) -> Result<i32, WeechatError> {
+    /// Returns this data as an epoch time if it is a time
+    pub fn as_time(&self) -> Result<i64, WeechatError> {
         match *self {
             Object::Tim(t) => Ok(t),
             _              => Err(ParseError("Item is not a time".to_string()))
@@ -229,6 +311,29 @@ impl Object {
     }
 }
 
+/// Parses the hex digits of a pointer (with or without a leading 0x) into
+/// its value, so pointers can be compared regardless of leading zeros or
+/// case. Returns None if it isn't hex or doesn't fit in a u64.
+pub fn pointer_value(ptr: &str) -> Option<u64> {
+    let digits = if ptr.starts_with("0x") || ptr.starts_with("0X") { &ptr[2..] } else { ptr };
+    if digits.is_empty() {
+        return None;
+    }
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// The identifier (or "(none)") and type on the first line, then the body
+/// (see the Display of HData and StrData). For debugging
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "{} ({})", self.identifier.as_ref().map_or("(none)", |id| id.as_str()), self.type_name()));
+        match self.data_type {
+            Type::StrData(ref strdata) => strdata.fmt(f),
+            Type::HData(ref hdata)     => hdata.fmt(f),
+        }
+    }
+}
+
 /// A simple display for Objects (all of the data types that can be returned
 /// as object in an HDAta). This is primarily used for debugging
 impl fmt::Display for Object {
@@ -272,3 +377,65 @@ impl fmt::Display for Object {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    use super::{Message, Object};
+    use testing::{int, string};
+
+    fn hash_of(object: &Object) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        object.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn text(s: &str) -> Object {
+        Object::Str(Some(s.to_string()))
+    }
+
+    #[test]
+    fn equal_hashtables_hash_the_same_whatever_their_order() {
+        let entries: Vec<(String, String)> = (0..32).map(|i| (format!("key{}", i), format!("value{}", i))).collect();
+        let mut forwards = HashMap::new();
+        for &(ref key, ref value) in &entries {
+            forwards.insert(text(key), text(value));
+        }
+        let mut backwards = HashMap::with_capacity(64);
+        for &(ref key, ref value) in entries.iter().rev() {
+            backwards.insert(text(key), text(value));
+        }
+        let (forwards, backwards) = (Object::Htb(forwards), Object::Htb(backwards));
+        assert_eq!(forwards, backwards);
+        assert_eq!(hash_of(&forwards), hash_of(&backwards));
+
+        // So one finds the other as a key (ex: in a hashtable of hashtables)
+        let mut nested = HashMap::new();
+        nested.insert(forwards, text("found"));
+        assert_eq!(nested.get(&backwards), Some(&text("found")));
+    }
+
+    #[test]
+    fn null_identifier() {
+        // The reply to a command sent without an (id) has a null one
+        let mut bytes = Vec::new();
+        int(&mut bytes, -1);
+        bytes.extend_from_slice(b"str");
+        string(&mut bytes, "hi");
+        assert_eq!(Message::identifier(&bytes).unwrap(), None);
+        let message = Message::new(&bytes).unwrap();
+        assert_eq!(message.identifier, None);
+        assert_eq!(message.as_strdata().unwrap().as_str(), Some("hi"));
+        assert_eq!(message.to_string(), "(none) (str)\n\"hi\"");
+
+        // An empty one is still an identifier
+        let mut bytes = Vec::new();
+        string(&mut bytes, "");
+        bytes.extend_from_slice(b"str");
+        string(&mut bytes, "hi");
+        assert_eq!(Message::new(&bytes).unwrap().identifier, Some(String::new()));
+    }
+}
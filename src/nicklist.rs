@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use errors::WeechatError;
+use errors::WeechatError::ParseError;
+use hdata::HData;
+use message::Object;
+
+/// A nick in a buffer's nicklist, as sent in the reply to a nicklist
+/// command. See:
+/// https://weechat.org/files/doc/devel/weechat_relay_protocol.en.html#command_nicklist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nick {
+    pub name: String,
+    /// Prefix shown before the nick (ex: "@" for an op), or "" (weechat
+    /// sends " " for no prefix)
+    pub prefix: String,
+    /// If the nick is shown in the nicklist
+    pub visible: bool,
+    /// Name of the group the nick is in (ex: "o" for ops), without the
+    /// "000|" weechat puts in front to sort the groups. None if the nick
+    /// came before any group.
+    pub group: Option<String>,
+}
+
+/// Parses the nicks out of a nicklist hdata. Its items are groups and nicks
+/// interleaved: each group comes first, followed by its nicks (and any
+/// groups nested in it, at a deeper level). So a nick is in the last group
+/// before it.
+pub fn parse(hdata: &HData) -> Result<Vec<Nick>, WeechatError> {
+    let mut nicks = Vec::new();
+    let mut group = None;
+    for item in &hdata.data {
+        let is_group = try!(try!(get(item, "group")).as_character()) == (1 as char);
+        let name = try!(try!(get(item, "name")).as_not_null_str());
+        if is_group {
+            group = Some(group_name(name).to_string());
+            continue;
+        }
+
+        let prefix = try!(try!(get(item, "prefix")).as_str()).unwrap_or("");
+        let visible = try!(try!(get(item, "visible")).as_character()) == (1 as char);
+        nicks.push(Nick {
+            name: name.to_string(),
+            prefix: prefix.trim().to_string(),
+            visible: visible,
+            group: group.clone(),
+        });
+    }
+    Ok(nicks)
+}
+
+/// A group's name without its sort prefix (ex: "000|o" is "o")
+fn group_name(name: &str) -> &str {
+    match name.find('|') {
+        Some(i) if name[..i].chars().all(|c| c.is_digit(10)) => &name[i + 1..],
+        _                                                    => name,
+    }
+}
+
+/// Looks up a key in an hdata item, erroring if it isn't there
+fn get<'a>(item: &'a HashMap<String, Object>, key: &str) -> Result<&'a Object, WeechatError> {
+    item.get(key).ok_or(ParseError(format!("Nicklist item is missing '{}'", key)))
+}
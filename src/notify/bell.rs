@@ -0,0 +1,80 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use sys;
+
+// The BEL control character. Terminals turn this into a beep (or a visual
+// flag, depending on the terminal or multiplexer)
+const BELL: &'static [u8] = b"\x07";
+
+// Our controlling terminal, which is still there when stdout is redirected
+// if we were started from a terminal (but not under most supervisors)
+#[cfg(unix)]
+const CONTROLLING_TTY: &'static str = "/dev/tty";
+
+/// Rings the terminal bell for notifications
+pub struct Bell {
+    /// The tty to ring, or None to ring through stdout
+    tty: Option<File>,
+    /// False if stdout isn't a tty, bell_only_tty is set and there is no
+    /// controlling terminal to ring instead, in which case the bell is
+    /// dropped
+    enabled: bool,
+}
+
+impl Bell {
+    /// Creates the backend, opening the configured tty if there is one
+    pub fn new(settings: &Settings) -> Result<Bell, String> {
+        match settings.bell_path {
+            Some(ref path) => {
+                let tty = try!(OpenOptions::new().write(true).open(path)
+                    .map_err(|e| format!("Can't open bell tty {}: {}", path.display(), e)));
+                Ok(Bell { tty: Some(tty), enabled: true })
+            },
+            None           => {
+                if sys::stdout_is_tty() || !settings.bell_only_tty {
+                    return Ok(Bell { tty: None, enabled: true });
+                }
+                match controlling_tty() {
+                    Some(tty) => Ok(Bell { tty: Some(tty), enabled: true }),
+                    None      => Ok(Bell { tty: None, enabled: false }),
+                }
+            },
+        }
+    }
+}
+
+/// Opens our controlling terminal, or returns None if we don't have one
+#[cfg(unix)]
+fn controlling_tty() -> Option<File> {
+    OpenOptions::new().write(true).open(CONTROLLING_TTY).ok()
+}
+
+#[cfg(windows)]
+fn controlling_tty() -> Option<File> {
+    None
+}
+
+impl Notifier for Bell {
+    fn notify(&self, _event: &NotificationEvent) -> Result<(), NotifyError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        // The bell is written as a single byte in a single write (with stdout
+        // locked), so it can never land in the middle of other output
+        let result = match self.tty {
+            Some(ref tty) => {
+                let mut tty: &File = tty;
+                tty.write_all(BELL)
+            },
+            None          => {
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                stdout.write_all(BELL).and_then(|_| stdout.flush())
+            },
+        };
+        result.map_err(|e| NotifyError::new(format!("could not ring the bell: {}", e)))
+    }
+}
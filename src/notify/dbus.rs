@@ -0,0 +1,906 @@
+use std::cmp;
+use std::env;
+use std::io::prelude::*;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::unix::io::FromRawFd;
+use std::os::unix::net::UnixStream;
+use std::str;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use libc;
+
+use notify::{EventClass, NotificationEvent, Notifier, NotifyError};
+use notify::queue::{Queue, QUEUE_SIZE};
+
+/// Name we take on the bus, and the interface of our signal and methods
+pub const INTERFACE: &'static str = "org.weechat.Alert";
+
+/// Path of the object we export
+pub const PATH: &'static str = "/org/weechat/Alert";
+
+// Seconds to wait for the bus while connecting, and for each write
+const TIMEOUT: u64 = 5;
+
+// Milliseconds to wait for a notification before checking the bus for
+// method calls, and how long each check waits for data
+const POLL_INTERVAL: u64 = 500;
+const POLL_READ_TIMEOUT: u64 = 50;
+
+// Seconds to wait before trying to connect to the bus again after it went
+// away (or was never there)
+const RECONNECT_DELAY: u64 = 60;
+
+// Biggest message we accept from the bus. Calls to us have a few small
+// arguments at most, so anything this size is a bus gone wrong
+const MAX_MESSAGE_LENGTH: usize = 1024 * 1024;
+
+/// Message types
+pub const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+pub const SIGNAL: u8 = 4;
+
+// Message flag, set on calls that don't want a reply
+const NO_REPLY_EXPECTED: u8 = 0x1;
+
+// Header fields
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+// Flag for RequestName, so we fail instead of waiting in line for the name
+const DO_NOT_QUEUE: u32 = 0x4;
+
+/// Interfaces and errors every object deals with
+pub const INTROSPECTABLE: &'static str = "org.freedesktop.DBus.Introspectable";
+pub const PROPERTIES: &'static str = "org.freedesktop.DBus.Properties";
+pub const UNKNOWN_METHOD: &'static str = "org.freedesktop.DBus.Error.UnknownMethod";
+pub const INVALID_ARGS: &'static str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+const INTROSPECTION: &'static str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.weechat.Alert">
+    <signal name="Highlight">
+      <arg name="buffer" type="s"/>
+      <arg name="nick" type="s"/>
+      <arg name="message" type="s"/>
+      <arg name="class" type="s"/>
+    </signal>
+    <method name="GetStats">
+      <arg name="stats" type="a{su}" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="data" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+/// Emits an org.weechat.Alert.Highlight signal on the session bus for every
+/// notification, for panel widgets and scripts to react to. The object it
+/// exports also answers GetStats, with how many signals of each class have
+/// been sent.
+///
+/// A missing session bus is not an error, signals are just dropped until one
+/// shows up.
+pub struct Dbus {
+    /// Queue of signals waiting to be emitted
+    queue: Queue<Highlight>,
+}
+
+/// The arguments of a Highlight signal
+struct Highlight {
+    buffer: String,
+    nick: String,
+    message: String,
+    class: EventClass,
+}
+
+impl Dbus {
+    /// Creates the backend, connecting to the session bus and starting the
+    /// thread that emits signals and answers method calls
+    pub fn new() -> Dbus {
+        let bus = match connect() {
+            Ok(bus) => Some(bus),
+            Err(e)  => {
+                warn!("could not connect to the session bus ({}), dbus signals will be dropped until it is there",
+                      e);
+                None
+            },
+        };
+        Dbus { queue: Queue::start("dbus", QUEUE_SIZE, move |pending| run(bus, pending)) }
+    }
+}
+
+impl Notifier for Dbus {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.queue.send(Highlight {
+            buffer: event.buffer.clone().unwrap_or("weechat".to_string()),
+            nick: event.nick.clone().unwrap_or(String::new()),
+            message: event.message.clone(),
+            class: event.class,
+        })
+    }
+}
+
+/// How many signals of each class have been emitted, for GetStats
+struct Stats {
+    private: u32,
+    highlight: u32,
+    watch: u32,
+}
+
+impl Stats {
+    fn record(&mut self, class: EventClass) {
+        match class {
+            EventClass::Private   => self.private += 1,
+            EventClass::Highlight => self.highlight += 1,
+            EventClass::Watch     => self.watch += 1,
+        }
+    }
+}
+
+/// Emits every queued signal, answering method calls between them. If the
+/// bus goes away we try to get it back every RECONNECT_DELAY seconds, and
+/// signals are dropped until then.
+fn run(mut bus: Option<Bus>, pending: Receiver<Highlight>) {
+    let mut stats = Stats { private: 0, highlight: 0, watch: 0 };
+    let mut last_attempt = Instant::now();
+    loop {
+        let highlight = match pending.recv_timeout(Duration::from_millis(POLL_INTERVAL)) {
+            Ok(highlight)                       => Some(highlight),
+            Err(RecvTimeoutError::Timeout)      => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        if bus.is_none() && last_attempt.elapsed() >= Duration::from_secs(RECONNECT_DELAY) {
+            last_attempt = Instant::now();
+            match connect() {
+                Ok(b)  => {
+                    info!("Connected to the session bus again");
+                    bus = Some(b);
+                },
+                Err(_) => (),
+            }
+        }
+
+        let result = match bus {
+            Some(ref mut bus) => {
+                let emitted = match highlight {
+                    Some(ref highlight) => bus.emit(highlight).map(|_| stats.record(highlight.class)),
+                    None                => Ok(()),
+                };
+                emitted.and_then(|_| bus.poll(Duration::from_millis(POLL_READ_TIMEOUT)))
+                       .and_then(|calls| {
+                           for call in calls.iter().filter(|m| m.kind == METHOD_CALL) {
+                               try!(answer(bus, call, &stats));
+                           }
+                           Ok(())
+                       })
+            },
+            None              => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("lost the session bus ({}), dbus signals will be dropped until it is back", e);
+            bus = None;
+            last_attempt = Instant::now();
+        }
+    }
+}
+
+/// Connects to the session bus and asks for our name
+fn connect() -> Result<Bus, String> {
+    let mut bus = try!(Bus::open());
+    // Another copy of us may have the name already. Our signals still go
+    // out, and GetStats still works through our unique name
+    try!(bus.request_name(INTERFACE));
+    Ok(bus)
+}
+
+/// A connection to the session bus
+pub struct Bus {
+    stream: UnixStream,
+    /// Serial of the last message we sent
+    serial: u32,
+    /// Data read from the bus that isn't a whole message yet
+    buf: Vec<u8>,
+    /// Calls and signals read while waiting for a reply, for the next poll
+    unread: Vec<Header>,
+}
+
+impl Bus {
+    /// Connects and authenticates to the session bus, then says hello
+    pub fn open() -> Result<Bus, String> {
+        let address = try!(session_address());
+        let mut stream = try!(open(&address).map_err(|e| format!("{}: {}", address, e)));
+        let timeout = Some(Duration::from_secs(TIMEOUT));
+        try!(stream.set_read_timeout(timeout).map_err(|e| e.to_string()));
+        try!(stream.set_write_timeout(timeout).map_err(|e| e.to_string()));
+        try!(authenticate(&mut stream));
+
+        let mut bus = Bus {
+            stream: stream,
+            serial: 0,
+            buf: Vec::new(),
+            unread: Vec::new(),
+        };
+        let hello = try!(bus.call("Hello", &Writer::new(), ""));
+        try!(bus.wait_for_reply(hello));
+        Ok(bus)
+    }
+
+    /// Asks for a well known name, without waiting in line for it if
+    /// someone else has it. The reply isn't waited for, as we work the
+    /// same through our unique name.
+    pub fn request_name(&mut self, name: &str) -> Result<(), String> {
+        let mut args = Writer::new();
+        args.string(name);
+        args.u32(DO_NOT_QUEUE);
+        self.call("RequestName", &args, "su").map(|_| ())
+    }
+
+    /// Asks the bus to send us the signals a match rule matches (ex:
+    /// "type='signal',member='NameOwnerChanged'"), without waiting for the
+    /// reply
+    pub fn add_match(&mut self, rule: &str) -> Result<(), String> {
+        let mut args = Writer::new();
+        args.string(rule);
+        self.call("AddMatch", &args, "s").map(|_| ())
+    }
+
+    /// Calls a method on the bus itself, returning the serial of the call
+    pub fn call(&mut self, member: &str, args: &Writer, signature: &str) -> Result<u32, String> {
+        self.call_method("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", member,
+                         args, signature)
+    }
+
+    /// Calls a method on another connection's object, returning the serial
+    /// of the call
+    pub fn call_method(&mut self, destination: &str, path: &str, interface: &str, member: &str,
+                       args: &Writer, signature: &str) -> Result<u32, String> {
+        let mut fields = Fields::new();
+        fields.object_path(FIELD_PATH, path);
+        fields.string(FIELD_INTERFACE, interface);
+        fields.string(FIELD_MEMBER, member);
+        fields.string(FIELD_DESTINATION, destination);
+        self.send(METHOD_CALL, fields, args, signature)
+    }
+
+    /// Emits a signal from one of our objects
+    pub fn signal(&mut self, path: &str, interface: &str, member: &str, args: &Writer,
+                  signature: &str) -> Result<(), String> {
+        let mut fields = Fields::new();
+        fields.object_path(FIELD_PATH, path);
+        fields.string(FIELD_INTERFACE, interface);
+        fields.string(FIELD_MEMBER, member);
+        self.send(SIGNAL, fields, args, signature).map(|_| ())
+    }
+
+    /// Emits a Highlight signal
+    fn emit(&mut self, highlight: &Highlight) -> Result<(), String> {
+        let mut args = Writer::new();
+        args.string(&highlight.buffer);
+        args.string(&highlight.nick);
+        args.string(&highlight.message);
+        args.string(highlight.class.name());
+        self.signal(PATH, INTERFACE, "Highlight", &args, "ssss")
+    }
+
+    /// Replies to a method call, unless the caller said not to
+    pub fn reply(&mut self, call: &Header, body: &Writer, signature: &str) -> Result<(), String> {
+        self.answer_with(call, METHOD_RETURN, None, body, signature)
+    }
+
+    /// Replies to a method call with an error, unless the caller said not
+    /// to
+    pub fn reply_error(&mut self, call: &Header, name: &str, message: &str) -> Result<(), String> {
+        let mut body = Writer::new();
+        body.string(message);
+        self.answer_with(call, ERROR, Some(name), &body, "s")
+    }
+
+    fn answer_with(&mut self, call: &Header, kind: u8, error: Option<&str>, body: &Writer,
+                   signature: &str) -> Result<(), String> {
+        if call.flags & NO_REPLY_EXPECTED != 0 {
+            return Ok(());
+        }
+        let mut fields = Fields::new();
+        fields.u32(FIELD_REPLY_SERIAL, call.serial);
+        if let Some(ref sender) = call.sender {
+            fields.string(FIELD_DESTINATION, sender);
+        }
+        if let Some(error) = error {
+            fields.string(FIELD_ERROR_NAME, error);
+        }
+        self.send(kind, fields, body, signature).map(|_| ())
+    }
+
+    /// Sends a message, returning its serial
+    fn send(&mut self, kind: u8, mut fields: Fields, body: &Writer, signature: &str) -> Result<u32, String> {
+        self.serial += 1;
+        if !signature.is_empty() {
+            fields.signature(FIELD_SIGNATURE, signature);
+        }
+
+        let mut message = Writer::new();
+        message.u8(b'l');
+        message.u8(kind);
+        message.u8(0);
+        message.u8(1);
+        message.u32(body.buf.len() as u32);
+        message.u32(self.serial);
+        message.u32(fields.0.buf.len() as u32);
+        message.buf.extend_from_slice(&fields.0.buf);
+        message.align(8);
+        message.buf.extend_from_slice(&body.buf);
+        try!(self.stream.write_all(&message.buf).map_err(|e| e.to_string()));
+        Ok(self.serial)
+    }
+
+    /// Reads until the reply to a call, failing if it is an error. Calls
+    /// and signals that come in first are kept for the next poll.
+    pub fn wait_for_reply(&mut self, serial: u32) -> Result<Header, String> {
+        loop {
+            let message = try!(self.read_message());
+            if message.reply_serial == Some(serial) {
+                return match message.kind {
+                    ERROR => Err(message.error_name.unwrap_or("unknown error".to_string())),
+                    _     => Ok(message),
+                };
+            }
+            if message.kind == METHOD_CALL || message.kind == SIGNAL {
+                self.unread.push(message);
+            }
+        }
+    }
+
+    /// Returns the method calls and signals that have come in, waiting up
+    /// to timeout for the first of them
+    pub fn poll(&mut self, timeout: Duration) -> Result<Vec<Header>, String> {
+        let mut messages = mem::replace(&mut self.unread, Vec::new());
+        try!(self.stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string()));
+        let mut chunk = [0; 4096];
+        let read = self.stream.read(&mut chunk);
+        try!(self.stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT)))
+             .map_err(|e| e.to_string()));
+        match read {
+            Ok(0)                                    => return Err("the bus closed the connection".to_string()),
+            Ok(n)                                    => self.buf.extend_from_slice(&chunk[..n]),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock ||
+                          e.kind() == ErrorKind::TimedOut => (),
+            Err(e)                                   => return Err(e.to_string()),
+        }
+
+        while let Some(message) = try!(self.take_message()) {
+            if message.kind == METHOD_CALL || message.kind == SIGNAL {
+                messages.push(message);
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Reads (waiting up to TIMEOUT) until there is a whole message
+    fn read_message(&mut self) -> Result<Header, String> {
+        loop {
+            if let Some(message) = try!(self.take_message()) {
+                return Ok(message);
+            }
+            let mut chunk = [0; 4096];
+            match self.stream.read(&mut chunk) {
+                Ok(0)  => return Err("the bus closed the connection".to_string()),
+                Ok(n)  => self.buf.extend_from_slice(&chunk[..n]),
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+    }
+
+    /// Takes the first message off of buf, if all of it has been read
+    fn take_message(&mut self) -> Result<Option<Header>, String> {
+        if self.buf.len() < 16 {
+            return Ok(None);
+        }
+        let endian = match self.buf[0] {
+            b'l' => Endian::Little,
+            b'B' => Endian::Big,
+            _    => return Err("the bus sent a message we can't read".to_string()),
+        };
+        let body_length = endian.u32(&self.buf[4..8]) as usize;
+        let fields_length = endian.u32(&self.buf[12..16]) as usize;
+        if body_length > MAX_MESSAGE_LENGTH || fields_length > MAX_MESSAGE_LENGTH {
+            return Err("the bus sent a message that is too big".to_string());
+        }
+        let fields_end = 16 + fields_length;
+        let length = align(fields_end, 8) + body_length;
+        if self.buf.len() < length {
+            return Ok(None);
+        }
+
+        let mut header = try!(parse_header(&self.buf[..fields_end], endian));
+        header.body = self.buf[align(fields_end, 8)..length].to_vec();
+        self.buf.drain(..length);
+        Ok(Some(header))
+    }
+}
+
+/// Replies to a method call on our object (or says it doesn't exist)
+fn answer(bus: &mut Bus, call: &Header, stats: &Stats) -> Result<(), String> {
+    let mut body = Writer::new();
+    match (call.path(), call.interface(), call.member()) {
+        (PATH, INTERFACE, "GetStats") | (PATH, "", "GetStats") => {
+            body.stats(stats);
+            bus.reply(call, &body, "a{su}")
+        },
+        (PATH, INTROSPECTABLE, "Introspect") | (PATH, "", "Introspect") => {
+            body.string(INTROSPECTION);
+            bus.reply(call, &body, "s")
+        },
+        _ => bus.reply_error(call, UNKNOWN_METHOD, "No such method"),
+    }
+}
+
+/// The address of the session bus. Only unix sockets are supported.
+fn session_address() -> Result<String, String> {
+    match env::var("DBUS_SESSION_BUS_ADDRESS") {
+        Ok(addresses) => {
+            addresses.split(';')
+                     .find(|a| a.starts_with("unix:path=") || a.starts_with("unix:abstract="))
+                     .map(|a| a.to_string())
+                     .ok_or(format!("no unix socket in DBUS_SESSION_BUS_ADDRESS ({})", addresses))
+        },
+        // The bus is usually here under systemd, even if the variable isn't
+        // set (ex: we were started from cron or ssh)
+        Err(_)        => match env::var("XDG_RUNTIME_DIR") {
+            Ok(dir) => Ok(format!("unix:path={}/bus", dir)),
+            Err(_)  => Err("DBUS_SESSION_BUS_ADDRESS is not set".to_string()),
+        },
+    }
+}
+
+/// Opens the socket of a bus address, such as "unix:path=/run/user/1000/bus"
+/// or "unix:abstract=/tmp/dbus-XXXXXX,guid=..."
+fn open(address: &str) -> io::Result<UnixStream> {
+    let rest = &address["unix:".len()..];
+    let (kind, value) = match rest.find('=') {
+        Some(i) => (&rest[..i], &rest[i + 1..]),
+        None    => return Err(io::Error::new(ErrorKind::InvalidInput, "bad bus address")),
+    };
+    let path = unescape(value.split(',').next().unwrap_or(""));
+    match kind {
+        "path" => UnixStream::connect(path),
+        _      => connect_abstract(&path),
+    }
+}
+
+/// Connects to a socket in the abstract namespace, which std can't do
+fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    unsafe {
+        let mut addr: libc::sockaddr_un = mem::zeroed();
+        addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        // The first byte of the path stays 0, which makes it abstract
+        if name.len() + 1 > addr.sun_path.len() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "abstract socket name is too long"));
+        }
+        for (i, b) in name.bytes().enumerate() {
+            addr.sun_path[i + 1] = b as libc::c_char;
+        }
+        let length = mem::size_of::<libc::sa_family_t>() + 1 + name.len();
+
+        let fd = libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let stream = UnixStream::from_raw_fd(fd);
+        if libc::connect(fd, &addr as *const _ as *const libc::sockaddr, length as libc::socklen_t) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(stream)
+    }
+}
+
+/// Undoes the %xx escapes in a bus address value
+fn unescape(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut unescaped = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok())
+        } else {
+            None
+        };
+        match hex {
+            Some(b) => {
+                unescaped.push(b);
+                i += 3;
+            },
+            None    => {
+                unescaped.push(bytes[i]);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&unescaped).into_owned()
+}
+
+/// Logs in with the EXTERNAL mechanism, where the bus checks our uid on the
+/// socket itself
+fn authenticate(stream: &mut UnixStream) -> Result<(), String> {
+    let uid = unsafe { libc::getuid() }.to_string();
+    let hex_uid: String = uid.bytes().map(|b| format!("{:02x}", b)).collect();
+    try!(stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", hex_uid).as_bytes()).map_err(|e| e.to_string()));
+
+    // Read the reply a byte at a time, so we don't read into the first
+    // message after it
+    let mut reply = Vec::new();
+    let mut byte = [0; 1];
+    while !reply.ends_with(b"\r\n") {
+        try!(stream.read_exact(&mut byte).map_err(|e| e.to_string()));
+        reply.push(byte[0]);
+        if reply.len() > 512 {
+            return Err("the bus sent a bad reply to AUTH".to_string());
+        }
+    }
+    let reply = String::from_utf8_lossy(&reply).into_owned();
+    if !reply.starts_with("OK ") {
+        return Err(format!("the bus rejected us ({})", reply.trim()));
+    }
+    stream.write_all(b"BEGIN\r\n").map_err(|e| e.to_string())
+}
+
+/// The header fields of a message we read that we care about, and its body
+pub struct Header {
+    pub kind: u8,
+    flags: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    sender: Option<String>,
+    endian: Endian,
+    body: Vec<u8>,
+}
+
+impl Header {
+    /// The object path, or "" if there isn't one
+    pub fn path(&self) -> &str {
+        self.path.as_ref().map_or("", |s| s.as_str())
+    }
+
+    /// The interface, or "" if there isn't one (calls can leave it out)
+    pub fn interface(&self) -> &str {
+        self.interface.as_ref().map_or("", |s| s.as_str())
+    }
+
+    /// The method or signal name, or "" if there isn't one
+    pub fn member(&self) -> &str {
+        self.member.as_ref().map_or("", |s| s.as_str())
+    }
+
+    /// A reader for the arguments in the body
+    pub fn args<'a>(&'a self) -> Reader<'a> {
+        Reader {
+            bytes: &self.body,
+            pos: 0,
+            endian: self.endian,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Endian {
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn u32(&self, bytes: &[u8]) -> u32 {
+        let (a, b, c, d) = (bytes[0] as u32, bytes[1] as u32, bytes[2] as u32, bytes[3] as u32);
+        match *self {
+            Endian::Little => d << 24 | c << 16 | b << 8 | a,
+            Endian::Big    => a << 24 | b << 16 | c << 8 | d,
+        }
+    }
+}
+
+/// Parses the fixed part and the header fields of a message
+fn parse_header(bytes: &[u8], endian: Endian) -> Result<Header, String> {
+    let mut header = Header {
+        kind: bytes[1],
+        flags: bytes[2],
+        serial: endian.u32(&bytes[8..12]),
+        path: None,
+        interface: None,
+        member: None,
+        error_name: None,
+        reply_serial: None,
+        sender: None,
+        endian: endian,
+        body: Vec::new(),
+    };
+
+    let bad = || "the bus sent a message with bad header fields".to_string();
+    let mut pos = 16;
+    while pos < bytes.len() {
+        pos = align(pos, 8);
+        if pos + 3 > bytes.len() {
+            return Err(bad());
+        }
+        let code = bytes[pos];
+        let signature_length = bytes[pos + 1] as usize;
+        let signature = &bytes[pos + 2..cmp::min(pos + 2 + signature_length, bytes.len())];
+        pos += 2 + signature_length + 1;
+
+        match signature {
+            b"s" | b"o" => {
+                pos = align(pos, 4);
+                if pos + 4 > bytes.len() {
+                    return Err(bad());
+                }
+                let length = endian.u32(&bytes[pos..pos + 4]) as usize;
+                pos += 4;
+                if pos + length > bytes.len() {
+                    return Err(bad());
+                }
+                let value = Some(String::from_utf8_lossy(&bytes[pos..pos + length]).into_owned());
+                pos += length + 1;
+                match code {
+                    FIELD_PATH       => header.path = value,
+                    FIELD_INTERFACE  => header.interface = value,
+                    FIELD_MEMBER     => header.member = value,
+                    FIELD_ERROR_NAME => header.error_name = value,
+                    FIELD_SENDER     => header.sender = value,
+                    _                => (),
+                }
+            },
+            b"u" => {
+                pos = align(pos, 4);
+                if pos + 4 > bytes.len() {
+                    return Err(bad());
+                }
+                if code == FIELD_REPLY_SERIAL {
+                    header.reply_serial = Some(endian.u32(&bytes[pos..pos + 4]));
+                }
+                pos += 4;
+            },
+            b"g" => {
+                if pos >= bytes.len() {
+                    return Err(bad());
+                }
+                pos += bytes[pos] as usize + 2;
+            },
+            // No field we know of has another type, and we can't tell how
+            // long this one is, so the rest are skipped
+            _    => break,
+        }
+    }
+    Ok(header)
+}
+
+/// Rounds pos up to a multiple of n
+fn align(pos: usize, n: usize) -> usize {
+    (pos + n - 1) / n * n
+}
+
+/// Marshals values in little endian. Offsets are aligned from the start of
+/// the buffer, which is fine as long as the buffer starts on an 8 byte
+/// boundary of the message (as the body and header fields array do)
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+/// Where an array started, for Writer::end_array to fill in its length
+pub struct ArrayStart {
+    length_at: usize,
+    start: usize,
+}
+
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { buf: Vec::new() }
+    }
+
+    /// Pads up to a multiple of n. Structs and dict entries start on a
+    /// multiple of 8.
+    pub fn align(&mut self, n: usize) {
+        let len = align(self.buf.len(), n);
+        self.buf.resize(len, 0);
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.u32(value as u32);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u32(value as u32);
+    }
+
+    /// Writes a string (or object path). Strings can't have a nul in them,
+    /// so any are left out
+    pub fn string(&mut self, value: &str) {
+        let value = value.replace('\0', "");
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// Writes an ay
+    pub fn byte_array(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.buf.extend_from_slice(value);
+    }
+
+    /// Writes a signature, which is also how a variant starts (followed by
+    /// its value)
+    pub fn signature(&mut self, value: &str) {
+        self.buf.push(value.len() as u8);
+        self.buf.extend_from_slice(value.as_bytes());
+        self.buf.push(0);
+    }
+
+    /// Starts an array of elements aligned to element_alignment. Write the
+    /// elements (aligning each one), then call end_array.
+    pub fn begin_array(&mut self, element_alignment: usize) -> ArrayStart {
+        self.u32(0);
+        let length_at = self.buf.len() - 4;
+        // The padding after the length isn't part of the array, even if
+        // there are no elements
+        self.align(element_alignment);
+        ArrayStart {
+            length_at: length_at,
+            start: self.buf.len(),
+        }
+    }
+
+    pub fn end_array(&mut self, array: ArrayStart) {
+        let length = (self.buf.len() - array.start) as u32;
+        self.buf[array.length_at..array.length_at + 4]
+            .copy_from_slice(&[length as u8, (length >> 8) as u8, (length >> 16) as u8, (length >> 24) as u8]);
+    }
+
+    /// Writes the a{su} GetStats returns
+    fn stats(&mut self, stats: &Stats) {
+        let entries = [("private", stats.private), ("highlight", stats.highlight), ("watch", stats.watch),
+                       ("total", stats.private + stats.highlight + stats.watch)];
+        let array = self.begin_array(8);
+        for &(name, count) in &entries {
+            self.align(8);
+            self.string(name);
+            self.u32(count);
+        }
+        self.end_array(array);
+    }
+}
+
+/// Reads the arguments of a message we got, in order
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    endian: Endian,
+}
+
+impl<'a> Reader<'a> {
+    fn bad() -> String {
+        "bad arguments".to_string()
+    }
+
+    /// Skips the padding up to a multiple of n
+    pub fn align(&mut self, n: usize) {
+        self.pos = align(self.pos, n);
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], String> {
+        if self.pos + length > self.bytes.len() {
+            return Err(Reader::bad());
+        }
+        let bytes = &self.bytes[self.pos..self.pos + length];
+        self.pos += length;
+        Ok(bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, String> {
+        self.align(4);
+        let bytes = try!(self.take(4));
+        Ok(self.endian.u32(bytes))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, String> {
+        self.u32().map(|value| value as i32)
+    }
+
+    /// Reads a string (or object path)
+    pub fn string(&mut self) -> Result<String, String> {
+        let length = try!(self.u32()) as usize;
+        let bytes = try!(self.take(length + 1));
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+
+    pub fn signature(&mut self) -> Result<String, String> {
+        let length = try!(self.take(1))[0] as usize;
+        let bytes = try!(self.take(length + 1));
+        Ok(String::from_utf8_lossy(&bytes[..length]).into_owned())
+    }
+
+    /// Reads the length of an array, and skips to its first element.
+    /// Returns where the array ends.
+    pub fn begin_array(&mut self, element_alignment: usize) -> Result<usize, String> {
+        let length = try!(self.u32()) as usize;
+        self.align(element_alignment);
+        if self.pos + length > self.bytes.len() {
+            return Err(Reader::bad());
+        }
+        Ok(self.pos + length)
+    }
+
+    /// If an array that ends at end has more elements
+    pub fn in_array(&self, end: usize) -> bool {
+        self.pos < end
+    }
+
+    /// Skips a variant holding a basic type. Any other variant is an error,
+    /// as nothing that calls us sends one.
+    pub fn skip_variant(&mut self) -> Result<(), String> {
+        match try!(self.signature()).as_str() {
+            "y"             => self.take(1).map(|_| ()),
+            "b" | "i" | "u" => self.u32().map(|_| ()),
+            "s" | "o"       => self.string().map(|_| ()),
+            "g"             => self.signature().map(|_| ()),
+            _               => Err(Reader::bad()),
+        }
+    }
+}
+
+/// The header fields array of a message we send
+struct Fields(Writer);
+
+impl Fields {
+    fn new() -> Fields {
+        Fields(Writer::new())
+    }
+
+    fn field(&mut self, code: u8, signature: &str) {
+        self.0.align(8);
+        self.0.u8(code);
+        self.0.signature(signature);
+    }
+
+    fn string(&mut self, code: u8, value: &str) {
+        self.field(code, "s");
+        self.0.string(value);
+    }
+
+    fn object_path(&mut self, code: u8, value: &str) {
+        self.field(code, "o");
+        self.0.string(value);
+    }
+
+    fn signature(&mut self, code: u8, value: &str) {
+        self.field(code, "g");
+        self.0.signature(value);
+    }
+
+    fn u32(&mut self, code: u8, value: u32) {
+        self.field(code, "u");
+        self.0.u32(value);
+    }
+}
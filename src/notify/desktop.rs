@@ -0,0 +1,368 @@
+#[cfg(feature = "dbus")]
+use std::collections::VecDeque;
+use std::process::Command;
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "dbus")]
+use std::sync::mpsc::RecvTimeoutError;
+#[cfg(feature = "dbus")]
+use std::time::{Duration, Instant};
+
+use notify::{self, Classes, NotificationEvent, Notifier, NotifyError, Settings, Urgency};
+#[cfg(feature = "dbus")]
+use notify::dbus::{Bus, Header, Writer, SIGNAL};
+use notify::priority::Scale;
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+use reply::Replies;
+
+// Format of the notification body (the buffer is the title)
+const BODY_FORMAT: &'static str = "<{nick}> {message}";
+
+// The notification daemon's name, object and interface
+#[cfg(feature = "dbus")]
+const NOTIFICATIONS: &'static str = "org.freedesktop.Notifications";
+#[cfg(feature = "dbus")]
+const NOTIFICATIONS_PATH: &'static str = "/org/freedesktop/Notifications";
+
+// Milliseconds to wait for a notification before checking the bus for
+// replies, and how long each check waits for data
+#[cfg(feature = "dbus")]
+const POLL_INTERVAL: u64 = 250;
+#[cfg(feature = "dbus")]
+const POLL_READ_TIMEOUT: u64 = 50;
+
+// Seconds to wait before trying to reach the notification daemon again
+#[cfg(feature = "dbus")]
+const RECONNECT_DELAY: u64 = 60;
+
+// Most notifications we remember the buffers of, for replies. Daemons say
+// when a notification closes, but one that doesn't can't grow this forever
+#[cfg(feature = "dbus")]
+const MAX_SHOWN: usize = 64;
+
+/// The desktop backend: over dbus with inline replies if desktop_reply is
+/// set (in a build with dbus), otherwise through notify-send
+pub fn build(settings: &Settings, replies: Option<&Replies>) -> Box<Notifier> {
+    match (settings.desktop_reply, replies) {
+        #[cfg(feature = "dbus")]
+        (true, Some(replies)) => Box::new(Replying::new(settings, replies.clone())),
+        #[cfg(feature = "dbus")]
+        (true, None)          => {
+            warn!("nothing takes replies, so desktop_reply is ignored");
+            Box::new(Desktop::new(settings))
+        },
+        #[cfg(not(feature = "dbus"))]
+        (true, _)             => {
+            warn!("built without dbus (see the dbus feature), so desktop_reply does nothing");
+            Box::new(Desktop::new(settings))
+        },
+        (false, _)            => Box::new(Desktop::new(settings)),
+    }
+}
+
+/// Shows notifications in the desktop's tray area, through notify-send
+pub struct Desktop {
+    /// Per class settings (for the icon)
+    classes: Classes,
+    /// Urgency for each priority
+    urgency: Scale<Urgency>,
+    body: Template,
+    /// Queue of notify-send commands waiting to run
+    queue: Queue<Command>,
+}
+
+impl Desktop {
+    pub fn new(settings: &Settings) -> Desktop {
+        Desktop {
+            classes: settings.classes.clone(),
+            urgency: settings.desktop_urgency,
+            body: body_template(settings),
+            queue: Queue::start("desktop", QUEUE_SIZE, run_all),
+        }
+    }
+}
+
+impl Notifier for Desktop {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let popup = Popup::new(event, &self.classes, &self.urgency, &self.body);
+        self.queue.send(popup.command())
+    }
+
+    fn startup_check(&self) -> Result<(), NotifyError> {
+        notify::find_program("notify-send")
+    }
+}
+
+/// Bodies are limited to the same length as the main format
+fn body_template(settings: &Settings) -> Template {
+    let mut body = Template::parse(BODY_FORMAT).unwrap();
+    body.set_max_length(settings.format.max_length());
+    body
+}
+
+/// A notification waiting to be shown
+struct Popup {
+    title: String,
+    body: String,
+    urgency: Urgency,
+    icon: Option<String>,
+    /// Full name of the buffer replies go to, if there is one
+    buffer: Option<String>,
+}
+
+impl Popup {
+    fn new(event: &NotificationEvent, classes: &Classes, urgency: &Scale<Urgency>, body: &Template) -> Popup {
+        Popup {
+            title: event.buffer.clone().unwrap_or("weechat".to_string()),
+            body: body.render(event),
+            urgency: urgency.get(event.priority),
+            icon: classes.get(event.class).icon.clone(),
+            buffer: event.buffer.clone(),
+        }
+    }
+
+    /// The notify-send command showing this
+    fn command(&self) -> Command {
+        let mut cmd = Command::new("notify-send");
+        cmd.arg("--app-name=weechat-alert")
+           .arg(format!("--urgency={}", self.urgency.name()));
+        if let Some(ref icon) = self.icon {
+            cmd.arg(format!("--icon={}", icon));
+        }
+        cmd.arg("--")
+           .arg(&self.title)
+           .arg(&self.body);
+        cmd
+    }
+}
+
+/// Runs every queued notify-send in order, so a slow notification daemon
+/// can't hold up processing messages
+fn run_all(pending: Receiver<Command>) {
+    for mut cmd in pending {
+        run(&mut cmd);
+    }
+}
+
+fn run(cmd: &mut Command) {
+    match cmd.status() {
+        Ok(ref status) if status.success() => (),
+        Ok(status) => error!("notify-send failed ({})", status),
+        Err(e)     => error!("could not run notify-send: {}", e),
+    }
+}
+
+/// Shows notifications by talking to the notification daemon over dbus,
+/// rather than through notify-send, so they can take inline replies where
+/// the daemon supports them (KDE's does, for one). A reply is typed into
+/// the buffer the notification is from.
+///
+/// While the daemon can't be reached, notifications go through notify-send
+/// instead (without replies).
+#[cfg(feature = "dbus")]
+pub struct Replying {
+    /// Per class settings (for the icon)
+    classes: Classes,
+    /// Urgency for each priority
+    urgency: Scale<Urgency>,
+    body: Template,
+    /// Queue of notifications waiting to be shown
+    queue: Queue<Popup>,
+}
+
+#[cfg(feature = "dbus")]
+impl Replying {
+    /// Creates the backend, starting the thread that shows notifications and
+    /// sends their replies
+    pub fn new(settings: &Settings, replies: Replies) -> Replying {
+        Replying {
+            classes: settings.classes.clone(),
+            urgency: settings.desktop_urgency,
+            body: body_template(settings),
+            queue: Queue::start("desktop", QUEUE_SIZE, move |pending| run_replying(pending, replies)),
+        }
+    }
+}
+
+#[cfg(feature = "dbus")]
+impl Notifier for Replying {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.queue.send(Popup::new(event, &self.classes, &self.urgency, &self.body))
+    }
+}
+
+/// A connection to the notification daemon
+#[cfg(feature = "dbus")]
+struct Daemon {
+    bus: Bus,
+    /// If the daemon takes inline replies
+    inline_reply: bool,
+    /// Ids of the notifications showing that take replies, with their
+    /// buffers, oldest first
+    shown: VecDeque<(u32, String)>,
+}
+
+#[cfg(feature = "dbus")]
+impl Daemon {
+    /// Connects to the session bus, asking for the daemon's signals and
+    /// whether it takes inline replies
+    fn connect() -> Result<Daemon, String> {
+        let mut bus = try!(Bus::open());
+        try!(bus.add_match(&format!("type='signal',interface='{}',path='{}'", NOTIFICATIONS,
+                                    NOTIFICATIONS_PATH)));
+        let serial = try!(bus.call_method(NOTIFICATIONS, NOTIFICATIONS_PATH, NOTIFICATIONS, "GetCapabilities",
+                                          &Writer::new(), ""));
+        let reply = try!(bus.wait_for_reply(serial));
+        let mut capabilities = Vec::new();
+        {
+            let mut args = reply.args();
+            let end = try!(args.begin_array(4));
+            while args.in_array(end) {
+                capabilities.push(try!(args.string()));
+            }
+        }
+
+        let inline_reply = capabilities.iter().any(|c| c == "inline-reply");
+        if !inline_reply {
+            warn!("the notification daemon doesn't take inline replies, so desktop notifications can't be replied to");
+        }
+        Ok(Daemon {
+            bus: bus,
+            inline_reply: inline_reply,
+            shown: VecDeque::new(),
+        })
+    }
+
+    /// Shows a notification, with a reply action if it is from a buffer and
+    /// the daemon takes replies
+    fn show(&mut self, popup: &Popup) -> Result<(), String> {
+        let buffer = if self.inline_reply { popup.buffer.clone() } else { None };
+
+        let mut args = Writer::new();
+        args.string("weechat-alert");
+        args.u32(0);
+        args.string(popup.icon.as_ref().map_or("", |s| s.as_str()));
+        args.string(&popup.title);
+        args.string(&popup.body);
+        let actions = args.begin_array(4);
+        if buffer.is_some() {
+            args.string("inline-reply");
+            args.string("Reply");
+        }
+        args.end_array(actions);
+        let hints = args.begin_array(8);
+        args.align(8);
+        args.string("urgency");
+        args.signature("y");
+        args.u8(urgency_byte(popup.urgency));
+        args.end_array(hints);
+        args.i32(-1);
+
+        let serial = try!(self.bus.call_method(NOTIFICATIONS, NOTIFICATIONS_PATH, NOTIFICATIONS, "Notify",
+                                               &args, "susssasa{sv}i"));
+        let reply = try!(self.bus.wait_for_reply(serial));
+        let id = try!(reply.args().u32());
+        if let Some(buffer) = buffer {
+            if self.shown.len() >= MAX_SHOWN {
+                self.shown.pop_front();
+            }
+            self.shown.push_back((id, buffer));
+        }
+        Ok(())
+    }
+
+    /// Handles the daemon's signals: sends replies into their buffers, and
+    /// forgets notifications that closed
+    fn handle(&mut self, signal: &Header, replies: &Replies) -> Result<(), String> {
+        let mut args = signal.args();
+        match signal.member() {
+            "NotificationReplied" => {
+                let id = try!(args.u32());
+                let text = try!(args.string());
+                let buffer = match self.shown.iter().find(|&&(shown, _)| shown == id) {
+                    Some(&(_, ref buffer)) => buffer.clone(),
+                    None                   => return Ok(()),
+                };
+                if let Err(e) = replies.send(&buffer, &text) {
+                    error!("could not reply to {}: {}", buffer, e);
+                    try!(self.show(&Popup {
+                        title: format!("Could not reply to {}", buffer),
+                        body: e,
+                        urgency: Urgency::Normal,
+                        icon: None,
+                        buffer: None,
+                    }));
+                }
+            },
+            "NotificationClosed"  => {
+                let id = try!(args.u32());
+                self.shown.retain(|&(shown, _)| shown != id);
+            },
+            _                     => (),
+        }
+        Ok(())
+    }
+}
+
+/// The freedesktop urgency hint for an urgency
+#[cfg(feature = "dbus")]
+fn urgency_byte(urgency: Urgency) -> u8 {
+    match urgency {
+        Urgency::Low      => 0,
+        Urgency::Normal   => 1,
+        Urgency::Critical => 2,
+    }
+}
+
+/// Shows every queued notification, sending replies between them. If the
+/// daemon can't be reached we try again every RECONNECT_DELAY seconds, and
+/// use notify-send until then.
+#[cfg(feature = "dbus")]
+fn run_replying(pending: Receiver<Popup>, replies: Replies) {
+    let mut daemon: Option<Daemon> = None;
+    let mut last_attempt: Option<Instant> = None;
+    loop {
+        let popup = match pending.recv_timeout(Duration::from_millis(POLL_INTERVAL)) {
+            Ok(popup)                           => Some(popup),
+            Err(RecvTimeoutError::Timeout)      => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+
+        if daemon.is_none() && last_attempt.map_or(true, |at| at.elapsed() >= Duration::from_secs(RECONNECT_DELAY)) {
+            last_attempt = Some(Instant::now());
+            match Daemon::connect() {
+                Ok(d)  => daemon = Some(d),
+                Err(e) => warn!("could not reach the notification daemon ({}), using notify-send", e),
+            }
+        }
+
+        let mut unshown = popup;
+        let result = match daemon {
+            Some(ref mut daemon) => {
+                let shown = match unshown {
+                    Some(ref popup) => daemon.show(popup),
+                    None            => Ok(()),
+                };
+                if shown.is_ok() {
+                    unshown = None;
+                }
+                shown.and_then(|_| daemon.bus.poll(Duration::from_millis(POLL_READ_TIMEOUT)))
+                     .and_then(|messages| {
+                         for signal in messages.iter().filter(|m| m.kind == SIGNAL) {
+                             try!(daemon.handle(signal, &replies));
+                         }
+                         Ok(())
+                     })
+            },
+            None                 => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("lost the notification daemon ({}), using notify-send", e);
+            daemon = None;
+            last_attempt = Some(Instant::now());
+        }
+        if let Some(popup) = unshown {
+            run(&mut popup.command());
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use http::{self, Response, Url};
+use notify::{utc_date, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::{self, Template};
+
+/// Longest message content (in characters) discord accepts
+pub const MAX_CONTENT_LENGTH: usize = 2000;
+
+// Longest embed title and description discord accepts
+const MAX_TITLE_LENGTH: usize = 256;
+const MAX_DESCRIPTION_LENGTH: usize = 4096;
+
+// Hosts webhook urls can be on
+const HOSTS: &'static [&'static str] = &["discord.com", "discordapp.com", "ptb.discord.com",
+                                         "canary.discord.com"];
+
+// Seconds to wait for the api
+const TIMEOUT: u64 = 10;
+
+// Seconds to wait after a rate limited post, if the response doesn't say
+const RATE_LIMIT_DELAY: u64 = 5;
+
+// Seconds to wait before trying a post again after any other failure
+const RETRY_DELAY: u64 = 5;
+
+// Attempts at sending a post before it is dropped. Rate limited attempts
+// don't count, as those are waited out instead
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Settings for the discord backend, from [notifier.discord]
+#[derive(Clone)]
+pub struct DiscordSettings {
+    /// Webhook to post to, checked to look like one
+    pub url: Url,
+    /// Post an embed instead of plain text
+    pub embed: bool,
+    /// Text of plain messages
+    pub format: Template,
+}
+
+/// Debug output leaves out the url, as it holds the webhook's token
+impl fmt::Debug for DiscordSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DiscordSettings")
+         .field("embed", &self.embed)
+         .field("format", &self.format)
+         .finish()
+    }
+}
+
+/// Checks a discord webhook url, which looks like
+/// https://discord.com/api/webhooks/<id>/<token>
+pub fn parse_url(url: &str) -> Result<Url, String> {
+    let invalid = "is not a discord webhook url (https://discord.com/api/webhooks/<id>/<token>)";
+    let parsed = try!(Url::parse(url).map_err(|_| invalid.to_string()));
+    if !parsed.https || !HOSTS.contains(&parsed.host.to_lowercase().as_str()) {
+        return Err(invalid.to_string());
+    }
+
+    let valid = {
+        let mut parts = parsed.path.trim_left_matches("/api/webhooks/").splitn(2, '/');
+        let id = parts.next().unwrap_or("");
+        let token = parts.next().unwrap_or("");
+        parsed.path.starts_with("/api/webhooks/") && !id.is_empty() &&
+            id.chars().all(|c| c.is_digit(10)) && !token.is_empty() && !token.contains('/')
+    };
+    if !valid {
+        return Err(invalid.to_string());
+    }
+    Ok(parsed)
+}
+
+/// Posts notifications to a discord channel through a webhook
+pub struct Discord {
+    settings: DiscordSettings,
+    /// Queue of posts (as json) waiting to be sent
+    queue: Queue<String>,
+}
+
+impl Discord {
+    /// Creates the backend, starting the thread that posts notifications
+    pub fn new(settings: &Settings) -> Result<Discord, String> {
+        let settings = try!(settings.discord.clone()
+                            .ok_or("The discord notifier needs a url in [notifier.discord]"));
+
+        let url = settings.url.clone();
+        let queue = Queue::start("discord", QUEUE_SIZE, move |pending| post_all(url, pending));
+
+        Ok(Discord {
+            settings: settings,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Discord {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut post = BTreeMap::new();
+        if self.settings.embed {
+            post.insert("embeds".to_string(), Json::Array(vec![embed(event)]));
+        } else {
+            let content = neutralize_mentions(&self.settings.format.render(event));
+            post.insert("content".to_string(), Json::String(template::truncate(content, MAX_CONTENT_LENGTH)));
+        }
+
+        // Don't let anything in the message ping anyone, including user and
+        // role mentions that can't be escaped in the text
+        let mut allowed_mentions = BTreeMap::new();
+        allowed_mentions.insert("parse".to_string(), Json::Array(Vec::new()));
+        post.insert("allowed_mentions".to_string(), Json::Object(allowed_mentions));
+        self.queue.send(Json::Object(post).to_string())
+    }
+}
+
+/// The embed posted for an event: the buffer as the title, and the nick and
+/// message as the description
+fn embed(event: &NotificationEvent) -> Json {
+    let buffer = event.buffer.as_ref().map(|s| s.as_str()).unwrap_or("weechat");
+    let description = match event.nick {
+        Some(ref nick) => format!("{}: {}", nick, event.message),
+        None           => event.message.clone(),
+    };
+
+    let mut embed = BTreeMap::new();
+    embed.insert("title".to_string(),
+                 Json::String(template::truncate(neutralize_mentions(buffer), MAX_TITLE_LENGTH)));
+    embed.insert("description".to_string(),
+                 Json::String(template::truncate(neutralize_mentions(&description), MAX_DESCRIPTION_LENGTH)));
+    embed.insert("timestamp".to_string(), Json::String(iso8601(event.date)));
+    Json::Object(embed)
+}
+
+/// Breaks up @everyone and @here with a zero width space, so they show up
+/// as written but don't ping the whole server
+pub fn neutralize_mentions(text: &str) -> String {
+    text.replace("@everyone", "@\u{200b}everyone").replace("@here", "@\u{200b}here")
+}
+
+/// Formats a unix timestamp as an iso 8601 utc date, like
+/// 2016-07-01T00:00:00Z
+fn iso8601(timestamp: i64) -> String {
+    let (year, month, day, secs) = utc_date(timestamp);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Posts every queued notification in order. When discord says the
+/// webhook's rate limit is used up, posts wait until it resets instead of
+/// being dropped.
+///
+/// Note: Nothing printed here may include the url, as it holds the webhook's
+///       token. http errors only name the host.
+fn post_all(url: Url, pending: Receiver<String>) {
+    for post in pending {
+        let mut attempts = 0;
+        loop {
+            let delay = match http::post(&url, &[], "application/json", post.as_bytes(),
+                                         Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => {
+                    if let Some(wait) = rate_limit_wait(response) {
+                        thread::sleep(wait);
+                    }
+                    break;
+                },
+                Ok(ref response) if response.status == 429 => {
+                    let wait = retry_after(response).unwrap_or(Duration::from_secs(RATE_LIMIT_DELAY));
+                    info!("Discord rate limit reached, holding posts for {} seconds", wait.as_secs());
+                    thread::sleep(wait);
+                    continue;
+                },
+                Ok(response) => {
+                    error!("discord returned {}: {}", response.status, api_error(&response.body));
+                    // Sending the same thing again won't fix a client error
+                    if response.status / 100 == 4 {
+                        break;
+                    }
+                    Duration::from_secs(RETRY_DELAY)
+                },
+                Err(e)       => {
+                    error!("discord failed: {}", e);
+                    Duration::from_secs(RETRY_DELAY)
+                },
+            };
+            attempts += 1;
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on a discord post after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Parses a number of seconds, which discord sends with a fraction
+fn seconds(s: &str) -> Option<Duration> {
+    match s.trim().parse::<f64>() {
+        Ok(secs) if secs >= 0.0 => Some(Duration::from_millis((secs * 1000.0).ceil() as u64)),
+        _                       => None,
+    }
+}
+
+/// How long to wait for the rate limit to reset, if the response says none
+/// of it is left
+fn rate_limit_wait(response: &Response) -> Option<Duration> {
+    match response.header("x-ratelimit-remaining").and_then(|r| r.parse::<i64>().ok()) {
+        Some(remaining) if remaining <= 0 => response.header("x-ratelimit-reset-after").and_then(seconds),
+        _                                 => None,
+    }
+}
+
+/// How long a rate limited response says to wait, from its body
+/// ({"retry_after": 1.5, ...}) or its headers
+fn retry_after(response: &Response) -> Option<Duration> {
+    Json::from_str(&response.body).ok()
+        .and_then(|json| json.find("retry_after").and_then(|r| r.as_f64()))
+        .map(|secs| Duration::from_millis((secs.max(0.0) * 1000.0).ceil() as u64))
+        .or_else(|| response.header("retry-after").and_then(seconds))
+}
+
+/// Pulls the error message out of an api response, which look like
+/// {"message": "...", "code": ...}
+fn api_error(body: &str) -> String {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find("message").and_then(|m| m.as_string()).map(|m| m.to_string()))
+        .unwrap_or("unknown error".to_string())
+}
@@ -0,0 +1,419 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::prelude::*;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use openssl::crypto::rand;
+use rustc_serialize::base64::{ToBase64, STANDARD};
+
+use http::{self, Trust};
+use notify::{utc_date, EventClass, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+/// Subject of emails if subject isn't set
+pub const DEFAULT_SUBJECT: &'static str = "weechat: {class} from {nick} in {buffer}";
+
+/// Longest subject we send
+pub const MAX_SUBJECT_LENGTH: usize = 150;
+
+// Seconds to wait for each reply from the server
+const TIMEOUT: u64 = 30;
+
+// Seconds to wait before trying an email again after a temporary failure
+const RETRY_DELAY: u64 = 60;
+
+// Attempts at sending an email before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+/// How to secure the connection to the smtp server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpTls {
+    /// Connect in the clear and upgrade with STARTTLS (normally port 587)
+    StartTls,
+    /// Tls from the start (normally port 465)
+    Implicit,
+}
+
+/// Settings for the email backend, from [notifier.email]
+#[derive(Clone)]
+pub struct EmailSettings {
+    pub host: String,
+    pub port: u16,
+    pub tls: SmtpTls,
+    /// Username and password to log in with, if the server needs them
+    pub credentials: Option<(String, String)>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub subject: Template,
+    /// How each notification is written in the body
+    pub format: Template,
+    /// Classes that send an email
+    pub classes: Vec<EventClass>,
+    /// Nicks whose lines send an email, or empty for anyone's
+    pub nicks: Vec<String>,
+    /// Most emails sent in any hour
+    pub max_per_hour: usize,
+    /// How long to wait after a notification for more to send in the same
+    /// email
+    pub batch_window: Duration,
+}
+
+/// Debug output leaves out the password, so it can't end up in a log
+impl fmt::Debug for EmailSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EmailSettings")
+         .field("host", &self.host)
+         .field("port", &self.port)
+         .field("tls", &self.tls)
+         .field("username", &self.credentials.as_ref().map(|c| &c.0))
+         .field("from", &self.from)
+         .field("to", &self.to)
+         .field("subject", &self.subject)
+         .field("format", &self.format)
+         .field("classes", &self.classes)
+         .field("nicks", &self.nicks)
+         .field("max_per_hour", &self.max_per_hour)
+         .field("batch_window", &self.batch_window)
+         .finish()
+    }
+}
+
+/// A notification waiting to go out in an email
+struct Pending {
+    subject: String,
+    line: String,
+}
+
+/// Sends notifications as plain text emails over smtp
+pub struct Email {
+    settings: EmailSettings,
+    /// Queue of notifications waiting to be batched into emails
+    queue: Queue<Pending>,
+}
+
+impl Email {
+    /// Creates the backend, starting the thread that sends emails
+    pub fn new(settings: &Settings) -> Result<Email, String> {
+        let settings = try!(settings.email.clone()
+                            .ok_or("The email notifier needs a host, from and to in [notifier.email]"));
+
+        let worker_settings = settings.clone();
+        let queue = Queue::start("email", QUEUE_SIZE, move |pending| send_all(worker_settings, pending));
+
+        Ok(Email {
+            settings: settings,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Email {
+    /// Queues the notification, if its class and nick are ones that email
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        if !self.settings.classes.contains(&event.class) {
+            return Ok(());
+        }
+        if !self.settings.nicks.is_empty() {
+            let nick = event.nick.as_ref().map(|n| n.to_lowercase()).unwrap_or(String::new());
+            if !self.settings.nicks.iter().any(|n| n.to_lowercase() == nick) {
+                return Ok(());
+            }
+        }
+
+        self.queue.send(Pending {
+            subject: self.settings.subject.render(event),
+            line: self.settings.format.render(event),
+        })
+    }
+}
+
+/// Sends the queued notifications. Notifications that come in within the
+/// batch window of the first go out in one email, and past max_per_hour
+/// emails in the last hour notifications are dropped.
+fn send_all(settings: EmailSettings, pending: Receiver<Pending>) {
+    let mut sent: VecDeque<Instant> = VecDeque::new();
+    loop {
+        let first = match pending.recv() {
+            Ok(first) => first,
+            Err(_)    => return,
+        };
+        let mut batch = vec![first];
+        let deadline = Instant::now() + settings.batch_window;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match pending.recv_timeout(deadline - now) {
+                Ok(next)                            => batch.push(next),
+                Err(RecvTimeoutError::Timeout)      => break,
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        while sent.front().map(|t| t.elapsed() >= Duration::from_secs(3600)).unwrap_or(false) {
+            sent.pop_front();
+        }
+        if sent.len() >= settings.max_per_hour {
+            warn!("already sent {} emails in the last hour, dropping {} notifications",
+                  sent.len(), batch.len());
+            continue;
+        }
+
+        let mut subject = batch[0].subject.clone();
+        if batch.len() > 1 {
+            subject = format!("{} (and {} more)", subject, batch.len() - 1);
+        }
+        let lines: Vec<&str> = batch.iter().map(|p| p.line.as_str()).collect();
+        let message = message(&settings, &subject, &lines.join("\n"));
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match send(&settings, &message) {
+                Ok(_)                         => break,
+                Err(SmtpError::Permanent(e))  => {
+                    error!("could not send an email: {}", e);
+                    break;
+                },
+                Err(SmtpError::Temporary(e))  => error!("could not send an email: {}", e),
+            }
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on an email after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(Duration::from_secs(RETRY_DELAY));
+        }
+        // Failed attempts count too, so a server that takes the email but
+        // says it didn't can't get us past the cap
+        sent.push_back(Instant::now());
+    }
+}
+
+/// Why an email didn't go out
+enum SmtpError {
+    /// The server rejected it (a 5xx reply), so trying again won't help
+    Permanent(String),
+    /// A 4xx reply or a connection problem, which may well be temporary
+    Temporary(String),
+}
+
+impl From<String> for SmtpError {
+    fn from(err: String) -> SmtpError {
+        SmtpError::Temporary(err)
+    }
+}
+
+/// A reply from the smtp server
+struct Reply {
+    code: u32,
+    /// The text of every line of the reply, without the codes
+    lines: Vec<String>,
+}
+
+impl Reply {
+    /// Error for a reply we didn't want, naming the step it failed at
+    fn error(&self, step: &str) -> SmtpError {
+        let e = format!("{} failed: {} {}", step, self.code, self.lines.join(" "));
+        if self.code / 100 == 5 { SmtpError::Permanent(e) } else { SmtpError::Temporary(e) }
+    }
+}
+
+/// Sends one email: connects, secures the connection, logs in, and hands
+/// over the message
+fn send(settings: &EmailSettings, message: &str) -> Result<(), SmtpError> {
+    let tcp_stream = try!(http::connect(&settings.host, settings.port, Duration::from_secs(TIMEOUT)));
+    let ehlo_name = domain(&settings.from).unwrap_or("localhost").to_string();
+
+    let mut stream = match settings.tls {
+        SmtpTls::Implicit => {
+            let mut stream = try!(http::connect_ssl(&settings.host, tcp_stream, &Trust::system()));
+            try!(expect(&mut stream, 220, "connecting"));
+            stream
+        },
+        // Logging in or sending anything in the clear is never done, so a
+        // server that doesn't offer STARTTLS is an error
+        SmtpTls::StartTls => {
+            let mut tcp_stream = tcp_stream;
+            try!(expect(&mut tcp_stream, 220, "connecting"));
+            let reply = try!(command(&mut tcp_stream, &format!("EHLO {}", ehlo_name), 250, "EHLO"));
+            if !has_extension(&reply, "STARTTLS") {
+                return Err(SmtpError::Permanent(format!("{} doesn't support STARTTLS", settings.host)));
+            }
+            try!(command(&mut tcp_stream, "STARTTLS", 220, "STARTTLS"));
+            try!(http::connect_ssl(&settings.host, tcp_stream, &Trust::system()))
+        },
+    };
+
+    let reply = try!(command(&mut stream, &format!("EHLO {}", ehlo_name), 250, "EHLO"));
+    if let Some((ref username, ref password)) = settings.credentials {
+        try!(login(&mut stream, &reply, username, password));
+    }
+
+    try!(command(&mut stream, &format!("MAIL FROM:<{}>", settings.from), 250, "MAIL FROM"));
+    for to in &settings.to {
+        let reply = try!(send_command(&mut stream, &format!("RCPT TO:<{}>", to)));
+        if reply.code != 250 && reply.code != 251 {
+            return Err(reply.error(&format!("RCPT TO {}", to)));
+        }
+    }
+    try!(command(&mut stream, "DATA", 354, "DATA"));
+    try!(write(&mut stream, message));
+    try!(command(&mut stream, ".", 250, "sending the message"));
+    // The email is sent by now, so a missing goodbye doesn't matter
+    let _ = send_command(&mut stream, "QUIT");
+    Ok(())
+}
+
+/// Logs in with AUTH PLAIN, or AUTH LOGIN for servers that only have that
+fn login<S: Read + Write>(stream: &mut S, ehlo: &Reply, username: &str, password: &str)
+                          -> Result<(), SmtpError> {
+    // Some older servers list them as AUTH=PLAIN LOGIN
+    let mechanisms: Vec<String> = ehlo.lines.iter()
+        .filter(|l| l.is_char_boundary(5) && (l[..5].to_uppercase() == "AUTH " || l[..5].to_uppercase() == "AUTH="))
+        .flat_map(|l| l[5..].split_whitespace().map(|m| m.to_uppercase()).collect::<Vec<_>>())
+        .collect();
+
+    if mechanisms.iter().any(|m| m == "PLAIN") {
+        let credentials = format!("\0{}\0{}", username, password).as_bytes().to_base64(STANDARD);
+        try!(command(stream, &format!("AUTH PLAIN {}", credentials), 235, "logging in"));
+    } else if mechanisms.iter().any(|m| m == "LOGIN") {
+        try!(command(stream, "AUTH LOGIN", 334, "logging in"));
+        try!(command(stream, &username.as_bytes().to_base64(STANDARD), 334, "logging in"));
+        try!(command(stream, &password.as_bytes().to_base64(STANDARD), 235, "logging in"));
+    } else {
+        return Err(SmtpError::Permanent("the server doesn't offer AUTH PLAIN or LOGIN".to_string()));
+    }
+    Ok(())
+}
+
+/// Sends a command and checks the reply has the code we want
+fn command<S: Read + Write>(stream: &mut S, line: &str, code: u32, step: &str) -> Result<Reply, SmtpError> {
+    let reply = try!(send_command(stream, line));
+    if reply.code != code {
+        return Err(reply.error(step));
+    }
+    Ok(reply)
+}
+
+fn send_command<S: Read + Write>(stream: &mut S, line: &str) -> Result<Reply, SmtpError> {
+    try!(write(stream, &format!("{}\r\n", line)));
+    read_reply(stream)
+}
+
+/// Reads a reply and checks it has the code we want
+fn expect<S: Read>(stream: &mut S, code: u32, step: &str) -> Result<Reply, SmtpError> {
+    let reply = try!(read_reply(stream));
+    if reply.code != code {
+        return Err(reply.error(step));
+    }
+    Ok(reply)
+}
+
+fn write<S: Write>(stream: &mut S, data: &str) -> Result<(), String> {
+    stream.write_all(data.as_bytes()).and_then(|_| stream.flush()).map_err(|e| e.to_string())
+}
+
+/// Reads a whole reply, which can be several lines ("250-..." up to the last
+/// "250 ..."). Replies only come after our commands, so everything read is
+/// part of this one.
+fn read_reply<S: Read>(stream: &mut S) -> Result<Reply, SmtpError> {
+    let mut data = Vec::new();
+    let mut buf = [0; 1024];
+    loop {
+        match stream.read(&mut buf) {
+            Ok(0)  => return Err(SmtpError::Temporary("the server closed the connection".to_string())),
+            Ok(n)  => data.extend_from_slice(&buf[..n]),
+            Err(e) => return Err(SmtpError::Temporary(e.to_string())),
+        }
+
+        let text = String::from_utf8_lossy(&data).into_owned();
+        if !text.ends_with("\r\n") {
+            continue;
+        }
+        let lines: Vec<&str> = text.split("\r\n").filter(|l| !l.is_empty()).collect();
+        let last = match lines.last() {
+            Some(last) => *last,
+            None       => continue,
+        };
+        if last.len() > 3 && last.as_bytes()[3] == b'-' {
+            continue;
+        }
+        let code = try!(after(last, 3).and_then(|_| last[..3].parse().ok())
+                        .ok_or(format!("the server sent a bad reply: {}", last)));
+        return Ok(Reply {
+            code: code,
+            lines: lines.iter().map(|l| after(l, 4).unwrap_or("").to_string()).collect(),
+        });
+    }
+}
+
+/// The text from a byte offset on, if the line is that long
+fn after(line: &str, offset: usize) -> Option<&str> {
+    if line.len() >= offset && line.is_char_boundary(offset) { Some(&line[offset..]) } else { None }
+}
+
+/// Checks if an EHLO reply lists an extension
+fn has_extension(reply: &Reply, name: &str) -> bool {
+    reply.lines.iter().any(|l| l.split_whitespace().next().map(|e| e.to_uppercase()) == Some(name.to_string()))
+}
+
+/// Domain of an email address
+fn domain(address: &str) -> Option<&str> {
+    address.rfind('@').map(|i| &address[i + 1..]).and_then(|d| if d.is_empty() { None } else { Some(d) })
+}
+
+/// Builds the message, ready to follow DATA: headers, then the body with
+/// lines ending in CRLF and any leading dots doubled, then the final dot is
+/// left to the caller
+fn message(settings: &EmailSettings, subject: &str, body: &str) -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    let random: String = rand::rand_bytes(8).iter().map(|b| format!("{:02x}", b)).collect();
+
+    let mut message = String::new();
+    message.push_str(&format!("From: {}\r\n", settings.from));
+    message.push_str(&format!("To: {}\r\n", settings.to.join(", ")));
+    message.push_str(&format!("Subject: {}\r\n", header_value(subject)));
+    message.push_str(&format!("Date: {}\r\n", rfc2822(now)));
+    message.push_str(&format!("Message-ID: <weechat-alert.{}.{}@{}>\r\n",
+                              now, random, domain(&settings.from).unwrap_or("localhost")));
+    message.push_str("MIME-Version: 1.0\r\n");
+    message.push_str("Content-Type: text/plain; charset=utf-8\r\n");
+    message.push_str("Content-Transfer-Encoding: 8bit\r\n");
+    message.push_str("\r\n");
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line.trim_right_matches('\r'));
+        message.push_str("\r\n");
+    }
+    message
+}
+
+/// Makes text safe for a header: line breaks would end the header, and
+/// anything that isn't ascii is sent as an rfc 2047 encoded word
+fn header_value(text: &str) -> String {
+    let text = text.replace(|c| c == '\r' || c == '\n', " ");
+    if text.chars().all(|c| c >= ' ' && c <= '~') {
+        text
+    } else {
+        format!("=?UTF-8?B?{}?=", text.as_bytes().to_base64(STANDARD))
+    }
+}
+
+/// Formats a unix timestamp as an rfc 2822 date, like
+/// Fri, 01 Jul 2016 00:00:00 +0000
+fn rfc2822(timestamp: i64) -> String {
+    const DAYS: [&'static str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                        "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let (year, month, day, secs) = utc_date(timestamp);
+    let weekday = ((timestamp - secs) / 86400 % 7 + 7) % 7;
+    format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} +0000",
+            DAYS[weekday as usize], day, MONTHS[(month - 1) as usize], year,
+            secs / 3600, secs % 3600 / 60, secs % 60)
+}
@@ -0,0 +1,243 @@
+use std::cell::{Cell, RefCell};
+#[cfg(unix)]
+use std::ffi::CString;
+use std::fs::{self, File};
+#[cfg(unix)]
+use std::fs::OpenOptions;
+#[cfg(unix)]
+use std::io::{self, ErrorKind};
+use std::io::prelude::*;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use libc;
+
+use notify::NotificationEvent;
+use notify::json_log;
+
+/// How events are written to the fifo
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoFormat {
+    /// The same json lines as the json log
+    Json,
+    /// timestamp, class, buffer, nick, message and suppressed (0 or 1),
+    /// separated by tabs
+    Tsv,
+}
+
+impl FifoFormat {
+    /// Parses a format from its name in the config file
+    pub fn from_name(name: &str) -> Option<FifoFormat> {
+        match name {
+            "json" => Some(FifoFormat::Json),
+            "tsv"  => Some(FifoFormat::Tsv),
+            _      => None,
+        }
+    }
+}
+
+/// Settings for the fifo, from fifo_path and friends
+#[derive(Debug, Clone)]
+pub struct FifoSettings {
+    pub path: PathBuf,
+    pub format: FifoFormat,
+    /// Remove the fifo when we exit
+    pub remove_on_exit: bool,
+}
+
+/// Writes a line for every event to a named pipe, for a status bar or
+/// script to read as they come in. Writes never block: while nothing is
+/// reading (or the reader has fallen behind and the pipe is full) events are
+/// dropped and counted, and we try opening the pipe again on the next one.
+/// This is unix only, windows pipes aren't files.
+pub struct Fifo {
+    settings: FifoSettings,
+    /// The pipe, while something is reading it
+    file: RefCell<Option<File>>,
+    /// Events dropped since the last one that made it
+    dropped: Cell<u64>,
+}
+
+impl Fifo {
+    /// Creates the fifo (only readable by us) if it isn't there already
+    pub fn open(settings: &FifoSettings) -> Result<Fifo, String> {
+        try!(create(&settings.path));
+        Ok(Fifo {
+            settings: settings.clone(),
+            file: RefCell::new(None),
+            dropped: Cell::new(0),
+        })
+    }
+
+    /// Writes an event, or drops it if nothing is reading. Lines up to
+    /// PIPE_BUF (4096) bytes go into the pipe whole or not at all, so a
+    /// reader only sees part of a line if it is longer than that.
+    pub fn log(&self, event: &NotificationEvent, suppressed: bool) {
+        let mut file = self.file.borrow_mut();
+        if file.is_none() {
+            *file = open(&self.settings.path);
+        }
+
+        let line = match self.settings.format {
+            FifoFormat::Json => json_log::line(event, suppressed),
+            FifoFormat::Tsv  => tsv_line(event, suppressed),
+        };
+        let written = match file.as_mut() {
+            Some(f) => f.write_all(line.as_bytes()).is_ok(),
+            None    => false,
+        };
+
+        if written {
+            if self.dropped.get() > 0 {
+                info!("Something is reading {} again, after {} events were dropped",
+                      self.settings.path.display(), self.dropped.get());
+                self.dropped.set(0);
+            }
+        } else {
+            // The reader went away (or is too far behind). Close our end, so
+            // we find out when there is a reader again
+            *file = None;
+            self.dropped.set(self.dropped.get() + 1);
+            if self.dropped.get() == 1 {
+                warn!("nothing is reading {}, dropping events until something does",
+                      self.settings.path.display());
+            }
+        }
+    }
+}
+
+impl Drop for Fifo {
+    fn drop(&mut self) {
+        if self.settings.remove_on_exit {
+            let _ = fs::remove_file(&self.settings.path);
+        }
+    }
+}
+
+/// Makes the fifo at path, unless there already is one
+#[cfg(unix)]
+fn create(path: &Path) -> Result<(), String> {
+    match fs::metadata(path) {
+        Ok(ref metadata) if metadata.file_type().is_fifo() => Ok(()),
+        Ok(_)  => Err(format!("{} is already there and is not a fifo", path.display())),
+        Err(_) => {
+            let c_path = try!(CString::new(path.as_os_str().as_bytes())
+                              .map_err(|_| format!("{} has a nul in it", path.display())));
+            if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                return Err(format!("Could not create the fifo {}: {}", path.display(),
+                                   io::Error::last_os_error()));
+            }
+            Ok(())
+        },
+    }
+}
+
+#[cfg(windows)]
+fn create(path: &Path) -> Result<(), String> {
+    Err(format!("Can't create the fifo {}: fifos are unix only", path.display()))
+}
+
+/// Opens the write end of the fifo without blocking. This fails (ENXIO)
+/// while nothing has the read end open.
+#[cfg(unix)]
+fn open(path: &Path) -> Option<File> {
+    match OpenOptions::new().write(true).custom_flags(libc::O_NONBLOCK).open(path) {
+        Ok(file)                                            => Some(file),
+        Err(ref e) if e.raw_os_error() == Some(libc::ENXIO) => None,
+        Err(ref e) if e.kind() == ErrorKind::Interrupted    => None,
+        Err(e)                                              => {
+            error!("could not open {}: {}", path.display(), e);
+            None
+        },
+    }
+}
+
+// There is never a fifo to open, as create fails
+#[cfg(windows)]
+fn open(_path: &Path) -> Option<File> {
+    None
+}
+
+/// The tab separated line for an event. Tabs and newlines in the fields are
+/// turned into spaces, so every event is exactly one line with six fields.
+fn tsv_line(event: &NotificationEvent, suppressed: bool) -> String {
+    let clean = |s: &str| s.chars().map(|c| if c == '\t' || c == '\n' || c == '\r' { ' ' } else { c })
+                           .collect::<String>();
+    format!("{}\t{}\t{}\t{}\t{}\t{}\n",
+            event.date,
+            event.class.name(),
+            clean(event.buffer.as_ref().map(|s| s.as_str()).unwrap_or("")),
+            clean(event.nick.as_ref().map(|s| s.as_str()).unwrap_or("")),
+            clean(&event.message),
+            if suppressed { 1 } else { 0 })
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(unix)]
+    use std::env;
+    #[cfg(unix)]
+    use std::fs::{self, OpenOptions};
+    #[cfg(unix)]
+    use std::io::prelude::*;
+    #[cfg(unix)]
+    use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+
+    #[cfg(unix)]
+    use libc;
+
+    use super::*;
+    use alert::AlertReason;
+    use testing::event;
+
+    #[test]
+    fn tabs_and_new_lines_become_spaces() {
+        let mut event = event(AlertReason::Highlight, "irc.libera.#rust", "bob", "me:\tlook\nhere");
+        event.date = 1480000000;
+        assert_eq!(tsv_line(&event, false), "1480000000\thighlight\tirc.libera.#rust\tbob\tme: look here\t0\n");
+        event.nick = None;
+        assert_eq!(tsv_line(&event, true), "1480000000\thighlight\tirc.libera.#rust\t\tme: look here\t1\n");
+        assert_eq!(FifoFormat::from_name("tsv"), Some(FifoFormat::Tsv));
+        assert_eq!(FifoFormat::from_name("csv"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn drops_events_until_something_reads() {
+        let dir = env::temp_dir().join("weechat-alert-fifo-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let settings = FifoSettings {
+            path: dir.join("events"),
+            format: FifoFormat::Tsv,
+            remove_on_exit: true,
+        };
+        let fifo = Fifo::open(&settings).unwrap();
+        assert!(fs::metadata(&settings.path).unwrap().file_type().is_fifo());
+
+        fifo.log(&event(AlertReason::Private, "irc.libera.alice", "alice", "one"), false);
+        assert_eq!(fifo.dropped.get(), 1);
+
+        let mut reader = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK)
+                                          .open(&settings.path).unwrap();
+        fifo.log(&event(AlertReason::Private, "irc.libera.alice", "alice", "two"), false);
+        assert_eq!(fifo.dropped.get(), 0);
+        let mut buf = [0; 256];
+        let n = reader.read(&mut buf).unwrap();
+        let line = String::from_utf8_lossy(&buf[..n]).into_owned();
+        assert!(line.ends_with("\tprivate\tirc.libera.alice\talice\ttwo\t0\n"), "{}", line);
+
+        // Opening it again finds the fifo already there, and only the one
+        // that removes it on exit does
+        let again = FifoSettings { remove_on_exit: false, ..settings.clone() };
+        drop(Fifo::open(&again).unwrap());
+        assert!(fs::metadata(&settings.path).is_ok());
+        drop(fifo);
+        assert!(fs::metadata(&settings.path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use http::{self, Trust, Url};
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::priority::Scale;
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::{self, Template};
+
+/// The gotify priority (0 to 10) for each priority, if
+/// [notifier.gotify.priority] doesn't set it. The android app shows 8 and up
+/// as high priority notifications, which pop up on screen
+pub const DEFAULT_PRIORITIES: Scale<u8> = Scale { low: 2, normal: 5, high: 8, critical: 10 };
+
+// Seconds to wait for the server
+const TIMEOUT: u64 = 10;
+
+// Longest title we send
+const MAX_TITLE_LENGTH: usize = 250;
+
+// Seconds to wait before trying a message again after a failure
+const RETRY_DELAY: u64 = 5;
+
+// Attempts at sending a message before it is dropped
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Settings for the gotify backend, from [notifier.gotify]
+#[derive(Clone)]
+pub struct GotifySettings {
+    /// Url of the server, such as https://gotify.example.com
+    pub url: String,
+    /// Token of the gotify application the messages come from
+    pub token: String,
+    /// Which certificates to trust, for a server with a self-signed one
+    pub trust: Trust,
+    pub format: Template,
+    /// Gotify priority for each priority
+    pub priorities: Scale<u8>,
+}
+
+/// Debug output leaves out the token, so it can't end up in a log
+impl fmt::Debug for GotifySettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GotifySettings")
+         .field("url", &self.url)
+         .field("trust", &self.trust)
+         .field("format", &self.format)
+         .field("priorities", &self.priorities)
+         .finish()
+    }
+}
+
+/// Sends notifications as messages to a gotify server
+pub struct Gotify {
+    settings: GotifySettings,
+    /// Queue of messages (as json) waiting to be sent
+    queue: Queue<String>,
+}
+
+impl Gotify {
+    /// Creates the backend, checking that the server can be reached and
+    /// starting the thread that sends messages
+    pub fn new(settings: &Settings) -> Result<Gotify, String> {
+        let settings = try!(settings.gotify.clone()
+                            .ok_or("The gotify notifier needs a url and token in [notifier.gotify]"));
+        let base = settings.url.trim_right_matches('/').to_string();
+        let version_url = try!(Url::parse(&format!("{}/version", base))
+                               .map_err(|e| format!("'notifier.gotify.url': {}", e)));
+        let message_url = try!(Url::parse(&format!("{}/message", base))
+                               .map_err(|e| format!("'notifier.gotify.url': {}", e)));
+        let headers = vec![("X-Gotify-Key".to_string(), settings.token.clone())];
+
+        // This catches a wrong url or an untrusted certificate up front. An
+        // application token can only send messages, so a bad token shows up
+        // as a 401 on the first message. If the server is down right now we
+        // carry on, as it may well be back by the time there is something
+        // to send
+        match http::request("GET", &version_url, &headers, None, Duration::from_secs(TIMEOUT),
+                            &settings.trust) {
+            Ok(ref response) if response.success() && version(&response.body).is_some() => (),
+            Ok(ref response) if response.success() => {
+                return Err(format!("{} doesn't look like a gotify server", base));
+            },
+            Ok(response) => {
+                warn!("could not check the gotify server ({}: {})",
+                      response.status, api_error(&response.body));
+            },
+            Err(e)       => warn!("could not check the gotify server ({})", e),
+        }
+
+        let trust = settings.trust.clone();
+        let queue = Queue::start("gotify", QUEUE_SIZE,
+                                 move |pending| send_all(message_url, headers, trust, pending));
+
+        Ok(Gotify {
+            settings: settings,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Gotify {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let title = event.buffer.as_ref().map(|s| s.as_str()).unwrap_or("weechat").to_string();
+
+        let mut message = BTreeMap::new();
+        message.insert("title".to_string(), Json::String(template::truncate(title, MAX_TITLE_LENGTH)));
+        message.insert("message".to_string(), Json::String(self.settings.format.render(event)));
+        message.insert("priority".to_string(), Json::U64(self.settings.priorities.get(event.priority) as u64));
+        self.queue.send(Json::Object(message).to_string())
+    }
+}
+
+/// Sends every queued message in order
+///
+/// Note: Nothing printed here may include the headers, as they hold the
+///       token.
+fn send_all(url: Url, headers: Vec<(String, String)>, trust: Trust, pending: Receiver<String>) {
+    for message in pending {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match http::request("POST", &url, &headers, Some(("application/json", message.as_bytes())),
+                                Duration::from_secs(TIMEOUT), &trust) {
+                Ok(ref response) if response.success() => break,
+                Ok(response) => {
+                    error!("gotify returned {}: {}", response.status, api_error(&response.body));
+                    // Sending the same thing again won't fix a client error
+                    if response.status / 100 == 4 {
+                        break;
+                    }
+                },
+                Err(e)       => error!("gotify failed: {}", e),
+            }
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on a gotify message after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(Duration::from_secs(RETRY_DELAY));
+        }
+    }
+}
+
+/// The version from a /version response, like {"version": "2.4.0", ...}
+fn version(body: &str) -> Option<String> {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find("version").and_then(|v| v.as_string()).map(|v| v.to_string()))
+}
+
+/// Pulls the error message out of an api response, which look like
+/// {"error": "Unauthorized", "errorCode": 401, "errorDescription": "..."}
+fn api_error(body: &str) -> String {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find("errorDescription").and_then(|d| d.as_string()).map(|d| d.to_string()))
+        .unwrap_or("unknown error".to_string())
+}
@@ -0,0 +1,226 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use notify::{self, NotificationEvent};
+use notify::time_format::TimeFormat;
+
+/// How many rotated logs are kept if highlight_log_keep isn't set
+pub const DEFAULT_KEEP: u32 = 5;
+
+/// Settings for the highlight log, from highlight_log and friends
+#[derive(Debug, Clone)]
+pub struct HighlightLogSettings {
+    pub path: PathBuf,
+    /// Size in bytes the log is rotated at, or 0 to never rotate it
+    pub max_size: u64,
+    /// How many rotated logs (path.1 being the newest) are kept
+    pub keep: u32,
+    /// How times are written: time_format, in utc if highlight_log_utc or
+    /// time_utc is set
+    pub time_format: TimeFormat,
+}
+
+/// Appends a line for every event to a plain text log, for reading back
+/// what you were notified about:
+///
+/// 2024-05-01 14:03:12 irc.libera.#rust <alice> hey, can you look at ...
+///
+/// Events that didn't notify have "(suppressed)" after the time. The log
+/// can be rotated by size (so it is renamed to path.1, path.1 to path.2 and
+/// so on), and is reopened on SIGHUP for anyone using logrotate instead.
+pub struct HighlightLog {
+    settings: HighlightLogSettings,
+    /// The open log, or None if it couldn't be opened last time we tried
+    file: RefCell<Option<File>>,
+    /// Size of the open log, so we know when to rotate it
+    size: Cell<u64>,
+    /// notify::hangups() when the file was opened
+    opened_at: Cell<usize>,
+}
+
+impl HighlightLog {
+    /// Opens the log, creating its directory if needed
+    pub fn open(settings: &HighlightLogSettings) -> Result<HighlightLog, String> {
+        let path = &settings.path;
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e)));
+        }
+        let (file, size) = try!(open(path));
+        Ok(HighlightLog {
+            settings: settings.clone(),
+            file: RefCell::new(Some(file)),
+            size: Cell::new(size),
+            opened_at: Cell::new(notify::hangups()),
+        })
+    }
+
+    /// Appends an event, rotating the log first if the line would take it
+    /// over the max size
+    pub fn log(&self, event: &NotificationEvent, suppressed: bool) {
+        let line = self.line(event, suppressed);
+        let mut file = self.file.borrow_mut();
+
+        let max_size = self.settings.max_size;
+        let full = max_size > 0 && self.size.get() > 0 && self.size.get() + line.len() as u64 > max_size;
+        if full && file.is_some() {
+            // Close it first, so the new file is opened below
+            *file = None;
+            if let Err(e) = self.rotate() {
+                error!("could not rotate {}: {}", self.settings.path.display(), e);
+            }
+        }
+
+        if self.opened_at.get() != notify::hangups() || file.is_none() {
+            self.opened_at.set(notify::hangups());
+            *file = match open(&self.settings.path) {
+                Ok((file, size)) => {
+                    self.size.set(size);
+                    Some(file)
+                },
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                },
+            };
+        }
+
+        let written = file.as_mut().map(|f| f.write_all(line.as_bytes()));
+        match written {
+            Some(Ok(()))  => self.size.set(self.size.get() + line.len() as u64),
+            Some(Err(e))  => {
+                // Try opening it again for the next event, in case it was
+                // moved out from under us
+                error!("could not write to {}: {}", self.settings.path.display(), e);
+                *file = None;
+            },
+            None          => (),
+        }
+    }
+
+    /// The line (newline included) for an event. Control characters (such
+    /// as newlines in the message) are replaced with spaces, so each event
+    /// is one line.
+    fn line(&self, event: &NotificationEvent, suppressed: bool) -> String {
+        let mut line = self.settings.time_format.render(event.date);
+        if suppressed {
+            line.push_str(" (suppressed)");
+        }
+        line.push(' ');
+        line.push_str(event.buffer.as_ref().map(|s| s.as_str()).unwrap_or("-"));
+        if let Some(ref nick) = event.nick {
+            if !nick.is_empty() {
+                line.push_str(&format!(" <{}>", nick));
+            }
+        }
+        line.push(' ');
+        line.push_str(&event.message);
+
+        let mut line: String = line.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+        line.push('\n');
+        line
+    }
+
+    /// Shifts path.1 to path.2 and so on (dropping the oldest), then moves
+    /// the log to path.1. With keep set to 0, the log is just removed.
+    fn rotate(&self) -> io::Result<()> {
+        let path = &self.settings.path;
+        if self.settings.keep == 0 {
+            return fs::remove_file(path);
+        }
+        for i in (1..self.settings.keep).rev() {
+            match fs::rename(numbered(path, i), numbered(path, i + 1)) {
+                Err(ref e) if e.kind() == ErrorKind::NotFound => (),
+                result                                         => try!(result),
+            }
+        }
+        fs::rename(path, numbered(path, 1))
+    }
+}
+
+/// Opens the log for appending, returning it and how big it already is
+fn open(path: &Path) -> Result<(File, u64), String> {
+    let file = try!(OpenOptions::new().create(true).append(true).open(path)
+                    .map_err(|e| format!("Could not open {}: {}", path.display(), e)));
+    let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+    Ok((file, size))
+}
+
+/// The path of a rotated log (ex: highlights.log.1)
+fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+    use std::path::Path;
+
+    use super::{numbered, HighlightLog, HighlightLogSettings};
+    use alert::AlertReason;
+    use notify::time_format::TimeFormat;
+    use testing::event;
+
+    fn settings(dir: &Path, max_size: u64, keep: u32) -> HighlightLogSettings {
+        HighlightLogSettings {
+            path: dir.join("logs").join("highlights.log"),
+            max_size: max_size,
+            keep: keep,
+            time_format: TimeFormat::parse("%Y-%m-%d %H:%M:%S", true).unwrap(),
+        }
+    }
+
+    fn read(path: &Path) -> String {
+        let mut text = String::new();
+        File::open(path).unwrap().read_to_string(&mut text).unwrap();
+        text
+    }
+
+    #[test]
+    fn one_line_per_event() {
+        let dir = env::temp_dir().join("weechat-alert-highlight-log-line-test");
+        let _ = fs::remove_dir_all(&dir);
+        let log = HighlightLog::open(&settings(&dir, 0, 0)).unwrap();
+
+        let mut highlight = event(AlertReason::Highlight, "irc.libera.#rust", "alice", "hey,\ncan you look");
+        highlight.date = 1480000000;
+        assert_eq!(log.line(&highlight, false), "2016-11-24 15:06:40 irc.libera.#rust <alice> hey, can you look\n");
+        highlight.nick = None;
+        highlight.buffer = None;
+        assert_eq!(log.line(&highlight, true), "2016-11-24 15:06:40 (suppressed) - hey, can you look\n");
+
+        log.log(&highlight, true);
+        assert_eq!(read(&settings(&dir, 0, 0).path), "2016-11-24 15:06:40 (suppressed) - hey, can you look\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotates_at_the_max_size() {
+        let dir = env::temp_dir().join("weechat-alert-highlight-log-rotate-test");
+        let _ = fs::remove_dir_all(&dir);
+        let mut event = event(AlertReason::Private, "irc.libera.alice", "alice", "");
+        event.date = 1480000000;
+        // Each line is 55 bytes, so three fit in 170 bytes
+        let settings = settings(&dir, 170, 2);
+        let log = HighlightLog::open(&settings).unwrap();
+        for i in 0..10 {
+            event.message = format!("message {}", i);
+            log.log(&event, false);
+        }
+
+        let lines = |path: &Path| read(path).lines().map(|l| l.rsplit(' ').next().unwrap().to_string())
+                                            .collect::<Vec<_>>();
+        assert_eq!(lines(&settings.path), vec!["9"]);
+        assert_eq!(lines(&numbered(&settings.path, 1)), vec!["6", "7", "8"]);
+        assert_eq!(lines(&numbered(&settings.path, 2)), vec!["3", "4", "5"]);
+        assert!(fs::metadata(numbered(&settings.path, 3)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
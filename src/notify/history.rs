@@ -0,0 +1,143 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use notify::NotificationEvent;
+
+/// How many events are kept if history_size isn't set
+pub const DEFAULT_SIZE: usize = 100;
+
+// Messages longer than this are cut short when the history is printed
+const MAX_MESSAGE_LENGTH: usize = 80;
+
+/// An event in the history, with just what gets printed
+#[derive(Debug, Clone)]
+struct Entry {
+    /// The event's date, formatted with time_format
+    time: String,
+    buffer: Option<String>,
+    nick: Option<String>,
+    message: String,
+    suppressed: bool,
+}
+
+/// The last few events (including ones that didn't notify), kept in memory
+/// so you can ask what you missed. It lasts as long as the process, across
+/// reconnects to the relay. Clones share the same history, so the control
+/// socket can read it from its own thread.
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: Arc<Mutex<VecDeque<Entry>>>,
+    /// Most events kept. 0 keeps nothing.
+    size: usize,
+}
+
+impl History {
+    pub fn new(size: usize) -> History {
+        History {
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(size))),
+            size: size,
+        }
+    }
+
+    /// Adds an event, dropping the oldest one if the history is full
+    pub fn add(&self, event: &NotificationEvent, suppressed: bool) {
+        if self.size == 0 {
+            return;
+        }
+        let entry = Entry {
+            time: event.time.clone(),
+            buffer: event.buffer.clone(),
+            nick: event.nick.clone(),
+            message: event.message.clone(),
+            suppressed: suppressed,
+        };
+
+        // A reader that panicked while holding the lock can't have left the
+        // entries half changed, so carry on with them
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= self.size {
+            entries.pop_front();
+        }
+        entries.push_back(entry);
+    }
+
+    /// Up to count of the latest events as lines (without newlines), newest
+    /// first, like:
+    ///
+    /// 2024-05-01 14:03:12 irc.libera.#rust <alice> hey, can you look at ...
+    pub fn lines(&self, count: usize) -> Vec<String> {
+        // Only copy the entries while holding the lock, so adding events
+        // never waits on formatting
+        let latest: Vec<Entry> = {
+            let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+            entries.iter().rev().take(count).cloned().collect()
+        };
+        latest.iter().map(line).collect()
+    }
+}
+
+/// The line for an entry, with its message cut short and any control
+/// characters replaced by spaces
+fn line(entry: &Entry) -> String {
+    let mut line = entry.time.clone();
+    if entry.suppressed {
+        line.push_str(" (suppressed)");
+    }
+    line.push(' ');
+    line.push_str(entry.buffer.as_ref().map(|s| s.as_str()).unwrap_or("-"));
+    if let Some(ref nick) = entry.nick {
+        if !nick.is_empty() {
+            line.push_str(&format!(" <{}>", nick));
+        }
+    }
+    line.push(' ');
+    if entry.message.chars().count() > MAX_MESSAGE_LENGTH {
+        line.extend(entry.message.chars().take(MAX_MESSAGE_LENGTH - 3));
+        line.push_str("...");
+    } else {
+        line.push_str(&entry.message);
+    }
+    line.chars().map(|c| if c.is_control() { ' ' } else { c }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::History;
+    use alert::AlertReason;
+    use testing::event;
+
+    #[test]
+    fn newest_first_up_to_the_size() {
+        let history = History::new(3);
+        for i in 0..5 {
+            let mut event = event(AlertReason::Highlight, "irc.libera.#rust", "alice", &format!("message {}", i));
+            event.time = format!("14:0{}", i);
+            history.add(&event, i == 4);
+        }
+        assert_eq!(history.lines(10), vec!["14:04 (suppressed) irc.libera.#rust <alice> message 4",
+                                           "14:03 irc.libera.#rust <alice> message 3",
+                                           "14:02 irc.libera.#rust <alice> message 2"]);
+        assert_eq!(history.lines(1).len(), 1);
+        // Clones share it
+        assert_eq!(history.clone().lines(10).len(), 3);
+
+        let nothing = History::new(0);
+        nothing.add(&event(AlertReason::Private, "irc.libera.alice", "alice", "hi"), false);
+        assert!(nothing.lines(10).is_empty());
+    }
+
+    #[test]
+    fn long_messages_are_cut_short() {
+        let history = History::new(1);
+        let mut event = event(AlertReason::Private, "irc.libera.alice", "", &"é".repeat(100));
+        event.time = "14:00".to_string();
+        event.message.push('\n');
+        history.add(&event, false);
+        assert_eq!(history.lines(1), vec![format!("14:00 irc.libera.alice {}...", "é".repeat(77))]);
+
+        event.buffer = None;
+        event.message = "a line\nbreak".to_string();
+        history.add(&event, false);
+        assert_eq!(history.lines(1), vec!["14:00 - a line break"]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::cell::{Cell, RefCell};
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use rustc_serialize::json::Json;
+
+use notify::{self, NotificationEvent};
+use notify::webhook;
+
+/// Appends every event as a line of json to a file, for a machine readable
+/// archive. The object is the webhook payload plus "suppressed":
+///
+/// {"buffer": "irc.libera.#rust", "class": "highlight", "highlight": true,
+///  "message": "...", "nick": "bob", "reason": "highlight", "server": "libera",
+///  "suppressed": false, "tags": ["irc_privmsg", ...], "timestamp": 1480000000}
+///
+/// Each line goes out in a single write to a file opened for appending, so
+/// a crash can't leave half a line behind. The file is reopened on SIGHUP,
+/// for logrotate.
+pub struct JsonLog {
+    path: PathBuf,
+    /// The open log, or None if it couldn't be opened last time we tried
+    file: RefCell<Option<File>>,
+    /// notify::hangups() when the file was opened
+    opened_at: Cell<usize>,
+}
+
+impl JsonLog {
+    /// Opens the log, creating its directory if needed
+    pub fn open(path: &Path) -> Result<JsonLog, String> {
+        if let Some(dir) = path.parent() {
+            try!(fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e)));
+        }
+        let file = try!(open(path));
+        Ok(JsonLog {
+            path: path.to_path_buf(),
+            file: RefCell::new(Some(file)),
+            opened_at: Cell::new(notify::hangups()),
+        })
+    }
+
+    /// Appends an event. suppressed is set for events that didn't notify
+    /// (ex: because of the buffer cooldown).
+    pub fn log(&self, event: &NotificationEvent, suppressed: bool) {
+        let mut file = self.file.borrow_mut();
+        if self.opened_at.get() != notify::hangups() || file.is_none() {
+            self.opened_at.set(notify::hangups());
+            *file = match open(&self.path) {
+                Ok(file) => Some(file),
+                Err(e)   => {
+                    error!("{}", e);
+                    None
+                },
+            };
+        }
+
+        let line = line(event, suppressed);
+        let written = file.as_mut().map(|f| f.write_all(line.as_bytes()));
+        if let Some(Err(e)) = written {
+            // Try opening it again for the next event, in case it was
+            // moved out from under us
+            error!("could not write to {}: {}", self.path.display(), e);
+            *file = None;
+        }
+    }
+}
+
+/// The json line (newline included) for an event
+pub fn line(event: &NotificationEvent, suppressed: bool) -> String {
+    let mut doc = webhook::event_doc(event);
+    doc.insert("suppressed".to_string(), Json::Boolean(suppressed));
+    format!("{}\n", Json::Object(doc))
+}
+
+fn open(path: &Path) -> Result<File, String> {
+    OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|e| format!("Could not open {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+
+    use rustc_serialize::json::Json;
+
+    use super::{line, JsonLog};
+    use alert::AlertReason;
+    use testing::event;
+
+    #[test]
+    fn event_as_a_line_of_json() {
+        let mut event = event(AlertReason::Highlight, "irc.libera.#rust", "bob", "me: \"quoted\"\nand a new line");
+        event.tags = vec!["irc_privmsg".to_string(), "nick_bob".to_string()];
+        event.date = 1480000000;
+        let line = line(&event, true);
+        assert!(line.ends_with('\n'));
+        assert_eq!(line.lines().count(), 1);
+
+        let doc = Json::from_str(&line).unwrap();
+        let doc = doc.as_object().unwrap();
+        assert_eq!(doc["buffer"].as_string(), Some("irc.libera.#rust"));
+        assert_eq!(doc["server"].as_string(), Some("libera"));
+        assert_eq!(doc["class"].as_string(), Some("highlight"));
+        assert_eq!(doc["nick"].as_string(), Some("bob"));
+        assert_eq!(doc["message"].as_string(), Some("me: \"quoted\"\nand a new line"));
+        let tags: Vec<&str> = doc["tags"].as_array().unwrap().iter().map(|t| t.as_string().unwrap()).collect();
+        assert_eq!(tags, vec!["irc_privmsg", "nick_bob"]);
+        assert_eq!(doc["highlight"].as_boolean(), Some(true));
+        assert_eq!(doc["timestamp"].as_i64(), Some(1480000000));
+        assert_eq!(doc["suppressed"].as_boolean(), Some(true));
+    }
+
+    #[test]
+    fn log_appends_a_line_per_event() {
+        let dir = env::temp_dir().join("weechat-alert-json-log-test");
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("events.json");
+        let log = JsonLog::open(&path).unwrap();
+        log.log(&event(AlertReason::Private, "irc.libera.alice", "alice", "one"), false);
+        log.log(&event(AlertReason::Private, "irc.libera.alice", "alice", "two"), true);
+
+        let mut text = String::new();
+        File::open(&path).unwrap().read_to_string(&mut text).unwrap();
+        let docs: Vec<Json> = text.lines().map(|l| Json::from_str(l).unwrap()).collect();
+        assert_eq!(docs.len(), 2);
+        assert_eq!(docs[0].find("message").and_then(|m| m.as_string()), Some("one"));
+        assert_eq!(docs[1].find("suppressed").and_then(|s| s.as_boolean()), Some(true));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
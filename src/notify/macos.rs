@@ -0,0 +1,97 @@
+use std::process::Command;
+use std::sync::mpsc::Receiver;
+
+use notify::{self, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+// Format of the notification body (the buffer is the title)
+const BODY_FORMAT: &'static str = "<{nick}> {message}";
+
+// Shows the notification from the title and body given as arguments, so
+// neither is ever read as applescript
+const SCRIPT: [&'static str; 3] = ["on run argv",
+                                   "display notification (item 2 of argv) with title (item 1 of argv)",
+                                   "end run"];
+
+/// Shows notifications in the macOS notification center, through osascript's
+/// display notification. This is what "desktop" is on macOS.
+///
+/// The notifications come from Script Editor rather than us, and there are
+/// no icons or urgencies. Posting our own needs the UserNotifications
+/// framework, which only works for a signed app bundle, while osascript
+/// works for any binary on every macOS since 10.9.
+pub struct MacOs {
+    body: Template,
+    /// Queue of osascript commands waiting to run
+    queue: Queue<Command>,
+}
+
+impl MacOs {
+    pub fn new(settings: &Settings) -> MacOs {
+        // Bodies are limited to the same length as the main format
+        let mut body = Template::parse(BODY_FORMAT).unwrap();
+        body.set_max_length(settings.format.max_length());
+        MacOs {
+            body: body,
+            queue: Queue::start("desktop", QUEUE_SIZE, run_all),
+        }
+    }
+
+    /// The arguments to osascript for an event: the script, then the title
+    /// and body it shows
+    fn arguments(&self, event: &NotificationEvent) -> Vec<String> {
+        let mut args = Vec::new();
+        for line in &SCRIPT {
+            args.push("-e".to_string());
+            args.push(line.to_string());
+        }
+        args.push(event.buffer.clone().unwrap_or("weechat".to_string()));
+        args.push(self.body.render(event));
+        args
+    }
+}
+
+impl Notifier for MacOs {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut cmd = Command::new("osascript");
+        cmd.args(&self.arguments(event));
+        self.queue.send(cmd)
+    }
+
+    fn startup_check(&self) -> Result<(), NotifyError> {
+        notify::find_program("osascript")
+    }
+}
+
+/// Runs every queued osascript in order, so a slow notification center
+/// can't hold up processing messages
+fn run_all(pending: Receiver<Command>) {
+    for mut cmd in pending {
+        match cmd.status() {
+            Ok(ref status) if status.success() => (),
+            Ok(status) => error!("osascript failed ({})", status),
+            Err(e)     => error!("could not run osascript: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MacOs;
+    use alert::AlertReason;
+    use notify::Settings;
+    use testing::event;
+
+    #[test]
+    fn title_and_body_are_arguments_of_the_script() {
+        let macos = MacOs::new(&Settings::new());
+        let mut event = event(AlertReason::Highlight, "irc.libera.#rust", "alice", "\" & (do shell script \"ls\")");
+        let args = macos.arguments(&event);
+        assert_eq!(args.iter().filter(|a| *a == "-e").count(), 3);
+        assert_eq!(&args[6..], ["irc.libera.#rust", "<alice> \" & (do shell script \"ls\")"]);
+
+        event.buffer = None;
+        assert_eq!(macos.arguments(&event)[6], "weechat");
+    }
+}
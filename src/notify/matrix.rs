@@ -0,0 +1,208 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustc_serialize::json::Json;
+
+use http::{self, Url};
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+// Seconds to wait for the homeserver
+const TIMEOUT: u64 = 10;
+
+// Seconds to wait after a rate limited message, if the response doesn't say
+const RATE_LIMIT_DELAY: u64 = 5;
+
+// Seconds to wait before trying a message again after any other failure
+const RETRY_DELAY: u64 = 5;
+
+// Attempts at sending a message before it is dropped. Rate limited attempts
+// don't count, as those are waited out instead
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Settings for the matrix backend, from [notifier.matrix]
+#[derive(Clone)]
+pub struct MatrixSettings {
+    /// Url of the homeserver, such as https://matrix.org
+    pub homeserver: String,
+    /// Access token of the account sending the messages
+    pub access_token: String,
+    /// Room to send messages to, such as "!abcdef:matrix.org"
+    pub room_id: String,
+    /// Plain text body of messages
+    pub format: Template,
+    /// Html body of messages, if they should have one
+    pub html_format: Option<Template>,
+}
+
+/// Debug output leaves out the access token, so it can't end up in a log
+impl fmt::Debug for MatrixSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MatrixSettings")
+         .field("homeserver", &self.homeserver)
+         .field("room_id", &self.room_id)
+         .field("format", &self.format)
+         .field("html_format", &self.html_format)
+         .finish()
+    }
+}
+
+/// A message waiting to be sent, with the transaction id it is always sent
+/// with (so if a retry reaches the homeserver twice, it is only posted once)
+struct Pending {
+    txn_id: String,
+    event: String,
+}
+
+/// Sends notifications as messages in a matrix room
+pub struct Matrix {
+    settings: MatrixSettings,
+    /// Start of every transaction id, so ids from an earlier run are never
+    /// reused
+    txn_prefix: String,
+    /// Number of messages sent so far, which ends every transaction id
+    txn_count: Cell<u64>,
+    /// Queue of messages waiting to be sent
+    queue: Queue<Pending>,
+}
+
+impl Matrix {
+    /// Creates the backend, checking that the access token works and starting
+    /// the thread that sends messages
+    pub fn new(settings: &Settings) -> Result<Matrix, String> {
+        let settings = try!(settings.matrix.clone()
+                            .ok_or("The matrix notifier needs a homeserver, access_token and room_id in [notifier.matrix]"));
+        let homeserver = settings.homeserver.trim_right_matches('/').to_string();
+        let headers = vec![("Authorization".to_string(), format!("Bearer {}", settings.access_token))];
+
+        // If the homeserver can't be reached right now we carry on, as it may
+        // well be back by the time there is something to send
+        let whoami = try!(Url::parse(&format!("{}/_matrix/client/v3/account/whoami", homeserver))
+                          .map_err(|e| format!("'notifier.matrix.homeserver': {}", e)));
+        match http::get(&whoami, &headers, Duration::from_secs(TIMEOUT)) {
+            Ok(ref response) if response.status == 401 || response.status == 403 => {
+                return Err(format!("The matrix access token is invalid ({})", api_error(&response.body)));
+            },
+            Ok(ref response) if !response.success() => {
+                warn!("could not check the matrix access token ({}: {})",
+                      response.status, api_error(&response.body));
+            },
+            Ok(_) => (),
+            Err(e) => warn!("could not check the matrix access token ({})", e),
+        }
+
+        let send_url = format!("{}/_matrix/client/v3/rooms/{}/send/m.room.message",
+                               homeserver, http::percent_encode(&settings.room_id));
+        let queue = Queue::start("matrix", QUEUE_SIZE,
+                                 move |pending| send_all(send_url, headers, pending));
+
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        Ok(Matrix {
+            settings: settings,
+            txn_prefix: format!("weechat-alert.{}", started),
+            txn_count: Cell::new(0),
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Matrix {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut content = BTreeMap::new();
+        content.insert("msgtype".to_string(), Json::String("m.text".to_string()));
+        content.insert("body".to_string(), Json::String(self.settings.format.render(event)));
+        if let Some(ref html_format) = self.settings.html_format {
+            content.insert("format".to_string(), Json::String("org.matrix.custom.html".to_string()));
+            content.insert("formatted_body".to_string(),
+                           Json::String(html_format.render_escaped(event, escape_html)));
+        }
+
+        self.txn_count.set(self.txn_count.get() + 1);
+        let pending = Pending {
+            txn_id: format!("{}.{}", self.txn_prefix, self.txn_count.get()),
+            event: Json::Object(content).to_string(),
+        };
+        self.queue.send(pending)
+    }
+}
+
+/// Escapes text for the html body of a message
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Sends every queued message in order. When the homeserver says we are
+/// rate limited, messages wait for as long as it asks instead of being
+/// dropped.
+///
+/// Note: Nothing printed here may include the headers, as they hold the
+///       access token.
+fn send_all(send_url: String, headers: Vec<(String, String)>, pending: Receiver<Pending>) {
+    for message in pending {
+        let url = match Url::parse(&format!("{}/{}", send_url, http::percent_encode(&message.txn_id))) {
+            Ok(url) => url,
+            Err(e)  => {
+                error!("matrix failed: {}", e);
+                continue;
+            },
+        };
+
+        let mut attempts = 0;
+        loop {
+            let delay = match http::put(&url, &headers, "application/json", message.event.as_bytes(),
+                                        Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => break,
+                Ok(ref response) if response.status == 429 => {
+                    let wait = retry_after(&response.body).unwrap_or(Duration::from_secs(RATE_LIMIT_DELAY));
+                    info!("Matrix rate limit reached, holding messages for {} seconds", wait.as_secs());
+                    thread::sleep(wait);
+                    continue;
+                },
+                Ok(response) => {
+                    error!("matrix returned {}: {}", response.status, api_error(&response.body));
+                    // Sending the same thing again won't fix a client error
+                    if response.status / 100 == 4 {
+                        break;
+                    }
+                    Duration::from_secs(RETRY_DELAY)
+                },
+                Err(e)       => {
+                    error!("matrix failed: {}", e);
+                    Duration::from_secs(RETRY_DELAY)
+                },
+            };
+            attempts += 1;
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on a matrix message after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// How long a rate limited response says to wait, from a body like
+/// {"errcode": "M_LIMIT_EXCEEDED", "retry_after_ms": 2000}
+fn retry_after(body: &str) -> Option<Duration> {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find("retry_after_ms").and_then(|r| r.as_u64()))
+        .map(Duration::from_millis)
+}
+
+/// Pulls the error out of an api response, which look like
+/// {"errcode": "M_FORBIDDEN", "error": "..."}
+fn api_error(body: &str) -> String {
+    let json = Json::from_str(body).ok();
+    let field = |name| json.as_ref().and_then(|j| j.find(name)).and_then(|e| e.as_string());
+    match (field("errcode"), field("error")) {
+        (Some(code), Some(error)) => format!("{}: {}", code, error),
+        (Some(code), None)        => code.to_string(),
+        (None, Some(error))       => error.to_string(),
+        (None, None)              => "unknown error".to_string(),
+    }
+}
@@ -0,0 +1,867 @@
+use std::env;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use libc;
+
+use alert::AlertReason;
+use buffers;
+use errors::WeechatError;
+use colors;
+use line::BufferLine;
+use reply::Replies;
+
+pub mod bell;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(not(any(target_os = "macos", windows)))]
+pub mod desktop;
+pub mod discord;
+pub mod fifo;
+pub mod email;
+pub mod gotify;
+pub mod highlight_log;
+pub mod history;
+pub mod json_log;
+#[cfg(target_os = "macos")]
+pub mod macos;
+pub mod matrix;
+pub mod ntfy;
+pub mod priority;
+pub mod sound;
+#[cfg(feature = "pushbullet")]
+pub mod pushbullet;
+pub mod pushover;
+pub mod queue;
+pub mod slack;
+pub mod status;
+pub mod stdout;
+pub mod syslog;
+pub mod telegram;
+pub mod template;
+pub mod time_format;
+#[cfg(windows)]
+pub mod toast;
+#[cfg(feature = "tray")]
+pub mod tray;
+pub mod tts;
+pub mod twilio;
+pub mod webhook;
+#[cfg(feature = "xmpp")]
+pub mod xmpp;
+
+use self::bell::Bell;
+#[cfg(feature = "dbus")]
+use self::dbus::Dbus;
+use self::discord::{Discord, DiscordSettings};
+use self::email::{Email, EmailSettings};
+use self::fifo::{Fifo, FifoSettings};
+use self::gotify::{Gotify, GotifySettings};
+use self::highlight_log::{HighlightLog, HighlightLogSettings};
+use self::history::History;
+use self::json_log::JsonLog;
+#[cfg(target_os = "macos")]
+use self::macos::MacOs;
+use self::matrix::{Matrix, MatrixSettings};
+use self::ntfy::{Ntfy, NtfySettings};
+use self::priority::{PriorityRule, Scale};
+#[cfg(feature = "pushbullet")]
+use self::pushbullet::{Pushbullet, PushbulletSettings};
+use self::pushover::{Pushover, PushoverSettings};
+use self::slack::{Slack, SlackSettings};
+use self::sound::{SoundPlayer, SoundPolicy};
+use self::status::Status;
+use self::stdout::Stdout;
+use self::syslog::{Syslog, SyslogSettings};
+use self::telegram::{Telegram, TelegramSettings};
+use self::template::{Template, DEFAULT_FORMAT};
+use self::time_format::TimeFormat;
+#[cfg(windows)]
+use self::toast::Toast;
+use self::tts::Tts;
+use self::twilio::{Twilio, TwilioSettings};
+use self::webhook::{Webhook, WebhookSettings};
+#[cfg(feature = "xmpp")]
+use self::xmpp::{Xmpp, XmppSettings};
+
+// Sound played for notifications if none is configured, in the home directory
+const DEFAULT_SOUND: &'static str = "weechat_alert.wav";
+
+// Every notifier, whether or not this build has it (see check_name)
+const NAMES: [&'static str; 19] = ["sound", "desktop", "stdout", "bell", "tts", "webhook", "pushover", "pushbullet",
+                                   "telegram", "discord", "slack", "matrix", "ntfy", "gotify", "xmpp", "email",
+                                   "twilio", "syslog", "dbus"];
+
+// Counts the SIGHUPs we got, so each log can tell if it should reopen its
+// file (for logrotate) since it last opened it
+static HANGUPS: AtomicUsize = ATOMIC_USIZE_INIT;
+
+#[cfg(unix)]
+extern "C" fn hangup(_: libc::c_int) {
+    HANGUPS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Why a line is being notified on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventClass {
+    /// A private message
+    Private,
+    /// A highlight in a channel
+    Highlight,
+    /// Any line in a watched buffer
+    Watch,
+}
+
+impl EventClass {
+    /// Parses a class from its name in the config file
+    pub fn from_name(name: &str) -> Option<EventClass> {
+        match name {
+            "private"   => Some(EventClass::Private),
+            "highlight" => Some(EventClass::Highlight),
+            "watch"     => Some(EventClass::Watch),
+            _           => None,
+        }
+    }
+
+    /// Name of the class, as used in the config file
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EventClass::Private   => "private",
+            EventClass::Highlight => "highlight",
+            EventClass::Watch     => "watch",
+        }
+    }
+}
+
+/// How urgent a desktop notification is, in the freedesktop notification
+/// urgency levels. The desktop backends get this from the priority.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Urgency {
+    Low,
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    /// Parses an urgency from its name in the config file
+    pub fn from_name(name: &str) -> Option<Urgency> {
+        match name {
+            "low"      => Some(Urgency::Low),
+            "normal"   => Some(Urgency::Normal),
+            "critical" => Some(Urgency::Critical),
+            _          => None,
+        }
+    }
+
+    /// Name of the urgency, which is also the freedesktop name for it
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Urgency::Low      => "low",
+            Urgency::Normal   => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+/// How important a notification is. The priority rules pick this for every
+/// event, and each backend turns it into its own scale (a pushover priority,
+/// a desktop urgency, etc).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+    Critical,
+}
+
+impl Priority {
+    /// Every priority, lowest first
+    pub fn all() -> [Priority; 4] {
+        [Priority::Low, Priority::Normal, Priority::High, Priority::Critical]
+    }
+
+    /// Parses a priority from its name in the config file
+    pub fn from_name(name: &str) -> Option<Priority> {
+        match name {
+            "low"      => Some(Priority::Low),
+            "normal"   => Some(Priority::Normal),
+            "high"     => Some(Priority::High),
+            "critical" => Some(Priority::Critical),
+            _          => None,
+        }
+    }
+
+    /// Name of the priority, as used in the config file
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Priority::Low      => "low",
+            Priority::Normal   => "normal",
+            Priority::High     => "high",
+            Priority::Critical => "critical",
+        }
+    }
+}
+
+/// Settings that can differ per event class
+#[derive(Debug, Clone)]
+pub struct ClassSettings {
+    /// If lines of this class alert at all
+    pub enabled: bool,
+    /// Priority of the class's notifications, unless a priority rule matches
+    pub priority: Priority,
+    /// Icon for desktop notifications, either a path or an icon theme name
+    pub icon: Option<String>,
+    /// Sound to play instead of the global one
+    pub sound: Option<PathBuf>,
+    /// Volume of the sound, from 0.0 (silent) to 1.0 (as loud as the file)
+    pub volume: f32,
+}
+
+impl ClassSettings {
+    /// Settings for a class that alerts with this priority, at full volume
+    pub fn new(priority: Priority) -> ClassSettings {
+        ClassSettings {
+            enabled: true,
+            priority: priority,
+            icon: None,
+            sound: None,
+            volume: 1.0,
+        }
+    }
+}
+
+/// Settings for every event class
+#[derive(Debug, Clone)]
+pub struct Classes {
+    pub private: ClassSettings,
+    pub highlight: ClassSettings,
+    pub watch: ClassSettings,
+}
+
+impl Classes {
+    /// Default settings: private messages are high priority, highlights
+    /// normal, and lines in watched buffers low
+    pub fn new() -> Classes {
+        Classes {
+            private: ClassSettings::new(Priority::High),
+            highlight: ClassSettings::new(Priority::Normal),
+            watch: ClassSettings::new(Priority::Low),
+        }
+    }
+
+    pub fn get(&self, class: EventClass) -> &ClassSettings {
+        match class {
+            EventClass::Private   => &self.private,
+            EventClass::Highlight => &self.highlight,
+            EventClass::Watch     => &self.watch,
+        }
+    }
+
+    pub fn get_mut(&mut self, class: EventClass) -> &mut ClassSettings {
+        match class {
+            EventClass::Private   => &mut self.private,
+            EventClass::Highlight => &mut self.highlight,
+            EventClass::Watch     => &mut self.watch,
+        }
+    }
+}
+
+/// Settings for the buffers matching a pattern (a full buffer name, or one
+/// using '*' wildcards)
+#[derive(Debug, Clone)]
+pub struct BufferSettings {
+    pub pattern: String,
+    /// Sound to play instead of the class or global one
+    pub sound: Option<PathBuf>,
+    /// Minimum time between notifications from the buffer, instead of
+    /// buffer_cooldown
+    pub cooldown: Option<Duration>,
+}
+
+/// Settings shared by all of the notification backends
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Per class settings
+    pub classes: Classes,
+    /// Rules picking the priority of a notification, first match wins. An
+    /// event no rule matches gets its class's priority
+    pub priority_rules: Vec<PriorityRule>,
+    /// Per buffer settings
+    pub buffers: Vec<BufferSettings>,
+    /// How text based backends render notifications. Its max length also
+    /// applies to backends that render their own
+    pub format: Template,
+    /// How times are written in notifications ({time}), the highlight log
+    /// and the history
+    pub time_format: TimeFormat,
+    /// Remove color and formatting codes from the nick and message
+    pub strip_formatting: bool,
+    /// Sound to play for notifications without a buffer or class override
+    pub sound_path: PathBuf,
+    /// What the sound notifier does with sounds while one is playing
+    pub sound_policy: SoundPolicy,
+    /// Sound to play when the connection to the relay is lost
+    pub disconnect_sound: Option<PathBuf>,
+    /// Sound to play when the connection to the relay is back after being lost
+    pub reconnect_sound: Option<PathBuf>,
+    /// File every event is appended to as a line of json, if it is set
+    pub json_log: Option<PathBuf>,
+    /// Fifo every event is written to, if it is set
+    pub fifo: Option<FifoSettings>,
+    /// Plain text log of every event, if it is set
+    pub highlight_log: Option<HighlightLogSettings>,
+    /// How many of the latest events are kept in memory, for the control
+    /// socket's history
+    pub history_size: usize,
+    /// Tty the bell backend rings (ex: /dev/tty), or None for stdout
+    pub bell_path: Option<PathBuf>,
+    /// Only ring the bell through stdout if stdout is a tty
+    pub bell_only_tty: bool,
+    /// Show desktop notifications over dbus, so they take inline replies
+    /// where the notification daemon supports them
+    pub desktop_reply: bool,
+    /// Desktop notification urgency for each priority
+    pub desktop_urgency: Scale<Urgency>,
+    /// Text to speech command and its arguments, which reads text on stdin
+    pub tts_command: Vec<String>,
+    /// What the tts backend says
+    pub tts_format: Template,
+    /// Where the webhook backend posts to, if it is configured
+    pub webhook: Option<WebhookSettings>,
+    /// Pushover credentials and settings, if it is configured
+    pub pushover: Option<PushoverSettings>,
+    /// Pushbullet access token and settings, if it is configured
+    #[cfg(feature = "pushbullet")]
+    pub pushbullet: Option<PushbulletSettings>,
+    /// Telegram bot token and chat, if it is configured
+    pub telegram: Option<TelegramSettings>,
+    /// Discord webhook and settings, if it is configured
+    pub discord: Option<DiscordSettings>,
+    /// Slack webhook and settings, if it is configured
+    pub slack: Option<SlackSettings>,
+    /// Matrix account and room, if it is configured
+    pub matrix: Option<MatrixSettings>,
+    /// Ntfy server and topic, if it is configured
+    pub ntfy: Option<NtfySettings>,
+    /// Gotify server and application token, if it is configured
+    pub gotify: Option<GotifySettings>,
+    /// Xmpp account and recipient, if it is configured
+    #[cfg(feature = "xmpp")]
+    pub xmpp: Option<XmppSettings>,
+    /// Smtp server and addresses, if it is configured
+    pub email: Option<EmailSettings>,
+    /// Twilio account, numbers and which notifications text, if it is
+    /// configured
+    pub twilio: Option<TwilioSettings>,
+    /// Facility and ident for the syslog backend
+    pub syslog: SyslogSettings,
+}
+
+impl Settings {
+    /// Creates the default settings
+    pub fn new() -> Settings {
+        let mut sound_path = env::home_dir().unwrap_or(PathBuf::new());
+        sound_path.push(DEFAULT_SOUND);
+
+        let mut tts_format = Template::parse(tts::DEFAULT_FORMAT).unwrap();
+        tts_format.set_max_length(tts::DEFAULT_MAX_LENGTH);
+
+        Settings {
+            classes: Classes::new(),
+            priority_rules: Vec::new(),
+            buffers: Vec::new(),
+            format: Template::parse(DEFAULT_FORMAT).unwrap(),
+            time_format: TimeFormat::parse(time_format::DEFAULT_FORMAT, false).unwrap(),
+            strip_formatting: true,
+            sound_path: sound_path,
+            sound_policy: SoundPolicy::Coalesce,
+            disconnect_sound: None,
+            reconnect_sound: None,
+            json_log: None,
+            fifo: None,
+            highlight_log: None,
+            history_size: history::DEFAULT_SIZE,
+            bell_path: None,
+            bell_only_tty: true,
+            desktop_reply: false,
+            desktop_urgency: priority::DEFAULT_DESKTOP_URGENCY,
+            tts_command: vec!["espeak-ng".to_string()],
+            tts_format: tts_format,
+            webhook: None,
+            pushover: None,
+            #[cfg(feature = "pushbullet")]
+            pushbullet: None,
+            telegram: None,
+            discord: None,
+            slack: None,
+            matrix: None,
+            ntfy: None,
+            gotify: None,
+            #[cfg(feature = "xmpp")]
+            xmpp: None,
+            email: None,
+            twilio: None,
+            syslog: SyslogSettings::new(),
+        }
+    }
+
+    /// Returns the settings for a buffer. If several patterns match, a
+    /// pattern that is exactly the buffer name wins, and otherwise the longest
+    /// (most specific) pattern does.
+    pub fn buffer(&self, name: Option<&str>) -> Option<&BufferSettings> {
+        let name = match name {
+            Some(name) => name,
+            None       => return None,
+        };
+        if let Some(settings) = self.buffers.iter().find(|b| b.pattern == name) {
+            return Some(settings);
+        }
+        self.buffers.iter()
+            .filter(|b| buffers::matches(&b.pattern, name))
+            .max_by_key(|b| b.pattern.len())
+    }
+}
+
+/// Everything a backend needs to know to notify about a line
+#[derive(Debug, Clone)]
+pub struct NotificationEvent {
+    /// Why we are notifying
+    pub class: EventClass,
+    /// Why we are notifying, in more detail (ex: which watch pattern
+    /// matched)
+    pub reason: AlertReason,
+    /// How important the notification is
+    pub priority: Priority,
+    /// Full name of the buffer the line is in, if we know it
+    pub buffer: Option<String>,
+    /// Nick (line prefix) that sent the line
+    pub nick: Option<String>,
+    /// The line itself
+    pub message: String,
+    /// Tags weechat attached to the line
+    pub tags: Vec<String>,
+    /// If weechat flagged the line as a highlight
+    pub highlight: bool,
+    /// Date of the line as an epoch timestamp, or when it arrived if
+    /// weechat didn't send one
+    pub date: i64,
+    /// The date, formatted with time_format
+    pub time: String,
+}
+
+impl NotificationEvent {
+    pub fn new(reason: AlertReason, priority: Priority, buffer: Option<&str>,
+               line: &BufferLine, time_format: &TimeFormat) -> NotificationEvent {
+        // Lines without a date (missing or 0) are dated when they arrived
+        let date = match line.date() {
+            0    => now(),
+            date => date,
+        };
+        NotificationEvent {
+            class: reason.class(),
+            reason: reason,
+            priority: priority,
+            buffer: buffer.map(|s| s.to_string()),
+            nick: line.prefix().map(|s| s.to_string()),
+            message: line.message().unwrap_or("").to_string(),
+            tags: line.tags().to_vec(),
+            highlight: line.highlight(),
+            date: date,
+            time: time_format.render(date),
+        }
+    }
+
+    /// Name of the network (irc server) the line is from. This is taken from
+    /// the buffer's full name, which is plugin.server.buffer for irc.
+    pub fn network(&self) -> Option<&str> {
+        self.buffer.as_ref().and_then(|b| b.split('.').nth(1))
+    }
+}
+
+/// Why a backend couldn't send a notification
+#[derive(Debug)]
+pub struct NotifyError {
+    description: String,
+}
+
+impl NotifyError {
+    pub fn new<S: Into<String>>(description: S) -> NotifyError {
+        NotifyError { description: description.into() }
+    }
+}
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.description)
+    }
+}
+
+impl error::Error for NotifyError {
+    fn description(&self) -> &str {
+        &self.description
+    }
+}
+
+impl From<io::Error> for NotifyError {
+    fn from(err: io::Error) -> NotifyError {
+        NotifyError::new(err.to_string())
+    }
+}
+
+/// A notification backend. Backends are enabled by name from the config (see
+/// Notifiers::enable), or registered with Notifiers::register. Every enabled
+/// backend gets every notification, and one failing never stops the others.
+///
+/// notify is called on the thread reading from the relay, so a backend that
+/// does anything slow (ex: network requests) should hand the work to its own
+/// thread (see queue::Queue) and return right away.
+pub trait Notifier {
+    /// Sends a notification. Backends that queue their work only return
+    /// errors from queueing it; failures after that are logged where they
+    /// happen.
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError>;
+
+    /// Checks the backend looks like it can work (ex: that a command it runs
+    /// is installed), once it is enabled. A failure is a warning, as it may
+    /// start working later.
+    fn startup_check(&self) -> Result<(), NotifyError> {
+        Ok(())
+    }
+
+    /// Called when we connect to the relay (every time, not just after the
+    /// connection was lost)
+    fn connected(&self, _host: &str) {}
+
+    /// Called when the connection to the relay was lost, and we alerted on it
+    fn disconnected(&self, _host: &str, _err: &WeechatError) {}
+
+    /// Called with the error that ended (or prevented) a connection to the
+    /// relay
+    fn error(&self, _host: &str, _err: &WeechatError) {}
+}
+
+/// The set of backends notifications are sent to
+pub struct Notifiers {
+    settings: Settings,
+    /// Enabled backends, by name, in the order they were enabled
+    backends: Vec<(String, Box<Notifier>)>,
+    json_log: Option<JsonLog>,
+    fifo: Option<Fifo>,
+    highlight_log: Option<HighlightLog>,
+    history: History,
+    status: Status,
+    /// Where backends that take replies to notifications (ntfy and desktop)
+    /// send them, if anywhere
+    replies: Option<Replies>,
+}
+
+impl Notifiers {
+    /// Creates a set with no backends enabled
+    pub fn new(settings: Settings) -> Notifiers {
+        let history = History::new(settings.history_size);
+        Notifiers {
+            settings: settings,
+            backends: Vec::new(),
+            json_log: None,
+            fifo: None,
+            highlight_log: None,
+            history: history,
+            status: Status::new(),
+            replies: None,
+        }
+    }
+
+    /// Enables a backend by its name in the config file ("sound", "desktop",
+    /// "stdout", "bell", "tts", "webhook", "pushover", "pushbullet",
+    /// "telegram", "discord", "slack", "matrix", "ntfy", "gotify", "xmpp",
+    /// "email", "twilio", "syslog" or "dbus"). Returns an error for an
+    /// unknown backend, or if the settings are not valid for it. Enabling a
+    /// backend twice replaces it.
+    pub fn enable(&mut self, name: &str) -> Result<(), String> {
+        let notifier = try!(build(name, &self.settings, self.replies.as_ref()));
+        self.register(name, notifier);
+        Ok(())
+    }
+
+    /// Sets where backends that take replies to notifications send them.
+    /// This only applies to backends enabled after it.
+    pub fn set_replies(&mut self, replies: Replies) {
+        self.replies = Some(replies);
+    }
+
+    /// Adds a backend, running its startup check. A backend already
+    /// registered with the same name is replaced.
+    pub fn register(&mut self, name: &str, notifier: Box<Notifier>) {
+        if let Err(e) = notifier.startup_check() {
+            warn!("the {} notifier may not work: {}", name, e);
+        }
+        self.backends.retain(|&(ref n, _)| n != name);
+        self.backends.push((name.to_string(), notifier));
+    }
+
+    /// Opens the json log, the fifo and the highlight log, if they are
+    /// configured, and starts reopening the logs on SIGHUP (on unix, where
+    /// there is one)
+    pub fn open_logs(&mut self) -> Result<(), String> {
+        if let Some(ref path) = self.settings.json_log {
+            self.json_log = Some(try!(JsonLog::open(path)));
+        }
+        if let Some(ref settings) = self.settings.fifo {
+            self.fifo = Some(try!(Fifo::open(settings)));
+        }
+        if let Some(ref settings) = self.settings.highlight_log {
+            self.highlight_log = Some(try!(HighlightLog::open(settings)));
+        }
+        #[cfg(unix)]
+        if self.json_log.is_some() || self.highlight_log.is_some() {
+            unsafe { libc::signal(libc::SIGHUP, hangup as extern "C" fn(libc::c_int) as libc::sighandler_t) };
+        }
+        Ok(())
+    }
+
+    /// Writes an event to the json log, the fifo and the highlight log, if
+    /// there are any, and adds it to the history. This is every event,
+    /// including the ones that don't notify (suppressed).
+    pub fn log(&self, event: &NotificationEvent, suppressed: bool) {
+        self.history.add(event, suppressed);
+        if let Some(ref json_log) = self.json_log {
+            json_log.log(event, suppressed);
+        }
+        if let Some(ref fifo) = self.fifo {
+            fifo.log(event, suppressed);
+        }
+        if let Some(ref highlight_log) = self.highlight_log {
+            highlight_log.log(event, suppressed);
+        }
+    }
+
+    /// The history of the latest events. This shares the history the
+    /// notifiers add to, rather than copying it.
+    pub fn history(&self) -> History {
+        self.history.clone()
+    }
+
+    /// The mute, unread count and connection status. Like the history, this
+    /// is shared rather than copied.
+    pub fn status(&self) -> Status {
+        self.status.clone()
+    }
+
+    /// The cooldown the settings for a buffer set, if they do
+    pub fn buffer_cooldown(&self, buffer: Option<&str>) -> Option<Duration> {
+        self.settings.buffer(buffer).and_then(|b| b.cooldown)
+    }
+
+    /// If lines of a class alert, going by the class's enabled setting
+    pub fn enabled(&self, class: EventClass) -> bool {
+        self.settings.classes.get(class).enabled
+    }
+
+    /// If notifications are muted (or snoozed) from the control socket or
+    /// the tray
+    pub fn muted(&self) -> bool {
+        self.status.is_muted()
+    }
+
+    /// Builds the event for notifying on a line, with the priority the
+    /// rules give it
+    pub fn event(&self, reason: AlertReason, buffer: Option<&str>, line: &BufferLine) -> NotificationEvent {
+        let default = self.settings.classes.get(reason.class()).priority;
+        let mut event = NotificationEvent::new(reason, default, buffer, line, &self.settings.time_format);
+        if self.settings.strip_formatting {
+            event.nick = event.nick.map(|n| colors::strip(&n));
+            event.message = colors::strip(&event.message);
+        }
+        event.priority = priority::pick(&self.settings.priority_rules, &event, default);
+        event
+    }
+
+    /// Builds an event for a line that was never sent, for trying out the
+    /// backends (ex: weechat-alert test-notification). It is dated now, and
+    /// gets its priority the way a real line's event does.
+    pub fn test_event(&self, reason: AlertReason, buffer: &str, nick: &str, message: &str) -> NotificationEvent {
+        let default = self.settings.classes.get(reason.class()).priority;
+        let date = now();
+        let mut event = NotificationEvent {
+            class: reason.class(),
+            reason: reason,
+            priority: default,
+            buffer: Some(buffer.to_string()),
+            nick: Some(nick.to_string()),
+            message: message.to_string(),
+            tags: Vec::new(),
+            highlight: false,
+            date: date,
+            time: self.settings.time_format.render(date),
+        };
+        event.highlight = event.class == EventClass::Highlight;
+        event.priority = priority::pick(&self.settings.priority_rules, &event, default);
+        event
+    }
+
+    /// Sends the event to every enabled backend, as notify does, but
+    /// returns how each did (by name, in the order they were enabled) instead
+    /// of logging failures. A backend whose startup check fails is counted
+    /// as failing without being sent the event. Backends that send from
+    /// their own thread only report if the event was queued; they log
+    /// failures after that.
+    pub fn test(&self, event: &NotificationEvent) -> Vec<(String, Result<(), NotifyError>)> {
+        self.backends.iter()
+            .map(|&(ref name, ref notifier)| {
+                (name.clone(), notifier.startup_check().and_then(|_| notifier.notify(event)))
+            })
+            .collect()
+    }
+
+    /// Alerts that the connection to the relay was lost
+    pub fn disconnected(&self, host: &str, err: &WeechatError) {
+        if let Some(ref path) = self.settings.disconnect_sound {
+            sound::play(path);
+        }
+        for &(_, ref notifier) in &self.backends {
+            notifier.disconnected(host, err);
+        }
+    }
+
+    /// Records that we connected to the relay (this is every time, unlike
+    /// reconnected)
+    pub fn connected(&self, host: &str) {
+        self.status.set_connected(true);
+        for &(_, ref notifier) in &self.backends {
+            notifier.connected(host);
+        }
+    }
+
+    /// Records an error that ended (or prevented) a connection to the relay
+    pub fn error(&self, host: &str, err: &WeechatError) {
+        self.status.set_connected(false);
+        for &(_, ref notifier) in &self.backends {
+            notifier.error(host, err);
+        }
+    }
+
+    /// Alerts that the connection to the relay is back after being lost
+    pub fn reconnected(&self) {
+        if let Some(ref path) = self.settings.reconnect_sound {
+            sound::play(path);
+        }
+    }
+
+    /// Sends the event to every enabled backend, logging the ones that fail,
+    /// and counts it as unread
+    pub fn notify(&self, event: &NotificationEvent) {
+        self.status.add_unread();
+        for &(ref name, ref notifier) in &self.backends {
+            if let Err(e) = notifier.notify(event) {
+                error!("the {} notifier failed: {}", name, e);
+            }
+        }
+    }
+}
+
+/// Creates a backend by its name in the config file (see Notifiers::enable)
+fn build(name: &str, settings: &Settings, replies: Option<&Replies>) -> Result<Box<Notifier>, String> {
+    try!(check_name(name));
+    let notifier: Box<Notifier> = match name {
+        "sound"      => Box::new(try!(SoundPlayer::new(settings))),
+        #[cfg(not(any(target_os = "macos", windows)))]
+        "desktop"    => desktop::build(settings, replies),
+        #[cfg(target_os = "macos")]
+        "desktop"    => Box::new(MacOs::new(settings)),
+        #[cfg(windows)]
+        "desktop"    => Box::new(Toast::new(settings)),
+        "stdout"     => Box::new(Stdout::new(settings)),
+        "bell"       => Box::new(try!(Bell::new(settings))),
+        "tts"        => Box::new(try!(Tts::new(settings))),
+        "webhook"    => Box::new(try!(Webhook::new(settings))),
+        "pushover"   => Box::new(try!(Pushover::new(settings))),
+        #[cfg(feature = "pushbullet")]
+        "pushbullet" => Box::new(try!(Pushbullet::new(settings))),
+        "telegram"   => Box::new(try!(Telegram::new(settings))),
+        "discord"    => Box::new(try!(Discord::new(settings))),
+        "slack"      => Box::new(try!(Slack::new(settings))),
+        "matrix"     => Box::new(try!(Matrix::new(settings))),
+        "ntfy"       => Box::new(try!(Ntfy::new(settings, replies))),
+        "gotify"     => Box::new(try!(Gotify::new(settings))),
+        #[cfg(feature = "xmpp")]
+        "xmpp"       => Box::new(try!(Xmpp::new(settings))),
+        "email"      => Box::new(try!(Email::new(settings))),
+        "twilio"     => Box::new(try!(Twilio::new(settings))),
+        "syslog"     => Box::new(try!(Syslog::new(settings))),
+        #[cfg(feature = "dbus")]
+        "dbus"       => Box::new(Dbus::new()),
+        _            => return Err(format!("Unknown notifier '{}'", name)),
+    };
+    Ok(notifier)
+}
+
+/// Checks that a notifier name (as in the notifiers list of the config) is
+/// one this build has, without starting it
+pub fn check_name(name: &str) -> Result<(), String> {
+    match name {
+        "pushbullet" if !cfg!(feature = "pushbullet") => {
+            Err("Built without the pushbullet notifier (see the pushbullet feature)".to_string())
+        },
+        "xmpp" if !cfg!(feature = "xmpp")             => {
+            Err("Built without the xmpp notifier (see the xmpp feature)".to_string())
+        },
+        "dbus" if !cfg!(feature = "dbus")             => {
+            Err("Built without the dbus notifier (see the dbus feature)".to_string())
+        },
+        _ if NAMES.contains(&name)                    => Ok(()),
+        _                                             => Err(format!("Unknown notifier '{}'", name)),
+    }
+}
+
+/// Checks that a program can be run: that it exists if it is a path, or is
+/// in a directory in PATH otherwise. For startup checks.
+pub fn find_program(program: &str) -> Result<(), NotifyError> {
+    let found = if program.contains('/') {
+        Path::new(program).is_file()
+    } else {
+        env::var_os("PATH").map_or(false, |paths| env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+    };
+    if found {
+        Ok(())
+    } else {
+        Err(NotifyError::new(format!("{} is not installed (or not in PATH)", program)))
+    }
+}
+
+/// Number of SIGHUPs so far. A log that saw a different number when it
+/// opened its file should reopen it.
+pub fn hangups() -> usize {
+    HANGUPS.load(Ordering::SeqCst)
+}
+
+/// The current time as an epoch timestamp
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Splits a unix timestamp into a utc date (year, month, day) and the
+/// seconds into that day
+pub fn utc_date(timestamp: i64) -> (i64, i64, i64, i64) {
+    // Rounds down for timestamps before the epoch
+    let days = if timestamp >= 0 { timestamp / 86400 } else { (timestamp - 86399) / 86400 };
+    let secs = timestamp - days * 86400;
+
+    // Converts days since the epoch to a date in the proleptic gregorian
+    // calendar (see http://howardhinnant.github.io/date_algorithms.html)
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, secs)
+}
@@ -0,0 +1,283 @@
+use std::cmp;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::Json;
+
+use http::{self, Url};
+use notify::{EventClass, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::priority::Scale;
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::{self, Template};
+use reply::Replies;
+
+/// Server to publish to if server isn't set
+pub const DEFAULT_SERVER: &'static str = "https://ntfy.sh";
+
+/// The ntfy priority (1 to 5) for each priority, if [notifier.ntfy.priority]
+/// doesn't set it. Only critical is urgent, as urgent notifications vibrate
+/// and ring for a long time
+pub const DEFAULT_PRIORITIES: Scale<u8> = Scale { low: 2, normal: 3, high: 4, critical: 5 };
+
+// Seconds to wait for the server
+const TIMEOUT: u64 = 10;
+
+// Longest title we send
+const MAX_TITLE_LENGTH: usize = 250;
+
+// How long to wait before trying a message again. This starts at
+// MIN_BACKOFF seconds and doubles after every failure up to MAX_BACKOFF, so
+// a self-hosted server that is restarting has time to come back
+const MIN_BACKOFF: u64 = 5;
+const MAX_BACKOFF: u64 = 300;
+
+// Attempts at sending a message before it is dropped
+const MAX_ATTEMPTS: u32 = 8;
+
+// Seconds between checks of the reply topic for new messages
+const REPLY_INTERVAL: u64 = 5;
+
+/// How to authenticate with the ntfy server
+#[derive(Clone)]
+pub enum NtfyAuth {
+    Token(String),
+    Basic(String, String),
+}
+
+/// Settings for the ntfy backend, from [notifier.ntfy]
+#[derive(Clone)]
+pub struct NtfySettings {
+    /// Url of the server, such as https://ntfy.sh
+    pub server: String,
+    pub topic: String,
+    pub auth: Option<NtfyAuth>,
+    /// Url opened when the notification is tapped (ex: a web client)
+    pub click: Option<String>,
+    pub format: Template,
+    /// Topic whose messages are sent into the buffer in their title, for
+    /// replying to notifications, if there is one
+    pub reply_topic: Option<String>,
+    /// Ntfy priority for each priority
+    pub priorities: Scale<u8>,
+}
+
+/// Debug output leaves out the credentials, so they can't end up in a log
+impl fmt::Debug for NtfySettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NtfySettings")
+         .field("server", &self.server)
+         .field("topic", &self.topic)
+         .field("click", &self.click)
+         .field("format", &self.format)
+         .field("reply_topic", &self.reply_topic)
+         .field("priorities", &self.priorities)
+         .finish()
+    }
+}
+
+/// A message waiting to be published
+struct Pending {
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+/// Publishes notifications to an ntfy topic
+pub struct Ntfy {
+    settings: NtfySettings,
+    /// Headers sent with every message
+    headers: Vec<(String, String)>,
+    /// Queue of messages waiting to be published
+    queue: Queue<Pending>,
+}
+
+impl Ntfy {
+    /// Creates the backend, starting the thread that publishes messages, and
+    /// the one that checks the reply topic if there is one
+    pub fn new(settings: &Settings, replies: Option<&Replies>) -> Result<Ntfy, String> {
+        let settings = try!(settings.ntfy.clone()
+                            .ok_or("The ntfy notifier needs a topic in [notifier.ntfy]"));
+        let url = try!(topic_url(&settings.server, &settings.topic, ""));
+
+        let mut auth = Vec::new();
+        match settings.auth {
+            Some(NtfyAuth::Token(ref token)) => {
+                auth.push(("Authorization".to_string(), format!("Bearer {}", token)));
+            },
+            Some(NtfyAuth::Basic(ref user, ref password)) => {
+                let credentials = format!("{}:{}", user, password).as_bytes().to_base64(STANDARD);
+                auth.push(("Authorization".to_string(), format!("Basic {}", credentials)));
+            },
+            None => (),
+        }
+        let mut headers = auth.clone();
+        if let Some(ref click) = settings.click {
+            headers.push(("Click".to_string(), header_value(click)));
+        }
+        match (settings.reply_topic.as_ref(), replies) {
+            (Some(topic), Some(replies)) => {
+                // The button opens the reply topic in ntfy's web app, which
+                // can publish to it
+                let reply_url = format!("{}/{}", settings.server.trim_right_matches('/'),
+                                        http::percent_encode(topic));
+                headers.push(("Actions".to_string(), header_value(&format!("view, Reply, {}", reply_url))));
+                let poll_url = try!(topic_url(&settings.server, topic, "/json?poll=1"));
+                listen_replies(poll_url, url.clone(), auth, replies.clone());
+            },
+            (Some(_), None)              => warn!("nothing takes replies, so the ntfy reply topic is ignored"),
+            (None, _)                    => (),
+        }
+
+        let queue = Queue::start("ntfy", QUEUE_SIZE, move |pending| publish_all(url, pending));
+
+        Ok(Ntfy {
+            settings: settings,
+            headers: headers,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Ntfy {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let title = event.buffer.as_ref().map(|s| s.as_str()).unwrap_or("weechat").to_string();
+        let mut headers = self.headers.clone();
+        headers.push(("Title".to_string(), header_value(&template::truncate(title, MAX_TITLE_LENGTH))));
+        headers.push(("Priority".to_string(), self.settings.priorities.get(event.priority).to_string()));
+        headers.push(("Tags".to_string(), tag(event.class).to_string()));
+
+        let pending = Pending {
+            headers: headers,
+            body: self.settings.format.render(event),
+        };
+        self.queue.send(pending)
+    }
+}
+
+/// The url of a topic on a server, with anything after it (ex: "/json")
+fn topic_url(server: &str, topic: &str, rest: &str) -> Result<Url, String> {
+    Url::parse(&format!("{}/{}{}", server.trim_right_matches('/'), http::percent_encode(topic), rest))
+        .map_err(|e| format!("'notifier.ntfy.server': {}", e))
+}
+
+/// The tag of a class's messages, which ntfy shows as an emoji
+fn tag(class: EventClass) -> &'static str {
+    match class {
+        EventClass::Private   => "speech_balloon",
+        EventClass::Highlight => "bell",
+        EventClass::Watch     => "eyes",
+    }
+}
+
+/// Makes text safe to send as a header value. Line breaks would end the
+/// header, and anything that isn't ascii is sent as an rfc 2047 encoded
+/// word, which ntfy decodes.
+fn header_value(text: &str) -> String {
+    let text = text.replace(|c| c == '\r' || c == '\n', " ");
+    if text.chars().all(|c| c >= ' ' && c <= '~') {
+        text
+    } else {
+        format!("=?UTF-8?B?{}?=", text.as_bytes().to_base64(STANDARD))
+    }
+}
+
+/// Publishes every queued message in order. Failures back off instead of
+/// being dropped right away, as a self-hosted server may be restarting.
+///
+/// Note: Nothing printed here may include the headers, as they hold the
+///       credentials.
+fn publish_all(url: Url, pending: Receiver<Pending>) {
+    for message in pending {
+        let mut attempts = 0;
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            attempts += 1;
+            match http::post(&url, &message.headers, "text/plain; charset=utf-8",
+                             message.body.as_bytes(), Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => break,
+                Ok(response) => {
+                    error!("ntfy returned {}: {}", response.status, response.body.trim());
+                    // Sending the same thing again won't fix a client error,
+                    // unless we were just rate limited
+                    if response.status / 100 == 4 && response.status != 429 {
+                        break;
+                    }
+                },
+                Err(e)       => error!("ntfy failed: {}", e),
+            }
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on an ntfy message after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(Duration::from_secs(backoff));
+            backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+        }
+    }
+}
+
+/// Checks the reply topic for new messages every REPLY_INTERVAL seconds, on
+/// its own thread, and sends each one into the buffer named by its title
+/// (the title of our notifications). A reply that can't be sent is answered
+/// on the notification topic, where whoever sent it is looking.
+///
+/// Note: As with publishing, nothing printed here may include the headers.
+fn listen_replies(poll_url: Url, notify_url: Url, auth: Vec<(String, String)>, replies: Replies) {
+    thread::spawn(move || {
+        // Only messages from after we started are replies to us. After the
+        // first check, we carry on from the last message we saw
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let mut since = started.to_string();
+        // Only log the first failure in a row, so a server that is down
+        // doesn't log every check
+        let mut failing = false;
+        loop {
+            let mut url = poll_url.clone();
+            url.path = format!("{}&since={}", url.path, http::percent_encode(&since));
+            match http::get(&url, &auth, Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => {
+                    failing = false;
+                    for message in response.body.lines().filter_map(|line| Json::from_str(line).ok()) {
+                        if let Some(id) = message.find("id").and_then(|id| id.as_string()) {
+                            since = id.to_string();
+                        }
+                        if message.find("event").and_then(|e| e.as_string()) == Some("message") {
+                            reply(&message, &notify_url, &auth, &replies);
+                        }
+                    }
+                },
+                Ok(_) | Err(_) if failing => (),
+                Ok(response) => {
+                    error!("checking the ntfy reply topic returned {}: {}",
+                           response.status, response.body.trim());
+                    failing = true;
+                },
+                Err(e) => {
+                    error!("checking the ntfy reply topic failed: {}", e);
+                    failing = true;
+                },
+            }
+            thread::sleep(Duration::from_secs(REPLY_INTERVAL));
+        }
+    });
+}
+
+/// Sends a message from the reply topic into its buffer, or says why not
+fn reply(message: &Json, notify_url: &Url, auth: &[(String, String)], replies: &Replies) {
+    let text = message.find("message").and_then(|m| m.as_string()).unwrap_or("");
+    let result = match message.find("title").and_then(|t| t.as_string()) {
+        Some(buffer) => replies.send(buffer, text).map_err(|e| format!("Could not reply to {}: {}", buffer, e)),
+        None         => Err("Could not reply: the title must be the buffer to reply to".to_string()),
+    };
+    if let Err(e) = result {
+        error!("{}", e);
+        let mut headers = auth.to_vec();
+        headers.push(("Title".to_string(), "weechat-alert".to_string()));
+        headers.push(("Tags".to_string(), "warning".to_string()));
+        if let Err(e) = http::post(notify_url, &headers, "text/plain; charset=utf-8", e.as_bytes(),
+                                   Duration::from_secs(TIMEOUT)) {
+            error!("telling ntfy a reply failed: {}", e);
+        }
+    }
+}
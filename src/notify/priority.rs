@@ -0,0 +1,157 @@
+use buffers;
+use notify::{EventClass, NotificationEvent, Priority, Urgency};
+
+/// Desktop urgency for each priority if [desktop_urgency] doesn't set it.
+/// Both high and critical are critical, which most notification daemons keep
+/// on screen until dismissed.
+pub const DEFAULT_DESKTOP_URGENCY: Scale<Urgency> = Scale {
+    low: Urgency::Low,
+    normal: Urgency::Normal,
+    high: Urgency::Critical,
+    critical: Urgency::Critical,
+};
+
+/// A backend's own value for each priority (ex: 2 to 5 for ntfy)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale<T> {
+    pub low: T,
+    pub normal: T,
+    pub high: T,
+    pub critical: T,
+}
+
+impl<T: Copy> Scale<T> {
+    pub fn new(low: T, normal: T, high: T, critical: T) -> Scale<T> {
+        Scale {
+            low: low,
+            normal: normal,
+            high: high,
+            critical: critical,
+        }
+    }
+
+    pub fn get(&self, priority: Priority) -> T {
+        match priority {
+            Priority::Low      => self.low,
+            Priority::Normal   => self.normal,
+            Priority::High     => self.high,
+            Priority::Critical => self.critical,
+        }
+    }
+
+    /// The scale with f applied to every value
+    pub fn map<U, F: Fn(T) -> U>(&self, f: F) -> Scale<U> {
+        Scale {
+            low: f(self.low),
+            normal: f(self.normal),
+            high: f(self.high),
+            critical: f(self.critical),
+        }
+    }
+
+    pub fn set(&mut self, priority: Priority, value: T) {
+        match priority {
+            Priority::Low      => self.low = value,
+            Priority::Normal   => self.normal = value,
+            Priority::High     => self.high = value,
+            Priority::Critical => self.critical = value,
+        }
+    }
+}
+
+/// A [[priority_rule]] from the config: the priority of the events it
+/// matches. An event has to match everything the rule sets, and a rule has
+/// to set at least one of class, buffers and keywords.
+#[derive(Debug, Clone)]
+pub struct PriorityRule {
+    /// Class the event has to be, if it is set
+    pub class: Option<EventClass>,
+    /// Full buffer names or patterns using '*', one of which the event's
+    /// buffer has to match. Empty matches any buffer
+    pub buffers: Vec<String>,
+    /// Words, one of which the message has to contain (in any case). Empty
+    /// matches any message
+    pub keywords: Vec<String>,
+    pub priority: Priority,
+}
+
+impl PriorityRule {
+    /// If the rule applies to an event
+    pub fn matches(&self, event: &NotificationEvent) -> bool {
+        if self.class.map_or(false, |class| class != event.class) {
+            return false;
+        }
+        if !self.buffers.is_empty() {
+            let buffer = match event.buffer {
+                Some(ref buffer) => buffer,
+                None             => return false,
+            };
+            if !self.buffers.iter().any(|pattern| buffers::matches(pattern, buffer)) {
+                return false;
+            }
+        }
+        if !self.keywords.is_empty() {
+            let message = event.message.to_lowercase();
+            if !self.keywords.iter().any(|k| message.contains(&k.to_lowercase()[..])) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// The priority of the first rule matching an event, or default (its class's
+/// priority) if none do
+pub fn pick(rules: &[PriorityRule], event: &NotificationEvent, default: Priority) -> Priority {
+    rules.iter()
+         .find(|rule| rule.matches(event))
+         .map_or(default, |rule| rule.priority)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick, PriorityRule};
+    use alert::AlertReason;
+    use notify::{EventClass, Priority};
+    use testing::event;
+
+    fn rule(class: Option<EventClass>, buffers: &[&str], keywords: &[&str], priority: Priority) -> PriorityRule {
+        PriorityRule {
+            class: class,
+            buffers: buffers.iter().map(|b| b.to_string()).collect(),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            priority: priority,
+        }
+    }
+
+    #[test]
+    fn rules_match_everything_they_set() {
+        let highlight = event(AlertReason::Highlight, "irc.libera.#rust", "alice", "the build is DOWN again");
+        assert!(rule(Some(EventClass::Highlight), &[], &[], Priority::High).matches(&highlight));
+        assert!(!rule(Some(EventClass::Private), &[], &[], Priority::High).matches(&highlight));
+        assert!(rule(None, &["irc.libera.*"], &[], Priority::High).matches(&highlight));
+        assert!(!rule(None, &["irc.oftc.*", "irc.libera.#weechat"], &[], Priority::High).matches(&highlight));
+        assert!(rule(None, &[], &["outage", "down"], Priority::High).matches(&highlight));
+        assert!(!rule(None, &[], &["outage"], Priority::High).matches(&highlight));
+        assert!(!rule(Some(EventClass::Highlight), &["irc.libera.*"], &["outage"], Priority::High)
+            .matches(&highlight));
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let rules = [rule(None, &["irc.libera.#rust"], &[], Priority::Low),
+                     rule(Some(EventClass::Highlight), &[], &[], Priority::Critical)];
+        let highlight = event(AlertReason::Highlight, "irc.libera.#rust", "alice", "hi");
+        assert_eq!(pick(&rules, &highlight, Priority::Normal), Priority::Low);
+        let elsewhere = event(AlertReason::Highlight, "irc.libera.#weechat", "alice", "hi");
+        assert_eq!(pick(&rules, &elsewhere, Priority::Normal), Priority::Critical);
+    }
+
+    #[test]
+    fn no_matching_rule_falls_back_to_the_default() {
+        let private = event(AlertReason::Private, "irc.libera.alice", "alice", "hi");
+        let rules = [rule(Some(EventClass::Highlight), &[], &[], Priority::Critical)];
+        assert_eq!(pick(&rules, &private, Priority::High), Priority::High);
+        assert_eq!(pick(&[], &private, Priority::Low), Priority::Low);
+    }
+}
@@ -0,0 +1,188 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rustc_serialize::json::Json;
+
+use http::{self, Response, Url};
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+/// Title of pushes if title isn't set
+pub const DEFAULT_TITLE: &'static str = "{buffer}";
+
+/// Body of pushes if body isn't set
+pub const DEFAULT_BODY: &'static str = "<{nick}> {message}";
+
+const PUSHES_URL: &'static str = "https://api.pushbullet.com/v2/pushes";
+const USER_URL: &'static str = "https://api.pushbullet.com/v2/users/me";
+
+// Seconds to wait for the api
+const TIMEOUT: u64 = 10;
+
+// Seconds to wait after a rate limited push, if the response doesn't say
+// when the limit resets
+const RATE_LIMIT_DELAY: u64 = 60;
+
+// Seconds to wait before trying a push again after any other failure
+const RETRY_DELAY: u64 = 5;
+
+// Attempts at sending a push before it is dropped
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Settings for the pushbullet backend, from [notifier.pushbullet]
+#[derive(Clone)]
+pub struct PushbulletSettings {
+    /// Access token of the account to push to
+    pub token: String,
+    /// Device to push to, instead of all of the account's devices
+    pub device_iden: Option<String>,
+    /// Channel to push to, instead of the account's devices
+    pub channel_tag: Option<String>,
+    pub title: Template,
+    pub body: Template,
+}
+
+/// Debug output leaves out the access token, so it can't end up in a log
+impl fmt::Debug for PushbulletSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PushbulletSettings")
+         .field("device_iden", &self.device_iden)
+         .field("channel_tag", &self.channel_tag)
+         .field("title", &self.title)
+         .field("body", &self.body)
+         .finish()
+    }
+}
+
+/// Sends notifications as pushbullet notes
+pub struct Pushbullet {
+    settings: PushbulletSettings,
+    /// Queue of pushes (as json) waiting to be sent
+    queue: Queue<String>,
+}
+
+impl Pushbullet {
+    /// Creates the backend, checking that the access token works and starting
+    /// the thread that sends pushes
+    pub fn new(settings: &Settings) -> Result<Pushbullet, String> {
+        let settings = try!(settings.pushbullet.clone()
+                            .ok_or("The pushbullet notifier needs a token in [notifier.pushbullet]"));
+        let headers = vec![("Access-Token".to_string(), settings.token.clone())];
+
+        // If the api can't be reached right now we carry on, as it may well
+        // be back by the time there is something to push
+        match http::get(&Url::parse(USER_URL).unwrap(), &headers, Duration::from_secs(TIMEOUT)) {
+            Ok(ref response) if response.status == 401 || response.status == 403 => {
+                return Err("The pushbullet access token is invalid".to_string());
+            },
+            Ok(ref response) if !response.success() => {
+                warn!("could not check the pushbullet access token ({}: {})",
+                      response.status, api_error(&response.body));
+            },
+            Ok(_) => (),
+            Err(e) => warn!("could not check the pushbullet access token ({})", e),
+        }
+
+        let queue = Queue::start("pushbullet", QUEUE_SIZE,
+                                 move |pending| send_all(headers, pending));
+
+        Ok(Pushbullet {
+            settings: settings,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Pushbullet {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut push = BTreeMap::new();
+        push.insert("type".to_string(), Json::String("note".to_string()));
+        push.insert("title".to_string(), Json::String(self.settings.title.render(event)));
+        push.insert("body".to_string(), Json::String(self.settings.body.render(event)));
+        if let Some(ref device) = self.settings.device_iden {
+            push.insert("device_iden".to_string(), Json::String(device.clone()));
+        }
+        if let Some(ref channel) = self.settings.channel_tag {
+            push.insert("channel_tag".to_string(), Json::String(channel.clone()));
+        }
+        self.queue.send(Json::Object(push).to_string())
+    }
+}
+
+/// Sends every queued push in order. Once pushbullet says we have used up our
+/// rate limit, pushes wait until it resets instead of being rejected.
+///
+/// Note: Nothing printed here may include the headers, as they hold the
+///       access token.
+fn send_all(headers: Vec<(String, String)>, pending: Receiver<String>) {
+    let url = Url::parse(PUSHES_URL).unwrap();
+    for push in pending {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let delay = match http::post(&url, &headers, "application/json", push.as_bytes(),
+                                         Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => {
+                    if let Some(wait) = rate_limit_wait(response) {
+                        info!("Pushbullet rate limit used up, holding pushes for {} seconds",
+                              wait.as_secs());
+                        thread::sleep(wait);
+                    }
+                    break;
+                },
+                Ok(ref response) if response.status == 429 => {
+                    let wait = rate_limit_wait(response).unwrap_or(Duration::from_secs(RATE_LIMIT_DELAY));
+                    error!("pushbullet rate limit reached, trying again in {} seconds",
+                           wait.as_secs());
+                    wait
+                },
+                Ok(response) => {
+                    error!("pushbullet returned {}: {}", response.status, api_error(&response.body));
+                    // Sending the same thing again won't fix a client error
+                    if response.status / 100 == 4 {
+                        break;
+                    }
+                    Duration::from_secs(RETRY_DELAY)
+                },
+                Err(e)       => {
+                    error!("pushbullet failed: {}", e);
+                    Duration::from_secs(RETRY_DELAY)
+                },
+            };
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on a pushbullet push after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// How long to wait for the rate limit to reset, if the response says none
+/// of it is left. Pushbullet sends the reset time as an epoch timestamp.
+fn rate_limit_wait(response: &Response) -> Option<Duration> {
+    let remaining = response.header("x-ratelimit-remaining").and_then(|r| r.parse::<i64>().ok());
+    if remaining.map_or(true, |r| r > 0) {
+        return None;
+    }
+
+    let reset = response.header("x-ratelimit-reset").and_then(|r| r.parse::<u64>().ok());
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs());
+    match (reset, now) {
+        (Some(reset), Some(now)) if reset > now => Some(Duration::from_secs(reset - now)),
+        _                                       => None,
+    }
+}
+
+/// Pulls the error message out of an api response, which look like
+/// {"error": {"message": "...", ...}}
+fn api_error(body: &str) -> String {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find_path(&["error", "message"]).and_then(|m| m.as_string())
+                             .map(|m| m.to_string()))
+        .unwrap_or("unknown error".to_string())
+}
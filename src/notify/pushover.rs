@@ -0,0 +1,208 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use http::{self, Url};
+use notify::{EventClass, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::priority::Scale;
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::{self, Template};
+
+/// Seconds between repeats of an emergency (priority 2) notification if
+/// retry isn't set. Pushover doesn't allow less than 30
+pub const DEFAULT_RETRY: u64 = 60;
+
+/// Seconds emergency notifications repeat for if expire isn't set. Pushover
+/// doesn't allow more than 10800 (3 hours)
+pub const DEFAULT_EXPIRE: u64 = 3600;
+
+/// The pushover priority (-2 to 2) for each priority, if
+/// [notifier.pushover.priority] doesn't set it. Only critical is an
+/// emergency, which repeats until acknowledged
+pub const DEFAULT_PRIORITIES: Scale<i32> = Scale { low: -1, normal: 0, high: 1, critical: 2 };
+
+const API_URL: &'static str = "https://api.pushover.net/1/messages.json";
+
+// Seconds to wait for the api
+const TIMEOUT: u64 = 10;
+
+// Format of the notification body (the buffer is the title)
+const BODY_FORMAT: &'static str = "<{nick}> {message}";
+
+// Most characters pushover accepts in a title and message
+const MAX_TITLE_LENGTH: usize = 250;
+const MAX_MESSAGE_LENGTH: usize = 1024;
+
+// How long to wait before trying a notification again. A rate limited
+// notification waits MIN_BACKOFF seconds, doubling every time it is rate
+// limited again up to MAX_BACKOFF. Anything else waits RETRY_DELAY
+const RETRY_DELAY: u64 = 5;
+const MIN_BACKOFF: u64 = 60;
+const MAX_BACKOFF: u64 = 3600;
+
+// Attempts at sending a notification before it is dropped
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Settings for the pushover backend, from [notifier.pushover]
+#[derive(Clone)]
+pub struct PushoverSettings {
+    /// Api token of the pushover application
+    pub token: String,
+    /// User (or group) key to notify
+    pub user: String,
+    /// Device to notify, or None for all of the user's devices
+    pub device: Option<String>,
+    /// Pushover priority for each priority
+    pub priorities: Scale<i32>,
+    /// Pushover priority per event class, which wins over priorities. This
+    /// is how older configs set it, before there were priority rules
+    pub class_priorities: HashMap<EventClass, i32>,
+    /// Seconds between repeats of emergency notifications
+    pub retry: u64,
+    /// Seconds emergency notifications repeat for, until acknowledged
+    pub expire: u64,
+}
+
+/// Debug output leaves out the credentials, so they can't end up in a log
+impl fmt::Debug for PushoverSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PushoverSettings")
+         .field("device", &self.device)
+         .field("priorities", &self.priorities)
+         .field("class_priorities", &self.class_priorities)
+         .field("retry", &self.retry)
+         .field("expire", &self.expire)
+         .finish()
+    }
+}
+
+/// Sends notifications to phones (or anything else) through pushover.net
+pub struct Pushover {
+    settings: PushoverSettings,
+    body: Template,
+    /// Queue of requests (as form fields) waiting to be sent
+    queue: Queue<Vec<(&'static str, String)>>,
+}
+
+impl Pushover {
+    /// Creates the backend, starting the thread that sends notifications
+    pub fn new(settings: &Settings) -> Result<Pushover, String> {
+        let pushover = try!(settings.pushover.clone()
+                            .ok_or("The pushover notifier needs a token and user in [notifier.pushover]"));
+
+        // Bodies are limited to the same length as the main format, but never
+        // more than pushover allows (even if the main format is unlimited)
+        let max_length = match settings.format.max_length() {
+            0   => MAX_MESSAGE_LENGTH,
+            max => cmp::min(max, MAX_MESSAGE_LENGTH),
+        };
+        let mut body = Template::parse(BODY_FORMAT).unwrap();
+        body.set_max_length(max_length);
+
+        let queue = Queue::start("pushover", QUEUE_SIZE, move |pending| send_all(pending));
+
+        Ok(Pushover {
+            settings: pushover,
+            body: body,
+            queue: queue,
+        })
+    }
+
+    /// Pushover priority for an event
+    fn priority(&self, event: &NotificationEvent) -> i32 {
+        match self.settings.class_priorities.get(&event.class) {
+            Some(priority) => *priority,
+            None           => self.settings.priorities.get(event.priority),
+        }
+    }
+}
+
+impl Notifier for Pushover {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let title = event.buffer.clone().unwrap_or("weechat".to_string());
+        let priority = self.priority(event);
+
+        let mut fields = vec![
+            ("token", self.settings.token.clone()),
+            ("user", self.settings.user.clone()),
+            ("title", template::truncate(title, MAX_TITLE_LENGTH)),
+            ("message", self.body.render(event)),
+            ("timestamp", event.date.to_string()),
+            ("priority", priority.to_string()),
+        ];
+        if let Some(ref device) = self.settings.device {
+            fields.push(("device", device.clone()));
+        }
+        // Emergency notifications repeat until they are acknowledged, and
+        // pushover requires saying how often and for how long
+        if priority == 2 {
+            fields.push(("retry", self.settings.retry.to_string()));
+            fields.push(("expire", self.settings.expire.to_string()));
+        }
+        self.queue.send(fields)
+    }
+}
+
+/// Sends every queued request in order. Requests that fail in a way that
+/// might be temporary are tried again before moving on to the next one.
+///
+/// Note: Nothing printed here may include the request, as it holds the token
+///       and user key.
+fn send_all(pending: Receiver<Vec<(&'static str, String)>>) {
+    let url = Url::parse(API_URL).unwrap();
+    for fields in pending {
+        let pairs: Vec<(&str, &str)> = fields.iter().map(|&(name, ref value)| (name, value.as_str())).collect();
+        let body = http::form_encode(&pairs);
+
+        let mut attempts = 0;
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            attempts += 1;
+            let delay = match http::post(&url, &[], "application/x-www-form-urlencoded",
+                                         body.as_bytes(), Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => break,
+                Ok(ref response) if response.status == 429 => {
+                    error!("pushover rate limit reached, trying again in {} seconds", backoff);
+                    let delay = backoff;
+                    backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+                    delay
+                },
+                Ok(response) => {
+                    error!("pushover returned {}: {}", response.status, api_errors(&response.body));
+                    // Sending the same thing again won't fix a client error
+                    if response.status / 100 == 4 {
+                        break;
+                    }
+                    RETRY_DELAY
+                },
+                Err(e)       => {
+                    error!("pushover failed: {}", e);
+                    RETRY_DELAY
+                },
+            };
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on a pushover notification after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(Duration::from_secs(delay));
+        }
+    }
+}
+
+/// Pulls the error strings out of an api response, which look like
+/// {"status": 0, "errors": ["user identifier is invalid"], ...}
+fn api_errors(body: &str) -> String {
+    let errors = Json::from_str(body).ok()
+        .and_then(|json| json.find("errors").and_then(|e| e.as_array()).map(|errors| {
+            errors.iter().filter_map(|e| e.as_string()).collect::<Vec<_>>().join(", ")
+        }));
+    match errors {
+        Some(ref errors) if !errors.is_empty() => errors.clone(),
+        _                                      => "unknown error".to_string(),
+    }
+}
@@ -0,0 +1,68 @@
+use std::cell::Cell;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+use notify::NotifyError;
+
+/// Most notifications a backend holds while it is busy with earlier ones.
+/// Past this (ex: a slow webhook during a burst of highlights) notifications
+/// are dropped rather than piling up in memory.
+pub const QUEUE_SIZE: usize = 32;
+
+/// A bounded queue feeding a backend's worker thread, so a slow backend
+/// never holds up reading from the relay and never uses more than one
+/// thread. Notifications that don't fit are dropped, counted, and logged.
+pub struct Queue<T> {
+    /// Name of the backend, for logging
+    name: &'static str,
+    sender: SyncSender<T>,
+    /// Notifications dropped since the queue last had room
+    dropped: Cell<u64>,
+    /// Notifications dropped since we started
+    total_dropped: Cell<u64>,
+}
+
+impl<T: Send + 'static> Queue<T> {
+    /// Creates the queue, starting the thread that works through it. The
+    /// worker is handed everything queued, in order.
+    pub fn start<F>(name: &'static str, size: usize, worker: F) -> Queue<T>
+        where F: FnOnce(Receiver<T>) + Send + 'static
+    {
+        let (sender, pending) = sync_channel(size);
+        thread::spawn(move || worker(pending));
+        Queue {
+            name: name,
+            sender: sender,
+            dropped: Cell::new(0),
+            total_dropped: Cell::new(0),
+        }
+    }
+
+    /// Queues a notification, or drops it if the queue is full. Dropping is
+    /// logged here (only when it starts and stops, as it can go on for a
+    /// while), so the only error is the worker thread being gone.
+    pub fn send(&self, item: T) -> Result<(), NotifyError> {
+        match self.sender.try_send(item) {
+            Ok(_) => {
+                let dropped = self.dropped.get();
+                if dropped > 0 {
+                    info!("The {} notifier caught up, after dropping {} notifications ({} since starting)",
+                          self.name, dropped, self.total_dropped.get());
+                    self.dropped.set(0);
+                }
+                Ok(())
+            },
+            Err(TrySendError::Full(_)) => {
+                self.dropped.set(self.dropped.get() + 1);
+                self.total_dropped.set(self.total_dropped.get() + 1);
+                if self.dropped.get() == 1 {
+                    warn!("the {} notifier is backed up, dropping notifications until it catches up",
+                          self.name);
+                }
+                Ok(())
+            },
+            // The worker thread died, and that already printed why
+            Err(TrySendError::Disconnected(_)) => Err(NotifyError::new("its worker thread stopped")),
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use http::{self, Url};
+use notify::{EventClass, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+// Seconds to wait for the webhook
+const TIMEOUT: u64 = 10;
+
+// Seconds to wait after a rate limited post, if the response doesn't say
+const RATE_LIMIT_DELAY: u64 = 30;
+
+// Seconds to wait before retrying a failed post
+const RETRY_DELAY: u64 = 5;
+
+/// Prefix of a class's messages if its prefix isn't set, so they are easy to
+/// tell apart at a glance
+pub fn default_prefix(class: EventClass) -> Option<&'static str> {
+    match class {
+        EventClass::Private   => Some("🔴"),
+        EventClass::Highlight => Some("🟡"),
+        EventClass::Watch     => None,
+    }
+}
+
+/// Settings for the slack backend, from [notifier.slack]
+#[derive(Clone)]
+pub struct SlackSettings {
+    /// Incoming webhook url
+    pub url: Url,
+    /// Overrides for the webhook's channel, username and icon
+    pub channel: Option<String>,
+    pub username: Option<String>,
+    pub icon_emoji: Option<String>,
+    pub format: Template,
+    /// Text put in front of each class's messages
+    pub prefixes: HashMap<EventClass, String>,
+}
+
+/// Debug output leaves out the url, as it is the webhook's secret
+impl fmt::Debug for SlackSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SlackSettings")
+         .field("channel", &self.channel)
+         .field("username", &self.username)
+         .field("icon_emoji", &self.icon_emoji)
+         .field("format", &self.format)
+         .field("prefixes", &self.prefixes)
+         .finish()
+    }
+}
+
+/// Posts notifications to slack through an incoming webhook
+pub struct Slack {
+    settings: SlackSettings,
+    /// Queue of posts (as json) waiting to be sent
+    queue: Queue<String>,
+}
+
+impl Slack {
+    /// Creates the backend, starting the thread that posts notifications
+    pub fn new(settings: &Settings) -> Result<Slack, String> {
+        let settings = try!(settings.slack.clone()
+                            .ok_or("The slack notifier needs a url in [notifier.slack]"));
+
+        let url = settings.url.clone();
+        let queue = Queue::start("slack", QUEUE_SIZE, move |pending| post_all(url, pending));
+
+        Ok(Slack {
+            settings: settings,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Slack {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut text = self.settings.format.render_escaped(event, escape);
+        if let Some(prefix) = self.settings.prefixes.get(&event.class) {
+            text = format!("{} {}", prefix, text);
+        }
+
+        let mut post = BTreeMap::new();
+        post.insert("text".to_string(), Json::String(text));
+        let overrides = [("channel", &self.settings.channel),
+                         ("username", &self.settings.username),
+                         ("icon_emoji", &self.settings.icon_emoji)];
+        for &(name, value) in &overrides {
+            if let Some(ref value) = *value {
+                post.insert(name.to_string(), Json::String(value.clone()));
+            }
+        }
+        self.queue.send(Json::Object(post).to_string())
+    }
+}
+
+/// Escapes the characters slack reads as markup for links and mentions, so
+/// text from irc shows up as is
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Posts every queued notification in order. Failures that might be
+/// temporary are retried once, and rate limited posts wait for as long as
+/// slack asks.
+///
+/// Note: Nothing printed here may include the url, as it is the webhook's
+///       secret. http errors only name the host.
+fn post_all(url: Url, pending: Receiver<String>) {
+    for post in pending {
+        let mut retried = false;
+        loop {
+            let delay = match http::post(&url, &[], "application/json", post.as_bytes(),
+                                         Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => break,
+                Ok(ref response) if response.status == 429 => {
+                    let wait = response.header("retry-after").and_then(|r| r.parse().ok())
+                                       .unwrap_or(RATE_LIMIT_DELAY);
+                    error!("slack rate limit reached, trying again in {} seconds", wait);
+                    Some(Duration::from_secs(wait))
+                },
+                // Slack explains errors in a plain text body, such as
+                // "invalid_payload" or "channel_not_found"
+                Ok(response) => {
+                    error!("slack returned {}: {}", response.status, response.body.trim());
+                    if response.status / 100 == 5 { Some(Duration::from_secs(RETRY_DELAY)) } else { None }
+                },
+                Err(e)       => {
+                    error!("slack failed: {}", e);
+                    Some(Duration::from_secs(RETRY_DELAY))
+                },
+            };
+            match delay {
+                Some(delay) if !retried => {
+                    retried = true;
+                    thread::sleep(delay);
+                },
+                _                       => break,
+            }
+        }
+    }
+}
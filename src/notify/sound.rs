@@ -0,0 +1,399 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+#[cfg(all(windows, not(feature = "ears")))]
+use std::os::windows::ffi::OsStrExt;
+#[cfg(all(target_os = "macos", not(feature = "ears")))]
+use std::process::Command;
+use std::sync::mpsc::Receiver;
+use std::thread;
+
+#[cfg(feature = "ears")]
+use ears::{Sound, AudioController};
+#[cfg(all(windows, not(feature = "ears")))]
+use libc::c_void;
+
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+
+/// Most sounds waiting to play while one is playing, for the queue policy if
+/// sound_queue_depth isn't set. A burst of notifications doesn't need more
+/// than a couple of sounds
+pub const DEFAULT_QUEUE_DEPTH: usize = 2;
+
+/// What happens to sounds for notifications that come in while a sound is
+/// playing. Sounds never play over each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoundPolicy {
+    /// Play each of them in turn, with up to this many waiting (any more are
+    /// dropped)
+    Queue(usize),
+    /// Play one sound once the current one is done, the latest of them
+    Coalesce,
+    /// Don't play them
+    Drop,
+}
+
+impl SoundPolicy {
+    /// Parses a policy from its name in the config file, with the depth
+    /// to use for "queue"
+    pub fn from_name(name: &str, depth: usize) -> Option<SoundPolicy> {
+        match name {
+            "queue"    => Some(SoundPolicy::Queue(depth)),
+            "coalesce" => Some(SoundPolicy::Coalesce),
+            "drop"     => Some(SoundPolicy::Drop),
+            _          => None,
+        }
+    }
+}
+
+/// Something that plays a sound file at a volume (0.0 to 1.0), blocking
+/// until it is done. The sound worker plays through this, so the tests can
+/// put a fake in.
+trait Play: Send + 'static {
+    fn play(&self, path: &Path, volume: f32);
+}
+
+/// Plays through ears, afplay or PlaySound, whichever this was built with
+struct SystemPlayer;
+
+impl Play for SystemPlayer {
+    fn play(&self, path: &Path, volume: f32) {
+        play_now(path, volume);
+    }
+}
+
+/// Plays a sound for notifications. Which sound is picked per notification,
+/// from the first of these that is set:
+///
+///  1. The sound for the notification's buffer
+///  2. The sound for the notification's event class
+///  3. The global sound
+///
+/// It plays at the volume of the notification's event class. Sounds play one
+/// at a time, with the ones that come in meanwhile handled
+/// by the sound_policy setting.
+pub struct SoundPlayer {
+    settings: Settings,
+    /// Queue of sounds (and their volumes) waiting to play
+    queue: Queue<(PathBuf, f32)>,
+}
+
+impl SoundPlayer {
+    /// Creates the player, checking that every sound it could play exists so
+    /// typos are caught at startup instead of at the first notification
+    pub fn new(settings: &Settings) -> Result<SoundPlayer, String> {
+        try!(check_sound(&settings.sound_path));
+        for class in &[settings.classes.private.sound.as_ref(),
+                       settings.classes.highlight.sound.as_ref(),
+                       settings.classes.watch.sound.as_ref()] {
+            if let Some(path) = *class {
+                try!(check_sound(path));
+            }
+        }
+        for buffer in &settings.buffers {
+            if let Some(ref path) = buffer.sound {
+                try!(check_sound(path));
+            }
+        }
+
+        Ok(SoundPlayer::start(settings, SystemPlayer))
+    }
+
+    /// Creates the player, playing through player, without checking the
+    /// sounds
+    fn start<P: Play>(settings: &Settings, player: P) -> SoundPlayer {
+        // A queue of 0 only takes a sound while the worker is waiting for
+        // one, so nothing is playing
+        let queue = match settings.sound_policy {
+            SoundPolicy::Queue(depth) => Queue::start("sound", depth, move |pending| play_all(pending, player)),
+            SoundPolicy::Coalesce     => Queue::start("sound", QUEUE_SIZE, move |pending| coalesce(pending, player)),
+            SoundPolicy::Drop         => Queue::start("sound", 0, move |pending| play_all(pending, player)),
+        };
+        SoundPlayer {
+            settings: settings.clone(),
+            queue: queue,
+        }
+    }
+
+    /// The sound to play for an event
+    fn sound_for(&self, event: &NotificationEvent) -> &Path {
+        let buffer = self.settings.buffer(event.buffer.as_ref().map(|s| s.as_str()));
+        if let Some(path) = buffer.and_then(|b| b.sound.as_ref()) {
+            return path;
+        }
+        if let Some(ref path) = self.settings.classes.get(event.class).sound {
+            return path;
+        }
+        &self.settings.sound_path
+    }
+}
+
+impl Notifier for SoundPlayer {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let volume = self.settings.classes.get(event.class).volume;
+        self.queue.send((self.sound_for(event).to_path_buf(), volume))
+    }
+}
+
+/// Plays every queued sound, one at a time
+fn play_all<P: Play>(pending: Receiver<(PathBuf, f32)>, player: P) {
+    for (path, volume) in pending {
+        player.play(&path, volume);
+    }
+}
+
+/// Plays queued sounds one at a time, except that when several are waiting
+/// only the latest is played and the others are skipped
+fn coalesce<P: Play>(pending: Receiver<(PathBuf, f32)>, player: P) {
+    while let Ok(mut sound) = pending.recv() {
+        while let Ok(next) = pending.try_recv() {
+            sound = next;
+        }
+        player.play(&sound.0, sound.1);
+    }
+}
+
+/// Plays a sound file in the background, outside of the notification queue
+/// (for the one off connection sounds)
+pub fn play(path: &Path) {
+    let path = path.to_path_buf();
+    thread::spawn(move || play_now(&path, 1.0));
+}
+
+/// Plays a sound file at a volume (0.0 to 1.0), blocking until it is done.
+/// The play is a blocking
+/// call, and if we don't loop for is_playing it seems to go out of scope and
+/// get destroyed before it can actually play the sound, so this has to run
+/// in its own thread to not hold up processing messages.
+#[cfg(feature = "ears")]
+fn play_now(path: &Path, volume: f32) {
+    let path = path.to_string_lossy();
+    let mut snd = match Sound::new(&path) {
+        Some(snd) => snd,
+        None      => {
+            error!("could not load sound {}", path);
+            return;
+        }
+    };
+    snd.set_volume(volume);
+    snd.play();
+    while snd.is_playing() {}
+}
+
+/// Plays a sound file with afplay at a volume (0.0 to 1.0), blocking until
+/// it is done
+#[cfg(all(target_os = "macos", not(feature = "ears")))]
+fn play_now(path: &Path, volume: f32) {
+    match Command::new("afplay").arg("-v").arg(volume.to_string()).arg(path).status() {
+        Ok(ref status) if status.success() => (),
+        Ok(status) => error!("afplay failed to play {} ({})", path.display(), status),
+        Err(e)     => error!("could not run afplay: {}", e),
+    }
+}
+
+// Flags to PlaySound, from mmsystem.h: the sound is a file name, and if
+// it can't be played nothing is (rather than the default sound)
+#[cfg(all(windows, not(feature = "ears")))]
+const SND_NODEFAULT: u32 = 0x0002;
+#[cfg(all(windows, not(feature = "ears")))]
+const SND_FILENAME: u32 = 0x00020000;
+
+#[cfg(all(windows, not(feature = "ears")))]
+#[link(name = "winmm")]
+extern "system" {
+    fn PlaySoundW(sound: *const u16, module: *mut c_void, flags: u32) -> i32;
+}
+
+/// Plays a wav file with PlaySound, blocking until it is done. PlaySound has
+/// no volume of its own, so the volume is ignored
+#[cfg(all(windows, not(feature = "ears")))]
+fn play_now(path: &Path, _volume: f32) {
+    let mut wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+    wide.push(0);
+    if unsafe { PlaySoundW(wide.as_ptr(), 0 as *mut c_void, SND_FILENAME | SND_NODEFAULT) } == 0 {
+        error!("could not play {}", path.display());
+    }
+}
+
+/// Without ears (or afplay or PlaySound) there is nothing to play sounds
+/// with, which check_sound already refused at startup
+#[cfg(all(not(target_os = "macos"), not(windows), not(feature = "ears")))]
+fn play_now(path: &Path, _volume: f32) {
+    error!("built without ears, so {} can't be played", path.display());
+}
+
+/// Checks that a sound file exists and is a format ears can play (ears plays
+/// sounds through libsndfile, which can't play mp3). This goes by the magic
+/// bytes at the start of the file rather than its extension. afplay also
+/// plays mp3, so those are fine on macOS builds without ears, while
+/// PlaySound only plays wav on windows builds without ears.
+pub fn check_sound(path: &Path) -> Result<(), String> {
+    if cfg!(all(not(target_os = "macos"), not(windows), not(feature = "ears"))) {
+        return Err(format!("Can't play {}: built without ears (see the ears feature)", path.display()));
+    }
+    if !path.is_file() {
+        return Err(format!("Sound file {} does not exist", path.display()));
+    }
+
+    let mut header = Vec::new();
+    try!(File::open(path).and_then(|f| f.take(12).read_to_end(&mut header))
+         .map_err(|e| format!("Can't read sound file {}: {}", path.display(), e)));
+
+    let is_wav = header.starts_with(b"RIFF") && header.len() == 12 && &header[8..] == b"WAVE";
+    let is_aiff = header.starts_with(b"FORM") && header.len() == 12 && &header[8..11] == b"AIF";
+    let is_ogg = header.starts_with(b"OggS");
+    let is_flac = header.starts_with(b"fLaC");
+    if cfg!(all(windows, not(feature = "ears"))) && !is_wav {
+        return Err(format!("Sound file {}: PlaySound can only play WAV", path.display()));
+    }
+    if is_wav || is_aiff || is_ogg || is_flac {
+        return Ok(());
+    }
+
+    // Mp3s either start with an id3 tag or go straight into a frame sync
+    let is_mp3 = header.starts_with(b"ID3") ||
+                 (header.len() >= 2 && header[0] == 0xFF && header[1] & 0xE0 == 0xE0);
+    if is_mp3 && cfg!(all(target_os = "macos", not(feature = "ears"))) {
+        Ok(())
+    } else if is_mp3 {
+        Err(format!("Sound file {}: ears cannot play MP3, use WAV, OGG or FLAC", path.display()))
+    } else {
+        Err(format!("Sound file {} is not a WAV, OGG or FLAC file", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::time::Duration;
+
+    use super::{Play, SoundPlayer, SoundPolicy};
+    use alert::AlertReason;
+    use notify::{BufferSettings, EventClass, Notifier, Settings};
+    use testing::event;
+
+    /// Tells the test each sound it starts, then plays it until the test says
+    /// it is done, so the test knows what is playing when
+    struct Handshake {
+        started: Sender<(PathBuf, f32)>,
+        finish: Receiver<()>,
+    }
+
+    impl Play for Handshake {
+        fn play(&self, path: &Path, volume: f32) {
+            self.started.send((path.to_path_buf(), volume)).unwrap();
+            let _ = self.finish.recv();
+        }
+    }
+
+    /// A player with a fake behind it, along with the sounds it starts and
+    /// the sender that finishes the one playing
+    fn player(settings: &Settings) -> (SoundPlayer, Receiver<(PathBuf, f32)>, Sender<()>) {
+        let (started_sender, started) = channel();
+        let (finish, finish_receiver) = channel();
+        let player = SoundPlayer::start(settings, Handshake { started: started_sender, finish: finish_receiver });
+        (player, started, finish)
+    }
+
+    /// Sends a highlight from the first buffer, then from the others while
+    /// the first one's sound plays, and returns the sounds played once the
+    /// player is done with them all
+    fn burst(settings: &Settings, buffers: &[&str]) -> Vec<(PathBuf, f32)> {
+        let (player, started, finish) = player(settings);
+
+        // The drop policy's queue only takes a sound while the worker is
+        // waiting for one, which it may not be yet
+        let mut played = Vec::new();
+        let first = event(AlertReason::Highlight, buffers[0], "alice", "hi");
+        player.notify(&first).unwrap();
+        if settings.sound_policy == SoundPolicy::Drop {
+            loop {
+                if let Ok(sound) = started.recv_timeout(Duration::from_millis(10)) {
+                    played.push(sound);
+                    break;
+                }
+                player.notify(&first).unwrap();
+            }
+        } else {
+            played.push(started.recv().unwrap());
+        }
+
+        for buffer in &buffers[1..] {
+            player.notify(&event(AlertReason::Highlight, buffer, "alice", "hi")).unwrap();
+        }
+        // Without the player, the worker stops (dropping the fake) once it
+        // played everything it took
+        drop(player);
+        let _ = finish.send(());
+        for sound in started {
+            played.push(sound);
+            let _ = finish.send(());
+        }
+        played
+    }
+
+    /// The default settings, with buffers that have sounds of their own
+    /// (irc.libera.#rust plays rust.wav, and so on)
+    fn settings() -> Settings {
+        let mut settings = Settings::new();
+        for name in &["rust", "a", "b", "c", "d"] {
+            settings.buffers.push(BufferSettings {
+                pattern: format!("irc.libera.#{}", name),
+                sound: Some(PathBuf::from(format!("{}.wav", name))),
+                cooldown: None,
+            });
+        }
+        settings
+    }
+
+    fn paths(played: &[(PathBuf, f32)]) -> Vec<&Path> {
+        played.iter().map(|p| p.0.as_path()).collect()
+    }
+
+    #[test]
+    fn buffer_then_class_then_global_sound() {
+        let mut settings = settings();
+        settings.sound_path = PathBuf::from("global.wav");
+        settings.classes.get_mut(EventClass::Highlight).sound = Some(PathBuf::from("highlight.wav"));
+        settings.classes.get_mut(EventClass::Highlight).volume = 0.5;
+        let (player, started, finish) = player(&settings);
+
+        let mut played = Vec::new();
+        for &(ref reason, buffer) in &[(AlertReason::Highlight, "irc.libera.#rust"),
+                                       (AlertReason::Highlight, "irc.libera.#weechat"),
+                                       (AlertReason::Private, "irc.libera.alice")] {
+            player.notify(&event(reason.clone(), buffer, "alice", "hi")).unwrap();
+            played.push(started.recv().unwrap());
+            finish.send(()).unwrap();
+        }
+        assert_eq!(paths(&played), vec![Path::new("rust.wav"), Path::new("highlight.wav"), Path::new("global.wav")]);
+        assert_eq!(played[0].1, 0.5);
+        assert_eq!(played[2].1, settings.classes.get(EventClass::Private).volume);
+    }
+
+    #[test]
+    fn queue_plays_in_turn_and_drops_the_rest() {
+        let mut settings = settings();
+        settings.sound_policy = SoundPolicy::Queue(1);
+        let played = burst(&settings, &["irc.libera.#a", "irc.libera.#b", "irc.libera.#c", "irc.libera.#d"]);
+        assert_eq!(paths(&played), vec![Path::new("a.wav"), Path::new("b.wav")]);
+    }
+
+    #[test]
+    fn coalesce_plays_the_latest_waiting() {
+        let mut settings = settings();
+        settings.sound_policy = SoundPolicy::Coalesce;
+        let played = burst(&settings, &["irc.libera.#a", "irc.libera.#b", "irc.libera.#c", "irc.libera.#d"]);
+        assert_eq!(paths(&played), vec![Path::new("a.wav"), Path::new("d.wav")]);
+    }
+
+    #[test]
+    fn drop_plays_only_while_nothing_is_playing() {
+        let mut settings = settings();
+        settings.sound_policy = SoundPolicy::Drop;
+        let played = burst(&settings, &["irc.libera.#a", "irc.libera.#b", "irc.libera.#c"]);
+        assert_eq!(paths(&played), vec![Path::new("a.wav")]);
+    }
+}
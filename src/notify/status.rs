@@ -0,0 +1,122 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// What the tray shows, at one moment
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    /// Notifications since the count was last cleared
+    pub unread: u32,
+    /// Muted until it is turned off again
+    pub muted: bool,
+    /// Minutes (rounded up) left of a snooze, or None if not snoozed
+    pub snoozed_for: Option<u64>,
+    /// Connected to the relay
+    pub connected: bool,
+}
+
+impl Summary {
+    /// If notifications are held back, by a mute or a snooze
+    pub fn is_muted(&self) -> bool {
+        self.muted || self.snoozed_for.is_some()
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    unread: u32,
+    muted: bool,
+    snoozed_until: Option<Instant>,
+    connected: bool,
+}
+
+/// Whether notifications are muted, how many there have been since they
+/// were last cleared, and if we are connected to the relay. Clones share the
+/// same status, so the control socket and the tray can change it from their
+/// own threads.
+///
+/// Lines that come in while muted still make it to the logs and the
+/// history, as suppressed, they just don't notify.
+#[derive(Debug, Clone)]
+pub struct Status {
+    state: Arc<Mutex<State>>,
+}
+
+impl Status {
+    pub fn new() -> Status {
+        Status {
+            state: Arc::new(Mutex::new(State {
+                unread: 0,
+                muted: false,
+                snoozed_until: None,
+                connected: false,
+            })),
+        }
+    }
+
+    fn lock<'a>(&'a self) -> MutexGuard<'a, State> {
+        // Nothing here can be left half changed by a panic, so carry on
+        // with the state of a thread that panicked holding the lock
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Mutes notifications until unmute, replacing any snooze
+    pub fn mute(&self) {
+        let mut state = self.lock();
+        state.muted = true;
+        state.snoozed_until = None;
+    }
+
+    /// Mutes notifications for a while, replacing any mute or snooze
+    pub fn snooze(&self, duration: Duration) {
+        let mut state = self.lock();
+        state.muted = false;
+        state.snoozed_until = Some(Instant::now() + duration);
+    }
+
+    /// Ends a mute or snooze
+    pub fn unmute(&self) {
+        let mut state = self.lock();
+        state.muted = false;
+        state.snoozed_until = None;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.summary().is_muted()
+    }
+
+    /// Counts a notification that went out
+    pub fn add_unread(&self) {
+        let mut state = self.lock();
+        state.unread = state.unread.saturating_add(1);
+    }
+
+    /// Sets the count of notifications back to 0
+    pub fn clear(&self) {
+        self.lock().unread = 0;
+    }
+
+    pub fn set_connected(&self, connected: bool) {
+        self.lock().connected = connected;
+    }
+
+    pub fn summary(&self) -> Summary {
+        let mut state = self.lock();
+        let now = Instant::now();
+        if state.snoozed_until.map_or(false, |until| until <= now) {
+            state.snoozed_until = None;
+        }
+        Summary {
+            unread: state.unread,
+            muted: state.muted,
+            snoozed_for: state.snoozed_until.map(|until| minutes_until(now, until)),
+            connected: state.connected,
+        }
+    }
+}
+
+/// Whole minutes from now until a later instant, rounded up
+fn minutes_until(now: Instant, until: Instant) -> u64 {
+    let left = until.duration_since(now);
+    let seconds = left.as_secs() + if left.subsec_nanos() > 0 { 1 } else { 0 };
+    (seconds + 59) / 60
+}
@@ -0,0 +1,20 @@
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::template::Template;
+
+/// Prints notifications to stdout
+pub struct Stdout {
+    format: Template,
+}
+
+impl Stdout {
+    pub fn new(settings: &Settings) -> Stdout {
+        Stdout { format: settings.format.clone() }
+    }
+}
+
+impl Notifier for Stdout {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        println!("{}", self.format.render(event));
+        Ok(())
+    }
+}
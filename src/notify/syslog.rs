@@ -0,0 +1,144 @@
+use std::ffi::CString;
+
+#[cfg(unix)]
+use libc::c_char;
+use libc::c_int;
+
+use errors::WeechatError;
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::template::Template;
+
+/// Name messages are logged under if none is configured
+pub const DEFAULT_IDENT: &'static str = "weechat-alert";
+
+// Priorities, from syslog.h
+const LOG_ERR: c_int = 3;
+const LOG_NOTICE: c_int = 5;
+const LOG_INFO: c_int = 6;
+
+// Option to openlog that puts our pid in every message
+#[cfg(unix)]
+const LOG_PID: c_int = 0x01;
+
+#[cfg(unix)]
+extern "C" {
+    fn openlog(ident: *const c_char, option: c_int, facility: c_int);
+    fn syslog(priority: c_int, format: *const c_char, ...);
+}
+
+/// Settings for the syslog backend, from [notifier.syslog]
+#[derive(Debug, Clone)]
+pub struct SyslogSettings {
+    /// Facility code (already shifted, as in syslog.h)
+    pub facility: i32,
+    /// Name every message is logged under
+    pub ident: String,
+}
+
+impl SyslogSettings {
+    /// The default settings, logging as weechat-alert to the user facility
+    pub fn new() -> SyslogSettings {
+        SyslogSettings {
+            facility: facility("user").unwrap(),
+            ident: DEFAULT_IDENT.to_string(),
+        }
+    }
+}
+
+/// Looks up a facility by its name in the config file ("user", "daemon",
+/// or "local0" through "local7")
+pub fn facility(name: &str) -> Option<i32> {
+    let code = match name {
+        "user"   => 1,
+        "daemon" => 3,
+        "local0" => 16,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _        => return None,
+    };
+    Some(code << 3)
+}
+
+/// Logs notifications, and the connection to the relay coming and going, to
+/// syslog (or journald, which picks up syslog)
+pub struct Syslog {
+    format: Template,
+    /// Ident handed to openlog, which keeps a pointer to it rather than a
+    /// copy, so it has to live as long as we log
+    _ident: CString,
+}
+
+impl Syslog {
+    pub fn new(settings: &Settings) -> Result<Syslog, String> {
+        let ident = try!(CString::new(settings.syslog.ident.clone())
+                         .map_err(|_| "'notifier.syslog.ident' can't have a nul in it".to_string()));
+        try!(open(&ident, settings.syslog.facility));
+        Ok(Syslog {
+            format: settings.format.clone(),
+            _ident: ident,
+        })
+    }
+}
+
+impl Notifier for Syslog {
+    /// Logs a notification at INFO, saying why it alerted (ex: "alert:
+    /// highlight: ...")
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        log(LOG_INFO, &format!("alert: {}: {}", event.reason, self.format.render(event)));
+        Ok(())
+    }
+
+    /// Logs that the relay connection is up, at NOTICE
+    fn connected(&self, host: &str) {
+        log(LOG_NOTICE, &format!("Connected to relay {}", host));
+    }
+
+    /// Logs that the relay connection was lost, at NOTICE
+    fn disconnected(&self, host: &str, err: &WeechatError) {
+        log(LOG_NOTICE, &format!("Lost connection to relay {} ({})", host, err));
+    }
+
+    /// Logs auth and parse errors at ERR, as they mean something is wrong
+    /// with our setup or the relay rather than just the network. Anything
+    /// else is left to disconnected.
+    fn error(&self, host: &str, err: &WeechatError) {
+        match *err {
+            WeechatError::BadPassword      |
+            WeechatError::PasswordRequired |
+            WeechatError::ParseError(_)    => log(LOG_ERR, &format!("Relay {}: {}", host, err)),
+            _                              => (),
+        }
+    }
+}
+
+/// Opens the log for every message after this, as ident
+#[cfg(unix)]
+fn open(ident: &CString, facility: c_int) -> Result<(), String> {
+    unsafe { openlog(ident.as_ptr(), LOG_PID, facility) };
+    Ok(())
+}
+
+#[cfg(windows)]
+fn open(_ident: &CString, _facility: c_int) -> Result<(), String> {
+    Err("There is no syslog on windows (see json_log or highlight_log instead)".to_string())
+}
+
+/// Logs a message, with any control characters (such as newlines or irc
+/// formatting codes) replaced by spaces so a line can't forge log entries
+#[cfg(unix)]
+fn log(priority: c_int, message: &str) {
+    let message: String = message.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    // Control characters (nul included) are gone, so this can't fail
+    let message = CString::new(message).unwrap();
+    unsafe { syslog(priority, b"%s\0".as_ptr() as *const c_char, message.as_ptr()) };
+}
+
+// Never called, as open fails
+#[cfg(windows)]
+fn log(_priority: c_int, _message: &str) {
+}
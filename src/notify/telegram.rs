@@ -0,0 +1,172 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use http::{self, Url};
+use notify::{NotificationEvent, Notifier, NotifyError, Priority, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+/// Longest message (in characters) telegram accepts
+pub const MAX_MESSAGE_LENGTH: usize = 4096;
+
+// Seconds to wait for the api
+const TIMEOUT: u64 = 10;
+
+// Seconds to wait after a rate limited message, if the response doesn't say
+const RATE_LIMIT_DELAY: u64 = 30;
+
+// Seconds to wait before trying a message again after any other failure
+const RETRY_DELAY: u64 = 5;
+
+// Attempts at sending a message before it is dropped
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Characters that have to be escaped with a backslash in MarkdownV2 text
+const MARKDOWN_SPECIAL: &'static str = "_*[]()~`>#+-=|{}.!\\";
+
+/// Settings for the telegram backend, from [notifier.telegram]
+#[derive(Clone)]
+pub struct TelegramSettings {
+    /// Token of the bot sending the messages
+    pub token: String,
+    /// Chat to send messages to, a numeric id or "@channelname"
+    pub chat_id: String,
+    pub format: Template,
+    /// Send messages as MarkdownV2, so the format can use markup
+    pub markdown: bool,
+    /// Send low priority notifications without a sound on the phone
+    pub silent: bool,
+}
+
+/// Debug output leaves out the bot token, so it can't end up in a log
+impl fmt::Debug for TelegramSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TelegramSettings")
+         .field("chat_id", &self.chat_id)
+         .field("format", &self.format)
+         .field("markdown", &self.markdown)
+         .field("silent", &self.silent)
+         .finish()
+    }
+}
+
+/// Sends notifications as messages from a telegram bot
+pub struct Telegram {
+    settings: TelegramSettings,
+    /// Queue of messages (as json) waiting to be sent
+    queue: Queue<String>,
+}
+
+impl Telegram {
+    /// Creates the backend, starting the thread that sends messages
+    pub fn new(settings: &Settings) -> Result<Telegram, String> {
+        let mut settings = try!(settings.telegram.clone()
+                                .ok_or("The telegram notifier needs a token and chat_id in [notifier.telegram]"));
+        let max_length = match settings.format.max_length() {
+            0   => MAX_MESSAGE_LENGTH,
+            max => cmp::min(max, MAX_MESSAGE_LENGTH),
+        };
+        settings.format.set_max_length(max_length);
+        let url = try!(Url::parse(&format!("https://api.telegram.org/bot{}/sendMessage", settings.token))
+                       .map_err(|_| "'notifier.telegram.token' is not a valid bot token".to_string()));
+
+        let queue = Queue::start("telegram", QUEUE_SIZE, move |pending| send_all(url, pending));
+
+        Ok(Telegram {
+            settings: settings,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Telegram {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let mut message = BTreeMap::new();
+        message.insert("chat_id".to_string(), Json::String(self.settings.chat_id.clone()));
+        if self.settings.markdown {
+            message.insert("text".to_string(),
+                           Json::String(self.settings.format.render_escaped(event, escape_markdown)));
+            message.insert("parse_mode".to_string(), Json::String("MarkdownV2".to_string()));
+        } else {
+            message.insert("text".to_string(), Json::String(self.settings.format.render(event)));
+        }
+        if self.settings.silent && event.priority == Priority::Low {
+            message.insert("disable_notification".to_string(), Json::Boolean(true));
+        }
+        self.queue.send(Json::Object(message).to_string())
+    }
+}
+
+/// Escapes text so telegram shows it as is in a MarkdownV2 message
+pub fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if MARKDOWN_SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Sends every queued message in order
+///
+/// Note: Nothing printed here may include the url, as it holds the bot token.
+///       http errors only name the host.
+fn send_all(url: Url, pending: Receiver<String>) {
+    for message in pending {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let delay = match http::post(&url, &[], "application/json", message.as_bytes(),
+                                         Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => break,
+                Ok(ref response) if response.status == 429 => {
+                    let wait = retry_after(&response.body).unwrap_or(Duration::from_secs(RATE_LIMIT_DELAY));
+                    error!("telegram rate limit reached, trying again in {} seconds",
+                           wait.as_secs());
+                    wait
+                },
+                Ok(response) => {
+                    error!("telegram returned {}: {}", response.status, api_error(&response.body));
+                    // Sending the same thing again won't fix a client error
+                    if response.status / 100 == 4 {
+                        break;
+                    }
+                    Duration::from_secs(RETRY_DELAY)
+                },
+                Err(e)       => {
+                    error!("telegram failed: {}", e);
+                    Duration::from_secs(RETRY_DELAY)
+                },
+            };
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on a telegram message after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// How long a rate limited response says to wait, from a body like
+/// {"ok": false, "parameters": {"retry_after": 5}, ...}
+fn retry_after(body: &str) -> Option<Duration> {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find_path(&["parameters", "retry_after"]).and_then(|r| r.as_u64()))
+        .map(Duration::from_secs)
+}
+
+/// Pulls the error message out of an api response, which look like
+/// {"ok": false, "description": "...", ...}
+fn api_error(body: &str) -> String {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find("description").and_then(|d| d.as_string()).map(|d| d.to_string()))
+        .unwrap_or("unknown error".to_string())
+}
@@ -0,0 +1,186 @@
+use std::cmp;
+
+use notify::NotificationEvent;
+
+/// Default format for text based notifications
+pub const DEFAULT_FORMAT: &'static str = "{buffer} <{nick}> {message}";
+
+/// Default maximum length (in characters) of a rendered notification
+pub const DEFAULT_MAX_LENGTH: usize = 300;
+
+/// Appended to notifications that were cut short
+const ELLIPSIS: char = '…';
+
+/// Placeholders that can be used in a template
+const PLACEHOLDERS: &'static [&'static str] = &["network", "buffer", "nick", "message", "class", "reason",
+                                                   "priority", "time"];
+
+/// One piece of a parsed template
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Placeholder(String),
+}
+
+/// A format string for rendering notifications, such as
+/// "{network} {buffer} <{nick}> {message}". Literal braces are written as
+/// "{{" and "}}".
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>,
+    /// Maximum length of the rendered text in characters (0 for no limit)
+    max_length: usize,
+}
+
+impl Template {
+    /// Parses a template, erroring on unknown placeholders or unbalanced braces
+    pub fn parse(format: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c)   => name.push(c),
+                            None      => return Err(format!("Unclosed '{{' in \"{}\"", format)),
+                        }
+                    }
+                    if !PLACEHOLDERS.contains(&name.as_str()) {
+                        return Err(format!("Unknown placeholder '{{{}}}' in \"{}\" (can use: {})",
+                                           name, format, PLACEHOLDERS.join(", ")));
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(text));
+                        text = String::new();
+                    }
+                    parts.push(Part::Placeholder(name));
+                },
+                '}' => return Err(format!("Unmatched '}}' in \"{}\"", format)),
+                _   => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template {
+            parts: parts,
+            max_length: DEFAULT_MAX_LENGTH,
+        })
+    }
+
+    /// Sets the maximum length in characters of the rendered text. Anything
+    /// longer is cut short and ends with an ellipsis. 0 means no limit.
+    pub fn set_max_length(&mut self, max_length: usize) {
+        self.max_length = max_length;
+    }
+
+    pub fn max_length(&self) -> usize {
+        self.max_length
+    }
+
+    /// Renders the template for a notification
+    pub fn render(&self, event: &NotificationEvent) -> String {
+        truncate(self.render_with(event, &|s: &str| s.to_string()), self.max_length)
+    }
+
+    /// Renders the template, passing the value of every placeholder through
+    /// escape (ex: to escape markup in it). The text of the template itself
+    /// is left as is. Cutting escaped text short could split an escape
+    /// sequence, so if the text is too long the message is shortened before
+    /// it is escaped instead, until the text fits.
+    pub fn render_escaped<F: Fn(&str) -> String>(&self, event: &NotificationEvent, escape: F) -> String {
+        let mut text = self.render_with(event, &escape);
+        if self.max_length == 0 {
+            return text;
+        }
+
+        let mut shortened = event.clone();
+        loop {
+            let length = text.chars().count();
+            let message_length = shortened.message.chars().count();
+            if length <= self.max_length || message_length <= 1 {
+                return text;
+            }
+            let new_length = cmp::max(message_length.saturating_sub(length - self.max_length), 1);
+            shortened.message = truncate(shortened.message, new_length);
+            text = self.render_with(&shortened, &escape);
+        }
+    }
+
+    fn render_with(&self, event: &NotificationEvent, escape: &Fn(&str) -> String) -> String {
+        let reason = event.reason.to_string();
+        let mut out = String::new();
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref text) => out.push_str(text),
+                Part::Placeholder(ref name) => {
+                    let value = match name.as_str() {
+                        "network"  => event.network().unwrap_or(""),
+                        "buffer"   => event.buffer.as_ref().map(|s| s.as_str()).unwrap_or(""),
+                        "nick"     => event.nick.as_ref().map(|s| s.as_str()).unwrap_or(""),
+                        "message"  => &event.message,
+                        "class"    => event.class.name(),
+                        "reason"   => &reason,
+                        "priority" => event.priority.name(),
+                        "time"     => &event.time,
+                        _          => unreachable!(),
+                    };
+                    out.push_str(&escape(value));
+                },
+            }
+        }
+        out
+    }
+}
+
+/// Cuts a string down to at most max_length characters (not bytes, so we
+/// never split a multi-byte character), ending it with an ellipsis if it was
+/// too long. A max_length of 0 means no limit.
+pub fn truncate(s: String, max_length: usize) -> String {
+    if max_length == 0 || s.chars().count() <= max_length {
+        return s;
+    }
+    let mut truncated: String = s.chars().take(max_length - 1).collect();
+    truncated.push(ELLIPSIS);
+    truncated
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str;
+
+    use super::{truncate, Template, ELLIPSIS};
+    use alert::AlertReason;
+    use testing::event;
+
+    #[test]
+    fn truncation_keeps_multi_byte_characters_whole() {
+        // "na" is 2 bytes and "ï" 2 more, so a cut at 3 bytes would split it
+        let truncated = truncate("naïve café".to_string(), 4);
+        assert!(str::from_utf8(truncated.as_bytes()).is_ok());
+        assert_eq!(truncated, format!("naï{}", ELLIPSIS));
+        assert_eq!(truncated.chars().count(), 4);
+        assert_eq!(truncate("naïve".to_string(), 5), "naïve");
+
+        let mut template = Template::parse("{nick}: {message}").unwrap();
+        template.set_max_length(9);
+        let rendered = template.render(&event(AlertReason::Highlight, "irc.libera.#rust", "zoë", "☕☕☕☕☕☕☕☕"));
+        assert!(str::from_utf8(rendered.as_bytes()).is_ok());
+        assert_eq!(rendered, format!("zoë: ☕☕☕{}", ELLIPSIS));
+        assert_eq!(rendered.chars().count(), 9);
+    }
+}
@@ -0,0 +1,197 @@
+use notify::utc_date;
+use sys;
+
+/// Format of times (the {time} placeholder, the highlight log and the
+/// history) if time_format isn't set
+pub const DEFAULT_FORMAT: &'static str = "%Y-%m-%d %H:%M:%S";
+
+// Conversions a time format can use, after a %
+const CONVERSIONS: &'static str = "YymdeHIMSpaAbBjzs";
+
+const WEEKDAYS: [&'static str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday",
+                                     "Saturday"];
+const MONTHS: [&'static str; 12] = ["January", "February", "March", "April", "May", "June", "July",
+                                    "August", "September", "October", "November", "December"];
+
+/// One piece of a parsed time format
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    /// The letter after a % (ex: 'Y')
+    Conversion(char),
+}
+
+/// A time broken down into what the conversions need
+struct Fields {
+    year: i64,
+    month: i64,
+    day: i64,
+    hour: i64,
+    minute: i64,
+    second: i64,
+    /// Seconds east of utc
+    offset: i64,
+    timestamp: i64,
+}
+
+/// How to turn a unix timestamp into text, with a strftime-like format.
+/// These conversions are understood:
+///
+/// %Y year, %y year without the century, %m month (01-12), %d day (01-31),
+/// %e day padded with a space, %H hour (00-23), %I hour (01-12), %M minute,
+/// %S second, %p AM or PM, %a and %A weekday (Mon, Monday), %b and %B month
+/// (Jan, January), %j day of the year (001-366), %z offset from utc (ex:
+/// +0200), %s the unix timestamp, and %% for a %.
+///
+/// Times are in the local time zone unless the format is for utc. A time the
+/// system can't convert to local time is in utc.
+#[derive(Debug, Clone)]
+pub struct TimeFormat {
+    parts: Vec<Part>,
+    utc: bool,
+}
+
+impl TimeFormat {
+    /// Parses a format, failing on a % with no conversion after it or one
+    /// that isn't understood
+    pub fn parse(format: &str, utc: bool) -> Result<TimeFormat, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                text.push(c);
+                continue;
+            }
+            let conversion = match chars.next() {
+                Some(conversion) => conversion,
+                None             => return Err("the format ends with a lone %".to_string()),
+            };
+            if conversion == '%' {
+                text.push('%');
+                continue;
+            }
+            if !CONVERSIONS.contains(conversion) {
+                return Err(format!("%{} is not a time conversion", conversion));
+            }
+            if !text.is_empty() {
+                parts.push(Part::Text(text));
+                text = String::new();
+            }
+            parts.push(Part::Conversion(conversion));
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(TimeFormat {
+            parts: parts,
+            utc: utc,
+        })
+    }
+
+    /// The same format, in utc
+    pub fn in_utc(&self) -> TimeFormat {
+        TimeFormat {
+            parts: self.parts.clone(),
+            utc: true,
+        }
+    }
+
+    /// Formats a unix timestamp
+    pub fn render(&self, timestamp: i64) -> String {
+        let fields = if self.utc { utc_fields(timestamp) } else { local_fields(timestamp) };
+        let days = days_from_civil(fields.year, fields.month, fields.day);
+        let mut out = String::new();
+        for part in &self.parts {
+            let conversion = match *part {
+                Part::Text(ref text)         => {
+                    out.push_str(text);
+                    continue;
+                },
+                Part::Conversion(conversion) => conversion,
+            };
+            let hour12 = if fields.hour % 12 == 0 { 12 } else { fields.hour % 12 };
+            let weekday = WEEKDAYS[modulo(days + 4, 7) as usize];
+            let month = MONTHS[(fields.month - 1) as usize];
+            let text = match conversion {
+                'Y' => format!("{}", fields.year),
+                'y' => format!("{:02}", modulo(fields.year, 100)),
+                'm' => format!("{:02}", fields.month),
+                'd' => format!("{:02}", fields.day),
+                'e' => format!("{:2}", fields.day),
+                'H' => format!("{:02}", fields.hour),
+                'I' => format!("{:02}", hour12),
+                'M' => format!("{:02}", fields.minute),
+                'S' => format!("{:02}", fields.second),
+                'p' => (if fields.hour < 12 { "AM" } else { "PM" }).to_string(),
+                'a' => weekday[..3].to_string(),
+                'A' => weekday.to_string(),
+                'b' => month[..3].to_string(),
+                'B' => month.to_string(),
+                'j' => format!("{:03}", days - days_from_civil(fields.year, 1, 1) + 1),
+                'z' => {
+                    let sign = if fields.offset < 0 { '-' } else { '+' };
+                    let offset = fields.offset.abs() / 60;
+                    format!("{}{:02}{:02}", sign, offset / 60, offset % 60)
+                },
+                's' => format!("{}", fields.timestamp),
+                _   => unreachable!(),
+            };
+            out.push_str(&text);
+        }
+        out
+    }
+}
+
+fn utc_fields(timestamp: i64) -> Fields {
+    let (year, month, day, secs) = utc_date(timestamp);
+    Fields {
+        year: year,
+        month: month,
+        day: day,
+        hour: secs / 3600,
+        minute: secs / 60 % 60,
+        second: secs % 60,
+        offset: 0,
+        timestamp: timestamp,
+    }
+}
+
+fn local_fields(timestamp: i64) -> Fields {
+    let local = match sys::local_time(timestamp) {
+        Some(local) => local,
+        None        => return utc_fields(timestamp),
+    };
+    let (month, day) = (local.month as i64, local.day as i64);
+    let (hour, minute, second) = (local.hour as i64, local.minute as i64, local.second as i64);
+    // The offset is how far the local time, read as if it were utc, is from
+    // the real timestamp
+    let as_utc = days_from_civil(local.year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Fields {
+        year: local.year,
+        month: month,
+        day: day,
+        hour: hour,
+        minute: minute,
+        second: second,
+        offset: as_utc - timestamp,
+        timestamp: timestamp,
+    }
+}
+
+/// Days since the epoch of a date in the proleptic gregorian calendar (the
+/// inverse of utc_date, see http://howardhinnant.github.io/date_algorithms.html)
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = (if year >= 0 { year } else { year - 399 }) / 400;
+    let yoe = year - era * 400;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// a mod b, never negative (the epoch was a thursday, so days before it need
+/// this to get their weekday)
+fn modulo(a: i64, b: i64) -> i64 {
+    ((a % b) + b) % b
+}
@@ -0,0 +1,79 @@
+use std::process::Command;
+use std::sync::mpsc::Receiver;
+
+use notify::{self, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+// Format of the notification body (the buffer is the title)
+const BODY_FORMAT: &'static str = "<{nick}> {message}";
+
+// Shows a toast with the title and body from the environment, so neither is
+// ever read as powershell. Toasts have to come from a registered app, so
+// this borrows powershell's.
+const SCRIPT: &'static str = "\
+$manager = [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime]
+$template = $manager::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)
+$text = $template.GetElementsByTagName('text')
+$text.Item(0).AppendChild($template.CreateTextNode($env:WEECHAT_ALERT_TITLE)) | Out-Null
+$text.Item(1).AppendChild($template.CreateTextNode($env:WEECHAT_ALERT_BODY)) | Out-Null
+$toast = [Windows.UI.Notifications.ToastNotification]::new($template)
+$app = '{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\\WindowsPowerShell\\v1.0\\powershell.exe'
+$manager::CreateToastNotifier($app).Show($toast)";
+
+/// Shows notifications as windows toasts, through powershell's access to
+/// the windows runtime. This is what "desktop" is on windows (8 and up).
+///
+/// The toasts say they are from Windows PowerShell, and there are no icons
+/// or urgencies. Toasts of our own would need us installed with a start
+/// menu shortcut carrying our app id.
+pub struct Toast {
+    body: Template,
+    /// Queue of powershell commands waiting to run
+    queue: Queue<Command>,
+}
+
+impl Toast {
+    pub fn new(settings: &Settings) -> Toast {
+        // Bodies are limited to the same length as the main format
+        let mut body = Template::parse(BODY_FORMAT).unwrap();
+        body.set_max_length(settings.format.max_length());
+        Toast {
+            body: body,
+            queue: Queue::start("desktop", QUEUE_SIZE, run_all),
+        }
+    }
+}
+
+impl Notifier for Toast {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        let title = event.buffer.clone().unwrap_or("weechat".to_string());
+        let body = self.body.render(event);
+
+        let mut cmd = Command::new("powershell");
+        cmd.arg("-NoProfile")
+           .arg("-NonInteractive")
+           .arg("-Command")
+           .arg(SCRIPT)
+           .env("WEECHAT_ALERT_TITLE", title)
+           .env("WEECHAT_ALERT_BODY", body);
+
+        self.queue.send(cmd)
+    }
+
+    fn startup_check(&self) -> Result<(), NotifyError> {
+        notify::find_program("powershell.exe")
+    }
+}
+
+/// Runs every queued powershell in order, as each one takes a moment to
+/// start and we don't want to hold up processing messages
+fn run_all(pending: Receiver<Command>) {
+    for mut cmd in pending {
+        match cmd.status() {
+            Ok(ref status) if status.success() => (),
+            Ok(status) => error!("powershell failed to show a toast ({})", status),
+            Err(e)     => error!("could not run powershell: {}", e),
+        }
+    }
+}
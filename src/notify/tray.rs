@@ -0,0 +1,735 @@
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use libc;
+
+use notify::dbus::{Bus, Header, Writer, INTROSPECTABLE, INVALID_ARGS, METHOD_CALL, PROPERTIES, SIGNAL,
+                   UNKNOWN_METHOD};
+use notify::status::{Status, Summary};
+
+// Our status notifier item, and the menu it points to
+const ITEM_PATH: &'static str = "/StatusNotifierItem";
+const ITEM_INTERFACE: &'static str = "org.kde.StatusNotifierItem";
+const MENU_PATH: &'static str = "/MenuBar";
+const MENU_INTERFACE: &'static str = "com.canonical.dbusmenu";
+
+// The tray's watcher, which our item registers with
+const WATCHER: &'static str = "org.kde.StatusNotifierWatcher";
+const WATCHER_PATH: &'static str = "/StatusNotifierWatcher";
+
+/// How long Snooze in the menu mutes for
+pub const SNOOZE_MINUTES: u64 = 30;
+
+// Milliseconds to wait for calls from the tray before looking for a change
+// to the status
+const POLL_INTERVAL: u64 = 250;
+
+// Seconds to wait before trying to connect to the bus again after it went
+// away (or was never there)
+const RECONNECT_DELAY: u64 = 60;
+
+// Ids of the menu items. 0 is the menu itself
+const MENU_ROOT: i32 = 0;
+const MENU_CLEAR: i32 = 1;
+const MENU_MUTE: i32 = 2;
+const MENU_SNOOZE: i32 = 3;
+const MENU_SEPARATOR: i32 = 4;
+const MENU_QUIT: i32 = 5;
+const MENU_ITEMS: [i32; 5] = [MENU_CLEAR, MENU_MUTE, MENU_SNOOZE, MENU_SEPARATOR, MENU_QUIT];
+
+// Properties of our item, for GetAll
+const ITEM_PROPERTIES: [&'static str; 14] = ["Category", "Id", "Title", "Status", "WindowId", "IconName",
+                                             "IconPixmap", "AttentionIconName", "AttentionIconPixmap",
+                                             "OverlayIconName", "OverlayIconPixmap", "ToolTip",
+                                             "ItemIsMenu", "Menu"];
+
+// Width and height of the icon, in pixels
+const ICON_SIZE: usize = 32;
+
+// Colors of the icon: normally, with unread notifications, while muted and
+// while not connected to the relay
+const COLOR_IDLE: (u8, u8, u8) = (0x34, 0x65, 0xa4);
+const COLOR_UNREAD: (u8, u8, u8) = (0xf5, 0x79, 0x00);
+const COLOR_MUTED: (u8, u8, u8) = (0x88, 0x8a, 0x85);
+const COLOR_DISCONNECTED: (u8, u8, u8) = (0xcc, 0x00, 0x00);
+
+// A 3x5 font for the count on the icon. Each row is 3 bits, the left column
+// being the highest.
+const DIGITS: [[u8; 5]; 10] = [[7, 5, 5, 5, 7], [2, 6, 2, 2, 7], [7, 1, 7, 4, 7], [7, 1, 7, 1, 7],
+                               [5, 5, 7, 1, 1], [7, 4, 7, 1, 7], [7, 4, 7, 5, 7], [7, 1, 1, 1, 1],
+                               [7, 5, 7, 5, 7], [7, 5, 7, 1, 7]];
+const PLUS: [u8; 5] = [0, 2, 7, 2, 0];
+
+const ITEM_INTROSPECTION: &'static str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.kde.StatusNotifierItem">
+    <property name="Category" type="s" access="read"/>
+    <property name="Id" type="s" access="read"/>
+    <property name="Title" type="s" access="read"/>
+    <property name="Status" type="s" access="read"/>
+    <property name="WindowId" type="i" access="read"/>
+    <property name="IconName" type="s" access="read"/>
+    <property name="IconPixmap" type="a(iiay)" access="read"/>
+    <property name="AttentionIconName" type="s" access="read"/>
+    <property name="AttentionIconPixmap" type="a(iiay)" access="read"/>
+    <property name="OverlayIconName" type="s" access="read"/>
+    <property name="OverlayIconPixmap" type="a(iiay)" access="read"/>
+    <property name="ToolTip" type="(sa(iiay)ss)" access="read"/>
+    <property name="ItemIsMenu" type="b" access="read"/>
+    <property name="Menu" type="o" access="read"/>
+    <method name="Activate">
+      <arg name="x" type="i" direction="in"/>
+      <arg name="y" type="i" direction="in"/>
+    </method>
+    <method name="SecondaryActivate">
+      <arg name="x" type="i" direction="in"/>
+      <arg name="y" type="i" direction="in"/>
+    </method>
+    <method name="ContextMenu">
+      <arg name="x" type="i" direction="in"/>
+      <arg name="y" type="i" direction="in"/>
+    </method>
+    <method name="Scroll">
+      <arg name="delta" type="i" direction="in"/>
+      <arg name="orientation" type="s" direction="in"/>
+    </method>
+    <signal name="NewTitle"/>
+    <signal name="NewIcon"/>
+    <signal name="NewAttentionIcon"/>
+    <signal name="NewOverlayIcon"/>
+    <signal name="NewToolTip"/>
+    <signal name="NewStatus">
+      <arg name="status" type="s"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="data" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+const MENU_INTROSPECTION: &'static str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="com.canonical.dbusmenu">
+    <property name="Version" type="u" access="read"/>
+    <property name="TextDirection" type="s" access="read"/>
+    <property name="Status" type="s" access="read"/>
+    <property name="IconThemePath" type="as" access="read"/>
+    <method name="GetLayout">
+      <arg name="parentId" type="i" direction="in"/>
+      <arg name="recursionDepth" type="i" direction="in"/>
+      <arg name="propertyNames" type="as" direction="in"/>
+      <arg name="revision" type="u" direction="out"/>
+      <arg name="layout" type="(ia{sv}av)" direction="out"/>
+    </method>
+    <method name="GetGroupProperties">
+      <arg name="ids" type="ai" direction="in"/>
+      <arg name="propertyNames" type="as" direction="in"/>
+      <arg name="properties" type="a(ia{sv})" direction="out"/>
+    </method>
+    <method name="GetProperty">
+      <arg name="id" type="i" direction="in"/>
+      <arg name="name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="Event">
+      <arg name="id" type="i" direction="in"/>
+      <arg name="eventId" type="s" direction="in"/>
+      <arg name="data" type="v" direction="in"/>
+      <arg name="timestamp" type="u" direction="in"/>
+    </method>
+    <method name="EventGroup">
+      <arg name="events" type="a(isvu)" direction="in"/>
+      <arg name="idErrors" type="ai" direction="out"/>
+    </method>
+    <method name="AboutToShow">
+      <arg name="id" type="i" direction="in"/>
+      <arg name="needUpdate" type="b" direction="out"/>
+    </method>
+    <method name="AboutToShowGroup">
+      <arg name="ids" type="ai" direction="in"/>
+      <arg name="updatesNeeded" type="ai" direction="out"/>
+      <arg name="idErrors" type="ai" direction="out"/>
+    </method>
+    <signal name="LayoutUpdated">
+      <arg name="revision" type="u"/>
+      <arg name="parent" type="i"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="property" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/>
+      <arg name="properties" type="a{sv}" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="data" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+/// Shows a tray icon with the count of notifications since it was last
+/// cleared, as a StatusNotifierItem on the session bus (which KDE, and GNOME
+/// and others with an appindicator extension, show in their tray). The icon
+/// is orange while there are unread notifications, grey while muted and red
+/// while we aren't connected to the relay. Its menu has Clear, Mute, Snooze
+/// and Quit, which do the same as the control socket's commands, and
+/// clicking the icon clears the count.
+///
+/// The icon runs on its own thread. If there is no session bus or no tray
+/// it just isn't shown, and shows up once there is.
+pub fn start(status: Status) {
+    thread::spawn(move || run(status));
+}
+
+/// Keeps our item on the bus, answering the tray's calls and telling it
+/// when the status changes. If the bus goes away we try to get it back every
+/// RECONNECT_DELAY seconds.
+fn run(status: Status) {
+    let mut tray = connect(&status);
+    let mut last_attempt = Instant::now();
+    loop {
+        if tray.is_none() {
+            thread::sleep(Duration::from_millis(POLL_INTERVAL));
+            if last_attempt.elapsed() >= Duration::from_secs(RECONNECT_DELAY) {
+                last_attempt = Instant::now();
+                tray = connect(&status);
+            }
+            continue;
+        }
+
+        let result = match tray {
+            Some(ref mut tray) => tray.step(),
+            None               => Ok(()),
+        };
+        if let Err(e) = result {
+            error!("lost the session bus ({}), the tray icon is gone until it is back", e);
+            tray = None;
+            last_attempt = Instant::now();
+        }
+    }
+}
+
+fn connect(status: &Status) -> Option<Tray> {
+    match Tray::connect(status.clone()) {
+        Ok(tray) => Some(tray),
+        Err(e)   => {
+            warn!("could not connect to the session bus ({}), there is no tray icon until it is there",
+                  e);
+            None
+        },
+    }
+}
+
+struct Tray {
+    bus: Bus,
+    status: Status,
+    /// The name we registered with the watcher
+    name: String,
+    /// The status the tray last heard about
+    shown: Summary,
+    /// Revision of the menu layout, bumped whenever an item changes
+    revision: u32,
+}
+
+impl Tray {
+    /// Puts our item on the bus and registers it with the watcher, if there
+    /// is one
+    fn connect(status: Status) -> Result<Tray, String> {
+        let mut bus = try!(Bus::open());
+        let name = format!("{}-{}-1", ITEM_INTERFACE, unsafe { libc::getpid() });
+        try!(bus.request_name(&name));
+        // Hear about the watcher coming and going, so we can register with
+        // a tray that starts (or restarts) after us
+        try!(bus.add_match(&format!("type='signal',sender='org.freedesktop.DBus',\
+                                     interface='org.freedesktop.DBus',member='NameOwnerChanged',\
+                                     arg0='{}'", WATCHER)));
+
+        let shown = status.summary();
+        let mut tray = Tray {
+            bus: bus,
+            status: status,
+            name: name,
+            shown: shown,
+            revision: 1,
+        };
+        if let Err(e) = tray.register() {
+            warn!("no tray is running ({}), the tray icon will show up when one starts", e);
+        }
+        Ok(tray)
+    }
+
+    fn register(&mut self) -> Result<(), String> {
+        let mut args = Writer::new();
+        args.string(&self.name);
+        let serial = try!(self.bus.call_method(WATCHER, WATCHER_PATH, WATCHER, "RegisterStatusNotifierItem",
+                                               &args, "s"));
+        self.bus.wait_for_reply(serial).map(|_| ())
+    }
+
+    /// Answers what has come in from the bus, then tells the tray if the
+    /// status changed
+    fn step(&mut self) -> Result<(), String> {
+        for message in try!(self.bus.poll(Duration::from_millis(POLL_INTERVAL))) {
+            if message.kind == METHOD_CALL {
+                try!(self.answer(&message));
+            } else if message.kind == SIGNAL && message.member() == "NameOwnerChanged" {
+                let mut args = message.args();
+                let (name, _, owner) = (try!(args.string()), try!(args.string()), try!(args.string()));
+                if name == WATCHER && !owner.is_empty() {
+                    if let Err(e) = self.register() {
+                        error!("could not register the tray icon with the new tray: {}", e);
+                    }
+                }
+            }
+        }
+
+        let summary = self.status.summary();
+        if summary != self.shown {
+            self.shown = summary;
+            try!(self.changed());
+        }
+        Ok(())
+    }
+
+    /// Tells the tray that everything about the item and its menu changed
+    fn changed(&mut self) -> Result<(), String> {
+        let none = Writer::new();
+        for member in &["NewIcon", "NewAttentionIcon", "NewTitle", "NewToolTip"] {
+            try!(self.bus.signal(ITEM_PATH, ITEM_INTERFACE, member, &none, ""));
+        }
+        let mut args = Writer::new();
+        args.string(item_status(&self.shown));
+        try!(self.bus.signal(ITEM_PATH, ITEM_INTERFACE, "NewStatus", &args, "s"));
+
+        self.revision += 1;
+        let mut args = Writer::new();
+        args.u32(self.revision);
+        args.i32(MENU_ROOT);
+        self.bus.signal(MENU_PATH, MENU_INTERFACE, "LayoutUpdated", &args, "ui")
+    }
+
+    /// Replies to a method call on the item or the menu (or says it
+    /// doesn't exist)
+    fn answer(&mut self, call: &Header) -> Result<(), String> {
+        let summary = self.status.summary();
+        let mut args = call.args();
+        let mut body = Writer::new();
+        // Menu items clicked, acted on after the reply (as Quit doesn't
+        // return)
+        let mut clicked = Vec::new();
+        let signature = match (call.path(), call.interface(), call.member()) {
+            (ITEM_PATH, INTROSPECTABLE, "Introspect") | (ITEM_PATH, "", "Introspect") => {
+                body.string(ITEM_INTROSPECTION);
+                "s"
+            },
+            (MENU_PATH, INTROSPECTABLE, "Introspect") | (MENU_PATH, "", "Introspect") => {
+                body.string(MENU_INTROSPECTION);
+                "s"
+            },
+            (ITEM_PATH, PROPERTIES, "Get") => {
+                let (_, name) = (try!(args.string()), try!(args.string()));
+                if !item_property(&mut body, &name, &summary) {
+                    return self.bus.reply_error(call, INVALID_ARGS, "No such property");
+                }
+                "v"
+            },
+            (ITEM_PATH, PROPERTIES, "GetAll") => {
+                let array = body.begin_array(8);
+                for name in &ITEM_PROPERTIES {
+                    body.align(8);
+                    body.string(name);
+                    item_property(&mut body, name, &summary);
+                }
+                body.end_array(array);
+                "a{sv}"
+            },
+            (ITEM_PATH, _, "Activate") => {
+                self.status.clear();
+                ""
+            },
+            (ITEM_PATH, _, "SecondaryActivate") |
+            (ITEM_PATH, _, "ContextMenu") |
+            (ITEM_PATH, _, "Scroll") => "",
+            (MENU_PATH, PROPERTIES, "Get") => {
+                let (_, name) = (try!(args.string()), try!(args.string()));
+                if !menu_property(&mut body, &name) {
+                    return self.bus.reply_error(call, INVALID_ARGS, "No such property");
+                }
+                "v"
+            },
+            (MENU_PATH, PROPERTIES, "GetAll") => {
+                let array = body.begin_array(8);
+                for name in &["Version", "TextDirection", "Status", "IconThemePath"] {
+                    body.align(8);
+                    body.string(name);
+                    menu_property(&mut body, name);
+                }
+                body.end_array(array);
+                "a{sv}"
+            },
+            (MENU_PATH, _, "GetLayout") => {
+                let parent = try!(args.i32());
+                if parent != MENU_ROOT && !MENU_ITEMS.contains(&parent) {
+                    return self.bus.reply_error(call, INVALID_ARGS, "No such menu item");
+                }
+                body.u32(self.revision);
+                menu_layout(&mut body, parent, &summary);
+                "u(ia{sv}av)"
+            },
+            (MENU_PATH, _, "GetGroupProperties") => {
+                // No ids means every item
+                let end = try!(args.begin_array(4));
+                let mut ids = Vec::new();
+                while args.in_array(end) {
+                    ids.push(try!(args.i32()));
+                }
+                if ids.is_empty() {
+                    ids.push(MENU_ROOT);
+                    ids.extend_from_slice(&MENU_ITEMS);
+                }
+
+                let array = body.begin_array(8);
+                for &id in ids.iter().filter(|&&id| id == MENU_ROOT || MENU_ITEMS.contains(&id)) {
+                    body.align(8);
+                    body.i32(id);
+                    write_properties(&mut body, &menu_item(id, &summary));
+                }
+                body.end_array(array);
+                "a(ia{sv})"
+            },
+            (MENU_PATH, _, "GetProperty") => {
+                let (id, name) = (try!(args.i32()), try!(args.string()));
+                match menu_item(id, &summary).into_iter().find(|&(n, _)| n == name) {
+                    Some((_, value)) => value.write(&mut body),
+                    None             => return self.bus.reply_error(call, INVALID_ARGS, "No such property"),
+                }
+                "v"
+            },
+            (MENU_PATH, _, "Event") => {
+                let (id, event) = (try!(args.i32()), try!(args.string()));
+                if event == "clicked" {
+                    clicked.push(id);
+                }
+                ""
+            },
+            (MENU_PATH, _, "EventGroup") => {
+                let end = try!(args.begin_array(8));
+                while args.in_array(end) {
+                    args.align(8);
+                    let (id, event) = (try!(args.i32()), try!(args.string()));
+                    try!(args.skip_variant());
+                    try!(args.u32());
+                    if event == "clicked" {
+                        clicked.push(id);
+                    }
+                }
+                let errors = body.begin_array(4);
+                body.end_array(errors);
+                "ai"
+            },
+            (MENU_PATH, _, "AboutToShow") => {
+                body.bool(false);
+                "b"
+            },
+            (MENU_PATH, _, "AboutToShowGroup") => {
+                for _ in 0..2 {
+                    let ids = body.begin_array(4);
+                    body.end_array(ids);
+                }
+                "aiai"
+            },
+            _ => return self.bus.reply_error(call, UNKNOWN_METHOD, "No such method"),
+        };
+        try!(self.bus.reply(call, &body, signature));
+        for id in clicked {
+            self.clicked(id);
+        }
+
+        // Let the tray know right away if a click changed anything
+        let summary = self.status.summary();
+        if summary != self.shown {
+            self.shown = summary;
+            try!(self.changed());
+        }
+        Ok(())
+    }
+
+    /// Acts on a menu item being clicked
+    fn clicked(&mut self, id: i32) {
+        let summary = self.status.summary();
+        match id {
+            MENU_CLEAR  => self.status.clear(),
+            MENU_MUTE   => if summary.muted { self.status.unmute() } else { self.status.mute() },
+            MENU_SNOOZE => if summary.snoozed_for.is_some() {
+                self.status.unmute()
+            } else {
+                self.status.snooze(Duration::from_secs(SNOOZE_MINUTES * 60))
+            },
+            MENU_QUIT   => {
+                info!("Quitting from the tray icon");
+                process::exit(0);
+            },
+            _           => (),
+        }
+    }
+}
+
+/// The Status property of our item. NeedsAttention makes the tray show the
+/// item even if it hides the quiet ones.
+fn item_status(summary: &Summary) -> &'static str {
+    if summary.unread > 0 && !summary.is_muted() {
+        "NeedsAttention"
+    } else {
+        "Active"
+    }
+}
+
+/// What the tooltip says under the title
+fn description(summary: &Summary) -> String {
+    let mut lines = Vec::new();
+    if !summary.connected {
+        lines.push("Not connected to the relay".to_string());
+    }
+    match (summary.muted, summary.snoozed_for) {
+        (true, _)           => lines.push("Muted".to_string()),
+        (false, Some(left)) => lines.push(format!("Snoozed for {} more minutes", left)),
+        (false, None)       => (),
+    }
+    lines.push(match summary.unread {
+        0 => "Nothing new".to_string(),
+        1 => "1 notification".to_string(),
+        n => format!("{} notifications", n),
+    });
+    lines.join("\n")
+}
+
+/// Writes a property of our item as a variant. Returns false if there is no
+/// such property.
+fn item_property(body: &mut Writer, name: &str, summary: &Summary) -> bool {
+    let title = match summary.unread {
+        0 => "weechat-alert".to_string(),
+        n => format!("weechat-alert ({})", n),
+    };
+    match name {
+        "Category"                          => Value::Str("Communications".to_string()).write(body),
+        "Id"                                => Value::Str("weechat-alert".to_string()).write(body),
+        "Title"                             => Value::Str(title).write(body),
+        "Status"                            => Value::Str(item_status(summary).to_string()).write(body),
+        "WindowId"                          => Value::Int(0).write(body),
+        "IconName" | "AttentionIconName" |
+        "OverlayIconName"                   => Value::Str(String::new()).write(body),
+        "IconPixmap" | "AttentionIconPixmap" => {
+            body.signature("a(iiay)");
+            write_pixmaps(body, Some(&icon(summary)));
+        },
+        "OverlayIconPixmap"                 => {
+            body.signature("a(iiay)");
+            write_pixmaps(body, None);
+        },
+        "ToolTip"                           => {
+            body.signature("(sa(iiay)ss)");
+            body.align(8);
+            body.string("");
+            write_pixmaps(body, None);
+            body.string(&title);
+            body.string(&description(summary));
+        },
+        "ItemIsMenu"                        => Value::Bool(false).write(body),
+        "Menu"                              => {
+            body.signature("o");
+            body.string(MENU_PATH);
+        },
+        _                                   => return false,
+    }
+    true
+}
+
+/// Writes a property of the menu as a variant. Returns false if there is no
+/// such property.
+fn menu_property(body: &mut Writer, name: &str) -> bool {
+    match name {
+        "Version"       => {
+            body.signature("u");
+            body.u32(3);
+        },
+        "TextDirection" => Value::Str("ltr".to_string()).write(body),
+        "Status"        => Value::Str("normal".to_string()).write(body),
+        "IconThemePath" => {
+            body.signature("as");
+            let paths = body.begin_array(4);
+            body.end_array(paths);
+        },
+        _               => return false,
+    }
+    true
+}
+
+/// A value of one of the basic types, for a property
+enum Value {
+    Str(String),
+    Bool(bool),
+    Int(i32),
+}
+
+impl Value {
+    /// Writes the value as a variant
+    fn write(&self, body: &mut Writer) {
+        match *self {
+            Value::Str(ref value) => {
+                body.signature("s");
+                body.string(value);
+            },
+            Value::Bool(value)    => {
+                body.signature("b");
+                body.bool(value);
+            },
+            Value::Int(value)     => {
+                body.signature("i");
+                body.i32(value);
+            },
+        }
+    }
+}
+
+/// The properties of a menu item
+fn menu_item(id: i32, summary: &Summary) -> Vec<(&'static str, Value)> {
+    let toggle = |on: bool| Value::Int(if on { 1 } else { 0 });
+    match id {
+        MENU_ROOT      => vec![("children-display", Value::Str("submenu".to_string()))],
+        MENU_CLEAR     => vec![("label", Value::Str("Clear".to_string())),
+                               ("enabled", Value::Bool(summary.unread > 0))],
+        MENU_MUTE      => vec![("label", Value::Str("Mute".to_string())),
+                               ("toggle-type", Value::Str("checkmark".to_string())),
+                               ("toggle-state", toggle(summary.muted))],
+        MENU_SNOOZE    => {
+            let label = match summary.snoozed_for {
+                Some(left) => format!("Snoozed ({} more minutes)", left),
+                None       => format!("Snooze for {} minutes", SNOOZE_MINUTES),
+            };
+            vec![("label", Value::Str(label)),
+                 ("toggle-type", Value::Str("checkmark".to_string())),
+                 ("toggle-state", toggle(summary.snoozed_for.is_some()))]
+        },
+        MENU_SEPARATOR => vec![("type", Value::Str("separator".to_string()))],
+        MENU_QUIT      => vec![("label", Value::Str("Quit".to_string()))],
+        _              => vec![],
+    }
+}
+
+/// Writes an a{sv} of properties
+fn write_properties(body: &mut Writer, properties: &[(&'static str, Value)]) {
+    let array = body.begin_array(8);
+    for &(name, ref value) in properties {
+        body.align(8);
+        body.string(name);
+        value.write(body);
+    }
+    body.end_array(array);
+}
+
+/// Writes the (ia{sv}av) layout of a menu item, with its children if it is
+/// the menu itself
+fn menu_layout(body: &mut Writer, id: i32, summary: &Summary) {
+    body.align(8);
+    body.i32(id);
+    write_properties(body, &menu_item(id, summary));
+    let children = body.begin_array(1);
+    if id == MENU_ROOT {
+        for &child in &MENU_ITEMS {
+            body.signature("(ia{sv}av)");
+            menu_layout(body, child, summary);
+        }
+    }
+    body.end_array(children);
+}
+
+/// Writes an a(iiay) of pixmaps, with the icon in it if there is one
+fn write_pixmaps(body: &mut Writer, icon: Option<&[u8]>) {
+    let array = body.begin_array(8);
+    if let Some(icon) = icon {
+        body.align(8);
+        body.i32(ICON_SIZE as i32);
+        body.i32(ICON_SIZE as i32);
+        body.byte_array(icon);
+    }
+    body.end_array(array);
+}
+
+/// Draws the icon: a circle colored for the status, with the count on it.
+/// The pixels are ARGB, in network byte order.
+fn icon(summary: &Summary) -> Vec<u8> {
+    let (r, g, b) = if !summary.connected {
+        COLOR_DISCONNECTED
+    } else if summary.is_muted() {
+        COLOR_MUTED
+    } else if summary.unread > 0 {
+        COLOR_UNREAD
+    } else {
+        COLOR_IDLE
+    };
+
+    let mut pixels = vec![0; ICON_SIZE * ICON_SIZE * 4];
+    let center = (ICON_SIZE as f32 - 1.0) / 2.0;
+    let radius = ICON_SIZE as f32 / 2.0;
+    for y in 0..ICON_SIZE {
+        for x in 0..ICON_SIZE {
+            let (dx, dy) = (x as f32 - center, y as f32 - center);
+            if dx * dx + dy * dy <= radius * radius {
+                let i = (y * ICON_SIZE + x) * 4;
+                pixels[i..i + 4].copy_from_slice(&[0xff, r, g, b]);
+            }
+        }
+    }
+
+    // A single digit is drawn bigger than two, and more than 99 is "9+"
+    let (glyphs, scale) = match summary.unread {
+        0            => (vec![], 0),
+        n if n < 10  => (vec![DIGITS[n as usize]], 4),
+        n if n < 100 => (vec![DIGITS[n as usize / 10], DIGITS[n as usize % 10]], 3),
+        _            => (vec![DIGITS[9], PLUS], 3),
+    };
+    let width = glyphs.len() * 4 * scale - scale;
+    let left = ICON_SIZE.saturating_sub(width) / 2;
+    let top = (ICON_SIZE - 5 * scale) / 2;
+    for (n, glyph) in glyphs.iter().enumerate() {
+        for (row, bits) in glyph.iter().enumerate() {
+            for column in 0..3 {
+                if bits & (4 >> column) == 0 {
+                    continue;
+                }
+                for y in top + row * scale..top + (row + 1) * scale {
+                    let x = left + (n * 4 + column) * scale;
+                    for x in x..x + scale {
+                        let i = (y * ICON_SIZE + x) * 4;
+                        pixels[i..i + 4].copy_from_slice(&[0xff, 0xff, 0xff, 0xff]);
+                    }
+                }
+            }
+        }
+    }
+    pixels
+}
@@ -0,0 +1,85 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Receiver;
+
+use notify::{self, NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+/// What is spoken if tts_format isn't set
+pub const DEFAULT_FORMAT: &'static str = "{nick} in {buffer}: {message}";
+
+/// Most characters that are spoken if tts_max_length isn't set. Listening to
+/// a wall of text being read out is a lot slower than reading it.
+pub const DEFAULT_MAX_LENGTH: usize = 200;
+
+/// Reads notifications aloud, by piping them to a text to speech command
+/// (such as espeak-ng, say, or festival --tts) on its stdin
+pub struct Tts {
+    format: Template,
+    /// The program the tts command runs
+    program: String,
+    /// Queue of text waiting to be spoken
+    queue: Queue<String>,
+}
+
+impl Tts {
+    /// Creates the backend, starting the thread that runs the tts command
+    pub fn new(settings: &Settings) -> Result<Tts, String> {
+        if settings.tts_command.is_empty() {
+            return Err("'tts_command' can't be empty".to_string());
+        }
+
+        let command = settings.tts_command.clone();
+        let queue = Queue::start("tts", QUEUE_SIZE, move |pending| speak_all(command, pending));
+
+        Ok(Tts {
+            format: settings.tts_format.clone(),
+            program: settings.tts_command[0].clone(),
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Tts {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.queue.send(self.format.render(event))
+    }
+
+    fn startup_check(&self) -> Result<(), NotifyError> {
+        notify::find_program(&self.program)
+    }
+}
+
+/// Speaks every queued text, one at a time so they don't talk over each other
+fn speak_all(command: Vec<String>, pending: Receiver<String>) {
+    for text in pending {
+        if let Err(e) = speak(&command, &text) {
+            error!("{}", e);
+        }
+    }
+}
+
+/// Runs the tts command with the text on its stdin, waiting for it to finish
+fn speak(command: &[String], text: &str) -> Result<(), String> {
+    let name = &command[0];
+    let mut child = try!(Command::new(name)
+        .args(&command[1..])
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run {}: {}", name, e)));
+
+    // Dropping stdin closes it, which tells the command the text is done
+    if let Some(mut stdin) = child.stdin.take() {
+        if let Err(e) = stdin.write_all(text.as_bytes()) {
+            let _ = child.wait();
+            return Err(format!("could not write to {}: {}", name, e));
+        }
+    }
+
+    match child.wait() {
+        Ok(ref status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{} failed ({})", name, status)),
+        Err(e)     => Err(format!("could not wait for {}: {}", name, e)),
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rustc_serialize::base64::{ToBase64, STANDARD};
+use rustc_serialize::json::Json;
+
+use buffers;
+use http::{self, Url};
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template;
+
+/// Most characters of the message put in a text
+pub const MAX_MESSAGE_LENGTH: usize = 120;
+
+// Seconds to wait for twilio
+const TIMEOUT: u64 = 10;
+
+// Seconds to wait before trying a text again after a server error
+const RETRY_DELAY: u64 = 10;
+
+// Attempts at sending a text before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+/// Settings for the twilio backend, from [notifier.twilio]
+#[derive(Clone)]
+pub struct TwilioSettings {
+    pub account_sid: String,
+    pub auth_token: String,
+    /// Twilio number texts are sent from, such as "+15551234567"
+    pub from: String,
+    /// Number texts are sent to
+    pub to: String,
+    /// Buffer names or patterns that text, or empty for any buffer
+    pub buffers: Vec<String>,
+    /// Words (any case) a message must have one of to text, or empty for
+    /// any message
+    pub keywords: Vec<String>,
+    /// Most texts sent in any 24 hours
+    pub max_per_day: usize,
+}
+
+/// Debug output leaves out the auth token, so it can't end up in a log
+impl fmt::Debug for TwilioSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("TwilioSettings")
+         .field("account_sid", &self.account_sid)
+         .field("from", &self.from)
+         .field("to", &self.to)
+         .field("buffers", &self.buffers)
+         .field("keywords", &self.keywords)
+         .field("max_per_day", &self.max_per_day)
+         .finish()
+    }
+}
+
+/// Sends texts through twilio, for the few notifications that are worth
+/// paying for
+pub struct Twilio {
+    settings: TwilioSettings,
+    /// Queue of texts waiting to be sent
+    queue: Queue<String>,
+}
+
+impl Twilio {
+    /// Creates the backend, starting the thread that sends texts
+    pub fn new(settings: &Settings) -> Result<Twilio, String> {
+        let settings = try!(settings.twilio.clone()
+                            .ok_or("The twilio notifier needs an account_sid, auth_token, from and to in [notifier.twilio]"));
+
+        let url = try!(Url::parse(&format!("https://api.twilio.com/2010-04-01/Accounts/{}/Messages.json",
+                                           http::percent_encode(&settings.account_sid))));
+        let credentials = format!("{}:{}", settings.account_sid, settings.auth_token).as_bytes().to_base64(STANDARD);
+        let headers = vec![("Authorization".to_string(), format!("Basic {}", credentials))];
+        let fields = vec![("From", settings.from.clone()), ("To", settings.to.clone())];
+        let max_per_day = settings.max_per_day;
+        let queue = Queue::start("twilio", QUEUE_SIZE,
+                                 move |pending| send_all(url, headers, fields, max_per_day, pending));
+
+        Ok(Twilio {
+            settings: settings,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Twilio {
+    /// Queues a text, if the line is in one of the buffers and has one of the
+    /// keywords
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        match text(&self.settings, event) {
+            Some(text) => self.queue.send(text),
+            None       => Ok(()),
+        }
+    }
+}
+
+/// The text for an event, or None if it isn't in one of the buffers or
+/// doesn't have one of the keywords
+fn text(settings: &TwilioSettings, event: &NotificationEvent) -> Option<String> {
+    let buffer = event.buffer.as_ref().map(|s| s.as_str()).unwrap_or("weechat");
+    if !settings.buffers.is_empty() && !settings.buffers.iter().any(|p| buffers::matches(p, buffer)) {
+        return None;
+    }
+    let message = event.message.to_lowercase();
+    if !settings.keywords.is_empty() && !settings.keywords.iter().any(|k| message.contains(&k.to_lowercase()[..])) {
+        return None;
+    }
+    Some(format!("{}: {}", buffer, template::truncate(event.message.clone(), MAX_MESSAGE_LENGTH)))
+}
+
+/// Sends every queued text in order, up to max_per_day in any 24 hours.
+/// Texts past the cap are dropped, loudly, as they are meant to be urgent.
+///
+/// Note: Nothing printed here may include the headers, as they hold the auth
+///       token.
+fn send_all(url: Url, headers: Vec<(String, String)>, fields: Vec<(&'static str, String)>,
+            max_per_day: usize, pending: Receiver<String>) {
+    let mut sent: VecDeque<Instant> = VecDeque::new();
+    for text in pending {
+        while sent.front().map(|t| t.elapsed() >= Duration::from_secs(86400)).unwrap_or(false) {
+            sent.pop_front();
+        }
+        if sent.len() >= max_per_day {
+            error!("NOT SENDING A TWILIO TEXT, the cap of {} texts a day has been reached: {}",
+                   max_per_day, text);
+            continue;
+        }
+
+        let mut pairs: Vec<(&str, &str)> = fields.iter().map(|&(name, ref value)| (name, value.as_str())).collect();
+        pairs.push(("Body", &text));
+        let body = http::form_encode(&pairs);
+
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match http::post(&url, &headers, "application/x-www-form-urlencoded", body.as_bytes(),
+                             Duration::from_secs(TIMEOUT)) {
+                Ok(ref response) if response.success() => {
+                    info!("Sent a twilio text ({})", message_sid(&response.body));
+                    sent.push_back(Instant::now());
+                    if sent.len() == max_per_day {
+                        error!("the twilio cap of {} texts a day has been reached, texts will be dropped for a while",
+                               max_per_day);
+                    }
+                    break;
+                },
+                Ok(response) => {
+                    error!("twilio returned {}: {}", response.status, response.body.trim());
+                    // Sending the same thing again won't fix a client error
+                    if response.status / 100 == 4 {
+                        break;
+                    }
+                },
+                Err(e)       => error!("twilio failed: {}", e),
+            }
+            if attempts >= MAX_ATTEMPTS {
+                error!("giving up on a twilio text after {} attempts", attempts);
+                break;
+            }
+            thread::sleep(Duration::from_secs(RETRY_DELAY));
+        }
+    }
+}
+
+/// The sid of the message from a successful response, like
+/// {"sid": "SM...", "status": "queued", ...}
+fn message_sid(body: &str) -> String {
+    Json::from_str(body).ok()
+        .and_then(|json| json.find("sid").and_then(|s| s.as_string()).map(|s| s.to_string()))
+        .unwrap_or("no message sid".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{message_sid, text, TwilioSettings, MAX_MESSAGE_LENGTH};
+    use alert::AlertReason;
+    use testing::event;
+
+    fn settings(buffers: &[&str], keywords: &[&str]) -> TwilioSettings {
+        TwilioSettings {
+            account_sid: "AC123".to_string(),
+            auth_token: "secret-token".to_string(),
+            from: "+15551234567".to_string(),
+            to: "+15557654321".to_string(),
+            buffers: buffers.iter().map(|b| b.to_string()).collect(),
+            keywords: keywords.iter().map(|k| k.to_string()).collect(),
+            max_per_day: 10,
+        }
+    }
+
+    #[test]
+    fn texts_only_for_the_buffers_and_keywords() {
+        let oncall = event(AlertReason::Highlight, "irc.work.#oncall", "pager", "Prod is DOWN");
+        let chatter = event(AlertReason::Highlight, "irc.libera.#rust", "alice", "prod is down, lol");
+        assert_eq!(text(&settings(&["irc.work.*"], &[]), &oncall), Some("irc.work.#oncall: Prod is DOWN".to_string()));
+        assert_eq!(text(&settings(&["irc.work.*"], &[]), &chatter), None);
+        assert!(text(&settings(&[], &["down"]), &chatter).is_some());
+        assert_eq!(text(&settings(&["irc.work.*"], &["outage"]), &oncall), None);
+
+        let long = event(AlertReason::Private, "irc.work.bob", "bob", &"x".repeat(500));
+        let long = text(&settings(&["irc.work.*"], &[]), &long).unwrap();
+        assert_eq!(long.chars().count(), "irc.work.bob: ".len() + MAX_MESSAGE_LENGTH);
+    }
+
+    #[test]
+    fn debug_leaves_out_the_auth_token() {
+        let debug = format!("{:?}", settings(&["irc.work.*"], &[]));
+        assert!(debug.contains("AC123"), "{}", debug);
+        assert!(!debug.contains("secret-token"), "{}", debug);
+    }
+
+    #[test]
+    fn sid_of_the_response() {
+        assert_eq!(message_sid(r#"{"sid": "SM0123", "status": "queued"}"#), "SM0123");
+        assert_eq!(message_sid("<html>oops</html>"), "no message sid");
+    }
+}
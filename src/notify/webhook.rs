@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::Receiver;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use http::{self, Url};
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+
+/// Seconds to wait for the endpoint if timeout isn't set
+pub const DEFAULT_TIMEOUT: u64 = 10;
+
+// Seconds to wait before retrying a failed post
+const RETRY_DELAY: u64 = 5;
+
+/// Settings for the webhook backend, from [notifier.webhook]
+#[derive(Debug, Clone)]
+pub struct WebhookSettings {
+    /// Url to post notifications to
+    pub url: String,
+    /// Extra headers to send (ex: for an auth token)
+    pub headers: Vec<(String, String)>,
+    /// Timeout for connecting, and for each read and write after that
+    pub timeout: Duration,
+}
+
+/// Posts a json document to a url for every notification
+pub struct Webhook {
+    /// Queue of json documents waiting to be posted
+    queue: Queue<String>,
+}
+
+impl Webhook {
+    /// Creates the backend, starting the thread that posts notifications
+    pub fn new(settings: &Settings) -> Result<Webhook, String> {
+        let settings = try!(settings.webhook.clone()
+                            .ok_or("The webhook notifier needs a url in [notifier.webhook]"));
+        let url = try!(Url::parse(&settings.url).map_err(|e| format!("'notifier.webhook.url': {}", e)));
+
+        let queue = Queue::start("webhook", QUEUE_SIZE,
+                                 move |pending| post_all(url, settings, pending));
+        Ok(Webhook { queue: queue })
+    }
+}
+
+impl Notifier for Webhook {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.queue.send(payload(event).to_string())
+    }
+}
+
+/// The json document posted for an event
+fn payload(event: &NotificationEvent) -> Json {
+    Json::Object(event_doc(event))
+}
+
+/// The fields of the json document for an event, which the json log adds to
+pub fn event_doc(event: &NotificationEvent) -> BTreeMap<String, Json> {
+    let optional = |s: Option<&str>| s.map_or(Json::Null, |s| Json::String(s.to_string()));
+
+    let mut doc = BTreeMap::new();
+    doc.insert("class".to_string(), Json::String(event.class.name().to_string()));
+    doc.insert("reason".to_string(), Json::String(event.reason.to_string()));
+    doc.insert("priority".to_string(), Json::String(event.priority.name().to_string()));
+    doc.insert("server".to_string(), optional(event.network()));
+    doc.insert("buffer".to_string(), optional(event.buffer.as_ref().map(|s| s.as_str())));
+    doc.insert("nick".to_string(), optional(event.nick.as_ref().map(|s| s.as_str())));
+    doc.insert("message".to_string(), Json::String(event.message.clone()));
+    doc.insert("tags".to_string(),
+               Json::Array(event.tags.iter().map(|t| Json::String(t.clone())).collect()));
+    doc.insert("highlight".to_string(), Json::Boolean(event.highlight));
+    doc.insert("timestamp".to_string(), Json::I64(event.date));
+    doc
+}
+
+/// Posts every queued document in order, so a slow endpoint only holds up
+/// other webhook posts and not message processing
+fn post_all(url: Url, settings: WebhookSettings, pending: Receiver<String>) {
+    for doc in pending {
+        // Retry once after a failure that might be temporary. Client errors
+        // (4xx) aren't retried, as sending the same thing again won't help
+        let mut retried = false;
+        loop {
+            let retry = match http::post(&url, &settings.headers, "application/json",
+                                         doc.as_bytes(), settings.timeout) {
+                Ok(ref response) if response.success() => false,
+                Ok(response) => {
+                    error!("webhook returned {}: {}", response.status, response.body.trim());
+                    response.status / 100 == 5
+                },
+                Err(e)       => {
+                    error!("webhook failed: {}", e);
+                    true
+                },
+            };
+            if !retry || retried {
+                break;
+            }
+            retried = true;
+            thread::sleep(Duration::from_secs(RETRY_DELAY));
+        }
+    }
+}
@@ -0,0 +1,599 @@
+use std::cmp;
+use std::fmt;
+use std::io::prelude::*;
+use std::io::ErrorKind;
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use openssl::crypto::{hash, hmac, pkcs5, rand};
+use openssl::ssl::SslStream;
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+
+use http::{self, Trust};
+use notify::{NotificationEvent, Notifier, NotifyError, Settings};
+use notify::queue::{Queue, QUEUE_SIZE};
+use notify::template::Template;
+
+/// Port to connect to if server doesn't set one
+pub const DEFAULT_PORT: u16 = 5222;
+
+// Seconds to wait for each reply while logging in, and for each write
+const TIMEOUT: u64 = 10;
+
+// Seconds between checks for pings from the server while no messages are
+// waiting, and how many milliseconds each check waits for data
+const POLL_INTERVAL: u64 = 5;
+const POLL_READ_TIMEOUT: u64 = 100;
+
+// Seconds of quiet before we send a keepalive, which is how we find out a
+// connection died
+const KEEPALIVE_INTERVAL: u64 = 60;
+
+// How long to wait before logging in again after the session drops. This
+// starts at MIN_BACKOFF seconds and doubles after every failure up to
+// MAX_BACKOFF
+const MIN_BACKOFF: u64 = 5;
+const MAX_BACKOFF: u64 = 300;
+
+// Attempts at sending a message before it is dropped
+const MAX_ATTEMPTS: u32 = 3;
+
+const NS_TLS: &'static str = "urn:ietf:params:xml:ns:xmpp-tls";
+const NS_SASL: &'static str = "urn:ietf:params:xml:ns:xmpp-sasl";
+const NS_BIND: &'static str = "urn:ietf:params:xml:ns:xmpp-bind";
+const NS_SESSION: &'static str = "urn:ietf:params:xml:ns:xmpp-session";
+const NS_STANZAS: &'static str = "urn:ietf:params:xml:ns:xmpp-stanzas";
+const NS_PING: &'static str = "urn:xmpp:ping";
+
+/// Settings for the xmpp backend, from [notifier.xmpp]
+#[derive(Clone)]
+pub struct XmppSettings {
+    /// Jid to log in as, such as "me@example.com"
+    pub jid: String,
+    pub password: String,
+    /// Host and port to connect to, if not the jid's domain on port 5222
+    pub server: Option<(String, u16)>,
+    /// Jid messages are sent to
+    pub recipient: String,
+    pub format: Template,
+}
+
+/// Debug output leaves out the password, so it can't end up in a log
+impl fmt::Debug for XmppSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("XmppSettings")
+         .field("jid", &self.jid)
+         .field("server", &self.server)
+         .field("recipient", &self.recipient)
+         .field("format", &self.format)
+         .finish()
+    }
+}
+
+/// Sends notifications as xmpp chat messages
+pub struct Xmpp {
+    format: Template,
+    /// Queue of message bodies waiting to be sent
+    queue: Queue<String>,
+}
+
+impl Xmpp {
+    /// Creates the backend, logging in and starting the thread that keeps the
+    /// session open and sends messages
+    pub fn new(settings: &Settings) -> Result<Xmpp, String> {
+        let settings = try!(settings.xmpp.clone()
+                            .ok_or("The xmpp notifier needs a jid, password and recipient in [notifier.xmpp]"));
+
+        // A wrong password is caught here. If the server can't be reached
+        // right now we carry on, and the worker keeps trying to log in
+        let session = match login(&settings) {
+            Ok(session)               => Some(session),
+            Err(LoginError::Auth(e))  => {
+                return Err(format!("Could not log in to xmpp as {}: {}", settings.jid, e));
+            },
+            Err(LoginError::Other(e)) => {
+                warn!("could not log in to xmpp ({}), trying again in the background", e);
+                None
+            },
+        };
+
+        let format = settings.format.clone();
+        let queue = Queue::start("xmpp", QUEUE_SIZE, move |pending| run(settings, session, pending));
+
+        Ok(Xmpp {
+            format: format,
+            queue: queue,
+        })
+    }
+}
+
+impl Notifier for Xmpp {
+    fn notify(&self, event: &NotificationEvent) -> Result<(), NotifyError> {
+        self.queue.send(self.format.render(event))
+    }
+}
+
+/// Splits a jid (ex: "me@example.com/laptop") into its user and domain,
+/// leaving out any resource
+pub fn split_jid(jid: &str) -> Option<(&str, &str)> {
+    let bare = jid.split('/').next().unwrap_or(jid);
+    match bare.find('@') {
+        Some(i) if i > 0 && i + 1 < bare.len() => Some((&bare[..i], &bare[i + 1..])),
+        _                                      => None,
+    }
+}
+
+/// Parses a server to connect to, as "host" or "host:port"
+pub fn parse_server(server: &str) -> Result<(String, u16), String> {
+    let (host, port) = match server.rfind(':') {
+        Some(i) => {
+            let port = try!(server[i + 1..].parse()
+                            .map_err(|_| format!("{} has an invalid port", server)));
+            (&server[..i], port)
+        },
+        None    => (server, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return Err(format!("{} has no host", server));
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Why logging in failed
+enum LoginError {
+    /// The server turned down the jid and password
+    Auth(String),
+    /// Anything else, which may well be temporary
+    Other(String),
+}
+
+impl From<String> for LoginError {
+    fn from(err: String) -> LoginError {
+        LoginError::Other(err)
+    }
+}
+
+/// A logged in xmpp session
+struct Session {
+    stream: SslStream<TcpStream>,
+    /// Data from the server that isn't a whole element yet
+    buf: Vec<u8>,
+    /// When we last sent the server anything
+    last_write: Instant,
+}
+
+impl Session {
+    fn send(&mut self, recipient: &str, body: &str) -> Result<(), String> {
+        let message = format!("<message to='{}' type='chat'><body>{}</body></message>",
+                              escape(recipient), escape(body));
+        try!(write(&mut self.stream, &message));
+        self.last_write = Instant::now();
+        Ok(())
+    }
+
+    /// Deals with anything the server sent since we last looked, and sends
+    /// a keepalive if we have been quiet for a while. An error means the
+    /// session is gone.
+    fn poll(&mut self) -> Result<(), String> {
+        let mut data = [0; 4096];
+        loop {
+            match self.stream.read(&mut data) {
+                Ok(0)  => return Err("the server closed the connection".to_string()),
+                Ok(n)  => self.buf.extend_from_slice(&data[..n]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        while let Some(element) = take_element(&mut self.buf) {
+            try!(self.handle(&element));
+        }
+
+        if self.last_write.elapsed() >= Duration::from_secs(KEEPALIVE_INTERVAL) {
+            try!(write(&mut self.stream, " "));
+            self.last_write = Instant::now();
+        }
+        Ok(())
+    }
+
+    /// Handles an element from the server. Servers ping clients to find dead
+    /// connections, which we answer, and any other request gets an error as
+    /// we don't do anything else.
+    fn handle(&mut self, element: &str) -> Result<(), String> {
+        let kind = attr(element, "type").unwrap_or(String::new());
+        match element_name(element).as_str() {
+            "/stream:stream"                           => Err("the server closed the stream".to_string()),
+            "stream:error"                             => {
+                Err(format!("the server closed the stream ({})", condition(element)))
+            },
+            "iq" if kind == "get" || kind == "set"     => {
+                let id = escape(&attr(element, "id").unwrap_or(String::new()));
+                let to = attr(element, "from").map(|from| format!(" to='{}'", escape(&from)))
+                                              .unwrap_or(String::new());
+                let reply = if element.contains(NS_PING) {
+                    format!("<iq type='result' id='{}'{}/>", id, to)
+                } else {
+                    format!("<iq type='error' id='{}'{}><error type='cancel'><service-unavailable xmlns='{}'/></error></iq>",
+                            id, to, NS_STANZAS)
+                };
+                try!(write(&mut self.stream, &reply));
+                self.last_write = Instant::now();
+                Ok(())
+            },
+            "message" if kind == "error"               => {
+                error!("an xmpp message to {} bounced ({})",
+                       attr(element, "from").unwrap_or(String::new()), condition(element));
+                Ok(())
+            },
+            _                                          => Ok(()),
+        }
+    }
+}
+
+/// Sends every queued message, keeping the session open in between and
+/// logging back in (with a growing delay) whenever it drops. Messages wait
+/// in the queue while the session is down, so a long outage drops them like
+/// any other backed up notifier rather than piling them up.
+fn run(settings: XmppSettings, mut session: Option<Session>, pending: Receiver<String>) {
+    let mut backoff = MIN_BACKOFF;
+    let mut message = None;
+    let mut attempts = 0;
+    loop {
+        let mut current = match session.take() {
+            Some(current) => current,
+            None          => match login(&settings) {
+                Ok(current) => {
+                    info!("Logged back in to xmpp as {}", settings.jid);
+                    backoff = MIN_BACKOFF;
+                    current
+                },
+                Err(LoginError::Auth(e)) | Err(LoginError::Other(e)) => {
+                    error!("could not log in to xmpp, trying again in {} seconds ({})", backoff, e);
+                    thread::sleep(Duration::from_secs(backoff));
+                    backoff = cmp::min(backoff * 2, MAX_BACKOFF);
+                    continue;
+                },
+            },
+        };
+
+        if message.is_none() {
+            match pending.recv_timeout(Duration::from_secs(POLL_INTERVAL)) {
+                Ok(body)                            => {
+                    message = Some(body);
+                    attempts = 0;
+                },
+                Err(RecvTimeoutError::Timeout)      => (),
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+
+        let result = match message {
+            Some(ref body) => current.send(&settings.recipient, body),
+            None           => current.poll(),
+        };
+        match result {
+            Ok(_)  => {
+                message = None;
+                session = Some(current);
+            },
+            // Dropping the session closes the connection, and we log back in
+            // next time around
+            Err(e) => {
+                error!("lost the xmpp session ({})", e);
+                if message.is_some() {
+                    attempts += 1;
+                    if attempts >= MAX_ATTEMPTS {
+                        error!("giving up on an xmpp message after {} attempts", attempts);
+                        message = None;
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// Connects and logs in: starttls, SASL (SCRAM-SHA-1 if the server offers
+/// it, otherwise PLAIN, which is fine inside tls), and binding a resource.
+/// Logging in without tls is never done, as it would send the password in
+/// the clear.
+fn login(settings: &XmppSettings) -> Result<Session, LoginError> {
+    let (user, domain) = try!(split_jid(&settings.jid).ok_or(format!("{} is not a valid jid", settings.jid)));
+    let (host, port) = match settings.server {
+        Some((ref host, port)) => (host.clone(), port),
+        None                   => (domain.to_string(), DEFAULT_PORT),
+    };
+
+    let mut tcp_stream = try!(http::connect(&host, port, Duration::from_secs(TIMEOUT)));
+    let mut buf = Vec::new();
+    let features = try!(open_stream(&mut tcp_stream, &mut buf, domain));
+    if !features.contains(NS_TLS) {
+        return Err(LoginError::Other(format!("{} doesn't support starttls", host)));
+    }
+    try!(write(&mut tcp_stream, &format!("<starttls xmlns='{}'/>", NS_TLS)));
+    let reply = try!(read_element(&mut tcp_stream, &mut buf));
+    if element_name(&reply) != "proceed" {
+        return Err(LoginError::Other(format!("{} refused starttls", host)));
+    }
+
+    // The certificate has to be for the jid's domain, which isn't always the
+    // host we connected to
+    let mut stream = try!(http::connect_ssl(domain, tcp_stream, &Trust::system()));
+    buf.clear();
+    let features = try!(open_stream(&mut stream, &mut buf, domain));
+    let mechanisms = mechanisms(&features);
+    if mechanisms.iter().any(|m| m == "SCRAM-SHA-1") {
+        try!(scram_sha1(&mut stream, &mut buf, user, &settings.password));
+    } else if mechanisms.iter().any(|m| m == "PLAIN") {
+        try!(plain(&mut stream, &mut buf, user, &settings.password));
+    } else {
+        return Err(LoginError::Other(format!("{} doesn't offer a login we support (it offers {})",
+                                             host, mechanisms.join(", "))));
+    }
+
+    // A random resource, so we never kick off another of the user's clients
+    let resource: String = rand::rand_bytes(4).iter().map(|b| format!("{:02x}", b)).collect();
+    let features = try!(open_stream(&mut stream, &mut buf, domain));
+    try!(write(&mut stream, &format!("<iq type='set' id='bind'><bind xmlns='{}'><resource>weechat-alert.{}</resource></bind></iq>",
+                                     NS_BIND, resource)));
+    try!(wait_for_iq(&mut stream, &mut buf, "bind"));
+    // Older servers need a session started before they route our messages
+    if features.contains(NS_SESSION) {
+        try!(write(&mut stream, &format!("<iq type='set' id='session'><session xmlns='{}'/></iq>", NS_SESSION)));
+        try!(wait_for_iq(&mut stream, &mut buf, "session"));
+    }
+
+    // From here on reads only check for anything the server sent us
+    try!(stream.get_ref().set_read_timeout(Some(Duration::from_millis(POLL_READ_TIMEOUT)))
+         .map_err(|e| e.to_string()));
+    Ok(Session {
+        stream: stream,
+        buf: buf,
+        last_write: Instant::now(),
+    })
+}
+
+/// Logs in with SCRAM-SHA-1 (rfc 5802), which never sends the password and
+/// checks that the server knows it too
+fn scram_sha1<S: Read + Write>(stream: &mut S, buf: &mut Vec<u8>, user: &str, password: &str)
+                               -> Result<(), LoginError> {
+    let nonce = rand::rand_bytes(18).to_base64(STANDARD);
+    let first_bare = format!("n={},r={}", user.replace('=', "=3D").replace(',', "=2C"), nonce);
+    let first = format!("n,,{}", first_bare);
+    try!(write(stream, &format!("<auth xmlns='{}' mechanism='SCRAM-SHA-1'>{}</auth>",
+                                NS_SASL, first.as_bytes().to_base64(STANDARD))));
+
+    let server_first = try!(String::from_utf8(try!(sasl_reply(stream, buf, "challenge")))
+                            .map_err(|_| "the server's scram challenge isn't utf-8".to_string()));
+    let field = |name: &str| {
+        server_first.split(',').find(|f| f.starts_with(name)).map(|f| f[name.len()..].to_string())
+    };
+    let server_nonce = try!(field("r=").ok_or("the server's scram challenge has no nonce".to_string()));
+    if !server_nonce.starts_with(&nonce[..]) {
+        return Err(LoginError::Other("the server's scram nonce doesn't match ours".to_string()));
+    }
+    let salt = try!(field("s=").and_then(|s| s.from_base64().ok())
+                    .ok_or("the server's scram challenge has no salt".to_string()));
+    let iterations = try!(field("i=").and_then(|i| i.parse().ok())
+                          .ok_or("the server's scram challenge has no iteration count".to_string()));
+
+    let salted = pkcs5::pbkdf2_hmac_sha1(password, &salt, iterations, 20);
+    let client_key = hmac::hmac(hash::Type::SHA1, &salted, b"Client Key");
+    let stored_key = hash::hash(hash::Type::SHA1, &client_key);
+    let final_bare = format!("c=biws,r={}", server_nonce);
+    let auth_message = format!("{},{},{}", first_bare, server_first, final_bare);
+    let signature = hmac::hmac(hash::Type::SHA1, &stored_key, auth_message.as_bytes());
+    let proof: Vec<u8> = client_key.iter().zip(signature.iter()).map(|(k, s)| k ^ s).collect();
+    let last = format!("{},p={}", final_bare, proof.to_base64(STANDARD));
+    try!(write(stream, &format!("<response xmlns='{}'>{}</response>",
+                                NS_SASL, last.as_bytes().to_base64(STANDARD))));
+
+    let server_key = hmac::hmac(hash::Type::SHA1, &salted, b"Server Key");
+    let server_signature = hmac::hmac(hash::Type::SHA1, &server_key, auth_message.as_bytes());
+    let expected = format!("v={}", server_signature.to_base64(STANDARD));
+    if try!(sasl_reply(stream, buf, "success")) != expected.as_bytes() {
+        return Err(LoginError::Other("the server's scram signature is wrong, so it may not be who it says".to_string()));
+    }
+    Ok(())
+}
+
+/// Logs in with PLAIN, which sends the password (inside tls)
+fn plain<S: Read + Write>(stream: &mut S, buf: &mut Vec<u8>, user: &str, password: &str)
+                          -> Result<(), LoginError> {
+    let credentials = format!("\0{}\0{}", user, password);
+    try!(write(stream, &format!("<auth xmlns='{}' mechanism='PLAIN'>{}</auth>",
+                                NS_SASL, credentials.as_bytes().to_base64(STANDARD))));
+    try!(sasl_reply(stream, buf, "success"));
+    Ok(())
+}
+
+/// Reads the server's answer to a SASL step, returning the data in it
+fn sasl_reply<S: Read>(stream: &mut S, buf: &mut Vec<u8>, expected: &str) -> Result<Vec<u8>, LoginError> {
+    let element = try!(read_element(stream, buf));
+    let name = element_name(&element);
+    if name == "failure" {
+        return Err(LoginError::Auth(format!("the server refused the login ({})", condition(&element))));
+    } else if name != expected {
+        return Err(LoginError::Other(format!("the server sent {} when we expected {}", name, expected)));
+    }
+
+    let data = text(&element);
+    let data = data.trim();
+    if data.is_empty() || data == "=" {
+        return Ok(Vec::new());
+    }
+    data.from_base64().map_err(|e| LoginError::Other(format!("the server sent bad base64 ({})", e)))
+}
+
+/// Opens a stream to the domain, returning the features the server offers
+fn open_stream<S: Read + Write>(stream: &mut S, buf: &mut Vec<u8>, domain: &str) -> Result<String, String> {
+    try!(write(stream, &format!("<?xml version='1.0'?><stream:stream to='{}' version='1.0' xmlns='jabber:client' xmlns:stream='http://etherx.jabber.org/streams'>",
+                                escape(domain))));
+    loop {
+        let element = try!(read_element(stream, buf));
+        match element_name(&element).as_str() {
+            "stream:features" => return Ok(element),
+            "stream:error"    => return Err(format!("the server closed the stream ({})", condition(&element))),
+            _                 => (),
+        }
+    }
+}
+
+/// Waits for the answer to the iq we sent with the id, skipping anything
+/// else the server sends
+fn wait_for_iq<S: Read>(stream: &mut S, buf: &mut Vec<u8>, id: &str) -> Result<(), String> {
+    loop {
+        let element = try!(read_element(stream, buf));
+        if element_name(&element) == "iq" && attr(&element, "id") == Some(id.to_string()) {
+            if attr(&element, "type") == Some("result".to_string()) {
+                return Ok(());
+            }
+            return Err(format!("the server refused our {} request ({})", id, condition(&element)));
+        }
+    }
+}
+
+fn write<S: Write>(stream: &mut S, data: &str) -> Result<(), String> {
+    stream.write_all(data.as_bytes()).and_then(|_| stream.flush()).map_err(|e| e.to_string())
+}
+
+/// Reads until there is a whole element, and returns it
+fn read_element<S: Read>(stream: &mut S, buf: &mut Vec<u8>) -> Result<String, String> {
+    let mut data = [0; 4096];
+    loop {
+        if let Some(element) = take_element(buf) {
+            return Ok(element);
+        }
+        match stream.read(&mut data) {
+            Ok(0)  => return Err("the server closed the connection".to_string()),
+            Ok(n)  => buf.extend_from_slice(&data[..n]),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Takes the first whole element off the front of the data from the server,
+/// skipping anything in front of it (such as whitespace keepalives and the
+/// xml declaration). This is only as much xml as xmpp needs: the opening
+/// and closing tags of the stream count as elements of their own, and
+/// elements are never nested in one of the same name. Returns None if there
+/// isn't a whole element yet.
+fn take_element(buf: &mut Vec<u8>) -> Option<String> {
+    loop {
+        let start = match find(buf, b"<", 0) {
+            Some(start) => start,
+            None        => {
+                buf.clear();
+                return None;
+            },
+        };
+        if buf[start..].starts_with(b"<?") {
+            match find(buf, b"?>", start) {
+                Some(end) => {
+                    buf.drain(..end + 2);
+                    continue;
+                },
+                None      => return None,
+            }
+        }
+
+        let tag_end = match find(buf, b">", start) {
+            Some(i) => i + 1,
+            None    => return None,
+        };
+        let name = tag_name(&buf[start..tag_end]);
+        let end = if name == "stream:stream" || name.starts_with('/') || buf[tag_end - 2] == b'/' {
+            tag_end
+        } else {
+            match find(buf, format!("</{}>", name).as_bytes(), tag_end) {
+                Some(i) => i + name.len() + 3,
+                None    => return None,
+            }
+        };
+        let element = String::from_utf8_lossy(&buf[start..end]).into_owned();
+        buf.drain(..end);
+        return Some(element);
+    }
+}
+
+fn find(data: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    data[from..].windows(needle.len()).position(|w| w == needle).map(|i| i + from)
+}
+
+/// Name of the element a tag opens (or, with a leading /, closes)
+fn tag_name(tag: &[u8]) -> String {
+    let name: Vec<u8> = tag.iter().skip(1).enumerate()
+        .take_while(|&(i, &c)| !(c == b' ' || c == b'\t' || c == b'\r' || c == b'\n' || c == b'>' ||
+                                 (c == b'/' && i > 0)))
+        .map(|(_, &c)| c)
+        .collect();
+    String::from_utf8_lossy(&name).into_owned()
+}
+
+fn element_name(element: &str) -> String {
+    tag_name(element.as_bytes())
+}
+
+/// Value of an attribute of the element
+fn attr(element: &str, name: &str) -> Option<String> {
+    let tag = &element[..element.find('>').unwrap_or(element.len())];
+    for quote in &['\'', '"'] {
+        let pattern = format!(" {}={}", name, quote);
+        if let Some(i) = tag.find(&pattern[..]) {
+            let value = &tag[i + pattern.len()..];
+            if let Some(end) = value.find(*quote) {
+                return Some(unescape(&value[..end]));
+            }
+        }
+    }
+    None
+}
+
+/// Text inside the element
+fn text(element: &str) -> String {
+    match (element.find('>'), element.rfind("</")) {
+        (Some(start), Some(end)) if start < end => unescape(&element[start + 1..end]),
+        _                                       => String::new(),
+    }
+}
+
+/// The SASL mechanisms in stream features
+fn mechanisms(features: &str) -> Vec<String> {
+    features.split("<mechanism>").skip(1)
+        .filter_map(|m| m.find("</mechanism>").map(|end| m[..end].trim().to_string()))
+        .collect()
+}
+
+/// The error condition of a failure, stream error, or error stanza, which
+/// is the first element inside it (or inside its <error>) that isn't text
+fn condition(element: &str) -> String {
+    let start = element.find("<error").unwrap_or(0);
+    element[start..].split('<').skip(2)
+        .map(|tag| tag_name(format!("<{}", tag).as_bytes()))
+        .find(|name| !name.starts_with('/') && name != "text")
+        .unwrap_or("unknown error".to_string())
+}
+
+/// Escapes text for xml. Control characters are left out, as xml has no way
+/// to send them
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&'                => escaped.push_str("&amp;"),
+            '<'                => escaped.push_str("&lt;"),
+            '>'                => escaped.push_str("&gt;"),
+            '"'                => escaped.push_str("&quot;"),
+            '\''               => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c < ' '       => (),
+            c                  => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"")
+        .replace("&apos;", "'").replace("&amp;", "&")
+}
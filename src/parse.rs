@@ -6,6 +6,36 @@ use message::Object;
 use errors::WeechatError;
 use errors::WeechatError::ParseError;
 
+/// How deeply arrays and hashtables can nest in a message, if
+/// max_parse_depth isn't set. Deeper messages fail to parse, so a hostile or
+/// buggy relay can't overflow the stack
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// How an object type is parsed: on its own, or (for the types that hold
+/// other objects) given how deeply it is nested and how deep it can go
+enum Parser {
+    Flat(fn(&[u8]) -> Result<Parse, WeechatError>),
+    Nested(fn(&[u8], usize, usize) -> Result<Parse, WeechatError>),
+}
+
+// Every object type we can parse, by its code in the protocol. parse_type and
+// object_types both go by this, so a type added here is supported everywhere
+const PARSERS: [(&'static str, Parser); 9] = [("chr", Parser::Flat(Parse::character)),
+                                               ("int", Parser::Flat(Parse::integer)),
+                                               ("lon", Parser::Flat(Parse::long)),
+                                               ("str", Parser::Flat(Parse::string)),
+                                               ("buf", Parser::Flat(Parse::buffer)),
+                                               ("ptr", Parser::Flat(Parse::pointer)),
+                                               ("tim", Parser::Flat(Parse::time)),
+                                               ("arr", Parser::Nested(Parse::array_nested)),
+                                               ("htb", Parser::Nested(Parse::hashtable_nested))];
+
+/// The codes of the object types the parser supports (ex: "chr", "htb"), in
+/// the order the protocol documents them
+pub fn object_types() -> Vec<&'static str> {
+    PARSERS.iter().map(|&(code, _)| code).collect()
+}
+
 /// Parses binary data into weechat message objects.
 pub struct Parse {
     /// Object type of this data
@@ -16,18 +46,26 @@ pub struct Parse {
 
 impl Parse {
     pub fn parse_type(data_type: &str, bytes: &[u8]) -> Result<Parse, WeechatError> {
-         Ok(match data_type {
-            "chr" => try!(Parse::character(bytes)),
-            "int" => try!(Parse::integer(bytes)),
-            "lon" => try!(Parse::long(bytes)),
-            "str" => try!(Parse::string(bytes)),
-            "buf" => try!(Parse::buffer(bytes)),
-            "ptr" => try!(Parse::pointer(bytes)),
-            "tim" => try!(Parse::time(bytes)),
-            "arr" => try!(Parse::array(bytes)),
-            "htb" => try!(Parse::hashtable(bytes)),
-            _     => return Err(ParseError("Unknown data type".to_string())),
-        })
+        Parse::parse_type_with_max_depth(data_type, bytes, DEFAULT_MAX_DEPTH)
+    }
+
+    /// Parses an object, failing if arrays and hashtables in it nest more
+    /// than max_depth deep
+    pub fn parse_type_with_max_depth(data_type: &str, bytes: &[u8], max_depth: usize) -> Result<Parse, WeechatError> {
+        Parse::parse_nested(data_type, bytes, 0, max_depth)
+    }
+
+    /// Parses an object depth arrays or hashtables down in a message, failing
+    /// once that is past the max depth
+    fn parse_nested(data_type: &str, bytes: &[u8], depth: usize, max_depth: usize) -> Result<Parse, WeechatError> {
+        match PARSERS.iter().find(|&&(code, _)| code == data_type) {
+            Some(&(_, Parser::Flat(parse)))   => parse(bytes),
+            Some(&(_, Parser::Nested(parse))) => parse(bytes, depth, max_depth),
+            None                              => {
+                Err(ParseError(format!("Unknown data type '{}' (the types we know are {})",
+                                       data_type, object_types().join(", "))))
+            },
+        }
     }
 
     /// Given a byte array which contains an encoded array (of some Object
@@ -44,6 +82,11 @@ impl Parse {
     ///       array as an empty array, instead of having an Array be of type
     ///       Option.
     pub fn array(bytes: &[u8]) -> Result<Parse, WeechatError> {
+        Parse::array_nested(bytes, 0, DEFAULT_MAX_DEPTH)
+    }
+
+    fn array_nested(bytes: &[u8], depth: usize, max_depth: usize) -> Result<Parse, WeechatError> {
+        try!(check_depth(depth, max_depth));
         if bytes.len() < 7 {
             return Err(ParseError("Not enough bytes to have an array".to_string()));
         }
@@ -53,7 +96,7 @@ impl Parse {
 
         let mut cur_pos = 7;  // Start position for bytes array elements
         for _ in 0..num_elements {
-            let parsed = try!(Parse::parse_type(arr_type, &bytes[cur_pos..]));
+            let parsed = try!(Parse::parse_nested(arr_type, &bytes[cur_pos..], depth + 1, max_depth));
             cur_pos += parsed.bytes_read;
             array.push(parsed.object);
         }
@@ -78,28 +121,27 @@ impl Parse {
             return Err(ParseError("Not enough bytes to parse buffer".to_string()));
         }
 
-        // Get the start and end limits for this string
-        let mut start = 0;
-        let mut end = 4;
-        let buf_size = try!(bytes_to_i32(&bytes[start..end]));
-        start = end;
-        end += buf_size as usize;
-        if bytes.len() >= end {
+        // Get the start and end limits for this buffer. As with strings, -1
+        // (a null buffer) has nothing after the length
+        let start = 4;
+        let buf_size = try!(bytes_to_i32(&bytes[0..start]));
+        if buf_size == -1 {
+            return Ok(Parse {
+                object: Object::Buf(None),
+                bytes_read: start,
+            });
+        }
+        if buf_size < 0 {
+            return Err(ParseError(format!("Negative buffer length ({})", buf_size)));
+        }
+        let end = start + buf_size as usize;
+        if bytes.len() < end {
             return Err(ParseError("Buffer larger then availiable bytes".to_string()));
         }
 
-        // Pull out and return the string
-        let buf_object = match buf_size {
-            -1 => None,              // Null buffer
-            0  => Some(Vec::new()),  // Empty buffer
-            _  => {
-                let mut buf = Vec::new();
-                buf.clone_from_slice(&bytes[start..end]);
-                Some(buf)
-            }
-        };
+        // Copy out just the buffer
         Ok(Parse{
-            object: Object::Buf(buf_object),
+            object: Object::Buf(Some(bytes[start..end].to_vec())),
             bytes_read: end
         })
     }
@@ -123,6 +165,11 @@ impl Parse {
     /// Int: Number of items
     /// Items
     pub fn hashtable(bytes: &[u8]) -> Result<Parse, WeechatError> {
+        Parse::hashtable_nested(bytes, 0, DEFAULT_MAX_DEPTH)
+    }
+
+    fn hashtable_nested(bytes: &[u8], depth: usize, max_depth: usize) -> Result<Parse, WeechatError> {
+        try!(check_depth(depth, max_depth));
         if bytes.len() < 10 {
             return Err(ParseError("Not enough bytes to have a hashtable".to_string()));
         }
@@ -133,10 +180,10 @@ impl Parse {
 
         let mut cur_pos = 10;  // Start position for hashmap elements
         for _ in 0..num_entries {
-            let parsed_key = try!(Parse::parse_type(key_type, &bytes[cur_pos..]));
+            let parsed_key = try!(Parse::parse_nested(key_type, &bytes[cur_pos..], depth + 1, max_depth));
             cur_pos += parsed_key.bytes_read;
 
-            let parsed_value = try!(Parse::parse_type(value_type, &bytes[cur_pos..]));
+            let parsed_value = try!(Parse::parse_nested(value_type, &bytes[cur_pos..], depth + 1, max_depth));
             cur_pos += parsed_value.bytes_read;
 
             map.insert(parsed_key.object, parsed_value.object);
@@ -170,18 +217,15 @@ impl Parse {
         if bytes.len() < 2 {
             return Err(ParseError("Not enough bytes to parse long".to_string()));
         }
-        let long_size = bytes[0] as i8;
+        let long_size = bytes[0] as usize;
         let start = 1;
-        let end = start + long_size as usize;
+        let end = start + long_size;
         if bytes.len() < end {
             return Err(ParseError("Long larger then available bytes".to_string()));
         }
 
         let long_str = try!(from_utf8(&bytes[start..end]));
-        let long: i64 = match long_str.parse() {
-            Err(_) => return Err(ParseError("String to long conversion failed".to_string())),
-            Ok(l)  => l,
-        };
+        let long = try!(parse_number(long_str, "long"));
         Ok(Parse {
             object: Object::Lon(long),
             bytes_read: end,
@@ -201,9 +245,9 @@ impl Parse {
             return Err(ParseError("Not enough bytes to parse pointer".to_string()));
         }
 
-        let ptr_size = bytes[0] as i8;
+        let ptr_size = bytes[0] as usize;
         let start = 1;
-        let end = start + ptr_size as usize;
+        let end = start + ptr_size;
         if bytes.len() < end {
             return Err(ParseError("Pointer larger then availiable bytes".to_string()));
         }
@@ -245,6 +289,9 @@ impl Parse {
             });
         }
 
+        if str_size < 0 {
+            return Err(ParseError(format!("Negative string length ({})", str_size)));
+        }
         start = end;
         end += str_size as usize;
         if bytes.len() < end {
@@ -273,18 +320,15 @@ impl Parse {
         if bytes.len() < 2 {
             return Err(ParseError("Not enough bytes parse time".to_string()));
         }
-        let time_size = bytes[0] as i8;
+        let time_size = bytes[0] as usize;
         let start = 1;
-        let end = start + time_size as usize;
+        let end = start + time_size;
         if bytes.len() < end {
             return Err(ParseError("Not enough bytes to parse time".to_string()));
         }
 
         let time_str = try!(from_utf8(&bytes[start..end]));
-        let timestamp: i32 = match time_str.parse() {
-            Err(_) => return Err(ParseError("String to i32 conversion failed".to_string())),
-            Ok(ts) => ts,
-        };
+        let timestamp = try!(parse_number(time_str, "time"));
         Ok(Parse {
             object: Object::Tim(timestamp),
             bytes_read: end,
@@ -292,6 +336,32 @@ impl Parse {
     }
 }
 
+/// Fails if an array or hashtable depth containers down (0 being one that
+/// isn't in another) is nested deeper than max
+fn check_depth(depth: usize, max: usize) -> Result<(), WeechatError> {
+    if depth >= max {
+        return Err(ParseError(format!("Arrays and hashtables are nested more than {} deep", max)));
+    }
+    Ok(())
+}
+
+/// Parses the decimal string of a long or time. A string of digits that
+/// doesn't fit in an i64 gets its own error, so a corrupt message can be told
+/// apart from a relay sending something that isn't a number at all.
+fn parse_number(num_str: &str, what: &str) -> Result<i64, WeechatError> {
+    match num_str.parse() {
+        Ok(n)  => Ok(n),
+        Err(_) => {
+            let digits = num_str.trim_left_matches(|c| c == '-' || c == '+');
+            if !digits.is_empty() && num_str.len() - digits.len() <= 1 && digits.chars().all(|c| c.is_digit(10)) {
+                Err(ParseError(format!("The {} {} is out of range for an i64", what, num_str)))
+            } else {
+                Err(ParseError(format!("The {} {:?} is not a number", what, num_str)))
+            }
+        },
+    }
+}
+
 /// Converts a 4 byte array slice into a 32 bit signed integer. The bytes
 /// are assumed to be encoded in a big-endian format
 fn bytes_to_i32(byte_array: &[u8]) -> Result<i32, WeechatError> {
@@ -311,3 +381,60 @@ fn bytes_to_i32(byte_array: &[u8]) -> Result<i32, WeechatError> {
         Ok(transmute::<[u8; 4], i32>(bytes))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{object_types, Parse};
+    use errors::WeechatError;
+    use message::Object;
+    use testing::{int, pointer, string, time};
+
+    /// An encoded object of each type object_types lists
+    fn sample(object_type: &str) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match object_type {
+            "chr"         => bytes.push(b'a'),
+            "int"         => int(&mut bytes, -5),
+            "lon" | "tim" => time(&mut bytes, 1480000000),
+            "str" | "buf" => string(&mut bytes, "hi"),
+            "ptr"         => pointer(&mut bytes, "55aa"),
+            "arr"         => {
+                bytes.extend_from_slice(b"int");
+                int(&mut bytes, 2);
+                int(&mut bytes, 1);
+                int(&mut bytes, 2);
+            },
+            "htb"         => {
+                bytes.extend_from_slice(b"strint");
+                int(&mut bytes, 1);
+                string(&mut bytes, "one");
+                int(&mut bytes, 1);
+            },
+            _             => panic!("no sample of {}", object_type),
+        }
+        bytes
+    }
+
+    #[test]
+    fn every_listed_type_parses() {
+        assert_eq!(object_types(), vec!["chr", "int", "lon", "str", "buf", "ptr", "tim", "arr", "htb"]);
+        for object_type in object_types() {
+            let bytes = sample(object_type);
+            let parsed = Parse::parse_type(object_type, &bytes).unwrap();
+            assert_eq!(parsed.bytes_read, bytes.len(), "{}", object_type);
+        }
+        let parsed = Parse::parse_type("arr", &sample("arr")).unwrap();
+        assert_eq!(parsed.object, Object::Arr(vec![Object::Int(1), Object::Int(2)]));
+    }
+
+    #[test]
+    fn unknown_types_list_the_known_ones() {
+        match Parse::parse_type("inf", &[0; 8]) {
+            Err(WeechatError::ParseError(e)) => {
+                assert!(e.contains("'inf'"), "{}", e);
+                assert!(e.contains("chr, int, lon, str, buf, ptr, tim, arr, htb"), "{}", e);
+            },
+            other                            => panic!("expected a ParseError, got {:?}", other.map(|p| p.object)),
+        }
+    }
+}
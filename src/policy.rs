@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+use std::io::ErrorKind;
+
+use errors::WeechatError;
+
+/// What to do after the connection to the relay fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Reconnect (after a backoff delay)
+    Retry,
+    /// Give up and exit
+    Fatal,
+}
+
+/// Decides which errors are worth reconnecting after, and which mean the
+/// setup is fundamentally broken so retrying would just loop forever.
+#[derive(Debug)]
+pub struct ErrorPolicy {
+    /// Per io error kind overrides of the default policy
+    overrides: HashMap<ErrorKind, Action>,
+}
+
+impl ErrorPolicy {
+    /// Creates the default policy
+    pub fn new() -> ErrorPolicy {
+        ErrorPolicy { overrides: HashMap::new() }
+    }
+
+    /// Overrides what to do for an io error kind
+    pub fn set(&mut self, kind: ErrorKind, action: Action) {
+        self.overrides.insert(kind, action);
+    }
+
+    /// Classifies an error that ended a relay connection
+    pub fn classify(&self, err: &WeechatError) -> Action {
+        match *err {
+            WeechatError::Io(ref e)         => match self.overrides.get(&e.kind()) {
+                Some(action) => *action,
+                None         => default_action(e.kind()),
+            },
+            // A parse error most likely means we are out of sync with the
+            // relay, which a fresh connection fixes
+            WeechatError::ParseError(_)     => Action::Retry,
+            // Retrying won't fix bad credentials or certificates, or a relay
+            // that doesn't speak websockets (or a wrong websocket_path)
+            WeechatError::BadPassword       => Action::Fatal,
+            WeechatError::PasswordRequired  => Action::Fatal,
+            WeechatError::SslError(_)       => Action::Fatal,
+            WeechatError::WebSocketError(_) => Action::Fatal,
+        }
+    }
+}
+
+/// The default action for an io error kind. Anything that looks like the
+/// network or the relay going away is retried, everything else (such as
+/// PermissionDenied or AddrNotAvailable) is fatal.
+fn default_action(kind: ErrorKind) -> Action {
+    match kind {
+        ErrorKind::ConnectionRefused |
+        ErrorKind::ConnectionReset   |
+        ErrorKind::ConnectionAborted |
+        ErrorKind::NotConnected      |
+        ErrorKind::BrokenPipe        |
+        ErrorKind::TimedOut          |
+        ErrorKind::Interrupted       |
+        ErrorKind::UnexpectedEof     => Action::Retry,
+        _                            => Action::Fatal,
+    }
+}
+
+/// Looks up an io error kind by its name in the config, which is the
+/// snake_case version of the ErrorKind (ex: "connection_reset")
+pub fn error_kind(name: &str) -> Option<ErrorKind> {
+    Some(match name {
+        "not_found"          => ErrorKind::NotFound,
+        "permission_denied"  => ErrorKind::PermissionDenied,
+        "connection_refused" => ErrorKind::ConnectionRefused,
+        "connection_reset"   => ErrorKind::ConnectionReset,
+        "connection_aborted" => ErrorKind::ConnectionAborted,
+        "not_connected"      => ErrorKind::NotConnected,
+        "addr_in_use"        => ErrorKind::AddrInUse,
+        "addr_not_available" => ErrorKind::AddrNotAvailable,
+        "broken_pipe"        => ErrorKind::BrokenPipe,
+        "already_exists"     => ErrorKind::AlreadyExists,
+        "would_block"        => ErrorKind::WouldBlock,
+        "invalid_input"      => ErrorKind::InvalidInput,
+        "invalid_data"       => ErrorKind::InvalidData,
+        "timed_out"          => ErrorKind::TimedOut,
+        "write_zero"         => ErrorKind::WriteZero,
+        "interrupted"        => ErrorKind::Interrupted,
+        "unexpected_eof"     => ErrorKind::UnexpectedEof,
+        "other"              => ErrorKind::Other,
+        _                    => return None,
+    })
+}
@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+// Seconds between profile reports, the same as for stats
+const REPORT_INTERVAL: u64 = 600;
+
+/// How many times something was timed, and how long it took in total and at
+/// worst
+#[derive(Debug, Clone, Copy)]
+pub struct Timing {
+    pub count: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl Timing {
+    pub fn new() -> Timing {
+        Timing {
+            count: 0,
+            total: Duration::from_secs(0),
+            max: Duration::from_secs(0),
+        }
+    }
+
+    /// Records one run that started at start
+    pub fn record(&mut self, start: Instant) {
+        let took = start.elapsed();
+        self.count += 1;
+        self.total += took;
+        if took > self.max {
+            self.max = took;
+        }
+    }
+
+    fn add(&self, other: &Timing) -> Timing {
+        Timing {
+            count: self.count + other.count,
+            total: self.total + other.total,
+            max: if other.max > self.max { other.max } else { self.max },
+        }
+    }
+
+    /// The timing as "<count> in <total> ms (average <avg> ms, max <max> ms)"
+    fn summary(&self) -> String {
+        let average = if self.count > 0 { millis(self.total) / self.count as f64 } else { 0.0 };
+        format!("{} in {:.1} ms (average {:.3} ms, max {:.3} ms)",
+                self.count, millis(self.total), average, millis(self.max))
+    }
+}
+
+/// Time a connection spends on what it reads: parsing messages (hdata
+/// separate from everything else) and inflating compressed ones
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    /// Messages parsed into hdata (Message::new and HData::new)
+    pub hdata: Timing,
+    /// Every other message parsed
+    pub other: Timing,
+    pub inflate: Timing,
+}
+
+impl Reading {
+    pub fn new() -> Reading {
+        Reading {
+            hdata: Timing::new(),
+            other: Timing::new(),
+            inflate: Timing::new(),
+        }
+    }
+
+    fn add(&self, other: &Reading) -> Reading {
+        Reading {
+            hdata: self.hdata.add(&other.hdata),
+            other: self.other.add(&other.other),
+            inflate: self.inflate.add(&other.inflate),
+        }
+    }
+}
+
+/// Where a relay's time goes, for --profile: reading messages (see Reading)
+/// and handling the lines that were added (the handler, which for
+/// weechat-alert is the alert rules and handing notifications to the
+/// backends). Nothing is timed unless the relay has one of these, so it
+/// costs nothing otherwise.
+pub struct Profile {
+    /// Reading, in replays and on connections that have been closed (open
+    /// ones time their own)
+    pub reading: Reading,
+    /// Lines added, from the handler getting them through notifying
+    pub lines: Timing,
+    last_report: Instant,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            reading: Reading::new(),
+            lines: Timing::new(),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Adds in the timing of a connection that is being closed
+    pub fn connection_closed(&mut self, reading: &Reading) {
+        self.reading = self.reading.add(reading);
+    }
+
+    /// Returns true if it is time to print another profile
+    pub fn due(&self) -> bool {
+        self.last_report.elapsed() >= Duration::from_secs(REPORT_INTERVAL)
+    }
+
+    /// Prints the timings, given the timing of the open connection (if any)
+    pub fn report(&mut self, host: &str, open: Option<&Reading>) {
+        let reading = match open {
+            Some(timing) => self.reading.add(timing),
+            None         => self.reading,
+        };
+        info!("Profile for relay {}:", host);
+        info!("  parsing hdata: {}", reading.hdata.summary());
+        info!("  parsing other messages: {}", reading.other.summary());
+        info!("  inflating: {}", reading.inflate.summary());
+        info!("  handling lines: {}", self.lines.summary());
+        self.last_report = Instant::now();
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1000000.0
+}
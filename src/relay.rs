@@ -1,201 +1,1078 @@
-use std::io::prelude::*;
-use std::net::Shutdown;
-use std::net::TcpStream;
+use std::cmp;
+use std::collections::HashMap;
+use std::fs::File;
 use std::thread;
+use std::path::Path;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
 use std::io;
-use std::path::PathBuf;
-
-use ears::{Sound, AudioController};
+use std::io::prelude::*;
 
-use openssl::ssl::{Ssl, SslMethod, SslContext, SslStream, MaybeSslStream,
-                   SslVerifyMode, SSL_VERIFY_NONE, SSL_VERIFY_PEER};
+#[cfg(unix)]
+use libc;
 
+use backoff::Schedule;
+use buffers::Buffers;
+use commands::{Command, HdataCommand, InitCommand, InputCommand, NicklistCommand, PingCommand, SyncCommand};
+use connection::{self, Compression, Connection, SslConfig, Transport, HEADER_LENGTH};
 use errors::WeechatError;
+use handler::{CommandSender, ControlFlow, RelayHandler};
 use hdata::HData;
-use message;
+use health::Health;
+use inflate;
+use line::BufferLine;
+use message::{Header, Message};
+use nicklist::{self, Nick};
+use notify::{Notifiers, Settings};
+use parse::DEFAULT_MAX_DEPTH;
+use policy::{Action, ErrorPolicy};
+use profile::Profile;
+use reply::Replies;
+use stats::Stats;
+
+// Default string appended to every command sent to the relay
+const DEFAULT_TERMINATOR: &'static str = "\n";
 
-// number of bytes that make up the message header
-const HEADER_LENGTH: usize = 5;
+// Seconds to wait for the pong to the ping we send after the init
+const PONG_TIMEOUT: u64 = 10;
+
+// Seconds between checks for replies to send, while waiting on the relay
+const REPLY_INTERVAL: u64 = 1;
+
+// Identifiers of the messages we act on ourselves. Anything else (from the
+// relay or a replay) is dropped without parsing its body, unless a handler is
+// registered for it with on, or the handler or run_with callback wants every
+// message (see RelayHandler::wants_all_messages)
+const HANDLED_IDENTIFIERS: &'static [&'static str] = &[
+    "_buffer_line_added",
+    "_buffer_opened",
+    "_buffer_renamed",
+    "_buffer_localvar_added",
+    "_buffer_localvar_changed",
+    "_buffer_localvar_removed",
+    "_buffer_closing",
+    "_pong",
+];
 
 /// Holds relay connection information
 pub struct Relay {
     host: String,
     port: i32,
-    password: String,
+    /// Password for the relay, or None if it doesn't have one
+    password: Option<String>,
     ssl: Option<SslConfig>,
+    /// String terminating every command we send ("\n" per spec, but some
+    /// line-rewriting proxies expect "\r\n")
+    terminator: String,
+    /// How messages are carried to and from the relay
+    transport: Transport,
+    /// If we ask the relay to compress what it sends
+    compression: Compression,
+    /// Buffer pointer to name mappings, kept up to date from buffer events
+    buffers: Buffers,
+    /// Which errors we reconnect after
+    error_policy: ErrorPolicy,
+    /// How long we wait between reconnect attempts, and when we alert
+    schedule: Schedule,
+    /// Where losing the connection is alerted, shared with the handler
+    notifiers: Rc<Notifiers>,
+    /// The connection to the relay, if we are connected
+    conn: Option<Connection>,
+    /// If the current connection made it through init
+    connected: bool,
+    /// When we lost a working connection, if we haven't gotten it back yet
+    lost_at: Option<Instant>,
+    /// If we alerted that the connection was lost
+    alerted: bool,
+    /// Seconds we waited before the last reconnect attempt, or 0 if we
+    /// haven't tried yet
+    delay: u64,
+    /// Totals of what we have read, if we are printing stats
+    stats: Option<Stats>,
+    /// Where our time goes, if we are profiling
+    profile: Option<Profile>,
+    /// File every message read is copied to, if we are dumping
+    dump: Option<File>,
+    /// Handlers registered with on, by message identifier
+    handlers: HashMap<String, Box<FnMut(&Message)>>,
+    /// Handler given to run_with_handler, if any
+    handler: Option<Box<RelayHandler>>,
+    /// Commands from the handler waiting to be sent
+    commands: CommandSender,
+    /// Callback given to run_with, if any
+    callback: Option<Box<FnMut(&Message) -> ControlFlow>>,
+    /// If the callback asked to stop
+    stopping: bool,
+    /// How long the relay can be quiet before we ping it, or zero to never
+    /// ping
+    keepalive: Duration,
+    /// Pings sent so far, for giving each one its own token
+    pings: u64,
+    /// Token of the ping we are waiting on the pong for, and when we sent it
+    pending_ping: Option<(String, Instant)>,
+    /// When the relay last answered one of our pings
+    last_pong: Option<Instant>,
+    /// If we are connected and hearing from the relay, for health checks
+    health: Health,
+    /// Replies to notifications waiting to be sent into their buffers, if
+    /// we take any
+    replies: Option<Replies>,
+    /// How deeply arrays and hashtables can nest in the messages we parse
+    max_parse_depth: usize,
 }
 
-/// Data for enabling SSL on the weechat relay
-pub struct SslConfig {
-    /// SSL verify mode
-    verify: SslVerifyMode,
-    /// Optional path to a file containing ca certificates. This is may be needed
-    /// if you are verifying the ssl cert. On linux, this is normally at
-    /// /etc/ssl/certs/ca-certificates.crt.
-    ca_cert_path: Option<PathBuf>,
-}
-
-impl SslConfig {
-    pub fn new(verify: bool, ca_cert_path: Option<String>) -> SslConfig {
-        let path = match ca_cert_path {
-            Some(s) => Some(PathBuf::from(s)),
-            None    => None,
-        };
-        let verify_mode = if verify == true { SSL_VERIFY_PEER } else { SSL_VERIFY_NONE };
-
-        SslConfig {
-            verify: verify_mode,
-            ca_cert_path: path,
-        }
-    }
-}
-
-/// Type alias
-type Stream = MaybeSslStream<TcpStream>;
-
 impl Relay {
-    pub fn new(host: String, port: i32, password: String, relay_ssl: Option<SslConfig>) -> Relay {
+    pub fn new(host: String, port: i32, password: Option<String>, relay_ssl: Option<SslConfig>) -> Relay {
          Relay {
             host: host,
             port: port,
             password: password,
             ssl: relay_ssl,
+            terminator: DEFAULT_TERMINATOR.to_string(),
+            transport: Transport::Tcp,
+            compression: Compression::Off,
+            buffers: Buffers::new(),
+            error_policy: ErrorPolicy::new(),
+            schedule: Schedule::new(),
+            notifiers: Rc::new(Notifiers::new(Settings::new())),
+            conn: None,
+            connected: false,
+            lost_at: None,
+            alerted: false,
+            delay: 0,
+            stats: None,
+            profile: None,
+            dump: None,
+            handlers: HashMap::new(),
+            handler: None,
+            commands: CommandSender::new(),
+            callback: None,
+            stopping: false,
+            keepalive: Duration::from_secs(0),
+            pings: 0,
+            pending_ping: None,
+            last_pong: None,
+            health: Health::new(),
+            replies: None,
+            max_parse_depth: DEFAULT_MAX_DEPTH,
         }
     }
 
-    fn connect_relay(&self) -> Result<Stream, WeechatError> {
-        // The initial tpc connection to the server
-        let addr = format!("{}:{}", self.host, self.port);
-        let tcp_stream = try!(TcpStream::connect(&*addr));
+    /// Sets the backends the connection to the relay being lost (and
+    /// coming back) is alerted on
+    pub fn set_notifiers(&mut self, notifiers: Notifiers) {
+        self.notifiers = Rc::new(notifiers);
+    }
+
+    /// The backends set with set_notifiers, for a handler to notify with
+    /// too (ex: an alert::AlertHandler)
+    pub fn notifiers(&self) -> Rc<Notifiers> {
+        self.notifiers.clone()
+    }
+
+    /// Sets the policy deciding which errors we reconnect after
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.error_policy = policy;
+    }
+
+    /// Sets the schedule deciding how long we wait between reconnect
+    /// attempts, and how long the relay can be gone before we alert
+    pub fn set_schedule(&mut self, schedule: Schedule) {
+        self.schedule = schedule;
+    }
+
+    /// Pings the relay whenever it has been quiet for the interval, and
+    /// reconnects if it doesn't answer within another interval. This catches
+    /// a connection that died without the socket noticing (ex: a NAT
+    /// dropping it). Zero (the default) never pings.
+    ///
+    /// Note: Only run pings. run_all waits on all of its relays at once, so
+    ///       relays there are never pinged.
+    pub fn set_keepalive(&mut self, interval: Duration) {
+        self.keepalive = interval;
+    }
+
+    /// Makes messages with arrays and hashtables nested more than depth deep
+    /// fail to parse (parse::DEFAULT_MAX_DEPTH unless this is called). This
+    /// applies from the next connection on.
+    pub fn set_max_parse_depth(&mut self, depth: usize) {
+        self.max_parse_depth = depth;
+    }
+
+    /// When the relay last answered a ping with the token we sent, or None
+    /// if it hasn't on this connection. Pongs to older pings don't count, so
+    /// a stale one sitting in a buffer can't make a dead connection look
+    /// alive.
+    pub fn last_pong(&self) -> Option<Instant> {
+        self.last_pong
+    }
+
+    /// Sends the replies queued on a reply queue (made from this relay's
+    /// buffers) as input into their buffers. Replies are checked for while
+    /// waiting on the relay, so they go out within a second or two.
+    pub fn set_replies(&mut self, replies: Replies) {
+        self.replies = Some(replies);
+    }
+
+    /// Whether we are connected and when we last heard from the relay,
+    /// shared with the relay loop that keeps it up to date (for health
+    /// checks from other threads)
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    /// The map of buffer pointers to names, shared with the relay loop that
+    /// keeps it up to date (so it can be read from other threads)
+    pub fn buffers(&self) -> Buffers {
+        self.buffers.clone()
+    }
+
+    /// Turns on printing how much we have read from the relay, every ten
+    /// minutes and when we exit
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(Stats::new());
+    }
+
+    /// Turns on timing how long parsing, inflating and handling lines take,
+    /// printed every ten minutes, when we exit, and after a replay
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    /// Copies every message read from the relay (on every connection) to a
+    /// file, which can be run back through the parser with replay
+    pub fn dump_to(&mut self, file: File) {
+        self.dump = Some(file);
+    }
+
+    /// Registers a handler for every message with an identifier (ex:
+    /// "_buffer_line_added", "_nicklist", or the id of a command sent with
+    /// command), replacing any handler already registered for it. Handlers
+    /// run after our own handling of the message, such as notifying.
+    ///
+    /// Note: We only sync buffers, so the relay doesn't send events like
+    ///       _nicklist unless something syncs them.
+    pub fn on<F: FnMut(&Message) + 'static>(&mut self, identifier: &str, handler: F) {
+        self.handlers.insert(identifier.to_string(), Box::new(handler));
+    }
+
+    /// Sets the string used to terminate commands sent to the relay
+    pub fn set_terminator(&mut self, terminator: String) {
+        self.terminator = terminator;
+    }
 
-        // Turn on ssl if configured
-        match self.ssl {
-            Some(ref ssl) => {
-                let mut ctx = try!(SslContext::new(SslMethod::Sslv23));
-                ctx.set_verify(ssl.verify, None);
-                match ssl.ca_cert_path {
-                    Some(ref path) => try!(ctx.set_CA_file(path)),
-                    None       => (),
+    /// Sets how messages are carried to and from the relay. Defaults to
+    /// straight over tcp.
+    pub fn set_transport(&mut self, transport: Transport) {
+        self.transport = transport;
+    }
+
+    /// Asks the relay to compress the messages it sends. Off by default. A
+    /// relay too old for the compression asked for is connected to without
+    /// it (see connect).
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+
+    /// Returns the connection to the relay, or an error if not connected
+    fn connection(&mut self) -> Result<&mut Connection, WeechatError> {
+        self.conn.as_mut().ok_or_else(not_connected)
+    }
+
+    /// Connects to the relay and inits the relay protocol with our password.
+    /// A relay that doesn't know the compression we ask for may close the
+    /// connection on the init (which looks like a bad password), so then we
+    /// try again without compression, and leave it off if that works.
+    pub fn connect(&mut self) -> Result<(), WeechatError> {
+        match self.open() {
+            Err(WeechatError::BadPassword) | Err(WeechatError::PasswordRequired)
+                if self.compression != Compression::Off => {
+                let asked = self.compression;
+                self.compression = Compression::Off;
+                let result = self.open();
+                match result {
+                    Ok(()) => warn!("relay {} closed the connection when asked for compression={}, \
+                                     so compression is off", self.host, asked.name()),
+                    Err(_) => self.compression = asked,
                 }
-                let ssl = try!(Ssl::new(&ctx));
-                let ssl_stream = try!(SslStream::connect(ssl, tcp_stream));
-                Ok(MaybeSslStream::Ssl(ssl_stream))
+                result
             },
-            None      => Ok(MaybeSslStream::Normal(tcp_stream))
+            result => result,
         }
     }
 
-    fn send_cmd(&self, stream: &mut Stream, mut cmd_str: String) -> Result<(), WeechatError> {
-        // Relay must end in \n per spec
-        if !cmd_str.ends_with("\n") {
-            cmd_str.push('\n');
+    /// Opens a new connection and inits the relay protocol on it, with the
+    /// compression currently asked for
+    fn open(&mut self) -> Result<(), WeechatError> {
+        self.close();
+        debug!("Connecting to relay {}:{} (compression={})", self.host, self.port, self.compression.name());
+        let mut conn = try!(Connection::connect(&self.host, self.port, self.ssl.as_ref(),
+                                                &self.terminator, &self.transport));
+        if let Some(ref dump) = self.dump {
+            conn.dump_to(try!(dump.try_clone()));
         }
-        try!(stream.write_all(cmd_str.as_bytes()));
-        Ok(())
-    }
-
-    fn recv_msg(&self, stream: &mut Stream) -> Result<message::Message, WeechatError> {
-        // header is first 5 bytes. The first 4 are the length, and the last
-        // one is if compression is enabled or not
-        let mut buffer = [0; HEADER_LENGTH];
-        try!(stream.read_exact(&mut buffer));
-        let header = try!(message::Header::new(&buffer));
-
-        // Now that we have the header, get the rest of the message.
-        let mut data = vec![0; header.length];
-        try!(stream.read_exact(data.as_mut_slice()));
-        message::Message::new(data.as_slice())
+        if self.profile.is_some() {
+            conn.profile();
+        }
+        conn.set_max_parse_depth(self.max_parse_depth);
+        self.conn = Some(conn);
+        self.init_relay()
     }
 
-    fn init_relay(&self, stream: &mut Stream) -> Result<(), WeechatError> {
+    fn init_relay(&mut self) -> Result<(), WeechatError> {
         // If initing the relay failed (due to a bad password) the protocol
         // will not actually send us a message saying that, it will just
         // silently disconnect the socket. To check this, we will do a ping
-        // pong right after initing, which if the password is bad should
-        // result in no bytes being read from the socket (UnexpectedEof)
-        let cmd_str = format!("init password={},compression=off", self.password);
-        try!(self.send_cmd(stream, cmd_str));
-        try!(self.send_cmd(stream, "ping".to_string()));
-
-        // UnexpectedEof means that a bad password was sent in. Any other
-        // error is something unexpected.
-        match self.recv_msg(stream) {
-            Err(e) => match e {
-                WeechatError::Io(err) => match err.kind() {
-                    io::ErrorKind::UnexpectedEof => Err(WeechatError::BadPassword),
-                    _                            => Err(WeechatError::Io(err)),
-                },
-                _                     => Err(e)
+        // pong right after initing, and if the relay closes the connection
+        // (however that shows up, see connection::is_closed) before the pong
+        // the password was bad (or the relay wants one, if we don't have
+        // one). Any other error is something unexpected.
+        let init = InitCommand::new(self.password.as_ref().map(|p| p.as_str()), self.compression);
+        let has_password = self.password.is_some();
+        let token = self.ping_token();
+        let result = {
+            let conn = try!(self.connection());
+            try!(conn.set_read_timeout(Some(Duration::from_secs(PONG_TIMEOUT))));
+            ping(conn, &init, &token).and_then(|_| conn.set_read_timeout(None))
+        };
+        match result {
+            Ok(())                                 => {
+                self.last_pong = Some(Instant::now());
+                Ok(())
+            },
+            Err(ref e) if connection::is_closed(e) => {
+                Err(if has_password { WeechatError::BadPassword } else { WeechatError::PasswordRequired })
             },
-            Ok(_) =>  Ok(())
+            Err(WeechatError::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock ||
+                                            e.kind() == io::ErrorKind::TimedOut => {
+                Err(WeechatError::Io(io::Error::new(io::ErrorKind::TimedOut,
+                    format!("The relay did not answer our ping within {} seconds of the init", PONG_TIMEOUT))))
+            },
+            Err(e)                                 => Err(e),
+        }
+    }
+
+    /// Sends a command to the relay with an "(id)" prefix, and returns the
+    /// first message the relay sends back with that identifier. This can be
+    /// used for any relay command that has a reply (such as hdata, info, or
+    /// nicklist).
+    ///
+    /// Note: Any other messages received while waiting for the reply (for
+    ///       example, events from a sync) are dropped. This waits forever for
+    ///       a command that the relay never replies to (such as input).
+    pub fn command<C: Command + ?Sized>(&mut self, cmd: &C, expected_id: &str) -> Result<Message, WeechatError> {
+        let max_depth = self.max_parse_depth;
+        let conn = try!(self.connection());
+        try!(conn.send(&cmd.with_id(expected_id)));
+        loop {
+            let data = try!(conn.recv_data());
+            if try!(Message::identifier(data.as_slice())).map_or(false, |id| id == expected_id) {
+                return Message::with_max_depth(data.as_slice(), max_depth);
+            }
         }
     }
 
-    /// Tell weechat we are done, and close our socket. The stream can no
-    /// longer be used after a call to close_relay. Any errors here are ignored
-    fn close_relay(&self, stream: &mut Stream) {
-        let cmd_str = "quit".to_string();
-        let _ = self.send_cmd(stream, cmd_str);
-        let _ = stream.flush();
-        let _ = stream.get_mut().shutdown(Shutdown::Both);
+    /// Sends a command the relay doesn't reply to (such as input), without
+    /// waiting for anything to come back
+    pub fn send<C: Command + ?Sized>(&mut self, cmd: &C) -> Result<(), WeechatError> {
+        try!(self.connection()).send(cmd)
+    }
+
+    /// Asks the relay for the nicks in a buffer (by its full name, ex:
+    /// irc.libera.#rust, or pointer). As with command, events from the sync
+    /// that come in while waiting for the reply are dropped.
+    pub fn request_nicklist(&mut self, buffer: &str) -> Result<Vec<Nick>, WeechatError> {
+        let msg = try!(self.command(&NicklistCommand::new(Some(buffer)), "nicklist"));
+        nicklist::parse(try!(msg.as_hdata()))
     }
 
-    fn buffer_line_added(&self, hdata: &HData) {
-        // Check if this line has a highlight or a private message that we
-        // should notify on
-        let mut play_sound = false;
-        for data in &hdata.data {
-            let highlight = data["highlight"].as_character().unwrap();
-            if highlight == (1 as char) {
-                play_sound = true;
-                break;
+    /// Tell weechat we are done and close the connection, if we are connected
+    pub fn close(&mut self) {
+        self.pending_ping = None;
+        self.last_pong = None;
+        self.health.set_connected(false);
+        if let Some(mut conn) = self.conn.take() {
+            conn.close();
+            if let Some(ref mut stats) = self.stats {
+                stats.connection_closed(conn.counts());
             }
+            if let (Some(profile), Some(reading)) = (self.profile.as_mut(), conn.reading()) {
+                profile.connection_closed(reading);
+            }
+        }
+    }
 
-            let tags_array = data["tags_array"].as_array().unwrap();
-            for element in tags_array {
-                let tag_str = element.as_not_null_str().unwrap();
-                if tag_str == "notify_private" {
-                    play_sound = true;
-                    break
-                }
+    /// Prints the stats line and profile (for those that are on), if
+    /// either it is time for one or force is set
+    fn report_stats(&mut self, force: bool) {
+        if let Some(ref mut stats) = self.stats {
+            if force || stats.due() {
+                stats.report(&self.host, self.conn.as_ref().map(|c| c.counts()));
             }
         }
+        if let Some(ref mut profile) = self.profile {
+            if force || profile.due() {
+                profile.report(&self.host, self.conn.as_ref().and_then(|c| c.reading()));
+            }
+        }
+    }
 
-        // The play is a blocking call, and if we don't loop for is_playing it
-        // seems to go out of scope and get destroyed before it can actually play
-        // the sound. So we will spawn it in a new thread, so that we don't have
-        // to wait x seconds for the sound to play before processing another
-        // message.
-        if play_sound {
-            thread::spawn(move || {
-                let mut snd = Sound::new("/home/lgbland/weechat_alert.wav").expect("Error loading the sound file");
-                snd.play();
-                while snd.is_playing() {}
-            });
+    /// Hands every line added to the handler (which for weechat-alert is an
+    /// alert::AlertHandler), with the name of its buffer
+    fn buffer_line_added(&mut self, hdata: &HData) -> Result<(), WeechatError> {
+        for line in hdata.items_as::<BufferLine>() {
+            let line = try!(line);
+            let buffer_name = self.buffers.name(line.buffer());
+            let buffer_name = buffer_name.as_ref().map(|s| s.as_str());
+            if let Some(ref mut handler) = self.handler {
+                handler.on_buffer_line(&line, buffer_name);
+            }
         }
+        Ok(())
     }
 
-    fn run_loop(&self, stream: &mut Stream) -> Result<(), WeechatError> {
-        try!(self.init_relay(stream));
+    /// Connects to the relay and gets it ready to process messages: looks up
+    /// the names of the open buffers, and starts the sync
+    fn start(&mut self) -> Result<(), WeechatError> {
+        try!(self.connect());
+        self.connected = true;
+        self.notifiers.connected(&self.host);
+        if self.lost_at.take().is_some() && self.alerted {
+            self.alerted = false;
+            self.notifiers.reconnected();
+        }
+
+        // Lines only tell us the pointer of their buffer, so grab the names
+        // of all the open buffers, and their local variables for our nick in
+        // them. Opened, renamed and closed buffers after this are picked up
+        // from the sync
+        let buffers = HdataCommand::new("buffer:gui_buffers(*)", &["full_name", "local_variables"]);
+        let msg = try!(self.command(&buffers, "buffers"));
+        self.buffers.refresh(try!(msg.as_hdata()));
 
         // We only need to sync buffers to get highlights. We don't need
         // nicklist or anything like that
-        let cmd_str = "sync * buffer".to_string();
-        try!(self.send_cmd(stream, cmd_str));
+        try!(try!(self.connection()).send(&SyncCommand::new(&["*"], &["buffer"])));
+        self.health.set_connected(true);
+        if let Some(ref mut handler) = self.handler {
+            handler.on_connect(&self.commands);
+        }
+        Ok(())
+    }
+
+    /// Reads the next message from the relay, and processes it if it is one
+    /// we want (see wanted) without parsing it otherwise. The relay closing
+    /// the connection (which ends the messages) is an error here, as we
+    /// reconnect after it.
+    fn process_next(&mut self) -> Result<(), WeechatError> {
+        self.report_stats(false);
+
+        if self.keepalive > Duration::from_secs(0) || self.replies.is_some() || self.handler.is_some() {
+            try!(self.wait());
+        }
+        let read = {
+            let all = self.wants_all_messages();
+            let handlers = &self.handlers;
+            let conn = try!(self.conn.as_mut().ok_or_else(not_connected));
+            conn.messages_filtered(|id| wanted(id, handlers, all)).next()
+        };
+        let msg = match read {
+            Some(msg) => try!(msg),
+            None      => {
+                return Err(WeechatError::Io(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                                           "The relay closed the connection")));
+            },
+        };
+        self.health.read();
+        match msg {
+            Some(msg) => self.process(&msg),
+            None      => Ok(()),
+        }
+    }
 
+    /// Waits for the relay to send something, sending any replies that come
+    /// in meanwhile, and pinging the relay each time it has been quiet for
+    /// the keepalive interval. Fails if a ping goes a whole interval without
+    /// its pong, even if the relay sent other messages in the meantime.
+    fn wait(&mut self) -> Result<(), WeechatError> {
+        let quiet_since = Instant::now();
         loop {
-            let msg = try!(self.recv_msg(stream));
-            match msg.identifier.as_ref() {
-                "_buffer_line_added" => self.buffer_line_added(try!(msg.as_hdata())),
-                _                    => (),
+            try!(self.send_replies());
+            let mut wait = if self.keepalive > Duration::from_secs(0) {
+                match self.pending_ping {
+                    Some((_, sent)) if sent.elapsed() >= self.keepalive => {
+                        return Err(WeechatError::Io(io::Error::new(io::ErrorKind::TimedOut,
+                            format!("The relay did not answer our ping within {} seconds",
+                                    self.keepalive.as_secs()))));
+                    },
+                    Some((_, sent)) => remaining(sent, self.keepalive),
+                    None            => {
+                        if quiet_since.elapsed() >= self.keepalive {
+                            let token = self.ping_token();
+                            try!(try!(self.connection()).send(&PingCommand::new(Some(&token))));
+                            self.pending_ping = Some((token, Instant::now()));
+                            continue;
+                        }
+                        remaining(quiet_since, self.keepalive)
+                    },
+                }
+            } else {
+                Duration::from_secs(REPLY_INTERVAL)
             };
+            if self.replies.is_some() || self.handler.is_some() {
+                wait = cmp::min(wait, Duration::from_secs(REPLY_INTERVAL));
+            }
+            if wait == Duration::from_secs(0) {
+                continue;
+            }
+            if try!(try!(self.connection()).wait_readable(wait)) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Sends the queued replies as input into their buffers, and the
+    /// handler's queued commands, if we are connected (otherwise they wait
+    /// for the next connection). A reply to a buffer that closed since it was
+    /// queued is dropped.
+    fn send_replies(&mut self) -> Result<(), WeechatError> {
+        if !self.connected || self.conn.is_none() {
+            return Ok(());
+        }
+        for cmd in self.commands.take() {
+            try!(try!(self.connection()).send_cmd(&cmd));
+        }
+        let replies = match self.replies {
+            Some(ref replies) => replies.clone(),
+            None              => return Ok(()),
+        };
+        for reply in replies.take() {
+            if !replies.is_open(&reply) {
+                error!("dropping a reply to {}, which was closed", reply.buffer);
+                continue;
+            }
+            try!(try!(self.connection()).send(&InputCommand::new(&reply.buffer, &reply.text)));
+        }
+        Ok(())
+    }
+
+    /// A new token for a ping, which the relay sends back in the pong
+    fn ping_token(&mut self) -> String {
+        self.pings += 1;
+        format!("weechat-alert-{}", self.pings)
+    }
+
+    /// Handles a pong, which only counts if it answers the ping we are
+    /// waiting on
+    fn pong(&mut self, msg: &Message) -> Result<(), WeechatError> {
+        let token = try!(msg.as_strdata()).as_str().map(|s| s.to_string());
+        let answered = match (self.pending_ping.as_ref(), token) {
+            (Some(&(ref pending, _)), Some(ref token)) => pending == token,
+            _                                          => false,
+        };
+        if answered {
+            self.pending_ping = None;
+            self.last_pong = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Processes the bytes of a message (after its header) from a replay.
+    /// Most messages in a busy sync are ones we ignore, so those aren't fully
+    /// parsed unless the handler or callback wants every message.
+    fn replay_data(&mut self, data: Vec<u8>) -> Result<(), WeechatError> {
+        let identifier = try!(Message::identifier(data.as_slice()));
+        let all = self.wants_all_messages();
+        if !wanted(identifier.as_ref().map(|id| id.as_str()), &self.handlers, all) {
+            return Ok(());
+        }
+        let msg = try!(self.parse(&data));
+        self.process(&msg)
+    }
+
+    /// Processes a message, from the relay or a replay
+    fn process(&mut self, msg: &Message) -> Result<(), WeechatError> {
+        // A message without an identifier is the reply to a command sent
+        // without an id. Nothing here asks for those, but a handler or
+        // callback may have
+        match msg.identifier {
+            Some(ref id) => debug!("Received {} from relay {}", id, self.host),
+            None         => debug!("Received a message without an identifier from relay {}", self.host),
+        }
+        if let Some(ref mut handler) = self.handler {
+            handler.on_message(msg);
+        }
+        let identifier: &str = match msg.identifier {
+            Some(ref identifier) => identifier,
+            None                 => return self.run_callback(msg),
+        };
+        match identifier {
+            "_buffer_line_added" => try!(self.timed_line_added(try!(event_hdata(msg)))),
+            "_buffer_opened"     => self.buffers.add(try!(event_hdata(msg))),
+            "_buffer_renamed"    => self.buffers.add(try!(event_hdata(msg))),
+            // Our nick is a local variable, which changes along with it
+            "_buffer_localvar_added" |
+            "_buffer_localvar_changed" |
+            "_buffer_localvar_removed" => self.buffers.add(try!(event_hdata(msg))),
+            "_buffer_closing"    => self.buffers.remove(try!(event_hdata(msg))),
+            "_pong"              => try!(self.pong(msg)),
+            _                    => (),
+        };
+        if let Some(handler) = self.handlers.get_mut(identifier) {
+            handler(msg);
+        }
+        self.run_callback(msg)
+    }
+
+    /// If every message is parsed: the handler wants them all, or there is a
+    /// run_with callback (which is called with every message)
+    fn wants_all_messages(&self) -> bool {
+        self.callback.is_some() || self.handler.as_ref().map_or(false, |handler| handler.wants_all_messages())
+    }
+
+    /// Hands a message to the run_with callback, if there is one
+    fn run_callback(&mut self, msg: &Message) -> Result<(), WeechatError> {
+        if let Some(ref mut callback) = self.callback {
+            if callback(msg) == ControlFlow::Stop {
+                self.stopping = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses a message from a replay, timing it if we are profiling (a
+    /// connection times its own)
+    fn parse(&mut self, data: &[u8]) -> Result<Message, WeechatError> {
+        let profile = match self.profile {
+            Some(ref mut profile) => profile,
+            None                  => return Message::with_max_depth(data, self.max_parse_depth),
+        };
+        let start = Instant::now();
+        let msg = try!(Message::with_max_depth(data, self.max_parse_depth));
+        match msg.as_hdata() {
+            Ok(_)  => profile.reading.hdata.record(start),
+            Err(_) => profile.reading.other.record(start),
+        }
+        Ok(msg)
+    }
+
+    /// Handles added lines, timing it if we are profiling
+    fn timed_line_added(&mut self, hdata: &HData) -> Result<(), WeechatError> {
+        if self.profile.is_none() {
+            return self.buffer_line_added(hdata);
+        }
+        let start = Instant::now();
+        let result = self.buffer_line_added(hdata);
+        if let Some(ref mut profile) = self.profile {
+            profile.lines.record(start);
+        }
+        result
+    }
+
+    /// Handles the connection to the relay failing. Returns the error if the
+    /// error policy says it is fatal, otherwise how long to wait before
+    /// reconnecting.
+    fn failed(&mut self, err: WeechatError) -> Result<Duration, WeechatError> {
+        self.close();
+        self.notifiers.error(&self.host, &err);
+        if let Some(ref mut handler) = self.handler {
+            handler.on_disconnect(&err);
+        }
+        if self.error_policy.classify(&err) == Action::Fatal {
+            self.report_stats(true);
+            return Err(err);
+        }
+
+        // Start backing off from scratch if we made it through init, as
+        // the relay was fine up until now
+        if self.connected {
+            self.connected = false;
+            self.delay = 0;
+            self.lost_at = Some(Instant::now());
+        }
+
+        // Alert once the relay has been gone for as long as the schedule
+        // says is worth alerting on, and only once, so a relay that stays
+        // down doesn't alert every retry
+        let backoff = self.schedule.current();
+        let gone_long_enough = self.lost_at.map_or(false, |at| at.elapsed().as_secs() >= backoff.alert_after);
+        if gone_long_enough && !self.alerted {
+            self.alerted = true;
+            self.notifiers.disconnected(&self.host, &err);
+        }
+
+        self.delay = backoff.next_delay(self.delay);
+        info!("Lost connection to relay {} ({}), reconnecting in {} seconds",
+              self.host, err, self.delay);
+        Ok(Duration::from_secs(self.delay))
+    }
+
+    /// Runs the relay client, reconnecting whenever the error policy says the
+    /// error that ended the connection is retryable. Only returns on a fatal
+    /// error (or when a run_with callback stops it).
+    pub fn run(&mut self) -> Result<(), WeechatError> {
+        loop {
+            let mut result = self.start();
+            while result.is_ok() && !self.stopping {
+                result = self.process_next();
+            }
+            if self.stopping {
+                self.stopping = false;
+                self.close();
+                return Ok(());
+            }
+            if let Err(e) = result {
+                thread::sleep(try!(self.failed(e)));
+            }
         }
     }
 
-    pub fn run(&self) -> Result<(), WeechatError> {
-        let mut stream = try!(self.connect_relay());
-        let result = self.run_loop(&mut stream);
-        self.close_relay(&mut stream);
+    /// Runs the relay client as run does, with a handler of your own getting
+    /// the connection, messages (every one if it wants them all), lines, and
+    /// disconnects (see RelayHandler). Lines are only alerted on by a handler, such as
+    /// alert::AlertHandler.
+    pub fn run_with_handler<H: RelayHandler + 'static>(&mut self, handler: H) -> Result<(), WeechatError> {
+        self.set_handler(handler);
+        self.run()
+    }
+
+    /// Runs the relay client as run does, calling back with every message
+    /// (after our own handling of it, such as notifying) until the callback
+    /// returns ControlFlow::Stop. Then the connection is closed and this
+    /// returns Ok. Reconnects don't stop it, and it still returns on a fatal
+    /// error.
+    pub fn run_with<F: FnMut(&Message) -> ControlFlow + 'static>(&mut self, callback: F) -> Result<(), WeechatError> {
+        self.callback = Some(Box::new(callback));
+        let result = self.run();
+        self.callback = None;
         result
     }
+
+    /// Sets the handler run and replay use, as run_with_handler does
+    pub fn set_handler<H: RelayHandler + 'static>(&mut self, handler: H) {
+        self.handler = Some(Box::new(handler));
+    }
+
+    /// Runs the messages in a file made with dump_to through the parser and
+    /// the notification logic, as if the relay had just sent them, without
+    /// connecting to anything. Stops at the first message that doesn't
+    /// parse, saying where it is in the file. Returns how many messages were
+    /// replayed.
+    pub fn replay(&mut self, path: &Path) -> Result<usize, WeechatError> {
+        let mut bytes = Vec::new();
+        try!(try!(File::open(path)).read_to_end(&mut bytes));
+
+        let mut offset = 0;
+        let mut count = 0;
+        while offset < bytes.len() {
+            match self.replay_message(&bytes[offset..]) {
+                Ok(length) => offset += length,
+                Err(e)     => {
+                    error!("replaying message {} (at byte {} of {})", count + 1, offset, path.display());
+                    return Err(e);
+                },
+            }
+            count += 1;
+        }
+        if let Some(ref mut profile) = self.profile {
+            profile.report(&self.host, None);
+        }
+        Ok(count)
+    }
+
+    /// Processes the first message in the bytes of a dump, returning its
+    /// length (header included)
+    fn replay_message(&mut self, bytes: &[u8]) -> Result<usize, WeechatError> {
+        if bytes.len() < HEADER_LENGTH {
+            return Err(WeechatError::ParseError("The dump ends part way through a header".to_string()));
+        }
+        let header = try!(Header::new(&bytes[..HEADER_LENGTH]));
+        let end = HEADER_LENGTH + header.length;
+        if bytes.len() < end {
+            return Err(WeechatError::ParseError("The dump ends part way through a message".to_string()));
+        }
+
+        // The reply with the names of the open buffers is read by start on
+        // a live connection, so it is handled here instead. Dumps hold
+        // messages as they were sent, so compressed ones are inflated here
+        let mut data = bytes[HEADER_LENGTH..end].to_vec();
+        if header.compression {
+            data = match self.profile {
+                Some(ref mut profile) => {
+                    let start = Instant::now();
+                    let inflated = try!(inflate::zlib(&data));
+                    profile.reading.inflate.record(start);
+                    inflated
+                },
+                None                  => try!(inflate::zlib(&data)),
+            };
+        }
+        if try!(Message::identifier(data.as_slice())).map_or(false, |id| id == "buffers") {
+            let msg = try!(Message::with_max_depth(data.as_slice(), self.max_parse_depth));
+            self.buffers.refresh(try!(msg.as_hdata()));
+        } else {
+            try!(self.replay_data(data));
+        }
+        Ok(end)
+    }
+}
+
+/// Runs several relay clients on the current thread, polling their sockets
+/// and processing messages from whichever relays have them. Each relay
+/// reconnects on its own schedule as in Relay::run. Only returns on a fatal
+/// error from any of the relays.
+///
+/// Note: Once a relay's socket is readable, we block until the whole message
+///       has arrived. The relay sends messages in one go, so this is fine
+///       unless a relay stalls part way through one. This is unix only, as
+///       it polls with libc.
+#[cfg(unix)]
+pub fn run_all(relays: &mut [Relay]) -> Result<(), WeechatError> {
+    // When each relay should next try connecting, or None while it is
+    // connected
+    let mut retry_at: Vec<Option<Instant>> = relays.iter().map(|_| Some(Instant::now())).collect();
+
+    loop {
+        let now = Instant::now();
+        for (relay, retry) in relays.iter_mut().zip(retry_at.iter_mut()) {
+            if retry.map_or(false, |at| at <= now) {
+                *retry = match relay.start() {
+                    Ok(_)  => None,
+                    Err(e) => Some(Instant::now() + try!(relay.failed(e))),
+                };
+            }
+        }
+
+        // Send any replies, then poll the connected relays, waking up in
+        // time for the next reconnect (and to check for replies again).
+        // Don't wait at all if ssl already has a message buffered
+        for (relay, retry) in relays.iter_mut().zip(retry_at.iter_mut()) {
+            if let Err(e) = relay.send_replies() {
+                *retry = Some(Instant::now() + try!(relay.failed(e)));
+            }
+        }
+        let replies = relays.iter().any(|r| r.replies.is_some());
+        let mut fds = Vec::new();
+        let mut owners = Vec::new();
+        let mut buffered = false;
+        for (i, relay) in relays.iter().enumerate() {
+            if let Some(ref conn) = relay.conn {
+                fds.push(libc::pollfd { fd: conn.raw_fd(), events: libc::POLLIN, revents: 0 });
+                owners.push(i);
+                buffered = buffered || conn.buffered();
+            }
+        }
+        let timeout = if buffered {
+            0
+        } else {
+            let mut wait = retry_at.iter().filter_map(|r| *r).min()
+                                   .map(|at| if at > now { at - now } else { Duration::from_secs(0) });
+            if replies {
+                wait = Some(wait.map_or(Duration::from_secs(REPLY_INTERVAL),
+                                        |w| cmp::min(w, Duration::from_secs(REPLY_INTERVAL))));
+            }
+            match wait {
+                Some(wait) => (wait.as_secs() * 1000 + wait.subsec_nanos() as u64 / 1000000) as libc::c_int,
+                None       => -1,
+            }
+        };
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(WeechatError::Io(err));
+        }
+
+        // A hung up or errored socket reads as an error, which is handled
+        // like any other failed connection
+        for (pollfd, &i) in fds.iter().zip(owners.iter()) {
+            let buffered = relays[i].conn.as_ref().map_or(false, |c| c.buffered());
+            if pollfd.revents == 0 && !buffered {
+                continue;
+            }
+            if let Err(e) = relays[i].process_next() {
+                retry_at[i] = Some(Instant::now() + try!(relays[i].failed(e)));
+            }
+        }
+    }
+}
+
+/// How much of a duration is left since an instant, or zero if it is over
+/// The error for using a relay that isn't connected
+fn not_connected() -> WeechatError {
+    WeechatError::Io(io::Error::new(io::ErrorKind::NotConnected, "Not connected to the relay"))
+}
+
+/// If a message with the identifier is parsed: we act on it, a handler is
+/// registered for it, or all says every message is
+fn wanted(identifier: Option<&str>, handlers: &HashMap<String, Box<FnMut(&Message)>>, all: bool) -> bool {
+    all || identifier.map_or(false, |id| HANDLED_IDENTIFIERS.contains(&id) || handlers.contains_key(id))
+}
+
+fn remaining(since: Instant, duration: Duration) -> Duration {
+    let elapsed = since.elapsed();
+    if elapsed >= duration { Duration::from_secs(0) } else { duration - elapsed }
+}
+
+/// Sends the init and a ping with the token, then reads until the pong that
+/// sends the token back
+fn ping(conn: &mut Connection, init: &InitCommand, token: &str) -> Result<(), WeechatError> {
+    try!(conn.send(init));
+    try!(conn.send(&PingCommand::new(Some(token))));
+    loop {
+        let data = try!(conn.recv_data());
+        if !try!(Message::identifier(data.as_slice())).map_or(false, |id| id == "_pong") {
+            continue;
+        }
+        let msg = try!(Message::with_max_depth(data.as_slice(), conn.max_parse_depth()));
+        if try!(msg.as_strdata()).as_str() == Some(token) {
+            return Ok(());
+        }
+    }
+}
+
+/// The hdata of an event that always has items. An empty one is handled as
+/// nothing to do, but it may mean we lost our place in the data from the
+/// relay, so it is logged (at debug, as a relay can send them harmlessly).
+fn event_hdata(msg: &Message) -> Result<&HData, WeechatError> {
+    let hdata = try!(msg.as_hdata());
+    if hdata.is_empty() {
+        debug!("got a {} with no items, the relay data may have been misread",
+              msg.identifier.as_ref().map_or("message", |id| id.as_str()));
+    }
+    Ok(hdata)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::rc::Rc;
+    use std::thread;
+
+    use super::Relay;
+    use errors::WeechatError;
+    use handler::{CommandSender, ControlFlow, RelayHandler};
+    use line::BufferLine;
+    use message::Message;
+    use testing;
+
+    /// Writes down everything a relay hands it
+    struct Recorder {
+        events: Rc<RefCell<Vec<String>>>,
+        /// What wants_all_messages says
+        all: bool,
+    }
+
+    impl RelayHandler for Recorder {
+        fn on_connect(&mut self, _commands: &CommandSender) {
+            self.events.borrow_mut().push("connect".to_string());
+        }
+
+        fn on_message(&mut self, msg: &Message) {
+            self.events.borrow_mut().push(format!("message {}", msg.identifier.as_ref().unwrap()));
+        }
+
+        fn on_buffer_line(&mut self, line: &BufferLine, buffer: Option<&str>) {
+            self.events.borrow_mut().push(format!("line {}: <{}> {}", buffer.unwrap_or("?"),
+                                                  line.prefix().unwrap(), line.message().unwrap()));
+        }
+
+        fn on_disconnect(&mut self, err: &WeechatError) {
+            self.events.borrow_mut().push(format!("disconnect {}", err));
+        }
+
+        fn wants_all_messages(&self) -> bool {
+            self.all
+        }
+    }
+
+    /// A relay on a local port that answers the init, the buffers hdata and
+    /// the sync (with one line), and returns the commands it was sent once
+    /// the client hangs up
+    fn mock_relay() -> (i32, thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port() as i32;
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut commands = Vec::new();
+            for cmd in BufReader::new(stream).lines() {
+                let cmd = cmd.unwrap();
+                let reply = if cmd.starts_with("ping ") {
+                    testing::str_message("_pong", &cmd[5..])
+                } else if cmd.starts_with("(buffers) hdata ") {
+                    testing::message("buffers", &testing::buffers_hdata(&[("55aa", "irc.libera.#rust")]))
+                } else if cmd.starts_with("sync") {
+                    let line = testing::line_item("55aa", "alice", "hi", &["irc_privmsg"], false);
+                    testing::message("_buffer_line_added", &testing::lines_hdata(&[line]))
+                } else {
+                    Vec::new()
+                };
+                writer.write_all(&reply).unwrap();
+                commands.push(cmd);
+            }
+            commands
+        });
+        (port, server)
+    }
+
+    #[test]
+    fn handler_hears_the_connection_and_lines() {
+        let (port, server) = mock_relay();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut relay = Relay::new("127.0.0.1".to_string(), port, None, None);
+        relay.set_handler(Recorder { events: events.clone(), all: false });
+        relay.run_with(|msg| {
+            if msg.identifier.as_ref().map_or(false, |id| id == "_buffer_line_added") {
+                ControlFlow::Stop
+            } else {
+                ControlFlow::Continue
+            }
+        }).unwrap();
+
+        assert_eq!(*events.borrow(), vec!["connect".to_string(),
+                                          "message _buffer_line_added".to_string(),
+                                          "line irc.libera.#rust: <alice> hi".to_string()]);
+        let commands = server.join().unwrap();
+        assert!(commands[0].starts_with("init "), "{:?}", commands);
+        assert!(commands[1].starts_with("ping "), "{:?}", commands);
+        assert!(commands[2].starts_with("(buffers) hdata buffer:gui_buffers(*)"), "{:?}", commands);
+        assert!(commands[3].starts_with("sync"), "{:?}", commands);
+        assert_eq!(commands.last().map(|c| c.as_str()), Some("quit"));
+    }
+
+    #[test]
+    fn events_without_items_do_nothing() {
+        let mut relay = Relay::new("127.0.0.1".to_string(), 9001, None, None);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        relay.set_handler(Recorder { events: events.clone(), all: false });
+        for id in &["_buffer_line_added", "_buffer_opened", "_buffer_renamed", "_buffer_localvar_added",
+                    "_buffer_closing"] {
+            let bytes = testing::message(id, &testing::hdata("", "", &[]));
+            let msg = Message::new(&bytes[5..]).unwrap();
+            relay.process(&msg).unwrap();
+        }
+        assert_eq!(events.borrow().len(), 5);
+        assert!(events.borrow().iter().all(|e| e.starts_with("message ")));
+        assert!(relay.buffers().name("55aa").is_none());
+    }
+
+    #[test]
+    fn only_handlers_that_want_every_message_hear_the_ignored_ones() {
+        let nicklist = testing::message("_nicklist", &testing::hdata("", "", &[]));
+        let pong = testing::str_message("_pong", "1");
+        for &all in &[false, true] {
+            let mut relay = Relay::new("127.0.0.1".to_string(), 9001, None, None);
+            let events = Rc::new(RefCell::new(Vec::new()));
+            relay.set_handler(Recorder { events: events.clone(), all: all });
+            relay.replay_data(nicklist[5..].to_vec()).unwrap();
+            relay.replay_data(pong[5..].to_vec()).unwrap();
+            let expected = if all { vec!["message _nicklist", "message _pong"] } else { vec!["message _pong"] };
+            assert_eq!(*events.borrow(), expected);
+        }
+    }
 }
@@ -0,0 +1,104 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use buffers::Buffers;
+
+// Most replies held while the relay is away. Past this they are refused, so
+// a long outage doesn't end in a flood of stale messages
+const MAX_PENDING: usize = 32;
+
+/// Text to send into a buffer, as if it were typed there
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reply {
+    /// Full name of the buffer (ex: irc.libera.#rust)
+    pub buffer: String,
+    /// The text, already made safe by clean
+    pub text: String,
+}
+
+/// Replies to notifications (from the control socket, an ntfy reply topic
+/// or a desktop notification), waiting for the relay loop to send them.
+/// Clones share the same queue, so they can be handed to other threads.
+///
+/// This is the one way for other threads to send input: send is safe to call
+/// from any number of them at once. Only the relay loop writes to the
+/// connection, one whole command at a time, so replies are never mixed up
+/// with each other or with the relay loop's own commands, and they go out
+/// in the order they were queued.
+///
+/// Replies are checked against the buffers the relay knows of when they are
+/// queued, so whoever sent one hears about a typo right away, and again when
+/// they are sent, in case the buffer closed in the meantime.
+#[derive(Debug, Clone)]
+pub struct Replies {
+    buffers: Buffers,
+    pending: Arc<Mutex<VecDeque<Reply>>>,
+}
+
+impl Replies {
+    /// Creates an empty queue for a relay, checking buffers against the ones
+    /// it knows of
+    pub fn new(buffers: Buffers) -> Replies {
+        Replies {
+            buffers: buffers,
+            pending: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    // Nothing here can be left half changed by a panic
+    fn lock<'a>(&'a self) -> MutexGuard<'a, VecDeque<Reply>> {
+        self.pending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Queues text to send into a buffer (by full name). Fails if the relay
+    /// doesn't know of the buffer, there is no text, or too many replies are
+    /// already waiting.
+    pub fn send(&self, buffer: &str, text: &str) -> Result<(), String> {
+        if !self.buffers.has_name(buffer) {
+            return Err(format!("there is no buffer named {}", buffer));
+        }
+        let text = clean(text);
+        if text.is_empty() {
+            return Err("there is nothing to reply".to_string());
+        }
+        let mut pending = self.lock();
+        if pending.len() >= MAX_PENDING {
+            return Err("too many replies are waiting for the relay".to_string());
+        }
+        pending.push_back(Reply {
+            buffer: buffer.to_string(),
+            text: text,
+        });
+        Ok(())
+    }
+
+    /// Takes every queued reply, oldest first
+    pub fn take(&self) -> Vec<Reply> {
+        self.lock().drain(..).collect()
+    }
+
+    /// If the buffer a reply is for is still open
+    pub fn is_open(&self, reply: &Reply) -> bool {
+        self.buffers.has_name(&reply.buffer)
+    }
+}
+
+/// Makes text safe to send as input: a newline would end the relay command
+/// (and start another one), so line breaks and tabs become spaces and other
+/// control characters are dropped. Text starting with "/" would run as a
+/// command, so that is doubled, which weechat sends as a single "/".
+pub fn clean(text: &str) -> String {
+    let text: String = text.chars()
+                           .filter_map(|c| match c {
+                               '\n' | '\r' | '\t'      => Some(' '),
+                               c if c.is_control()     => None,
+                               c                       => Some(c),
+                           })
+                           .collect();
+    let text = text.trim();
+    if text.starts_with('/') {
+        format!("/{}", text)
+    } else {
+        text.to_string()
+    }
+}
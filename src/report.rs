@@ -0,0 +1,292 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use rustc_serialize::json::Json;
+
+use notify;
+use notify::time_format::TimeFormat;
+
+// Most buffers and nicks listed in a text report (the json has them all)
+const MAX_LISTED: usize = 10;
+
+/// Which days of the event log a report covers, as local dates
+/// ("2024-05-01"). Both ends are included.
+#[derive(Debug, Clone)]
+pub struct Range {
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+impl Range {
+    /// Just today
+    pub fn today() -> Range {
+        let today = day_format().render(notify::now());
+        Range {
+            since: Some(today.clone()),
+            until: Some(today),
+        }
+    }
+
+    fn contains(&self, day: &str) -> bool {
+        self.since.as_ref().map_or(true, |since| day >= since.as_str()) &&
+        self.until.as_ref().map_or(true, |until| day <= until.as_str())
+    }
+}
+
+/// Checks a date given to --since or --until is YYYY-MM-DD, which are
+/// compared as strings
+pub fn parse_day(option: &str, day: &str) -> Result<String, String> {
+    let parts: Vec<&str> = day.split('-').collect();
+    let valid = parts.len() == 3 && parts[0].len() == 4 && parts[1].len() == 2 && parts[2].len() == 2 &&
+                parts.iter().all(|p| p.chars().all(|c| c.is_digit(10)));
+    if !valid {
+        return Err(format!("{} needs a date like 2024-05-01, not '{}'", option, day));
+    }
+    Ok(day.to_string())
+}
+
+/// What a day (or range of days) of events added up to
+pub struct Report {
+    range: Range,
+    events: u64,
+    suppressed: u64,
+    classes: BTreeMap<String, u64>,
+    buffers: BTreeMap<String, u64>,
+    nicks: BTreeMap<String, u64>,
+    /// Events in each local hour of the day
+    hours: [u64; 24],
+    /// Lines of the log that weren't events we could read
+    skipped: u64,
+}
+
+impl Report {
+    /// Reads a json_log (see notify::json_log) and adds up the events in the
+    /// range
+    pub fn read(path: &Path, range: Range) -> Result<Report, String> {
+        let file = try!(File::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e)));
+        let day_format = day_format();
+        let hour_format = TimeFormat::parse("%H", false).unwrap();
+
+        let mut report = Report {
+            range: range,
+            events: 0,
+            suppressed: 0,
+            classes: BTreeMap::new(),
+            buffers: BTreeMap::new(),
+            nicks: BTreeMap::new(),
+            hours: [0; 24],
+            skipped: 0,
+        };
+        for line in BufReader::new(file).lines() {
+            let line = try!(line.map_err(|e| format!("Could not read {}: {}", path.display(), e)));
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = match Json::from_str(&line) {
+                Ok(Json::Object(event)) => event,
+                _                       => {
+                    report.skipped += 1;
+                    continue;
+                },
+            };
+            let timestamp = match event.get("timestamp").and_then(|t| t.as_i64()) {
+                Some(timestamp) => timestamp,
+                None            => {
+                    report.skipped += 1;
+                    continue;
+                },
+            };
+            if !report.range.contains(&day_format.render(timestamp)) {
+                continue;
+            }
+
+            let string = |key: &str| event.get(key).and_then(|v| v.as_string());
+            report.events += 1;
+            if event.get("suppressed").and_then(|s| s.as_boolean()).unwrap_or(false) {
+                report.suppressed += 1;
+            }
+            *report.classes.entry(string("class").unwrap_or("-").to_string()).or_insert(0) += 1;
+            *report.buffers.entry(string("buffer").unwrap_or("-").to_string()).or_insert(0) += 1;
+            if let Some(nick) = string("nick") {
+                if !nick.is_empty() {
+                    *report.nicks.entry(nick.to_string()).or_insert(0) += 1;
+                }
+            }
+            if let Ok(hour) = hour_format.render(timestamp).parse::<usize>() {
+                report.hours[hour] += 1;
+            }
+        }
+        Ok(report)
+    }
+
+    /// The hour with the most events, and how many, if there were any
+    fn busiest_hour(&self) -> Option<(usize, u64)> {
+        // Ties go to the earliest hour
+        self.hours.iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .fold(None, |busiest, (hour, &count)| match busiest {
+                Some((_, most)) if most >= count => busiest,
+                _                                => Some((hour, count)),
+            })
+    }
+
+    /// The report for people, like:
+    ///
+    /// 2024-05-01: 42 events (5 suppressed)
+    /// By class:
+    ///   highlight  30
+    ///   ...
+    /// Busiest hour: 14:00 to 15:00 (12 events)
+    pub fn text(&self) -> String {
+        let mut out = format!("{}: {} events ({} suppressed)\n", self.range_name(), self.events, self.suppressed);
+        if self.events > 0 {
+            out.push_str(&section("By class", &self.classes));
+            out.push_str(&section("By buffer", &self.buffers));
+            out.push_str(&section("By nick", &self.nicks));
+        }
+        if let Some((hour, count)) = self.busiest_hour() {
+            out.push_str(&format!("Busiest hour: {:02}:00 to {:02}:00 ({} events)\n", hour, (hour + 1) % 24, count));
+        }
+        if self.skipped > 0 {
+            out.push_str(&format!("Skipped {} lines that weren't events\n", self.skipped));
+        }
+        out
+    }
+
+    /// The report as a json object, for other tools. Every buffer and nick
+    /// is in it, and hours are "00" to "23"
+    pub fn json(&self) -> String {
+        let counts = |map: &BTreeMap<String, u64>| {
+            Json::Object(map.iter().map(|(k, &v)| (k.clone(), Json::U64(v))).collect())
+        };
+        let day = |day: &Option<String>| day.as_ref().map_or(Json::Null, |d| Json::String(d.clone()));
+
+        let mut doc = BTreeMap::new();
+        doc.insert("since".to_string(), day(&self.range.since));
+        doc.insert("until".to_string(), day(&self.range.until));
+        doc.insert("events".to_string(), Json::U64(self.events));
+        doc.insert("suppressed".to_string(), Json::U64(self.suppressed));
+        doc.insert("classes".to_string(), counts(&self.classes));
+        doc.insert("buffers".to_string(), counts(&self.buffers));
+        doc.insert("nicks".to_string(), counts(&self.nicks));
+        let hours = self.hours.iter()
+                              .enumerate()
+                              .map(|(hour, &count)| (format!("{:02}", hour), Json::U64(count)))
+                              .collect();
+        doc.insert("hours".to_string(), Json::Object(hours));
+        doc.insert("busiest_hour".to_string(),
+                   self.busiest_hour().map_or(Json::Null, |(hour, _)| Json::U64(hour as u64)));
+        doc.insert("skipped".to_string(), Json::U64(self.skipped));
+        format!("{}\n", Json::Object(doc))
+    }
+
+    /// The range as a heading (ex: "2024-05-01" or "2024-05-01 to 2024-05-07")
+    fn range_name(&self) -> String {
+        match (self.range.since.as_ref(), self.range.until.as_ref()) {
+            (Some(since), Some(until)) if since == until => since.clone(),
+            (Some(since), Some(until))                   => format!("{} to {}", since, until),
+            (Some(since), None)                          => format!("Since {}", since),
+            (None, Some(until))                          => format!("Until {}", until),
+            (None, None)                                 => "Every event".to_string(),
+        }
+    }
+}
+
+/// Local dates the way ranges are written
+fn day_format() -> TimeFormat {
+    TimeFormat::parse("%Y-%m-%d", false).unwrap()
+}
+
+/// A heading and its counts, most first, with at most MAX_LISTED shown
+fn section(heading: &str, counts: &BTreeMap<String, u64>) -> String {
+    let mut sorted: Vec<(&String, &u64)> = counts.iter().collect();
+    // Names are already in order, and the sort is stable, so ties stay in
+    // name order
+    sorted.sort_by(|a, b| b.1.cmp(a.1));
+    let width = sorted.iter().take(MAX_LISTED).map(|&(name, _)| name.chars().count()).max().unwrap_or(0);
+
+    let mut out = format!("{}:\n", heading);
+    for &(name, count) in sorted.iter().take(MAX_LISTED) {
+        out.push_str(&format!("  {:width$}  {}\n", name, count, width = width));
+    }
+    if sorted.len() > MAX_LISTED {
+        out.push_str(&format!("  ({} more)\n", sorted.len() - MAX_LISTED));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::prelude::*;
+
+    use rustc_serialize::json::Json;
+
+    use super::{day_format, parse_day, Range, Report};
+    use notify::time_format::TimeFormat;
+
+    // Noon (utc) on a day, so the local date is the same day everywhere but
+    // the far east and west
+    const DAY: i64 = 1480075200;
+
+    fn event(timestamp: i64, class: &str, buffer: &str, nick: &str, suppressed: bool) -> String {
+        format!("{{\"timestamp\":{},\"class\":\"{}\",\"buffer\":\"{}\",\"nick\":\"{}\",\"suppressed\":{}}}\n",
+                timestamp, class, buffer, nick, suppressed)
+    }
+
+    #[test]
+    fn adds_up_the_events_of_a_day() {
+        let dir = env::temp_dir().join("weechat-alert-report-test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let path = dir.join("events.json");
+        {
+            let mut log = File::create(&path).unwrap();
+            log.write_all(event(DAY - 86400, "private", "irc.libera.bob", "bob", false).as_bytes()).unwrap();
+            log.write_all(event(DAY, "highlight", "irc.libera.#rust", "alice", false).as_bytes()).unwrap();
+            log.write_all(event(DAY + 60, "highlight", "irc.libera.#rust", "alice", true).as_bytes()).unwrap();
+            log.write_all(event(DAY + 3600, "private", "irc.libera.bob", "bob", false).as_bytes()).unwrap();
+            log.write_all(b"\nnot json\n{\"class\":\"highlight\"}\n").unwrap();
+        }
+        let day = day_format().render(DAY);
+        let range = Range {
+            since: Some(day.clone()),
+            until: Some(day.clone()),
+        };
+        let report = Report::read(&path, range).unwrap();
+        let hour = TimeFormat::parse("%H", false).unwrap().render(DAY).parse::<usize>().unwrap();
+
+        assert_eq!(report.events, 3);
+        assert_eq!(report.suppressed, 1);
+        assert_eq!(report.skipped, 2);
+        assert_eq!(report.busiest_hour(), Some((hour, 2)));
+        assert_eq!(report.text(),
+                   format!("{}: 3 events (1 suppressed)\n\
+                            By class:\n  highlight  2\n  private    1\n\
+                            By buffer:\n  irc.libera.#rust  2\n  irc.libera.bob    1\n\
+                            By nick:\n  alice  2\n  bob    1\n\
+                            Busiest hour: {:02}:00 to {:02}:00 (2 events)\n\
+                            Skipped 2 lines that weren't events\n", day, hour, (hour + 1) % 24));
+
+        let doc = Json::from_str(&report.json()).unwrap();
+        assert_eq!(doc.find("since").and_then(|d| d.as_string()), Some(day.as_str()));
+        assert_eq!(doc.find_path(&["buffers", "irc.libera.bob"]).and_then(|c| c.as_u64()), Some(1));
+        assert_eq!(doc.find("busiest_hour").and_then(|h| h.as_u64()), Some(hour as u64));
+
+        let every = Report::read(&path, Range { since: None, until: None }).unwrap();
+        assert_eq!(every.events, 4);
+        assert!(every.text().starts_with("Every event: 4 events (1 suppressed)\n"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn days_are_yyyy_mm_dd() {
+        assert_eq!(parse_day("--since", "2024-05-01"), Ok("2024-05-01".to_string()));
+        assert!(parse_day("--since", "2024-5-1").unwrap_err().contains("--since"));
+        assert!(parse_day("--until", "yesterday").is_err());
+    }
+}
@@ -0,0 +1,94 @@
+use std::time::{Duration, Instant};
+
+// Seconds between stats lines
+const REPORT_INTERVAL: u64 = 600;
+
+/// How much a connection has read from the relay
+#[derive(Debug, Clone, Copy)]
+pub struct Counts {
+    /// Messages read
+    pub messages: u64,
+    /// Bytes read, including message headers
+    pub bytes: u64,
+}
+
+impl Counts {
+    pub fn new() -> Counts {
+        Counts {
+            messages: 0,
+            bytes: 0,
+        }
+    }
+
+    /// Counts one message of the given size
+    pub fn record(&mut self, bytes: usize) {
+        self.messages += 1;
+        self.bytes += bytes as u64;
+    }
+
+    fn add(&self, other: &Counts) -> Counts {
+        Counts {
+            messages: self.messages + other.messages,
+            bytes: self.bytes + other.bytes,
+        }
+    }
+}
+
+/// Totals of what a relay has read over all of its connections, printed
+/// every so often (see --stats)
+pub struct Stats {
+    /// Counts from connections that have been closed
+    closed: Counts,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        let now = Instant::now();
+        Stats {
+            closed: Counts::new(),
+            started: now,
+            last_report: now,
+        }
+    }
+
+    /// Adds in the counts of a connection that is being closed
+    pub fn connection_closed(&mut self, counts: &Counts) {
+        self.closed = self.closed.add(counts);
+    }
+
+    /// Returns true if it is time to print another stats line
+    pub fn due(&self) -> bool {
+        self.last_report.elapsed() >= Duration::from_secs(REPORT_INTERVAL)
+    }
+
+    /// Prints the totals, given the counts of the open connection (if any)
+    pub fn report(&mut self, host: &str, current: Option<&Counts>) {
+        let totals = match current {
+            Some(counts) => self.closed.add(counts),
+            None         => self.closed,
+        };
+        let minutes = self.started.elapsed().as_secs() as f64 / 60.0;
+        let rate = if minutes > 0.0 { totals.bytes as f64 / minutes } else { 0.0 };
+        info!("Stats for relay {}: {} messages, {} in {:.0} minutes ({}/minute)",
+              host, totals.messages, format_bytes(totals.bytes as f64), minutes, format_bytes(rate));
+        self.last_report = Instant::now();
+    }
+}
+
+/// Formats a number of bytes with a binary unit, like "12.3 KiB"
+fn format_bytes(bytes: f64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{:.0} {}", size, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
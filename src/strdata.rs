@@ -1,3 +1,5 @@
+use std::fmt;
+
 use errors::WeechatError;
 use parse::Parse;
 
@@ -17,9 +19,56 @@ impl StrData {
         let parsed = try!(Parse::string(bytes));
         let s = try!(parsed.object.as_str()).map(|s| s.to_string());
         if bytes.len() != parsed.bytes_read {
-            Err(WeechatError::ParseError("Not all bytes in message consumed".to_string()))
+            Err(WeechatError::ParseError(format!("Not all bytes in message consumed (consumed {} of {} bytes)",
+                                                 parsed.bytes_read, bytes.len())))
         } else {
             Ok(StrData{ data: s })
         }
     }
+
+    /// The string, or None if weechat sent a null string
+    pub fn as_str(&self) -> Option<&str> {
+        self.data.as_ref().map(|s| s.as_str())
+    }
+
+    /// Takes the string out, or None if weechat sent a null string
+    pub fn into_string(self) -> Option<String> {
+        self.data
+    }
+}
+
+/// The string quoted (like an Object::Str), or null. For debugging
+impl fmt::Display for StrData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.data {
+            Some(ref s) => write!(f, "\"{}\"", s),
+            None        => write!(f, "null"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StrData;
+    use message::Message;
+    use testing::{int, str_message};
+
+    #[test]
+    fn as_str_of_a_parsed_message() {
+        let bytes = str_message("_pong", "weechat-alert-1");
+        let msg = Message::new(&bytes[5..]).unwrap();
+        let strdata = msg.as_strdata().unwrap();
+        assert_eq!(strdata.as_str(), Some("weechat-alert-1"));
+        assert_eq!(strdata.to_string(), "\"weechat-alert-1\"");
+    }
+
+    #[test]
+    fn null_string() {
+        let mut bytes = Vec::new();
+        int(&mut bytes, -1);
+        let strdata = StrData::new(&bytes).unwrap();
+        assert_eq!(strdata.as_str(), None);
+        assert_eq!(strdata.to_string(), "null");
+        assert_eq!(strdata.into_string(), None);
+    }
 }
@@ -0,0 +1,79 @@
+use std::mem;
+
+use libc;
+
+/// A time broken down in the local time zone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub year: i64,
+    /// 1 to 12
+    pub month: i32,
+    pub day: i32,
+    pub hour: i32,
+    pub minute: i32,
+    pub second: i32,
+}
+
+/// Converts a unix timestamp to local time, or returns None if the libc
+/// can't convert it
+#[cfg(unix)]
+pub fn local_time(timestamp: i64) -> Option<LocalTime> {
+    let time = timestamp as libc::time_t;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return None;
+    }
+    Some(LocalTime {
+        year: tm.tm_year as i64 + 1900,
+        month: tm.tm_mon + 1,
+        day: tm.tm_mday,
+        hour: tm.tm_hour,
+        minute: tm.tm_min,
+        second: tm.tm_sec,
+    })
+}
+
+// The C runtime's struct tm, which the libc crate doesn't have for windows
+#[cfg(windows)]
+#[repr(C)]
+struct Tm {
+    tm_sec: libc::c_int,
+    tm_min: libc::c_int,
+    tm_hour: libc::c_int,
+    tm_mday: libc::c_int,
+    tm_mon: libc::c_int,
+    tm_year: libc::c_int,
+    tm_wday: libc::c_int,
+    tm_yday: libc::c_int,
+    tm_isdst: libc::c_int,
+}
+
+#[cfg(windows)]
+extern "C" {
+    fn _localtime64_s(tm: *mut Tm, time: *const i64) -> libc::c_int;
+}
+
+/// Converts a unix timestamp to local time, or returns None if the C
+/// runtime can't convert it
+#[cfg(windows)]
+pub fn local_time(timestamp: i64) -> Option<LocalTime> {
+    let mut tm: Tm = unsafe { mem::zeroed() };
+    if unsafe { _localtime64_s(&mut tm, &timestamp) } != 0 {
+        return None;
+    }
+    Some(LocalTime {
+        year: tm.tm_year as i64 + 1900,
+        month: tm.tm_mon + 1,
+        day: tm.tm_mday,
+        hour: tm.tm_hour,
+        minute: tm.tm_min,
+        second: tm.tm_sec,
+    })
+}
+
+/// Checks if stdout is a terminal
+pub fn stdout_is_tty() -> bool {
+    // stdout is fd 1 everywhere, but the libc crate only has STDOUT_FILENO
+    // for unix
+    unsafe { libc::isatty(1) != 0 }
+}
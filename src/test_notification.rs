@@ -0,0 +1,75 @@
+use alert::AlertReason;
+use notify::{EventClass, Notifiers, Settings};
+
+/// Seconds to wait, if --wait isn't given, for backends that send from their
+/// own thread to finish (most of them give up on a request after ten seconds)
+pub const DEFAULT_WAIT: u64 = 10;
+
+/// What test-notification sends, from --class, --buffer and --message
+pub struct TestEvent {
+    pub class: EventClass,
+    pub buffer: String,
+    pub message: String,
+}
+
+impl TestEvent {
+    pub fn new() -> TestEvent {
+        TestEvent {
+            class: EventClass::Highlight,
+            buffer: "irc.example.#weechat-alert".to_string(),
+            message: "This is a test notification from weechat-alert".to_string(),
+        }
+    }
+}
+
+/// Enables the configured backends, without connecting to a relay, and sends
+/// each of them the test event, printing how each did. Returns true if every
+/// one of them took it. A backend that can't be enabled counts as failing,
+/// and the rest are still tried.
+pub fn send(settings: Settings, names: &[String], test: &TestEvent) -> bool {
+    let mut notifiers = Notifiers::new(settings);
+    let mut ok = true;
+    for name in names {
+        if let Err(e) = notifiers.enable(name) {
+            println!("{}: FAILED to start: {}", name, e);
+            ok = false;
+        }
+    }
+
+    let reason = match test.class {
+        EventClass::Private   => AlertReason::Private,
+        EventClass::Highlight => AlertReason::Highlight,
+        EventClass::Watch     => AlertReason::Watch(test.buffer.clone()),
+    };
+    let event = notifiers.test_event(reason, &test.buffer, "weechat-alert", &test.message);
+    for (name, result) in notifiers.test(&event) {
+        match result {
+            Ok(())  => println!("{}: ok", name),
+            Err(e)  => {
+                println!("{}: FAILED: {}", name, e);
+                ok = false;
+            },
+        }
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{send, TestEvent};
+    use notify::{EventClass, Settings};
+
+    #[test]
+    fn fails_if_any_backend_does() {
+        let mut test = TestEvent::new();
+        assert!(send(Settings::new(), &["stdout".to_string()], &test));
+        test.class = EventClass::Watch;
+        assert!(send(Settings::new(), &["stdout".to_string()], &test));
+
+        // Unknown, and missing its settings
+        assert!(!send(Settings::new(), &["stdout".to_string(), "carrier-pigeon".to_string()], &test));
+        assert!(!send(Settings::new(), &["webhook".to_string(), "stdout".to_string()], &test));
+        // Nothing configured is nothing failing
+        assert!(send(Settings::new(), &[], &test));
+    }
+}
@@ -0,0 +1,135 @@
+//! Builders for the bytes the relay sends, and the lines it parses to, for
+//! the tests
+
+use std::collections::HashMap;
+
+use alert::AlertReason;
+use line::BufferLine;
+use message::Object;
+use notify::{NotificationEvent, Notifiers, Settings};
+
+/// Keys of the lines in a _buffer_line_added hdata, as line_item sends them
+pub const LINE_KEYS: &'static str = "buffer:ptr,date:tim,date_printed:tim,displayed:chr,highlight:chr,\
+                                     tags_array:arr,prefix:str,message:str";
+
+pub fn int(bytes: &mut Vec<u8>, value: i32) {
+    for shift in &[24, 16, 8, 0] {
+        bytes.push((value >> shift) as u8);
+    }
+}
+
+pub fn string(bytes: &mut Vec<u8>, s: &str) {
+    int(bytes, s.len() as i32);
+    bytes.extend_from_slice(s.as_bytes());
+}
+
+/// A pointer (ex: "55d0c5a0e0f0", without the 0x), or "0" for a null one
+pub fn pointer(bytes: &mut Vec<u8>, ptr: &str) {
+    bytes.push(ptr.len() as u8);
+    bytes.extend_from_slice(ptr.as_bytes());
+}
+
+pub fn time(bytes: &mut Vec<u8>, timestamp: i64) {
+    let timestamp = timestamp.to_string();
+    bytes.push(timestamp.len() as u8);
+    bytes.extend_from_slice(timestamp.as_bytes());
+}
+
+/// A whole uncompressed message, header and all. The body starts with the
+/// type of its first object (ex: "str")
+pub fn message(id: &str, body: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    string(&mut payload, id);
+    payload.extend_from_slice(body);
+    let mut bytes = Vec::new();
+    int(&mut bytes, 5 + payload.len() as i32);
+    bytes.push(0);
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+/// A message with a str body
+pub fn str_message(id: &str, text: &str) -> Vec<u8> {
+    let mut body = b"str".to_vec();
+    string(&mut body, text);
+    message(id, &body)
+}
+
+/// An hdata object (with its "hda" type) of items already encoded, each
+/// being its path pointers then its values
+pub fn hdata(path: &str, keys: &str, items: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = b"hda".to_vec();
+    string(&mut bytes, path);
+    string(&mut bytes, keys);
+    int(&mut bytes, items.len() as i32);
+    for item in items {
+        bytes.extend_from_slice(item);
+    }
+    bytes
+}
+
+/// The buffers hdata the relay answers with when asked for their names
+pub fn buffers_hdata(buffers: &[(&str, &str)]) -> Vec<u8> {
+    let items: Vec<Vec<u8>> = buffers.iter().map(|&(ptr, name)| {
+        let mut item = Vec::new();
+        pointer(&mut item, ptr);
+        string(&mut item, name);
+        item
+    }).collect();
+    hdata("buffer", "full_name:str", &items)
+}
+
+/// A _buffer_line_added hdata of items made with line_item
+pub fn lines_hdata(items: &[Vec<u8>]) -> Vec<u8> {
+    hdata("line_data", LINE_KEYS, items)
+}
+
+/// An item of a _buffer_line_added hdata (see LINE_KEYS), printed now
+pub fn line_item(buffer: &str, prefix: &str, text: &str, tags: &[&str], highlight: bool) -> Vec<u8> {
+    let mut item = Vec::new();
+    pointer(&mut item, "1a2b3c");
+    pointer(&mut item, buffer);
+    time(&mut item, 0);
+    time(&mut item, ::notify::now());
+    item.push(1);
+    item.push(highlight as u8);
+    item.extend_from_slice(b"str");
+    int(&mut item, tags.len() as i32);
+    for tag in tags {
+        string(&mut item, tag);
+    }
+    string(&mut item, prefix);
+    string(&mut item, text);
+    item
+}
+
+/// A line as parsed from a _buffer_line_added, printed now and displayed
+pub fn line(buffer: &str, prefix: &str, text: &str, tags: &[&str], highlight: bool) -> BufferLine {
+    BufferLine::new(&line_fields(buffer, prefix, text, tags, highlight)).unwrap()
+}
+
+/// A private message to us, dated (by whoever sent it) date
+pub fn dated_line(date: i64, buffer: &str, text: &str) -> BufferLine {
+    let mut item = line_fields(buffer, "alice", text, &["notify_private"], false);
+    item.insert("date".to_string(), Object::Tim(date));
+    BufferLine::new(&item).unwrap()
+}
+
+fn line_fields(buffer: &str, prefix: &str, text: &str, tags: &[&str], highlight: bool) -> HashMap<String, Object> {
+    let mut item = HashMap::new();
+    item.insert("buffer".to_string(), Object::Ptr(Some(buffer.to_string())));
+    item.insert("date_printed".to_string(), Object::Tim(::notify::now()));
+    item.insert("displayed".to_string(), Object::Chr(1 as char));
+    item.insert("highlight".to_string(), Object::Chr(highlight as u8 as char));
+    item.insert("prefix".to_string(), Object::Str(Some(prefix.to_string())));
+    item.insert("message".to_string(), Object::Str(Some(text.to_string())));
+    let tags = tags.iter().map(|t| Object::Str(Some(t.to_string()))).collect();
+    item.insert("tags_array".to_string(), Object::Arr(tags));
+    item
+}
+
+/// An event for a line, with the default settings (so its priority is its
+/// class's)
+pub fn event(reason: AlertReason, buffer: &str, nick: &str, message: &str) -> NotificationEvent {
+    Notifiers::new(Settings::new()).test_event(reason, buffer, nick, message)
+}
@@ -0,0 +1,175 @@
+use std::io;
+use std::io::prelude::*;
+
+use openssl::crypto::{hash, rand};
+use rustc_serialize::base64::{ToBase64, STANDARD};
+
+use errors::WeechatError;
+use errors::WeechatError::{ParseError, WebSocketError};
+
+/// Path the weechat relay serves websockets on
+pub const DEFAULT_PATH: &'static str = "/weechat";
+
+// Appended to our key to get the accept key the server must send back
+const ACCEPT_GUID: &'static str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Biggest frame we accept. Relay messages can be large (ex: a long backlog),
+// but anything this size means we lost our place in the stream
+const MAX_FRAME_LENGTH: u64 = 64 * 1024 * 1024;
+
+// Frame opcodes
+const OP_CONTINUATION: u8 = 0x0;
+const OP_TEXT: u8 = 0x1;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Does the http upgrade handshake on a freshly opened stream, turning it
+/// into a websocket. See https://tools.ietf.org/html/rfc6455
+pub fn handshake<S: Read + Write>(stream: &mut S, host: &str, port: i32,
+                                  path: &str) -> Result<(), WeechatError> {
+    let key = rand::rand_bytes(16).to_base64(STANDARD);
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}:{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                           Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+                          path, host, port, key);
+    try!(stream.write_all(request.as_bytes()));
+    try!(stream.flush());
+
+    // Read the response a byte at a time, so we don't read into the first
+    // frame after it
+    let mut response = Vec::new();
+    let mut byte = [0; 1];
+    while !response.ends_with(b"\r\n\r\n") {
+        try!(stream.read_exact(&mut byte));
+        response.push(byte[0]);
+        if response.len() > 16384 {
+            return Err(WebSocketError("the response to the handshake is too long".to_string()));
+        }
+    }
+    let response = String::from_utf8_lossy(&response).into_owned();
+
+    let mut lines = response.split("\r\n");
+    let status = lines.next().unwrap_or("");
+    if status.split_whitespace().nth(1) != Some("101") {
+        return Err(WebSocketError(format!("the relay did not upgrade to a websocket ({})", status.trim())));
+    }
+    let expected = hash::hash(hash::Type::SHA1, format!("{}{}", key, ACCEPT_GUID).as_bytes()).to_base64(STANDARD);
+    let accepted = lines.filter_map(|line| line.find(':').map(|i| (&line[..i], line[i + 1..].trim())))
+                        .any(|(name, value)| name.to_lowercase() == "sec-websocket-accept" && value == expected);
+    if !accepted {
+        return Err(WebSocketError("the relay sent the wrong websocket accept key".to_string()));
+    }
+    Ok(())
+}
+
+/// The state of a websocket carrying the relay protocol. Relay messages are
+/// split over (or packed into) frames however the relay likes, so the
+/// payload of the frames is kept here until it is read.
+pub struct WebSocket {
+    /// Payload read that is not yet part of a message we returned
+    payload: Vec<u8>,
+}
+
+impl WebSocket {
+    pub fn new() -> WebSocket {
+        WebSocket { payload: Vec::new() }
+    }
+
+    /// Returns true if part of the next message has already been read off
+    /// the socket
+    pub fn buffered(&self) -> bool {
+        !self.payload.is_empty()
+    }
+
+    /// Fills buf from the payload of the frames, reading frames as needed
+    pub fn read_exact<S: Read + Write>(&mut self, stream: &mut S, buf: &mut [u8]) -> Result<(), WeechatError> {
+        while self.payload.len() < buf.len() {
+            try!(self.read_frame(stream));
+        }
+        buf.copy_from_slice(&self.payload[..buf.len()]);
+        self.payload.drain(..buf.len());
+        Ok(())
+    }
+
+    /// Sends a command as a text frame
+    pub fn send<S: Write>(&mut self, stream: &mut S, data: &[u8]) -> Result<(), WeechatError> {
+        write_frame(stream, OP_TEXT, data)
+    }
+
+    /// Reads a frame, adding any data in it to the payload. Pings are
+    /// answered, and a close (once answered) is an error, as the relay is
+    /// done with us.
+    fn read_frame<S: Read + Write>(&mut self, stream: &mut S) -> Result<(), WeechatError> {
+        let mut head = [0; 2];
+        try!(stream.read_exact(&mut head));
+        let opcode = head[0] & 0x0f;
+        let masked = head[1] & 0x80 != 0;
+        let length = match head[1] & 0x7f {
+            126 => {
+                let mut bytes = [0; 2];
+                try!(stream.read_exact(&mut bytes));
+                (bytes[0] as u64) << 8 | bytes[1] as u64
+            },
+            127 => {
+                let mut bytes = [0; 8];
+                try!(stream.read_exact(&mut bytes));
+                bytes.iter().fold(0, |length, &b| length << 8 | b as u64)
+            },
+            length => length as u64,
+        };
+        if length > MAX_FRAME_LENGTH {
+            return Err(ParseError(format!("Websocket frame of {} bytes is too big", length)));
+        }
+
+        // Servers never mask frames, but unmasking is cheap
+        let mut mask = [0; 4];
+        if masked {
+            try!(stream.read_exact(&mut mask));
+        }
+        let mut data = vec![0; length as usize];
+        try!(stream.read_exact(&mut data));
+        if masked {
+            for (i, b) in data.iter_mut().enumerate() {
+                *b ^= mask[i % 4];
+            }
+        }
+
+        match opcode {
+            OP_CONTINUATION | OP_TEXT | OP_BINARY => self.payload.extend_from_slice(&data),
+            OP_PING  => try!(write_frame(stream, OP_PONG, &data)),
+            OP_PONG  => (),
+            OP_CLOSE => {
+                let _ = write_frame(stream, OP_CLOSE, &data);
+                return Err(WeechatError::Io(io::Error::new(io::ErrorKind::ConnectionAborted,
+                                                           "The relay closed the websocket")));
+            },
+            _        => return Err(ParseError(format!("Unknown websocket opcode {}", opcode))),
+        }
+        Ok(())
+    }
+}
+
+/// Writes a whole frame. Frames from a client must be masked.
+fn write_frame<S: Write>(stream: &mut S, opcode: u8, data: &[u8]) -> Result<(), WeechatError> {
+    let mut frame = vec![0x80 | opcode];
+    if data.len() < 126 {
+        frame.push(0x80 | data.len() as u8);
+    } else if data.len() <= 0xffff {
+        frame.push(0x80 | 126);
+        frame.push((data.len() >> 8) as u8);
+        frame.push(data.len() as u8);
+    } else {
+        frame.push(0x80 | 127);
+        for i in (0..8).rev() {
+            frame.push((data.len() as u64 >> (i * 8)) as u8);
+        }
+    }
+
+    let mask = rand::rand_bytes(4);
+    frame.extend_from_slice(&mask);
+    frame.extend(data.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    try!(stream.write_all(&frame));
+    try!(stream.flush());
+    Ok(())
+}
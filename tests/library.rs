@@ -0,0 +1,80 @@
+//! Uses the crate the way another crate would, through its public API only:
+//! reading a message captured off the wire and handing its lines to a
+//! handler.
+
+extern crate weechat_alert;
+
+use std::io::Cursor;
+
+use weechat_alert::errors::WeechatError;
+use weechat_alert::handler::{CommandSender, RelayHandler};
+use weechat_alert::line::BufferLine;
+use weechat_alert::message::{read_message, Message, Object};
+
+// A _buffer_line_added for "alice: hi" in #rust, laid out the way the relay
+// sends one (uncompressed), with the keys a sync asks for
+const LINE_ADDED: &'static [u8] =
+    b"\x00\x00\x00\xf4\x00\
+      \x00\x00\x00\x12_buffer_line_added\
+      hda\
+      \x00\x00\x00\x09line_data\
+      \x00\x00\x00\x66buffer:ptr,date:tim,date_printed:tim,displayed:chr,highlight:chr,tags_array:arr,\
+prefix:str,message:str\
+      \x00\x00\x00\x01\
+      \x0c55d0c5a0e0f0\
+      \x0956aabbccd\
+      \x0a1480000000\
+      \x0a1480000001\
+      \x01\
+      \x01\
+      str\x00\x00\x00\x01\x00\x00\x00\x0birc_privmsg\
+      \x00\x00\x00\x05alice\
+      \x00\x00\x00\x09alice: hi";
+
+/// Keeps what the relay handed it, like a handler in another crate would
+struct Lines {
+    lines: Vec<(String, Option<String>)>,
+}
+
+impl RelayHandler for Lines {
+    fn on_buffer_line(&mut self, line: &BufferLine, buffer: Option<&str>) {
+        self.lines.push((line.message().unwrap_or("").to_string(), buffer.map(|b| b.to_string())));
+    }
+
+    fn on_disconnect(&mut self, _err: &WeechatError) {
+        self.lines.clear();
+    }
+}
+
+#[test]
+fn parse_a_captured_message() {
+    let message: Message = read_message(&mut Cursor::new(LINE_ADDED)).unwrap();
+    assert_eq!(message.identifier, Some("_buffer_line_added".to_string()));
+
+    let hdata = message.as_hdata().unwrap();
+    let item = hdata.find_by_pointer("buffer", "0x56aabbccd").unwrap();
+    assert_eq!(item["prefix"], Object::Str(Some("alice".to_string())));
+
+    let lines: Vec<BufferLine> = hdata.items_as::<BufferLine>().map(|line| line.unwrap()).collect();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0].message(), Some("alice: hi"));
+    assert_eq!(lines[0].date(), 1480000000);
+    assert!(lines[0].highlight());
+    assert!(lines[0].has_tag("irc_privmsg"));
+}
+
+#[test]
+fn handlers_from_other_crates() {
+    let message = read_message(&mut Cursor::new(LINE_ADDED)).unwrap();
+    let mut handler = Lines { lines: Vec::new() };
+    for line in message.as_hdata().unwrap().items_as::<BufferLine>() {
+        handler.on_buffer_line(&line.unwrap(), Some("irc.libera.#rust"));
+    }
+    assert_eq!(handler.lines, vec![("alice: hi".to_string(), Some("irc.libera.#rust".to_string()))]);
+
+    // The methods it doesn't implement do nothing
+    let commands = CommandSender::new();
+    handler.on_connect(&commands);
+    handler.on_message(&message);
+    assert!(commands.take().is_empty());
+}